@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 use std::sync::Mutex as StdMutex;
-use u5_lib::hal::{Dcmi, Delay, Pin, Rtc};
+use u5_lib::hal::{Dcmi, Delay, DmaError, Pin, Rtc};
 
 // Use 4 byte in first block to store the number of image files
 const IMG_START_BLOCK: u32 = 10;
@@ -174,13 +174,14 @@ impl CameraSdCard for MockSdCard {
 
 struct MockDcmi;
 impl Dcmi for MockDcmi {
-    async fn capture(&self, pic_buf: &mut [u8]) {
+    async fn capture(&self, pic_buf: &mut [u8]) -> Result<(), DmaError> {
         // Write some fake JPEG data (with FFD9 end marker)
         pic_buf[0] = 0xFF;
         pic_buf[1] = 0xD8;
         pic_buf[2] = 0x00;
         pic_buf[100] = 0xFF;
         pic_buf[101] = 0xD9; // FFD9
+        Ok(())
     }
 }
 
@@ -197,7 +198,9 @@ fn main() {
 
     futures::executor::block_on(async {
         // 1. Capture a frame using the camera driver
-        u5_lib::drivers::ov5640::capture_frame(&pin, &delay, &dcmi, &mut buf[16..]).await;
+        u5_lib::drivers::ov5640::capture_frame(&pin, &delay, &dcmi, &mut buf[16..])
+            .await
+            .expect("capture failed");
 
         // 2. Save the captured frame to SD card
         save_picture(&mut buf[16..], &sd, &rtc).await;
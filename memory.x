@@ -2,5 +2,16 @@ MEMORY
 {
   FLASH : ORIGIN = 0x08000000, LENGTH = 4096K
   RAM   : ORIGIN = 0x20000000, LENGTH =  768K
+  SRAM4 : ORIGIN = 0x28000000, LENGTH =   16K
   OTP   : ORIGIN = 0x0bfa0000, LENGTH =  512
 }
+
+SECTIONS
+{
+  /* LPDMA1 only reaches SRAM4 in STOP2: its linked lists and buffers, not initialized */
+  .sram4 (NOLOAD) : ALIGN(4)
+  {
+    *(.sram4 .sram4.*);
+    . = ALIGN(4);
+  } > SRAM4
+} INSERT AFTER .uninit;
@@ -0,0 +1,228 @@
+//! # Single producer, single consumer byte queue
+//!
+//! `ByteRing` is shared between a task that queues bytes and an interrupt handler that takes them
+//! out, without locking: each side only moves its own index. The buffer is attached at runtime, so
+//! the ring itself can live in a `static` next to the interrupt handler.
+//!
+//! One slot stays free to tell a full ring from an empty one, a buffer of `n` bytes queues up to
+//! `n - 1` bytes.
+
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+pub struct ByteRing {
+    buf: AtomicPtr<u8>,
+    len: AtomicUsize,
+    /// next byte to take out
+    start: AtomicUsize,
+    /// next free slot
+    end: AtomicUsize,
+}
+
+impl Default for ByteRing {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ByteRing {
+    pub const fn new() -> Self {
+        Self {
+            buf: AtomicPtr::new(core::ptr::null_mut()),
+            len: AtomicUsize::new(0),
+            start: AtomicUsize::new(0),
+            end: AtomicUsize::new(0),
+        }
+    }
+
+    /// Attach the buffer of `len` bytes at `buf` and empty the ring.
+    ///
+    /// # Safety
+    /// `buf` must stay valid and must not be used elsewhere until `deinit`. There must be at most
+    /// one producer and one consumer.
+    pub unsafe fn init(&self, buf: *mut u8, len: usize) {
+        assert!(len >= 2);
+        self.start.store(0, Ordering::Relaxed);
+        self.end.store(0, Ordering::Relaxed);
+        self.buf.store(buf, Ordering::Relaxed);
+        self.len.store(len, Ordering::Release);
+    }
+
+    /// Detach the buffer, the queued bytes are dropped.
+    pub fn deinit(&self) {
+        self.len.store(0, Ordering::Release);
+        self.buf.store(core::ptr::null_mut(), Ordering::Relaxed);
+    }
+
+    /// A buffer is attached.
+    pub fn is_active(&self) -> bool {
+        self.len.load(Ordering::Acquire) != 0
+    }
+
+    /// Number of queued bytes.
+    pub fn len(&self) -> usize {
+        let len = self.len.load(Ordering::Acquire);
+        if len == 0 {
+            return 0;
+        }
+        let start = self.start.load(Ordering::Acquire);
+        let end = self.end.load(Ordering::Acquire);
+        (end + len - start) % len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start.load(Ordering::Acquire) == self.end.load(Ordering::Acquire)
+    }
+
+    /// Producer: queue as much of `data` as fits. Return the number of bytes queued.
+    pub fn push(&self, data: &[u8]) -> usize {
+        let len = self.len.load(Ordering::Acquire);
+        if len == 0 {
+            return 0;
+        }
+        let buf = self.buf.load(Ordering::Relaxed);
+        let start = self.start.load(Ordering::Acquire);
+        let mut end = self.end.load(Ordering::Relaxed);
+        let free = (start + len - end - 1) % len;
+        let n = free.min(data.len());
+        for &b in &data[..n] {
+            unsafe { buf.add(end).write_volatile(b) };
+            end = (end + 1) % len;
+        }
+        self.end.store(end, Ordering::Release);
+        n
+    }
+
+    /// Consumer: take out the oldest byte.
+    pub fn pop(&self) -> Option<u8> {
+        let len = self.len.load(Ordering::Acquire);
+        let start = self.start.load(Ordering::Relaxed);
+        if len == 0 || start == self.end.load(Ordering::Acquire) {
+            return None;
+        }
+        let b = unsafe { self.buf.load(Ordering::Relaxed).add(start).read_volatile() };
+        self.start.store((start + 1) % len, Ordering::Release);
+        Some(b)
+    }
+
+    /// Consumer: the oldest queued bytes that are contiguous in the buffer, as address and length,
+    /// for a dma transfer. Release them with `consume` once they are sent.
+    pub fn readable(&self) -> (*const u8, usize) {
+        let len = self.len.load(Ordering::Acquire);
+        let buf = self.buf.load(Ordering::Relaxed);
+        let start = self.start.load(Ordering::Relaxed);
+        let end = self.end.load(Ordering::Acquire);
+        if len == 0 {
+            return (buf, 0);
+        }
+        let n = if end >= start {
+            end - start
+        } else {
+            len - start
+        };
+        (unsafe { buf.add(start) }, n)
+    }
+
+    /// Consumer: drop the `n` oldest bytes.
+    pub fn consume(&self, n: usize) {
+        let len = self.len.load(Ordering::Acquire);
+        if len == 0 {
+            return;
+        }
+        let start = self.start.load(Ordering::Relaxed);
+        self.start.store((start + n) % len, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ring(buf: &mut [u8]) -> ByteRing {
+        let ring = ByteRing::new();
+        unsafe { ring.init(buf.as_mut_ptr(), buf.len()) };
+        ring
+    }
+
+    #[test]
+    fn test_push_pop() {
+        let mut buf = [0; 8];
+        let ring = ring(&mut buf);
+        assert!(ring.is_active());
+        assert!(ring.is_empty());
+        assert_eq!(ring.pop(), None);
+        assert_eq!(ring.push(b"hello"), 5);
+        assert_eq!(ring.len(), 5);
+        for &b in b"hel" {
+            assert_eq!(ring.pop(), Some(b));
+        }
+        // wraps around the end of the buffer
+        assert_eq!(ring.push(b" world"), 5);
+        assert_eq!(ring.len(), 7);
+        assert_eq!(ring.push(b"!"), 0);
+        let out: Vec<u8> = core::iter::from_fn(|| ring.pop()).collect();
+        assert_eq!(out, b"lo worl");
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn test_readable_is_contiguous() {
+        let mut buf = [0; 8];
+        let base = buf.as_ptr() as usize;
+        let ring = ring(&mut buf);
+        assert_eq!(ring.readable().1, 0);
+        ring.push(b"abcdef");
+        let (ptr, n) = ring.readable();
+        assert_eq!((ptr as usize - base, n), (0, 6));
+        ring.consume(6);
+        // 2 bytes up to the end of the buffer, then 3 from the start
+        ring.push(b"ghijk");
+        let (ptr, n) = ring.readable();
+        assert_eq!((ptr as usize - base, n), (6, 2));
+        assert_eq!(unsafe { core::slice::from_raw_parts(ptr, n) }, b"gh");
+        ring.consume(n);
+        let (ptr, n) = ring.readable();
+        assert_eq!((ptr as usize - base, n), (0, 3));
+        assert_eq!(unsafe { core::slice::from_raw_parts(ptr, n) }, b"ijk");
+        ring.consume(n);
+        assert!(ring.is_empty());
+    }
+
+    #[test]
+    fn test_deinit() {
+        let mut buf = [0; 4];
+        let ring = ring(&mut buf);
+        ring.push(b"ab");
+        ring.deinit();
+        assert!(!ring.is_active());
+        assert_eq!(ring.len(), 0);
+        assert_eq!(ring.push(b"c"), 0);
+        assert_eq!(ring.pop(), None);
+    }
+
+    #[test]
+    fn test_producer_consumer_threads() {
+        static RING: ByteRing = ByteRing::new();
+        let buf = Box::leak(Box::new([0u8; 16]));
+        unsafe { RING.init(buf.as_mut_ptr(), buf.len()) };
+        let total = 10_000;
+        let producer = std::thread::spawn(move || {
+            let data: Vec<u8> = (0..total).map(|i| i as u8).collect();
+            let mut sent = 0;
+            while sent < total {
+                sent += RING.push(&data[sent..]);
+                std::thread::yield_now();
+            }
+        });
+        let mut received = 0;
+        while received < total {
+            if let Some(b) = RING.pop() {
+                assert_eq!(b, received as u8);
+                received += 1;
+            } else {
+                std::thread::yield_now();
+            }
+        }
+        producer.join().unwrap();
+        assert!(RING.is_empty());
+    }
+}
@@ -45,18 +45,19 @@ where
     res
 }
 
-pub async fn hclk_request_async<F, R>(freq: ClockFreqs, code: F)
+pub async fn hclk_request_async<F, R, T>(freq: ClockFreqs, code: F) -> T
 where
     F: FnOnce() -> R,
-    R: core::future::Future<Output = ()>,
+    R: core::future::Future<Output = T>,
 {
     let idx = freq.to_idx();
     CLOCK_REQUESTS[idx].fetch_add(1, Ordering::SeqCst);
     set_clock();
     let result = code();
-    result.await;
+    let res = result.await;
     CLOCK_REQUESTS[idx].fetch_sub(1, Ordering::SeqCst);
     set_clock();
+    res
 }
 
 fn set_pll(freq: u32) {
@@ -225,32 +226,123 @@ pub fn set_sdmmc_clock(sdmmc: stm32_metapac::sdmmc::Sdmmc, clk_src: SdmmcClockSo
     }
 }
 
-pub fn set_usart_clock() {
-    // set usart1 clock source to hsi
-    RCC.ccipr1()
-        .modify(|v| v.set_usart1sel(stm32_metapac::rcc::vals::Usart1sel::HSI));
-    // enable usart1 clock
-    RCC.apb2enr().modify(|v| v.set_usart1en(true));
+/// enable usart clock and use HSI16 as kernel clock. Return the kernel clock frequency.
+/// `usart_num` 1 - 3 are USART1 - USART3, 4 and 5 are UART4 and UART5, 6 is LPUART1.
+/// HSI16 is not kept on in stop mode, a usart armed for it requests the clock by itself (`UCESM`).
+/// LPUART1 is also clocked in stop mode so it can receive in STOP2.
+pub fn set_usart_clock(usart_num: u8) -> u32 {
+    use stm32_metapac::rcc::vals::{Lpuartsel, Usart1sel, Usartsel};
+    match usart_num {
+        1 => {
+            RCC.ccipr1().modify(|v| v.set_usart1sel(Usart1sel::HSI));
+            RCC.apb2enr().modify(|v| v.set_usart1en(true));
+        }
+        2 => {
+            RCC.ccipr1().modify(|v| v.set_usart2sel(Usartsel::HSI));
+            RCC.apb1enr1().modify(|v| v.set_usart2en(true));
+        }
+        3 => {
+            RCC.ccipr1().modify(|v| v.set_usart3sel(Usartsel::HSI));
+            RCC.apb1enr1().modify(|v| v.set_usart3en(true));
+        }
+        4 => {
+            RCC.ccipr1().modify(|v| v.set_uart4sel(Usartsel::HSI));
+            RCC.apb1enr1().modify(|v| v.set_uart4en(true));
+        }
+        5 => {
+            RCC.ccipr1().modify(|v| v.set_uart5sel(Usartsel::HSI));
+            RCC.apb1enr1().modify(|v| v.set_uart5en(true));
+        }
+        6 => {
+            RCC.ccipr3().modify(|v| v.set_lpuart1sel(Lpuartsel::HSI));
+            RCC.apb3enr().modify(|v| v.set_lpuart1en(true));
+            RCC.apb3smenr().modify(|v| v.set_lpuart1smen(true));
+        }
+        _ => panic!("Invalid usart number"),
+    }
+    HSI_FREQ
+}
+
+/// Kernel clock of the i2c peripherals.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum I2cClockSource {
+    /// HSI16, does not change with the system clock.
+    Hsi,
+    /// APB clock (PCLK1 for I2C1/2/4, PCLK3 for I2C3). The APB prescalers are not used, so this is
+    /// HCLK. The bus speed changes if the system clock is changed after the i2c is initialized.
+    Pclk,
+    /// MSIK 4Mhz, keeps running in stop mode.
+    Msik,
 }
 
-pub fn set_i2c_clock(i2c_num: u8) {
-    // set i2c1 clock source to hsi
+impl I2cClockSource {
+    pub fn freq(&self) -> u32 {
+        match self {
+            I2cClockSource::Hsi => HSI_FREQ,
+            I2cClockSource::Pclk => get_hclk(),
+            I2cClockSource::Msik => MSIK_FREQ,
+        }
+    }
+}
+
+/// enable i2c clock and select the kernel clock. Return the kernel clock frequency.
+pub fn set_i2c_clock(i2c_num: u8, source: I2cClockSource) -> u32 {
+    use stm32_metapac::rcc::vals::{I2c3sel, I2csel};
+    let (sel, sel3) = match source {
+        I2cClockSource::Hsi => {
+            RCC.cr().modify(|v| v.set_hsikeron(true));
+            (I2csel::HSI, I2c3sel::HSI)
+        }
+        I2cClockSource::Pclk => (I2csel::PCLK1, I2c3sel::PCLK3),
+        I2cClockSource::Msik => {
+            RCC.cr().modify(|v| v.set_msikeron(true));
+            (I2csel::MSIK, I2c3sel::MSIK)
+        }
+    };
     if i2c_num == 1 {
-        RCC.ccipr1().modify(|v| v.set_i2c1sel(stm32_metapac::rcc::vals::I2csel::HSI));
+        RCC.ccipr1().modify(|v| v.set_i2c1sel(sel));
         // enable i2c1 clock
         RCC.apb1enr1().modify(|v| v.set_i2c1en(true));
     } else if i2c_num == 2 {
-        RCC.ccipr1().modify(|v| v.set_i2c2sel(stm32_metapac::rcc::vals::I2csel::HSI));
+        RCC.ccipr1().modify(|v| v.set_i2c2sel(sel));
         // enable i2c2 clock
         RCC.apb1enr1().modify(|v| v.set_i2c2en(true));
         RCC.apb1smenr1().modify(|v| v.set_i2c2smen(true));
     } else if i2c_num == 3 {
-        RCC.ccipr3().modify(|v| v.set_i2c3sel(stm32_metapac::rcc::vals::I2c3sel::HSI));
+        RCC.ccipr3().modify(|v| v.set_i2c3sel(sel3));
         // enable i2c3 clock
         RCC.apb3enr().modify(|v| v.set_i2c3en(true));
+    } else if i2c_num == 4 {
+        RCC.ccipr2().modify(|v| v.set_i2c4sel(sel));
+        // enable i2c4 clock
+        RCC.apb1enr2().modify(|v| v.set_i2c4en(true));
     } else {
         panic!("Invalid i2c number");
     }
+    source.freq()
+}
+
+/// enable spi clock and use HSI16 as kernel clock. Return the kernel clock frequency.
+/// HSI16 is used so the SCK frequency does not change when the system clock is changed.
+pub fn set_spi_clock(spi_num: u8) -> u32 {
+    RCC.cr().modify(|v| v.set_hsikeron(true));
+    match spi_num {
+        1 => {
+            RCC.ccipr1().modify(|v| v.set_spi1sel(stm32_metapac::rcc::vals::Spi1sel::HSI));
+            RCC.apb2enr().modify(|v| v.set_spi1en(true));
+        }
+        2 => {
+            RCC.ccipr1().modify(|v| v.set_spi2sel(stm32_metapac::rcc::vals::Spi2sel::HSI));
+            RCC.apb1enr1().modify(|v| v.set_spi2en(true));
+        }
+        3 => {
+            RCC.ccipr3().modify(|v| v.set_spi3sel(stm32_metapac::rcc::vals::Spi3sel::HSI));
+            RCC.apb3enr().modify(|v| v.set_spi3en(true));
+        }
+        _ => panic!("Invalid spi number"),
+    }
+    HSI_FREQ
 }
 
 /// enable lptim for all mode and use LSE as clock source
@@ -650,3 +742,16 @@ impl crate::hal::Delay for Delay {
     }
 }
 
+#[cfg(feature = "embedded-hal")]
+impl embedded_hal::delay::DelayNs for Delay {
+    fn delay_ns(&mut self, ns: u32) {
+        // the DWT based delay has a microsecond resolution, round up
+        delay_us(ns.div_ceil(1_000));
+    }
+    fn delay_us(&mut self, us: u32) {
+        delay_us(us);
+    }
+    fn delay_ms(&mut self, ms: u32) {
+        delay_ms(ms);
+    }
+}
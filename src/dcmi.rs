@@ -3,6 +3,7 @@
 use crate::clock;
 use crate::clock::delay_tick;
 use crate::gpio::GpioPort;
+use crate::hal;
 use cortex_m::peripheral::NVIC;
 use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
 use embassy_sync::signal::Signal;
@@ -11,17 +12,22 @@ use stm32_metapac::RCC;
 
 pub struct DcmiPort {
     port: Dcmi,
+    dma: DmaChannel,
 }
 
-pub const DCMI: DcmiPort = DcmiPort {
-    port: stm32_metapac::DCMI,
-};
-
 use crate::dma::*;
 use crate::gpio::*;
 
 #[allow(clippy::too_many_arguments)]
 impl DcmiPort {
+    /// The DCMI with a dma channel taken for its captures.
+    pub fn new() -> Result<Self, hal::DmaError> {
+        Ok(Self {
+            port: stm32_metapac::DCMI,
+            dma: DmaChannel::take("DCMI", Dw::WORD)?,
+        })
+    }
+
     pub fn init(
         &self,
         d0: GpioPort,
@@ -51,11 +57,13 @@ impl DcmiPort {
         pclk.setup();
     }
 
-    pub async fn capture(&self, dma: DmaChannel, buf: &[u8]) {
+    /// Capture one frame into `buf`. The transfer is aborted when the frame has ended
+    /// (or this future is dropped), `buf` is not touched afterwards.
+    pub async fn capture(&self, buf: &mut [u8]) -> Result<(), hal::DmaError> {
         // this function requires 160mhz clock and the deep sleep mode not allowed
         // crate::clock::run_with_160mhz_async(|| async {
-        clock::hclk_request_async(clock::ClockFreqs::KernelFreq160Mhz, || async {
-            crate::low_power::run_no_deep_sleep_async(|| async {
+        clock::hclk_request_async(clock::ClockFreqs::KernelFreq160Mhz, move || async move {
+            crate::low_power::run_no_deep_sleep_async(move || async move {
                 self.port.cr().modify(|v| {
                     v.set_jpeg(true);
                     v.set_cm(false);
@@ -63,12 +71,10 @@ impl DcmiPort {
                     v.set_hspol(true);
                     v.set_pckpol(true);
                 });
-                let dst_addr = buf.as_ptr() as u32;
-                let len = buf.len() as u32;
                 // get draddress
                 let src_addr = self.port.dr().as_ptr() as u32;
-                // dma.start(src_addr, dst_addr, len);
-                dma.start(src_addr, true, dst_addr, true, len).await;
+                // the transfer is a local, dropped (and aborted) before the borrow of `buf` ends
+                let _transfer = unsafe { Transfer::read(&self.dma, src_addr, buf) }?;
 
                 // start capture
                 self.port.cr().modify(|v| {
@@ -77,18 +83,19 @@ impl DcmiPort {
                 });
 
                 self.get_picture_async().await;
-                self.stop_capture(dma);
+                self.stop_capture();
+                Ok(())
             })
-            .await;
+            .await
         })
-        .await;
+        .await
     }
-    pub fn stop_capture(&self, dma: DmaChannel) {
+    pub fn stop_capture(&self) {
         self.port.cr().modify(|v| v.set_capture(false));
         self.port.cr().modify(|v| v.set_enable(false));
         // claer all interrupt flags
         self.port.icr().write(|w| w.0 = 0x1f);
-        dma.stop();
+        self.dma.stop();
     }
     pub fn get_picture(&self) -> bool {
         self.port.ris().read().frame_ris()
@@ -107,8 +114,8 @@ impl DcmiPort {
 }
 
 impl crate::hal::Dcmi for DcmiPort {
-    async fn capture(&self, pic_buf: &mut [u8]) {
-        self.capture(crate::dma::DMA_DCMI, pic_buf).await
+    async fn capture(&self, pic_buf: &mut [u8]) -> Result<(), hal::DmaError> {
+        self.capture(pic_buf).await
     }
 }
 
@@ -1,20 +1,36 @@
 #![allow(unused)]
 
 use crate::clock::delay_tick;
+use crate::dma_lli::{build_double_buffer, build_linear, data_width, Block2d, Lli, LliTransfer, Rect2d, MAX_BLOCK};
+use crate::dma_request::{gpdma1_request, is_block_request, lpdma1_request, lpdma1_trigger};
+use crate::dma_ring::DmaRingError;
 use crate::gpio::GpioPort;
+use crate::hal;
+use stm32_metapac::common::{Read, Reg, Write};
 use stm32_metapac::gpdma::vals;
 use stm32_metapac::gpdma::vals::*;
 use stm32_metapac::gpdma::Channel;
 use stm32_metapac::gpdma::Gpdma;
 use stm32_metapac::RCC;
 
+pub use stm32_metapac::gpdma::vals::Dw;
+
+/// A GPDMA1 or LPDMA1 channel owned by a driver, taken from the free channels with
+/// `DmaChannel::take` (`take_lpdma`) and given back on drop.
 pub struct DmaChannel {
     ins: Gpdma,
     ch: usize,
+    /// index in the channel statics, GPDMA1 0 - 15, LPDMA1 16 - 19
+    slot: usize,
     request_source: u8,
+    /// a block per request (`BREQ`)
+    block_request: bool,
+    trigger: Option<DmaTrigger>,
     src_width: Dw, // ChTr1Dw,
     dst_width: Dw, // ChTr1Dw,
     tc_mode: Tcem, // ChTr2Tcem,
+    /// memory to memory, started by software instead of a peripheral request
+    software_request: bool,
 }
 /// Determines the AP port based on the STM32U5 address space.
 /// - PORT0 is used for peripherals: 0x4000_0000 to 0x5FFF_FFFF
@@ -27,59 +43,237 @@ fn get_ap_port_from_addr(addr: u32) -> Ap {
     }
 }
 
-// write a macro to define the dma channel
-macro_rules! define_dma_channel {
-    ($name:ident, $ins:ident, $ch:expr, $request_source:expr, $src_width:expr, $dst_width:expr, $tc_mode:expr) => {
-        pub const $name: DmaChannel = DmaChannel {
-            ins: stm32_metapac::$ins,
-            ch: $ch,
-            request_source: $request_source,
-            src_width: $src_width,
-            dst_width: $dst_width,
-            tc_mode: $tc_mode,
-        };
-    };
+use crate::gpio::*;
+
+/// Hardware trigger of a channel (`TRIGSEL`), e.g. a timer or EXTI line that paces the requests
+/// of an autonomous LPDMA1 transfer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DmaTrigger {
+    pub source: u8,
+    pub mode: TriggerMode,
+    pub polarity: TriggerPolarity,
 }
-// define the dma channel
-define_dma_channel!(DMA_DCMI, GPDMA1, 0, 86, Dw::WORD, Dw::WORD, Tcem::LAST_LINKED_LIST_ITEM);
-define_dma_channel!(DMA_USART1_RX, GPDMA1, 1, 24, Dw::BYTE, Dw::BYTE, Tcem::LAST_LINKED_LIST_ITEM);
-define_dma_channel!(DMA_USART1_TX, GPDMA1, 2, 25, Dw::BYTE, Dw::BYTE, Tcem::LAST_LINKED_LIST_ITEM);
-define_dma_channel!(DMA_USART2_RX, GPDMA1, 3, 26, Dw::BYTE, Dw::BYTE, Tcem::LAST_LINKED_LIST_ITEM);
-define_dma_channel!(DMA_USART2_TX, GPDMA1, 4, 27, Dw::BYTE, Dw::BYTE, Tcem::LAST_LINKED_LIST_ITEM);
-define_dma_channel!(DMA_USART3_RX, GPDMA1, 5, 28, Dw::BYTE, Dw::BYTE, Tcem::LAST_LINKED_LIST_ITEM);
-define_dma_channel!(DMA_USART3_TX, GPDMA1, 6, 29, Dw::BYTE, Dw::BYTE, Tcem::LAST_LINKED_LIST_ITEM);
-define_dma_channel!(DMA_USART4_RX, GPDMA1, 7, 30, Dw::BYTE, Dw::BYTE, Tcem::LAST_LINKED_LIST_ITEM);
-define_dma_channel!(DMA_USART4_TX, GPDMA1, 8, 31, Dw::BYTE, Dw::BYTE, Tcem::LAST_LINKED_LIST_ITEM);
-define_dma_channel!(DMA_USART5_RX, GPDMA1, 9, 32, Dw::BYTE, Dw::BYTE, Tcem::LAST_LINKED_LIST_ITEM);
-define_dma_channel!(DMA_USART5_TX, GPDMA1, 10, 33, Dw::BYTE, Dw::BYTE, Tcem::LAST_LINKED_LIST_ITEM);
 
-use crate::gpio::*;
+/// What a trigger edge starts (`TRIGM`).
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TriggerMode {
+    Block = 0,
+    LinkedListItem = 2,
+    /// a single data (or burst) per trigger
+    Single = 3,
+}
 
-#[repr(C)]
-#[derive(Copy, Clone)]
-struct ListNode {
-    br1: u32, // byte count
-    sar: u32, // source address
-    dar: u32, // destination address
-    llr: u32, // linked list register
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TriggerPolarity {
+    Rising = 1,
+    Falling = 2,
+}
+
+impl DmaTrigger {
+    /// The trigger `name` of the LPDMA1 trigger table (see `dma_request`), e.g. "LPTIM1_CH1".
+    pub fn lpdma1(name: &str, mode: TriggerMode, polarity: TriggerPolarity) -> Result<Self, hal::DmaError> {
+        let source = lpdma1_trigger(name).ok_or(hal::DmaError::UnknownRequest)?;
+        Ok(Self { source, mode, polarity })
+    }
 }
-type DmaList = [ListNode; 32];
 
-type DmaLists = [DmaList; 10];
+/// The items of a channel, aligned to their size so that a list never crosses a 64 KiB page.
+#[repr(C, align(512))]
+#[derive(Copy, Clone)]
+struct DmaList([Lli; 32]);
+
+/// linked lists of the channels, only changed by the owner of a channel while it is stopped
+static LINK_LISTS: [Mutex<CriticalSectionRawMutex, RefCell<DmaList>>; 16] =
+    [const { Mutex::new(RefCell::new(DmaList([Lli::new(); 32]))) }; 16];
+
+/// LPDMA1 reaches only SRAM4 in STOP2, so the lists of its channels are kept there (`.sram4`
+/// in memory.x). The section is not initialized, the items are built before a channel reads them.
+const LPDMA_LIST_LEN: usize = 8;
+struct LpdmaLists(UnsafeCell<[[Lli; LPDMA_LIST_LEN]; 4]>);
+// the list of a channel is only used by its owner, in a critical section
+unsafe impl Sync for LpdmaLists {}
+#[link_section = ".sram4"]
+static LPDMA_LINK_LISTS: LpdmaLists = LpdmaLists(UnsafeCell::new([[Lli::new(); LPDMA_LIST_LEN]; 4]));
 
-const DMA_SINGLE_XFER: u32 = 65532; // single transfer max size
-static mut LINK_LISTS: DmaLists = [[ListNode {
-    br1: 0,
-    sar: 0,
-    dar: 0,
-    llr: 0,
-}; 32]; 10];
+/// GPDMA1 channels 0 - 11 address linearly, 12 - 15 also support 2D addressing. The 4 LPDMA1
+/// channels follow as slots 16 - 19.
+const LINEAR_CHANNELS: core::ops::Range<usize> = 0..12;
+const CHANNELS_2D: core::ops::Range<usize> = 12..16;
+const LPDMA_CHANNELS: core::ops::Range<usize> = 16..20;
+const SLOTS: usize = 20;
+/// bit n set: slot n is owned by a `DmaChannel`
+static TAKEN_CHANNELS: AtomicU32 = AtomicU32::new(0);
+
+fn alloc_channel(channels: core::ops::Range<usize>) -> Option<usize> {
+    let mut ch = None;
+    TAKEN_CHANNELS
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |taken| {
+            ch = channels.clone().find(|&c| taken & (1 << c) == 0);
+            ch.map(|c| taken | (1 << c))
+        })
+        .ok()?;
+    ch
+}
 
 impl DmaChannel {
+    /// Take a free channel for the request `request` of the GPDMA1 request table (see
+    /// `dma_request`), e.g. `DmaChannel::take("USART2_TX", Dw::BYTE)`. Linear channels are
+    /// handed out first, the 2D channels only when they are the last ones free.
+    pub fn take(request: &str, width: Dw) -> Result<Self, hal::DmaError> {
+        let request_source = gpdma1_request(request).ok_or(hal::DmaError::UnknownRequest)?;
+        let ch = alloc_channel(LINEAR_CHANNELS).or_else(|| alloc_channel(CHANNELS_2D));
+        let mut dma = Self::new(ch.ok_or(hal::DmaError::NoFreeChannel)?, request_source, width);
+        dma.block_request = is_block_request(request);
+        Ok(dma)
+    }
+
+    /// Take a free 2D addressing channel (12 - 15).
+    pub fn take_2d(request: &str, width: Dw) -> Result<Self, hal::DmaError> {
+        let request_source = gpdma1_request(request).ok_or(hal::DmaError::UnknownRequest)?;
+        let ch = alloc_channel(CHANNELS_2D).ok_or(hal::DmaError::NoFreeChannel)?;
+        let mut dma = Self::new(ch, request_source, width);
+        dma.block_request = is_block_request(request);
+        Ok(dma)
+    }
+
+    /// Take a free LPDMA1 channel for the request `request` of the LPDMA1 request table, e.g.
+    /// `DmaChannel::take_lpdma("ADC4", Dw::HALF_WORD)`. With `set_autonomous` it keeps running
+    /// in STOP2, where it reaches SRAM4 and the SmartRun domain peripherals (LPUART1, SPI3, I2C3,
+    /// LPTIM1/3, ADC4, DAC1, ADF1).
+    pub fn take_lpdma(request: &str, width: Dw) -> Result<Self, hal::DmaError> {
+        let request_source = lpdma1_request(request).ok_or(hal::DmaError::UnknownRequest)?;
+        let slot = alloc_channel(LPDMA_CHANNELS).ok_or(hal::DmaError::NoFreeChannel)?;
+        let mut dma = Self::new(slot, request_source, width);
+        dma.block_request = is_block_request(request);
+        Ok(dma)
+    }
+
+    /// Take a free channel for memory to memory transfers of `width` data, started by software.
+    pub fn take_memory(width: Dw) -> Result<Self, hal::DmaError> {
+        let ch = alloc_channel(LINEAR_CHANNELS).or_else(|| alloc_channel(CHANNELS_2D));
+        let mut dma = Self::new(ch.ok_or(hal::DmaError::NoFreeChannel)?, 0, width);
+        dma.software_request = true;
+        Ok(dma)
+    }
+
+    /// Take a free 2D channel for memory to memory transfers, see `start_2d`.
+    pub fn take_memory_2d(width: Dw) -> Result<Self, hal::DmaError> {
+        let ch = alloc_channel(CHANNELS_2D).ok_or(hal::DmaError::NoFreeChannel)?;
+        let mut dma = Self::new(ch, 0, width);
+        dma.software_request = true;
+        Ok(dma)
+    }
+
+    fn new(slot: usize, request_source: u8, width: Dw) -> Self {
+        let (ins, ch) = if LPDMA_CHANNELS.contains(&slot) {
+            (stm32_metapac::LPDMA1, slot - LPDMA_CHANNELS.start)
+        } else {
+            (stm32_metapac::GPDMA1, slot)
+        };
+        Self {
+            ins,
+            ch,
+            slot,
+            request_source,
+            block_request: false,
+            trigger: None,
+            src_width: width,
+            dst_width: width,
+            tc_mode: Tcem::LAST_LINKED_LIST_ITEM,
+            software_request: false,
+        }
+    }
+
+    /// Channel number of its controller (GPDMA1 0 - 15, LPDMA1 0 - 3).
+    pub fn channel(&self) -> usize {
+        self.ch
+    }
+
+    /// The channel belongs to LPDMA1.
+    pub fn is_lpdma(&self) -> bool {
+        LPDMA_CHANNELS.contains(&self.slot)
+    }
+
+    /// Pace the requests by a hardware trigger from the next transfer on, `None` to start on the
+    /// request alone. For example DAC1 samples from SRAM4 on each LPTIM1 channel 1 edge:
+    ///
+    /// ```ignore
+    /// let mut dma = DmaChannel::take_lpdma("DAC1_CH1", Dw::HALF_WORD)?;
+    /// dma.set_trigger(Some(DmaTrigger::lpdma1("LPTIM1_CH1", TriggerMode::Single, TriggerPolarity::Rising)?));
+    /// ```
+    pub fn set_trigger(&mut self, trigger: Option<DmaTrigger>) {
+        self.trigger = trigger;
+    }
+
+    /// Keep the LPDMA1 (and SRAM4) clocked in STOP mode so the channel goes on transferring
+    /// while `low_power::Executor` deep-sleeps. The peripheral must run autonomously as well
+    /// (e.g. `ADC4AMEN` in `RCC_SRDAMR`). ADC4 samples into SRAM4:
+    ///
+    /// ```ignore
+    /// #[link_section = ".sram4"]
+    /// static mut SAMPLES: [u8; 1024] = [0; 1024];
+    ///
+    /// let dma = DmaChannel::take_lpdma("ADC4", Dw::HALF_WORD)?;
+    /// dma.set_autonomous(true);
+    /// let samples = unsafe { &mut *core::ptr::addr_of_mut!(SAMPLES) };
+    /// let mut halves = PingPong::new_static(&dma, stm32_metapac::ADC4.dr(), samples)?;
+    /// loop {
+    ///     // the core stays in STOP2 until a half is filled
+    ///     let half = halves.next().await?;
+    /// }
+    /// ```
+    pub fn set_autonomous(&self, enable: bool) {
+        assert!(self.is_lpdma(), "only LPDMA1 runs autonomously in STOP2");
+        RCC.ahb3smenr().modify(|v| {
+            v.set_lpdma1smen(enable);
+            if enable {
+                v.set_sram4smen(true);
+            }
+        });
+        RCC.srdamr().modify(|v| {
+            v.set_lpdma1amen(enable);
+            if enable {
+                v.set_sram4amen(true);
+            }
+        });
+    }
+
+    /// The AHB port for `addr`, LPDMA1 has a single one.
+    fn port(&self, addr: u32) -> Ap {
+        if self.is_lpdma() {
+            Ap::PORT0
+        } else {
+            get_ap_port_from_addr(addr)
+        }
+    }
+
+    /// Run `f` on the linked list of the channel.
+    fn with_list<R>(&self, f: impl FnOnce(&mut [Lli]) -> R) -> R {
+        if self.is_lpdma() {
+            critical_section::with(|_| f(unsafe { &mut (*LPDMA_LINK_LISTS.0.get())[self.ch] }))
+        } else {
+            LINK_LISTS[self.slot].lock(|list| f(&mut list.borrow_mut().0[..]))
+        }
+    }
+
+    /// Configure the channel. A transfer that is still running is aborted first.
     pub fn init(&self) {
-        RCC.ahb1enr().modify(|v| v.set_gpdma1en(true));
+        if self.is_lpdma() {
+            RCC.ahb3enr().modify(|v| {
+                v.set_lpdma1en(true);
+                v.set_sram4en(true);
+            });
+        } else {
+            RCC.ahb1enr().modify(|v| v.set_gpdma1en(true));
+        }
         // setup gpio ports
         let ch = self.ins.ch(self.ch);
+        if ch.cr().read().en() {
+            self.stop();
+        }
         ch.tr1().modify(|v| {
             v.set_sdw(self.src_width);
             v.set_ddw(self.dst_width);
@@ -87,102 +281,715 @@ impl DmaChannel {
         ch.tr2().modify(|v| {
             v.set_tcem(self.tc_mode);
             v.set_reqsel(self.request_source);
+            v.set_swreq(self.software_request);
+            v.set_breq(self.block_request);
+            match self.trigger {
+                Some(trigger) => {
+                    v.set_trigsel(trigger.source);
+                    v.set_trigm(vals::Trigm::from_bits(trigger.mode as u8));
+                    v.set_trigpol(vals::Trigpol::from_bits(trigger.polarity as u8));
+                }
+                None => v.set_trigpol(vals::Trigpol::from_bits(0)),
+            }
         });
+        if CHANNELS_2D.contains(&self.slot) {
+            // linear transfers on a 2D channel must not keep the offsets of a previous 2D one
+            ch.tr3().write(|v| v.0 = 0);
+            ch.br2().write(|v| v.0 = 0);
+        }
     }
-    pub async fn start(&self, src_addr: u32, src_inc: bool, dar_addr: u32, dst_inc: bool, len: u32) {
+    /// Start a transfer of `len` bytes between raw addresses. Prefer `Transfer::read_static` and
+    /// `Transfer::write_static`.
+    ///
+    /// # Safety
+    /// The memory at `src_addr` and `dar_addr` must stay valid until the returned transfer has
+    /// finished or is dropped.
+    pub unsafe fn start(
+        &self,
+        src_addr: u32,
+        src_inc: bool,
+        dar_addr: u32,
+        dst_inc: bool,
+        len: u32,
+    ) -> Result<Transfer<'_>, hal::DmaError> {
+        self.start_transfer(src_addr, src_inc, dar_addr, dst_inc, len)?;
+        Ok(Transfer {
+            dma: self,
+            buf: None,
+            _memory: PhantomData,
+            done: false,
+        })
+    }
+
+    /// Configure and enable the channel without waiting, usable from an interrupt handler.
+    ///
+    /// # Safety
+    /// The memory at `src_addr` and `dar_addr` must stay valid until the channel is idle or
+    /// stopped.
+    pub unsafe fn start_transfer(
+        &self,
+        src_addr: u32,
+        src_inc: bool,
+        dar_addr: u32,
+        dst_inc: bool,
+        len: u32,
+    ) -> Result<(), hal::DmaError> {
         self.init();
         let ch = self.ins.ch(self.ch);
         ch.tr1().modify(|v| {
             v.set_sinc(src_inc);
             v.set_dinc(dst_inc);
-            v.set_sap(get_ap_port_from_addr(src_addr));
-            v.set_dap(get_ap_port_from_addr(dar_addr));
+            v.set_sap(self.port(src_addr));
+            v.set_dap(self.port(dar_addr));
         });
-        assert!(len < DMA_SINGLE_XFER * 32);
-        let link_list = unsafe { &mut LINK_LISTS[self.ch] };
-        let num_link_list = len.div_ceil(DMA_SINGLE_XFER);
-        for i in 0..num_link_list as usize {
-            link_list[i].sar = src_addr + u32::from(src_inc) * (i as u32 * DMA_SINGLE_XFER);
-            link_list[i].dar = dar_addr + u32::from(dst_inc) * (i as u32 * DMA_SINGLE_XFER);
-            link_list[i].llr = &link_list[i + 1] as *const _ as u32;
-            link_list[i].llr &= 0x0000ffff;
-            link_list[i].br1 = DMA_SINGLE_XFER;
-            // let addr = &mut link_list.val[1] as *mut _ as u32;
-            // set llr to update br(29), sar(28), dar(27), llr(16);
-            link_list[i].llr |= (1 << 29) | (1 << 28) | (1 << 27) | (1 << 16);
-            // handle last link list
-            if i == num_link_list as usize - 1 {
-                link_list[i].llr = 0;
-                if !len.is_multiple_of(DMA_SINGLE_XFER) {
-                    link_list[i].br1 = len % DMA_SINGLE_XFER;
-                }
-            }
-        }
-        ch.lbar().write(|v| v.set_lba(((link_list.as_ptr() as u32) >> 16) as u16));
-        ch.sar().write_value(link_list[0].sar);
-        ch.dar().write_value(link_list[0].dar);
-        ch.br1().modify(|v| v.0 = link_list[0].br1);
-        ch.llr().modify(|v| v.0 = link_list[0].llr);
+        let transfer = LliTransfer {
+            src: src_addr,
+            src_inc,
+            dst: dar_addr,
+            dst_inc,
+            len,
+        };
+        self.with_list(|list| {
+            let base = list.as_ptr() as u32;
+            build_linear(list, base, transfer, false)?;
+            self.load_list(list);
+            Ok::<(), hal::DmaError>(())
+        })?;
+        self.enable();
+        Ok(())
+    }
 
+    /// Enable a one-shot transfer with its transfer complete and error interrupts.
+    fn enable(&self) {
+        let ch = self.ins.ch(self.ch);
+        CHANNEL_ERRORS[self.slot].store(0, Ordering::Relaxed);
+        ch.fcr().write(|v| {
+            v.set_tcf(true);
+            v.set_htf(true);
+            v.set_dtef(true);
+            v.set_ulef(true);
+            v.set_usef(true);
+        });
         ch.cr().modify(|v| {
-            v.set_en(true);
+            v.set_tcie(true);
+            v.set_dteie(true);
+            v.set_uleie(true);
+            v.set_useie(true);
+        });
+        unsafe { NVIC::unmask(channel_interrupt(self.slot)) };
+        ch.cr().modify(|v| v.set_en(true));
+    }
+
+    /// Copy the rectangle `rect` on a 2D channel (`take_2d`, `take_memory_2d`): one block per row,
+    /// the stride gaps are skipped after each row.
+    ///
+    /// # Safety
+    /// The memory of `rect` must stay valid until the returned transfer has finished or is
+    /// dropped.
+    pub unsafe fn start_2d(&self, rect: &Rect2d) -> Result<Transfer<'_>, hal::DmaError> {
+        assert!(CHANNELS_2D.contains(&self.slot), "not a 2D dma channel");
+        let block = Block2d::new(rect)?;
+        self.init();
+        let ch = self.ins.ch(self.ch);
+        ch.tr1().modify(|v| {
+            v.set_sinc(true);
+            v.set_dinc(true);
+            v.set_sap(self.port(rect.src));
+            v.set_dap(self.port(rect.dst));
         });
+        ch.sar().write_value(rect.src);
+        ch.dar().write_value(rect.dst);
+        ch.br1().modify(|v| v.0 = block.br1);
+        ch.br2().modify(|v| v.0 = block.br2);
+        ch.llr().modify(|v| v.0 = 0);
+        self.enable();
+        Ok(Transfer {
+            dma: self,
+            buf: None,
+            _memory: PhantomData,
+            done: false,
+        })
+    }
 
-        // enable interrupt
+    /// The channel is not transferring (never started, finished or stopped).
+    pub fn is_idle(&self) -> bool {
+        self.ins.ch(self.ch).sr().read().idlef()
+    }
+
+    /// Wait until the channel is idle, woken by the transfer complete interrupt.
+    pub async fn wait_idle(&self) {
+        unsafe { NVIC::unmask(channel_interrupt(self.slot)) };
+        poll_fn(|cx| {
+            CHANNEL_WAKERS[self.slot].register(cx.waker());
+            if self.is_idle() {
+                core::task::Poll::Ready(())
+            } else {
+                core::task::Poll::Pending
+            }
+        })
+        .await;
+    }
+
+    /// Load the channel registers with the first item of `list`.
+    fn load_list(&self, list: &[Lli]) {
+        let ch = self.ins.ch(self.ch);
+        ch.lbar().write(|v| v.set_lba(((list.as_ptr() as u32) >> 16) as u16));
+        ch.sar().write_value(list[0].sar);
+        ch.dar().write_value(list[0].dar);
+        ch.br1().modify(|v| v.0 = list[0].br1);
+        ch.llr().modify(|v| v.0 = list[0].llr);
+    }
+
+    /// Prepare a transfer that ends each block with a transfer complete and runs until `stop`.
+    fn init_continuous(&self, src_addr: u32, dst_addr: u32) {
+        self.init();
+        let ch = self.ins.ch(self.ch);
+        ch.tr1().modify(|v| {
+            v.set_sinc(false);
+            v.set_dinc(true);
+            v.set_sap(self.port(src_addr));
+            v.set_dap(self.port(dst_addr));
+        });
+        // transfer complete at the end of each block, half transfer in the middle
+        ch.tr2().modify(|v| v.set_tcem(Tcem::BLOCK));
+        LAPS[self.slot].store(0, Ordering::Relaxed);
+        HALF_TRANSFERS[self.slot].store(0, Ordering::Relaxed);
+        CHANNEL_ERRORS[self.slot].store(0, Ordering::Relaxed);
+        ch.fcr().write(|v| {
+            v.set_tcf(true);
+            v.set_htf(true);
+            v.set_dtef(true);
+            v.set_ulef(true);
+            v.set_usef(true);
+        });
+    }
+
+    /// Enable the continuous transfer loaded by `load_list`.
+    fn enable_continuous(&self) {
+        let ch = self.ins.ch(self.ch);
         ch.cr().modify(|v| {
             v.set_tcie(true);
+            v.set_htie(true);
+            v.set_dteie(true);
+            v.set_uleie(true);
+            v.set_useie(true);
         });
-        // wait for finished interrupt and return
+        unsafe { NVIC::unmask(channel_interrupt(self.slot)) };
+        ch.cr().modify(|v| v.set_en(true));
+    }
+
+    /// Start a circular transfer of `len` bytes from the peripheral register `src_addr` into the
+    /// buffer at `dst_addr`. A single linked-list item links back to itself, so the channel keeps
+    /// running until `stop`. The channel interrupt counts the completed passes (`laps`) and the
+    /// half transfers, and wakes the waker registered with `register_waker` at half and full
+    /// buffer. `len` is 1 to `MAX_BLOCK` bytes, one block.
+    ///
+    /// # Safety
+    /// The buffer must stay valid until the channel is stopped.
+    pub unsafe fn start_circular(&self, src_addr: u32, dst_addr: u32, len: u32) -> Result<(), hal::DmaError> {
+        if len > MAX_BLOCK {
+            return Err(hal::DmaError::TooManyBlocks);
+        }
+        self.init_continuous(src_addr, dst_addr);
+        let transfer = LliTransfer {
+            src: src_addr,
+            src_inc: false,
+            dst: dst_addr,
+            dst_inc: true,
+            len,
+        };
+        self.with_list(|list| {
+            let base = list.as_ptr() as u32;
+            build_linear(list, base, transfer, true)?;
+            self.load_list(list);
+            Ok::<(), hal::DmaError>(())
+        })?;
+        self.enable_continuous();
+        Ok(())
+    }
+
+    /// Start filling the two halves of the `len` bytes at `dst_addr` from the peripheral register
+    /// `src_addr`, one after the other until `stop`. Each half is a block of its own, so every
+    /// transfer complete (`laps`) is one filled half: half `laps % 2` is written next. See
+    /// `PingPong`. Each half must hold a whole number of data of the channel width.
+    ///
+    /// # Safety
+    /// The buffer must stay valid until the channel is stopped.
+    pub unsafe fn start_double_buffer(&self, src_addr: u32, dst_addr: u32, len: u32) -> Result<(), hal::DmaError> {
+        let half = len / 2;
+        if !len.is_multiple_of(2) || !half.is_multiple_of(self.data_bytes()) {
+            return Err(hal::DmaError::Length);
+        }
+        self.init_continuous(src_addr, dst_addr);
+        self.with_list(|list| {
+            let base = list.as_ptr() as u32;
+            let nodes = (&mut list[..2]).try_into().unwrap();
+            build_double_buffer(nodes, base, src_addr, [dst_addr, dst_addr + half], half)?;
+            self.load_list(list);
+            Ok::<(), hal::DmaError>(())
+        })?;
+        self.enable_continuous();
+        Ok(())
+    }
+
+    /// Bytes of the wider of the source and destination data.
+    fn data_bytes(&self) -> u32 {
+        1 << self.src_width.to_bits().max(self.dst_width.to_bits())
+    }
+
+    /// Bytes left in the current pass (`BNDT`).
+    pub fn remaining(&self) -> u32 {
+        self.ins.ch(self.ch).br1().read().bndt() as u32
+    }
+
+    /// Completed blocks of the circular or double buffer transfer.
+    pub fn laps(&self) -> u32 {
+        LAPS[self.slot].load(Ordering::Acquire)
+    }
+
+    /// Half transfer interrupts of the circular or double buffer transfer.
+    pub fn half_transfers(&self) -> u32 {
+        HALF_TRANSFERS[self.slot].load(Ordering::Acquire)
+    }
+
+    /// Enable or disable the half transfer interrupt, e.g. for a one-shot transfer that is
+    /// processed while its second half is still arriving. Continuous transfers enable it.
+    pub fn set_half_transfer_interrupt(&self, enable: bool) {
+        self.ins.ch(self.ch).cr().modify(|v| v.set_htie(enable));
+        if enable {
+            unsafe { NVIC::unmask(channel_interrupt(self.slot)) };
+        }
+    }
+
+    /// Wait for the next half transfer or transfer complete interrupt.
+    pub async fn wait_progress(&self) {
+        let start = (self.laps(), self.half_transfers());
         poll_fn(|cx| {
-            WAKER.register(cx.waker()); // register waker
-                                        // wait for the transfer complete
-            if ch.sr().read().tcf() {
-                ch.sr().write(|v| v.set_tcf(true));
-                // clear the interrupt
+            CHANNEL_WAKERS[self.slot].register(cx.waker());
+            if (self.laps(), self.half_transfers()) != start {
                 core::task::Poll::Ready(())
             } else {
                 core::task::Poll::Pending
             }
-        });
+        })
+        .await;
+    }
+
+    /// A transfer complete that the channel interrupt has not counted yet.
+    pub fn is_complete_pending(&self) -> bool {
+        self.ins.ch(self.ch).sr().read().tcf()
+    }
+
+    pub fn register_waker(&self, waker: &Waker) {
+        CHANNEL_WAKERS[self.slot].register(waker);
     }
+
+    /// Call `hook(arg, laps, remaining)` from the channel interrupt after each half and complete
+    /// transfer, for drivers that must react before their task runs. `None` removes it.
+    pub fn set_interrupt_hook(&self, hook: Option<(InterruptHook, usize)>) {
+        CHANNEL_HOOKS[self.slot].lock(|h| h.set(hook));
+    }
+
     pub fn stop(&self) {
         let ch = self.ins.ch(self.ch);
         ch.cr().modify(|v| {
             v.set_susp(true);
         });
+        // RESET is only taken on a suspended (or idle) channel
+        while !ch.sr().read().suspf() && !ch.sr().read().idlef() {}
         ch.cr().modify(|v| {
             v.set_reset(true);
         });
         while ch.cr().read().en() {}
+        ch.fcr().write(|v| v.set_suspf(true));
     }
 }
+use core::cell::{Cell, RefCell, UnsafeCell};
+use core::marker::PhantomData;
 use core::future::poll_fn;
+use core::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use core::task::Waker;
+use cortex_m::peripheral::NVIC;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
 
-// waker
 use embassy_sync::waitqueue::AtomicWaker;
-static WAKER: AtomicWaker = AtomicWaker::new();
 use crate::hal::DMA;
 use stm32_metapac::interrupt;
 
-/// DMA interrupt handler
-// #[interrupt]
-// fn GPDMA1_CHANNEL0() {
-//     let ch = unsafe { stm32_metapac::GPDMA1.ch(0) };
-//     if ch.sr().read().tcf() {
-//         // ch.fcr().write(|v| v.set_tcf(true));
-//         // clear the interrupt
-//         // disable the interrupt
-//         ch.cr().modify(|v| {
-//             v.set_en(false);
-//             v.set_tcie(false);
-//         });
-//         WAKER.wake();
-//     }
-// }
-use crate::hal;
+static CHANNEL_WAKERS: [AtomicWaker; SLOTS] = [const { AtomicWaker::new() }; SLOTS];
+/// completed blocks of circular and double buffer transfers
+static LAPS: [AtomicU32; SLOTS] = [const { AtomicU32::new(0) }; SLOTS];
+static HALF_TRANSFERS: [AtomicU32; SLOTS] = [const { AtomicU32::new(0) }; SLOTS];
+/// error flags of the last transfer, the hardware disables the channel on an error
+static CHANNEL_ERRORS: [AtomicU8; SLOTS] = [const { AtomicU8::new(0) }; SLOTS];
+const DATA_TRANSFER_ERROR: u8 = 1;
+const LINK_UPDATE_ERROR: u8 = 2;
+const USER_SETTING_ERROR: u8 = 4;
+
+/// The error that stopped channel `ch`, if any.
+fn channel_error(ch: usize) -> Option<hal::DmaError> {
+    let errors = CHANNEL_ERRORS[ch].load(Ordering::Acquire);
+    if errors & DATA_TRANSFER_ERROR != 0 {
+        Some(hal::DmaError::DataTransfer)
+    } else if errors & LINK_UPDATE_ERROR != 0 {
+        Some(hal::DmaError::LinkUpdate)
+    } else if errors & USER_SETTING_ERROR != 0 {
+        Some(hal::DmaError::UserSetting)
+    } else {
+        None
+    }
+}
+
+/// Called from the channel interrupt with its argument, the completed passes and `BNDT`.
+pub type InterruptHook = fn(usize, u32, u32);
+static CHANNEL_HOOKS: [Mutex<CriticalSectionRawMutex, Cell<Option<(InterruptHook, usize)>>>; SLOTS] =
+    [const { Mutex::new(Cell::new(None)) }; SLOTS];
+
+fn on_channel_interrupt(slot: usize) {
+    let regs = if LPDMA_CHANNELS.contains(&slot) {
+        stm32_metapac::LPDMA1.ch(slot - LPDMA_CHANNELS.start)
+    } else {
+        stm32_metapac::GPDMA1.ch(slot)
+    };
+    let sr = regs.sr().read();
+    if sr.tcf() {
+        LAPS[slot].fetch_add(1, Ordering::Release);
+    }
+    if sr.htf() {
+        HALF_TRANSFERS[slot].fetch_add(1, Ordering::Release);
+    }
+    let errors = u8::from(sr.dtef()) * DATA_TRANSFER_ERROR
+        | u8::from(sr.ulef()) * LINK_UPDATE_ERROR
+        | u8::from(sr.usef()) * USER_SETTING_ERROR;
+    if errors != 0 {
+        CHANNEL_ERRORS[slot].fetch_or(errors, Ordering::Release);
+    }
+    regs.fcr().write(|v| {
+        v.set_tcf(sr.tcf());
+        v.set_htf(sr.htf());
+        v.set_dtef(sr.dtef());
+        v.set_ulef(sr.ulef());
+        v.set_usef(sr.usef());
+    });
+    if let Some((hook, arg)) = CHANNEL_HOOKS[slot].lock(|h| h.get()) {
+        hook(arg, LAPS[slot].load(Ordering::Acquire), regs.br1().read().bndt() as u32);
+    }
+    CHANNEL_WAKERS[slot].wake();
+}
+
+macro_rules! channel_interrupts {
+    ($($irq:ident: $ch:literal),*) => {
+        $(
+            #[interrupt]
+            fn $irq() {
+                on_channel_interrupt($ch);
+            }
+        )*
+
+        fn channel_interrupt(ch: usize) -> stm32_metapac::Interrupt {
+            match ch {
+                $($ch => interrupt::$irq,)*
+                _ => panic!("invalid dma channel"),
+            }
+        }
+    };
+}
+
+channel_interrupts!(
+    GPDMA1_CHANNEL0: 0, GPDMA1_CHANNEL1: 1, GPDMA1_CHANNEL2: 2, GPDMA1_CHANNEL3: 3,
+    GPDMA1_CHANNEL4: 4, GPDMA1_CHANNEL5: 5, GPDMA1_CHANNEL6: 6, GPDMA1_CHANNEL7: 7,
+    GPDMA1_CHANNEL8: 8, GPDMA1_CHANNEL9: 9, GPDMA1_CHANNEL10: 10, GPDMA1_CHANNEL11: 11,
+    GPDMA1_CHANNEL12: 12, GPDMA1_CHANNEL13: 13, GPDMA1_CHANNEL14: 14, GPDMA1_CHANNEL15: 15,
+    LPDMA1_CHANNEL0: 16, LPDMA1_CHANNEL1: 17, LPDMA1_CHANNEL2: 18, LPDMA1_CHANNEL3: 19
+);
+
+impl Drop for DmaChannel {
+    fn drop(&mut self) {
+        if self.ins.ch(self.ch).cr().read().en() {
+            self.stop();
+        }
+        self.set_interrupt_hook(None);
+        TAKEN_CHANNELS.fetch_and(!(1 << self.slot), Ordering::AcqRel);
+    }
+}
+
+fn width_from_bytes(bytes: u32) -> Dw {
+    match bytes {
+        4 => Dw::WORD,
+        2 => Dw::HALF_WORD,
+        _ => Dw::BYTE,
+    }
+}
+
+/// Copy `src` into `dst` on a free channel. Words or halfwords are transferred when both buffers
+/// and the length are aligned for them, bytes otherwise. Panics if the lengths differ.
+pub async fn memcpy_async(dst: &mut [u8], src: &[u8]) -> Result<(), hal::DmaError> {
+    assert_eq!(dst.len(), src.len(), "dma copy length mismatch");
+    if dst.is_empty() {
+        return Ok(());
+    }
+    let (src_addr, dst_addr, len) = (src.as_ptr() as u32, dst.as_mut_ptr() as u32, dst.len() as u32);
+    let dma = DmaChannel::take_memory(width_from_bytes(data_width(&[src_addr, dst_addr, len])))?;
+    // the slices are borrowed until the transfer is finished or aborted on drop
+    let mut transfer = unsafe { dma.start(src_addr, true, dst_addr, true, len) }?;
+    transfer.wait().await?;
+    Ok(())
+}
+
+/// Fill `dst` with `value` on a free channel, in words when `dst` is word aligned.
+pub async fn memset_async(dst: &mut [u8], value: u8) -> Result<(), hal::DmaError> {
+    if dst.is_empty() {
+        return Ok(());
+    }
+    // read over and over by the channel, declared first so it outlives it
+    let pattern = u32::from_ne_bytes([value; 4]);
+    let (dst_addr, len) = (dst.as_mut_ptr() as u32, dst.len() as u32);
+    let dma = DmaChannel::take_memory(width_from_bytes(data_width(&[dst_addr, len])))?;
+    let mut transfer = unsafe { dma.start(&pattern as *const u32 as u32, false, dst_addr, true, len) }?;
+    transfer.wait().await?;
+    Ok(())
+}
+
+/// Copy `rows` rows of `width` bytes from `src` into `dst` on a free 2D channel, the rows
+/// `src_stride` and `dst_stride` bytes apart. For example a 64 x 48 region at (20, 10) of a
+/// 320 x 240 RGB565 frame:
+///
+/// ```ignore
+/// dma::memcpy_2d_async(&mut crop, 128, &frame[(10 * 320 + 20) * 2..], 640, 128, 48).await?;
+/// ```
+///
+/// `DmaError::Rect` if the rows do not fit in the buffers, a row is longer than a block, there
+/// are more than 2048 rows or a stride gap is larger than 64 KiB.
+pub async fn memcpy_2d_async(
+    dst: &mut [u8],
+    dst_stride: usize,
+    src: &[u8],
+    src_stride: usize,
+    width: usize,
+    rows: usize,
+) -> Result<(), hal::DmaError> {
+    if width == 0 || rows == 0 {
+        return Ok(());
+    }
+    let fits = |len: usize, stride: usize| {
+        let span = (rows - 1).checked_mul(stride).and_then(|s| s.checked_add(width));
+        span.is_some_and(|span| span <= len)
+    };
+    if !fits(src.len(), src_stride) || !fits(dst.len(), dst_stride) {
+        return Err(hal::DmaError::Rect);
+    }
+    let rect = Rect2d {
+        src: src.as_ptr() as u32,
+        src_stride: src_stride as u32,
+        dst: dst.as_mut_ptr() as u32,
+        dst_stride: dst_stride as u32,
+        width: width as u32,
+        rows: rows as u32,
+    };
+    let width = data_width(&[rect.src, rect.dst, rect.width, rect.src_stride, rect.dst_stride]);
+    let dma = DmaChannel::take_memory_2d(width_from_bytes(width))?;
+    // the rows are checked against the slices above
+    let mut transfer = unsafe { dma.start_2d(&rect) }?;
+    transfer.wait().await?;
+    Ok(())
+}
+
+/// Double buffer reception from a peripheral register: the buffer is split in two halves and
+/// `next` hands out the half the channel has just filled while it fills the other one.
+///
+/// ```ignore
+/// let dma = DmaChannel::take("ADC1", Dw::HALF_WORD)?;
+/// let mut samples = PingPong::new_static(&dma, stm32_metapac::ADC1.dr(), buf)?;
+/// loop {
+///     let half = samples.next().await?;
+///     process(half);
+/// }
+/// ```
+pub struct PingPong<'a> {
+    dma: &'a DmaChannel,
+    buf: &'a mut [u8],
+    read: u32,
+}
+
+impl<'a> PingPong<'a> {
+    /// Start filling `buf` (even length, at most two blocks) from `src_addr`.
+    ///
+    /// # Safety
+    /// `src_addr` must be a peripheral data register, and the `PingPong` must be dropped (which
+    /// stops the channel) before the borrow of `buf` ends, it must not be forgotten.
+    pub unsafe fn new(dma: &'a DmaChannel, src_addr: u32, buf: &'a mut [u8]) -> Result<Self, hal::DmaError> {
+        dma.start_double_buffer(src_addr, buf.as_mut_ptr() as u32, buf.len() as u32)?;
+        Ok(Self { dma, buf, read: 0 })
+    }
+
+    /// Start filling the static `buf` from the register `src`.
+    pub fn new_static<T: Copy, A: Read>(
+        dma: &'a DmaChannel,
+        src: Reg<T, A>,
+        buf: &'static mut [u8],
+    ) -> Result<Self, hal::DmaError> {
+        // a forgotten `PingPong` leaves the channel writing into memory nobody else can reach
+        unsafe { Self::new(dma, src.as_ptr() as u32, buf) }
+    }
+
+    /// Wait for the next filled half. The half stays valid until the channel has filled the
+    /// other one; `DmaRingError::Overrun` if it is already being overwritten, the next call then
+    /// returns the most recent half. `DmaRingError::Dma` once the channel has stopped on an error.
+    pub async fn next(&mut self) -> Result<&[u8], DmaRingError> {
+        let laps = poll_fn(|cx| {
+            self.dma.register_waker(cx.waker());
+            let laps = self.dma.laps();
+            if let Some(e) = channel_error(self.dma.slot) {
+                core::task::Poll::Ready(Err(e))
+            } else if laps != self.read {
+                core::task::Poll::Ready(Ok(laps))
+            } else {
+                core::task::Poll::Pending
+            }
+        })
+        .await?;
+        if laps.wrapping_sub(self.read) > 1 {
+            self.read = laps.wrapping_sub(1);
+            return Err(DmaRingError::Overrun);
+        }
+        let half = self.buf.len() / 2;
+        let start = (self.read % 2) as usize * half;
+        self.read = self.read.wrapping_add(1);
+        Ok(&self.buf[start..start + half])
+    }
+}
+
+impl Drop for PingPong<'_> {
+    fn drop(&mut self) {
+        self.dma.stop();
+    }
+}
+
+/// A one-shot transfer on a channel. It holds the buffer the channel reads or writes, and
+/// dropping an unfinished transfer aborts the channel with suspend and reset. The safe
+/// `read_static` and `write_static` own a static buffer and take a typed register; `read` and
+/// `write` borrow any buffer but are unsafe, the transfer must be dropped and not forgotten.
+///
+/// ```ignore
+/// let dma = DmaChannel::take("USART2_RX", Dw::BYTE)?;
+/// let mut transfer = Transfer::read_static(&dma, stm32_metapac::USART2.rdr(), buf)?;
+/// usart.enable_rx_dma();
+/// transfer.wait().await?;
+/// ```
+///
+/// Starting another transfer on the channel aborts this one.
+#[must_use = "dropping a transfer aborts it"]
+pub struct Transfer<'a> {
+    dma: &'a DmaChannel,
+    buf: Option<&'a mut [u8]>,
+    _memory: PhantomData<&'a [u8]>,
+    done: bool,
+}
+
+impl<'a> Transfer<'a> {
+    /// Read `buf.len()` bytes from the peripheral register `src_addr` into `buf`.
+    ///
+    /// # Safety
+    /// `src_addr` must be a peripheral data register, and the transfer must finish or be dropped
+    /// before the borrow of `buf` ends, it must not be forgotten.
+    pub unsafe fn read(dma: &'a DmaChannel, src_addr: u32, buf: &'a mut [u8]) -> Result<Self, hal::DmaError> {
+        let mut transfer = dma.start(src_addr, false, buf.as_mut_ptr() as u32, true, buf.len() as u32)?;
+        transfer.buf = Some(buf);
+        Ok(transfer)
+    }
+
+    /// Write `buf` to the peripheral register `dst_addr`.
+    ///
+    /// # Safety
+    /// `dst_addr` must be a peripheral data register, and the transfer must finish or be dropped
+    /// before the borrow of `buf` ends, it must not be forgotten.
+    pub unsafe fn write(dma: &'a DmaChannel, buf: &'a [u8], dst_addr: u32) -> Result<Self, hal::DmaError> {
+        dma.start(buf.as_ptr() as u32, true, dst_addr, false, buf.len() as u32)
+    }
+
+    /// Read `buf.len()` bytes from the register `src` into the static `buf`, which the transfer
+    /// owns until `into_buffer`.
+    pub fn read_static<T: Copy, A: Read>(
+        dma: &'a DmaChannel,
+        src: Reg<T, A>,
+        buf: &'static mut [u8],
+    ) -> Result<Self, hal::DmaError> {
+        // a forgotten transfer leaves the channel writing into memory nobody else can reach
+        unsafe { Self::read(dma, src.as_ptr() as u32, buf) }
+    }
+
+    /// Write the static `buf` to the register `dst`.
+    pub fn write_static<T: Copy, A: Write>(
+        dma: &'a DmaChannel,
+        buf: &'static [u8],
+        dst: Reg<T, A>,
+    ) -> Result<Self, hal::DmaError> {
+        unsafe { Self::write(dma, buf, dst.as_ptr() as u32) }
+    }
+
+    /// The channel has finished, failed or was aborted.
+    pub fn is_done(&self) -> bool {
+        self.done || self.dma.is_idle()
+    }
+
+    /// Bytes left in the current block (`BNDT`).
+    pub fn remaining(&self) -> u32 {
+        self.dma.remaining()
+    }
+
+    /// Wait until the transfer has finished, or the error that stopped the channel.
+    pub async fn wait(&mut self) -> Result<(), hal::DmaError> {
+        let ch = self.dma.slot;
+        let res = poll_fn(|cx| {
+            CHANNEL_WAKERS[ch].register(cx.waker());
+            if let Some(e) = channel_error(ch) {
+                core::task::Poll::Ready(Err(e))
+            } else if self.is_done() {
+                core::task::Poll::Ready(Ok(()))
+            } else {
+                core::task::Poll::Pending
+            }
+        })
+        .await;
+        self.done = true;
+        res
+    }
+
+    /// Abort the transfer (suspend and reset the channel).
+    pub fn abort(&mut self) {
+        if !self.done {
+            self.dma.stop();
+            self.done = true;
+        }
+    }
+
+    /// Abort the transfer if it is still running and give back the buffer of `read`.
+    pub fn into_buffer(mut self) -> Option<&'a mut [u8]> {
+        self.abort();
+        self.buf.take()
+    }
+}
+
+impl Drop for Transfer<'_> {
+    fn drop(&mut self) {
+        if !self.done && !self.dma.is_idle() {
+            self.dma.stop();
+        }
+    }
+}
+
 impl hal::DMA for DmaChannel {
-    async fn start(&self, src_addr: u32, src_inc: bool, dar_addr: u32, dst_inc: bool, len: u32) {
-        self.start(src_addr, src_inc, dar_addr, dst_inc, len).await;
+    async unsafe fn start(
+        &self,
+        src_addr: u32,
+        src_inc: bool,
+        dar_addr: u32,
+        dst_inc: bool,
+        len: u32,
+    ) -> Result<(), hal::DmaError> {
+        // dropping the future drops the transfer, which aborts it
+        let mut transfer = unsafe { self.start(src_addr, src_inc, dar_addr, dst_inc, len) }?;
+        transfer.wait().await
     }
 
     fn stop(&self) {
@@ -0,0 +1,375 @@
+//! # GPDMA linked-list items
+//!
+//! A GPDMA channel loads its next block from a linked-list item (LLI) in memory: the words of
+//! the registers selected by the update bits of `GPDMA_CxLLR`, followed by the next `LLR`. The
+//! items used here update `BR1`, `SAR`, `DAR` and `LLR`, in this order. `LLR` only holds the low
+//! 16 bits of the next item's address, the high bits come from `GPDMA_CxLBAR`, so all items of a
+//! list must lie in the same 64 KiB page.
+//!
+//! - one-shot: blocks of up to `MAX_BLOCK` bytes, the last item ends the list (`LLR = 0`)
+//! - circular: the last item links back to the first, the channel runs until it is stopped
+//! - double buffer: two blocks linked to each other, the transfer complete of each block tells
+//!   that one buffer is filled while the channel goes on with the other
+//!
+//! The channel registers are loaded with the first item, so its `LLR` points at the second one.
+//!
+//! Memory to memory copies also pick the data width from the alignment (`data_width`), and the
+//! 2D channels (12 - 15) copy a rectangle with one block per row (`Block2d`).
+
+use crate::hal;
+
+/// Largest block of one item, `BNDT` is 16 bits and stays word aligned.
+pub const MAX_BLOCK: u32 = 65532;
+
+/// `LLR` update bits: `BR1`, `SAR`, `DAR` and `LLR` are loaded from the item.
+pub const LLR_UB1: u32 = 1 << 29;
+pub const LLR_USA: u32 = 1 << 28;
+pub const LLR_UDA: u32 = 1 << 27;
+pub const LLR_ULL: u32 = 1 << 16;
+const LLR_UPDATE: u32 = LLR_UB1 | LLR_USA | LLR_UDA | LLR_ULL;
+
+/// A linked-list item as the channel reads it.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct Lli {
+    /// byte count of the block (`BNDT`)
+    pub br1: u32,
+    pub sar: u32,
+    pub dar: u32,
+    /// low 16 bits of the next item and the update bits, 0 ends the list
+    pub llr: u32,
+}
+
+impl Lli {
+    pub const fn new() -> Self {
+        Self {
+            br1: 0,
+            sar: 0,
+            dar: 0,
+            llr: 0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LliError {
+    /// zero length, or a double buffer larger than one block
+    Length,
+    /// more blocks than items
+    TooManyBlocks,
+    /// the items cross a 64 KiB page
+    CrossesPage,
+    /// a 2D row is larger than one block, there are more than 2048 rows, or a stride is smaller
+    /// than the row or too large for the 16 bit offset
+    Rect,
+}
+
+impl From<LliError> for hal::DmaError {
+    fn from(e: LliError) -> Self {
+        match e {
+            LliError::Length => hal::DmaError::Length,
+            LliError::TooManyBlocks => hal::DmaError::TooManyBlocks,
+            LliError::CrossesPage => hal::DmaError::CrossesPage,
+            LliError::Rect => hal::DmaError::Rect,
+        }
+    }
+}
+
+/// Source, destination and length of a transfer. Addresses that do not increment are peripheral
+/// registers.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LliTransfer {
+    pub src: u32,
+    pub src_inc: bool,
+    pub dst: u32,
+    pub dst_inc: bool,
+    /// bytes
+    pub len: u32,
+}
+
+/// `LLR` value linking to `nodes[index]` placed at `base`.
+fn link(base: u32, index: usize) -> u32 {
+    ((base + (index * core::mem::size_of::<Lli>()) as u32) & 0xffff) | LLR_UPDATE
+}
+
+/// Check that `count` items at `base` fit in one 64 KiB page.
+fn check_page(base: u32, count: usize) -> Result<(), LliError> {
+    let last = base + (count * core::mem::size_of::<Lli>()) as u32 - 1;
+    if base >> 16 != last >> 16 {
+        return Err(LliError::CrossesPage);
+    }
+    Ok(())
+}
+
+/// Build the list for `transfer` in `nodes`, which are placed at address `base`. `circular` links
+/// the last item back to the first. Returns the number of items used.
+pub fn build_linear(
+    nodes: &mut [Lli],
+    base: u32,
+    transfer: LliTransfer,
+    circular: bool,
+) -> Result<usize, LliError> {
+    if transfer.len == 0 {
+        return Err(LliError::Length);
+    }
+    let count = transfer.len.div_ceil(MAX_BLOCK) as usize;
+    if count > nodes.len() {
+        return Err(LliError::TooManyBlocks);
+    }
+    check_page(base, count)?;
+    for (i, node) in nodes[..count].iter_mut().enumerate() {
+        let offset = i as u32 * MAX_BLOCK;
+        node.sar = transfer.src + if transfer.src_inc { offset } else { 0 };
+        node.dar = transfer.dst + if transfer.dst_inc { offset } else { 0 };
+        node.br1 = (transfer.len - offset).min(MAX_BLOCK);
+        node.llr = if i + 1 < count {
+            link(base, i + 1)
+        } else if circular {
+            link(base, 0)
+        } else {
+            0
+        };
+    }
+    Ok(count)
+}
+
+/// Build a double buffer from the peripheral register `src` into `bufs`, `len` bytes each: two
+/// items at `base` linked to each other.
+pub fn build_double_buffer(
+    nodes: &mut [Lli; 2],
+    base: u32,
+    src: u32,
+    bufs: [u32; 2],
+    len: u32,
+) -> Result<(), LliError> {
+    if len == 0 || len > MAX_BLOCK {
+        return Err(LliError::Length);
+    }
+    check_page(base, 2)?;
+    for (i, node) in nodes.iter_mut().enumerate() {
+        node.sar = src;
+        node.dar = bufs[i];
+        node.br1 = len;
+        node.llr = link(base, 1 - i);
+    }
+    Ok(())
+}
+
+/// Largest data width in bytes (4, 2 or 1) that all of the addresses and lengths in `values` are
+/// aligned to.
+pub fn data_width(values: &[u32]) -> u32 {
+    [4, 2]
+        .into_iter()
+        .find(|w| values.iter().all(|v| v % w == 0))
+        .unwrap_or(1)
+}
+
+/// A rectangle of `rows` rows of `width` bytes, the rows `src_stride` and `dst_stride` bytes
+/// apart, e.g. a region cropped out of a frame buffer.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Rect2d {
+    pub src: u32,
+    pub src_stride: u32,
+    pub dst: u32,
+    pub dst_stride: u32,
+    pub width: u32,
+    pub rows: u32,
+}
+
+/// `BR1` and `BR2` of a 2D channel copying a `Rect2d`: a block per row (`BNDT`), repeated
+/// (`BRC`), with the stride gaps added to the addresses after each block (`BRSAO`, `BRDAO`).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Block2d {
+    pub br1: u32,
+    pub br2: u32,
+}
+
+impl Block2d {
+    pub fn new(rect: &Rect2d) -> Result<Self, LliError> {
+        let gap = |stride: u32| stride.checked_sub(rect.width).filter(|&g| g <= 0xffff);
+        let (Some(src_gap), Some(dst_gap)) = (gap(rect.src_stride), gap(rect.dst_stride)) else {
+            return Err(LliError::Rect);
+        };
+        if rect.width == 0 || rect.width > MAX_BLOCK || rect.rows == 0 || rect.rows > 2048 {
+            return Err(LliError::Rect);
+        }
+        Ok(Self {
+            br1: rect.width | (rect.rows - 1) << 16,
+            br2: src_gap | dst_gap << 16,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE: u32 = 0x2000_1000;
+
+    fn transfer(len: u32) -> LliTransfer {
+        LliTransfer {
+            src: 0x4001_3824,
+            src_inc: false,
+            dst: 0x2003_0000,
+            dst_inc: true,
+            len,
+        }
+    }
+
+    #[test]
+    fn test_single_block() {
+        let mut nodes = [Lli::new(); 4];
+        assert_eq!(build_linear(&mut nodes, BASE, transfer(100), false), Ok(1));
+        assert_eq!(
+            nodes[0],
+            Lli {
+                br1: 100,
+                sar: 0x4001_3824,
+                dar: 0x2003_0000,
+                llr: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_blocks() {
+        let mut nodes = [Lli::new(); 4];
+        let len = 2 * MAX_BLOCK + 10;
+        assert_eq!(build_linear(&mut nodes, BASE, transfer(len), false), Ok(3));
+        for (i, node) in nodes[..3].iter().enumerate() {
+            // the peripheral address stays, the memory address moves on
+            assert_eq!(node.sar, 0x4001_3824);
+            assert_eq!(node.dar, 0x2003_0000 + i as u32 * MAX_BLOCK);
+        }
+        assert_eq!(nodes.iter().map(|n| n.br1).sum::<u32>(), len);
+        assert_eq!(nodes[2].br1, 10);
+        assert_eq!(nodes[0].llr, 0x1010 | LLR_UB1 | LLR_USA | LLR_UDA | LLR_ULL);
+        assert_eq!(nodes[1].llr & 0xffff, 0x1020);
+        assert_eq!(nodes[2].llr, 0);
+        // an exact multiple has no empty last block
+        assert_eq!(
+            build_linear(&mut nodes, BASE, transfer(2 * MAX_BLOCK), false),
+            Ok(2)
+        );
+        assert_eq!(nodes[1].br1, MAX_BLOCK);
+    }
+
+    #[test]
+    fn test_circular() {
+        let mut nodes = [Lli::new(); 4];
+        assert_eq!(build_linear(&mut nodes, BASE, transfer(64), true), Ok(1));
+        // a single item reloads itself
+        assert_eq!(nodes[0].llr, 0x1000 | LLR_UB1 | LLR_USA | LLR_UDA | LLR_ULL);
+        assert_eq!(
+            build_linear(&mut nodes, BASE, transfer(MAX_BLOCK + 1), true),
+            Ok(2)
+        );
+        assert_eq!(nodes[0].llr & 0xffff, 0x1010);
+        assert_eq!(nodes[1].llr & 0xffff, 0x1000);
+    }
+
+    #[test]
+    fn test_double_buffer() {
+        let mut nodes = [Lli::new(); 2];
+        build_double_buffer(
+            &mut nodes,
+            BASE,
+            0x5002_c028,
+            [0x2000_0000, 0x2000_0200],
+            0x200,
+        )
+        .unwrap();
+        assert_eq!(nodes[0].dar, 0x2000_0000);
+        assert_eq!(nodes[1].dar, 0x2000_0200);
+        assert!(nodes.iter().all(|n| n.sar == 0x5002_c028 && n.br1 == 0x200));
+        assert_eq!(nodes[0].llr & 0xffff, 0x1010);
+        assert_eq!(nodes[1].llr & 0xffff, 0x1000);
+        assert_eq!(
+            build_double_buffer(&mut nodes, BASE, 0, [0, 0], MAX_BLOCK + 4),
+            Err(LliError::Length)
+        );
+    }
+
+    #[test]
+    fn test_data_width() {
+        assert_eq!(data_width(&[0x2000_0000, 0x2000_0100, 64]), 4);
+        assert_eq!(data_width(&[0x2000_0002, 0x2000_0100, 64]), 2);
+        assert_eq!(data_width(&[0x2000_0000, 0x2000_0100, 63]), 1);
+        assert_eq!(data_width(&[0x2000_0001]), 1);
+    }
+
+    #[test]
+    fn test_block_2d() {
+        // a 64 x 48 RGB565 region out of a 320 x 240 frame into a packed buffer
+        let rect = Rect2d {
+            src: 0x2000_0000 + (10 * 320 + 20) * 2,
+            src_stride: 640,
+            dst: 0x2004_0000,
+            dst_stride: 128,
+            width: 128,
+            rows: 48,
+        };
+        assert_eq!(
+            Block2d::new(&rect),
+            Ok(Block2d {
+                br1: 128 | 47 << 16,
+                br2: 512,
+            })
+        );
+        let padded = Rect2d {
+            dst_stride: 160,
+            ..rect
+        };
+        assert_eq!(Block2d::new(&padded).unwrap().br2, 512 | 32 << 16);
+        assert_eq!(
+            Block2d::new(&Rect2d {
+                src_stride: 100,
+                ..rect
+            }),
+            Err(LliError::Rect)
+        );
+        assert_eq!(
+            Block2d::new(&Rect2d { rows: 2049, ..rect }),
+            Err(LliError::Rect)
+        );
+        assert_eq!(
+            Block2d::new(&Rect2d {
+                src_stride: 0x10080,
+                ..rect
+            }),
+            Err(LliError::Rect)
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut nodes = [Lli::new(); 2];
+        assert_eq!(
+            build_linear(&mut nodes, BASE, transfer(0), false),
+            Err(LliError::Length)
+        );
+        assert_eq!(
+            build_linear(&mut nodes, BASE, transfer(3 * MAX_BLOCK), false),
+            Err(LliError::TooManyBlocks)
+        );
+        // the second item would be in the next page
+        assert_eq!(
+            build_linear(&mut nodes, 0x2000_fff0, transfer(MAX_BLOCK + 1), false),
+            Err(LliError::CrossesPage)
+        );
+        assert_eq!(
+            build_linear(&mut nodes, 0x2000_fff0, transfer(16), false),
+            Ok(1)
+        );
+    }
+
+    #[test]
+    fn test_dma_error() {
+        let mut nodes = [Lli::new(); 32];
+        let err = build_linear(&mut nodes, BASE, transfer(33 * MAX_BLOCK), false).unwrap_err();
+        assert_eq!(hal::DmaError::from(err), hal::DmaError::TooManyBlocks);
+        assert_eq!(hal::DmaError::from(LliError::Length), hal::DmaError::Length);
+        assert_eq!(hal::DmaError::from(LliError::Rect), hal::DmaError::Rect);
+    }
+}
@@ -0,0 +1,134 @@
+//! # GPDMA1 and LPDMA1 request sources
+//!
+//! The hardware request of a GPDMA channel is selected by number (`REQSEL` in `GPDMA_CxTR2`). The
+//! numbers follow the GPDMA1 request table of RM0456; drivers look them up by name, for example
+//! `gpdma1_request("USART2_TX")`, instead of hard-coding them. LPDMA1 has its own, shorter
+//! tables of requests and triggers (`TRIGSEL`) for the SmartRun domain peripherals.
+
+/// GPDMA1 request names, indexed by `REQSEL`.
+#[rustfmt::skip]
+pub const GPDMA1_REQUESTS: [&str; 114] = [
+    "ADC1", "ADC4", "DAC1_CH1", "DAC1_CH2", "TIM6_UPD", "TIM7_UPD", // 0 - 5
+    "SPI1_RX", "SPI1_TX", "SPI2_RX", "SPI2_TX", "SPI3_RX", "SPI3_TX", // 6 - 11
+    "I2C1_RX", "I2C1_TX", "I2C1_EVC", "I2C2_RX", "I2C2_TX", "I2C2_EVC", // 12 - 17
+    "I2C3_RX", "I2C3_TX", "I2C3_EVC", "I2C4_RX", "I2C4_TX", "I2C4_EVC", // 18 - 23
+    "USART1_RX", "USART1_TX", "USART2_RX", "USART2_TX", "USART3_RX", "USART3_TX", // 24 - 29
+    "UART4_RX", "UART4_TX", "UART5_RX", "UART5_TX", "LPUART1_RX", "LPUART1_TX", // 30 - 35
+    "SAI1_A", "SAI1_B", "SAI2_A", "SAI2_B", "OCTOSPI1", "OCTOSPI2", // 36 - 41
+    "TIM1_CC1", "TIM1_CC2", "TIM1_CC3", "TIM1_CC4", "TIM1_UPD", "TIM1_TRG", "TIM1_COM", // 42 - 48
+    "TIM8_CC1", "TIM8_CC2", "TIM8_CC3", "TIM8_CC4", "TIM8_UPD", "TIM8_TRG", "TIM8_COM", // 49 - 55
+    "TIM2_CC1", "TIM2_CC2", "TIM2_CC3", "TIM2_CC4", "TIM2_UPD", // 56 - 60
+    "TIM3_CC1", "TIM3_CC2", "TIM3_CC3", "TIM3_CC4", "TIM3_UPD", "TIM3_TRG", // 61 - 66
+    "TIM4_CC1", "TIM4_CC2", "TIM4_CC3", "TIM4_CC4", "TIM4_UPD", // 67 - 71
+    "TIM5_CC1", "TIM5_CC2", "TIM5_CC3", "TIM5_CC4", "TIM5_UPD", "TIM5_TRG", // 72 - 77
+    "TIM15_CC1", "TIM15_UPD", "TIM15_TRG", "TIM15_COM", // 78 - 81
+    "TIM16_CC1", "TIM16_UPD", "TIM17_CC1", "TIM17_UPD", // 82 - 85
+    "DCMI", "AES_IN", "AES_OUT", "HASH_IN", "UCPD1_TX", "UCPD1_RX", // 86 - 91
+    "MDF1_FLT0", "MDF1_FLT1", "MDF1_FLT2", "MDF1_FLT3", "MDF1_FLT4", "MDF1_FLT5", // 92 - 97
+    "ADF1_FLT0", "FMAC_RD", "FMAC_WR", "CORDIC_RD", "CORDIC_WR", "SAES_IN", "SAES_OUT", // 98 - 104
+    "LPTIM1_IC1", "LPTIM1_IC2", "LPTIM1_UE", "LPTIM2_IC1", "LPTIM2_IC2", "LPTIM2_UE", // 105 - 110
+    "LPTIM3_IC1", "LPTIM3_IC2", "LPTIM3_UE", // 111 - 113
+];
+
+/// `REQSEL` of the GPDMA1 request `name`.
+pub fn gpdma1_request(name: &str) -> Option<u8> {
+    GPDMA1_REQUESTS
+        .iter()
+        .position(|&r| r == name)
+        .map(|i| i as u8)
+}
+
+/// Name of the GPDMA1 request `reqsel`.
+pub fn gpdma1_request_name(reqsel: u8) -> Option<&'static str> {
+    GPDMA1_REQUESTS.get(reqsel as usize).copied()
+}
+
+/// LPDMA1 request names, indexed by `REQSEL`.
+#[rustfmt::skip]
+pub const LPDMA1_REQUESTS: [&str; 17] = [
+    "LPUART1_RX", "LPUART1_TX", "SPI3_RX", "SPI3_TX", "I2C3_RX", "I2C3_TX", "I2C3_EVC", // 0 - 6
+    "ADC4", "DAC1_CH1", "DAC1_CH2", "ADF1_FLT0", // 7 - 10
+    "LPTIM1_IC1", "LPTIM1_IC2", "LPTIM1_UE", "LPTIM3_IC1", "LPTIM3_IC2", "LPTIM3_UE", // 11 - 16
+];
+
+/// LPDMA1 trigger names, indexed by `TRIGSEL`.
+#[rustfmt::skip]
+pub const LPDMA1_TRIGGERS: [&str; 21] = [
+    "EXTI0", "EXTI1", "EXTI2", "EXTI3", "EXTI4", "TAMP_TRG1", "TAMP_TRG2", "TAMP_TRG3", // 0 - 7
+    "LPTIM1_CH1", "LPTIM1_CH2", "LPTIM3_CH1", "LPTIM4_OUT", "COMP1_OUT", "COMP2_OUT", // 8 - 13
+    "RTC_ALRA_TRG", "RTC_ALRB_TRG", "RTC_WUT_TRG", "ADC4_AWD1", // 14 - 17
+    "LPDMA1_CH0_TC", "LPDMA1_CH1_TC", "LPDMA1_CH2_TC", // 18 - 20
+];
+
+/// Requests that must be programmed as block requests (`BREQ`).
+const BLOCK_REQUESTS: [&str; 3] = ["LPTIM1_UE", "LPTIM2_UE", "LPTIM3_UE"];
+
+/// `REQSEL` of the LPDMA1 request `name`.
+pub fn lpdma1_request(name: &str) -> Option<u8> {
+    LPDMA1_REQUESTS
+        .iter()
+        .position(|&r| r == name)
+        .map(|i| i as u8)
+}
+
+/// `TRIGSEL` of the LPDMA1 trigger `name`.
+pub fn lpdma1_trigger(name: &str) -> Option<u8> {
+    LPDMA1_TRIGGERS
+        .iter()
+        .position(|&t| t == name)
+        .map(|i| i as u8)
+}
+
+/// The request `name` transfers a block per request instead of a single data.
+pub fn is_block_request(name: &str) -> bool {
+    BLOCK_REQUESTS.contains(&name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gpdma1_request() {
+        // the numbers the drivers used before the table
+        assert_eq!(gpdma1_request("DCMI"), Some(86));
+        assert_eq!(gpdma1_request("SPI1_RX"), Some(6));
+        assert_eq!(gpdma1_request("SPI3_TX"), Some(11));
+        assert_eq!(gpdma1_request("I2C2_RX"), Some(15));
+        assert_eq!(gpdma1_request("I2C4_TX"), Some(22));
+        assert_eq!(gpdma1_request("USART1_RX"), Some(24));
+        assert_eq!(gpdma1_request("UART5_TX"), Some(33));
+
+        assert_eq!(gpdma1_request("USART2_TX"), Some(27));
+        assert_eq!(gpdma1_request("LPUART1_RX"), Some(34));
+        assert_eq!(gpdma1_request("TIM1_COM"), Some(48));
+        assert_eq!(gpdma1_request("LPTIM3_UE"), Some(113));
+        assert_eq!(gpdma1_request("usart2_tx"), None);
+        assert_eq!(gpdma1_request("USART6_TX"), None);
+    }
+
+    #[test]
+    fn test_gpdma1_request_names() {
+        for (i, name) in GPDMA1_REQUESTS.iter().enumerate() {
+            // unique names
+            assert_eq!(gpdma1_request(name), Some(i as u8));
+            assert_eq!(gpdma1_request_name(i as u8), Some(*name));
+        }
+        assert_eq!(gpdma1_request_name(114), None);
+    }
+
+    #[test]
+    fn test_lpdma1() {
+        assert_eq!(lpdma1_request("LPUART1_RX"), Some(0));
+        assert_eq!(lpdma1_request("ADC4"), Some(7));
+        assert_eq!(lpdma1_request("LPTIM3_UE"), Some(16));
+        assert_eq!(lpdma1_request("USART1_RX"), None);
+        assert_eq!(lpdma1_trigger("EXTI4"), Some(4));
+        assert_eq!(lpdma1_trigger("LPTIM1_CH1"), Some(8));
+        assert_eq!(lpdma1_trigger("RTC_WUT_TRG"), Some(16));
+        assert_eq!(lpdma1_trigger("LPDMA1_CH2_TC"), Some(20));
+        assert_eq!(lpdma1_trigger("EXTI5"), None);
+        assert!(is_block_request("LPTIM1_UE"));
+        assert!(!is_block_request("ADC4"));
+    }
+}
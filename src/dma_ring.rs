@@ -0,0 +1,214 @@
+//! # Reader side of a circular DMA buffer
+//!
+//! A DMA channel in circular mode keeps writing into a ring buffer and only reports where it is
+//! (the remaining count of the current block) and how often it wrapped (transfer complete
+//! interrupts). `DmaRing` turns these into running totals, so the reader always knows how many
+//! bytes are waiting and notices when the DMA lapped it and overwrote unread data.
+
+use crate::hal;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DmaRingError {
+    /// the DMA wrote more than a buffer length ahead of the reader, unread data is lost
+    Overrun,
+    /// the channel stopped on an error
+    Dma(hal::DmaError),
+}
+
+impl From<hal::DmaError> for DmaRingError {
+    fn from(e: hal::DmaError) -> Self {
+        DmaRingError::Dma(e)
+    }
+}
+
+/// Total number of bytes written since the start of the transfer.
+///
+/// `laps` is the number of completed passes over the buffer, `remaining` the remaining count of
+/// the current pass (`BNDT`). `remaining == 0` means the pass is complete but the lap is not
+/// counted yet.
+pub fn written(laps: u32, remaining: usize, len: usize) -> u64 {
+    laps as u64 * len as u64 + (len - remaining) as u64
+}
+
+pub struct DmaRing {
+    len: usize,
+    /// total bytes consumed by the reader
+    read: u64,
+}
+
+impl DmaRing {
+    pub fn new(len: usize) -> Self {
+        Self { len, read: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of unread bytes, given the running total `written` of the DMA.
+    pub fn available(&self, written: u64) -> Result<usize, DmaRingError> {
+        let available = written.saturating_sub(self.read);
+        if available > self.len as u64 {
+            Err(DmaRingError::Overrun)
+        } else {
+            Ok(available as usize)
+        }
+    }
+
+    /// Copy the unread bytes of `buf` to `out`, oldest first. Return the number of bytes copied.
+    ///
+    /// The check against `written` must be repeated after the copy because the DMA keeps writing;
+    /// `read` does it with `written_after`, the running total read again after the copy.
+    pub fn read(
+        &mut self,
+        buf: &[u8],
+        written: u64,
+        out: &mut [u8],
+        written_after: impl FnOnce() -> u64,
+    ) -> Result<usize, DmaRingError> {
+        let n = self.available(written)?.min(out.len());
+        let start = (self.read % self.len as u64) as usize;
+        let first = n.min(self.len - start);
+        out[..first].copy_from_slice(&buf[start..start + first]);
+        out[first..n].copy_from_slice(&buf[..n - first]);
+        // the copied bytes may have been overwritten while copying
+        if written_after().saturating_sub(self.read) > self.len as u64 {
+            return Err(DmaRingError::Overrun);
+        }
+        self.read += n as u64;
+        Ok(n)
+    }
+
+    /// Total bytes consumed by the reader.
+    pub fn read_total(&self) -> u64 {
+        self.read
+    }
+
+    /// Drop everything written so far, used to recover from an overrun.
+    pub fn clear(&mut self, written: u64) {
+        self.read = written;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// DMA model writing consecutive byte values into the ring.
+    struct Dma {
+        buf: Vec<u8>,
+        total: u64,
+    }
+
+    impl Dma {
+        fn new(len: usize) -> Self {
+            Self {
+                buf: vec![0; len],
+                total: 0,
+            }
+        }
+
+        fn write(&mut self, n: usize) {
+            for _ in 0..n {
+                let len = self.buf.len() as u64;
+                self.buf[(self.total % len) as usize] = self.total as u8;
+                self.total += 1;
+            }
+        }
+
+        /// the registers as the driver sees them
+        fn registers(&self) -> (u32, usize) {
+            let len = self.buf.len() as u64;
+            ((self.total / len) as u32, (len - self.total % len) as usize)
+        }
+    }
+
+    #[test]
+    fn test_written() {
+        assert_eq!(written(0, 16, 16), 0);
+        assert_eq!(written(0, 6, 16), 10);
+        // block done, transfer complete not handled yet
+        assert_eq!(written(0, 0, 16), 16);
+        assert_eq!(written(2, 16, 16), 32);
+        assert_eq!(written(2, 1, 16), 47);
+    }
+
+    #[test]
+    fn test_read_wraps() {
+        let mut dma = Dma::new(8);
+        let mut ring = DmaRing::new(8);
+        let mut out = [0; 8];
+        let mut expected = 0u8;
+        for chunk in [3, 5, 7, 1, 8, 2] {
+            dma.write(chunk);
+            let (laps, remaining) = dma.registers();
+            let total = written(laps, remaining, 8);
+            assert_eq!(ring.available(total), Ok(chunk));
+            let n = ring.read(&dma.buf, total, &mut out, || total).unwrap();
+            assert_eq!(n, chunk);
+            for &b in &out[..n] {
+                assert_eq!(b, expected);
+                expected = expected.wrapping_add(1);
+            }
+            assert_eq!(ring.available(total), Ok(0));
+        }
+    }
+
+    #[test]
+    fn test_partial_read() {
+        let mut dma = Dma::new(8);
+        let mut ring = DmaRing::new(8);
+        dma.write(6);
+        let mut out = [0; 4];
+        assert_eq!(ring.read(&dma.buf, dma.total, &mut out, || 6), Ok(4));
+        assert_eq!(out, [0, 1, 2, 3]);
+        dma.write(4);
+        assert_eq!(ring.available(dma.total), Ok(6));
+        assert_eq!(ring.read(&dma.buf, dma.total, &mut out, || 10), Ok(4));
+        assert_eq!(out, [4, 5, 6, 7]);
+        assert_eq!(ring.read(&dma.buf, dma.total, &mut out, || 10), Ok(2));
+        assert_eq!(out[..2], [8, 9]);
+    }
+
+    #[test]
+    fn test_overrun() {
+        let mut dma = Dma::new(8);
+        let mut ring = DmaRing::new(8);
+        let mut out = [0; 8];
+        // a full buffer is still readable
+        dma.write(8);
+        assert_eq!(ring.available(dma.total), Ok(8));
+        dma.write(1);
+        assert_eq!(ring.available(dma.total), Err(DmaRingError::Overrun));
+        assert_eq!(
+            ring.read(&dma.buf, dma.total, &mut out, || 9),
+            Err(DmaRingError::Overrun)
+        );
+        ring.clear(dma.total);
+        assert_eq!(ring.read_total(), 9);
+        assert_eq!(ring.available(dma.total), Ok(0));
+        dma.write(2);
+        assert_eq!(ring.read(&dma.buf, dma.total, &mut out, || 11), Ok(2));
+        assert_eq!(out[..2], [9, 10]);
+    }
+
+    #[test]
+    fn test_overwritten_while_copying() {
+        let mut dma = Dma::new(8);
+        let mut ring = DmaRing::new(8);
+        let mut out = [0; 8];
+        dma.write(7);
+        // the dma wrote 2 more bytes during the copy and hit the oldest unread byte
+        assert_eq!(
+            ring.read(&dma.buf, 7, &mut out, || 9),
+            Err(DmaRingError::Overrun)
+        );
+        // nothing was consumed
+        assert_eq!(ring.available(8), Ok(8));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::hal::{I2c, I2cError, Pin};
+use crate::hal::{I2c, I2cError, I2cOperation, Pin};
 use crate::shared_i2c::SharedI2cManager;
 use embassy_sync::blocking_mutex::raw::RawMutex;
 
@@ -20,15 +20,30 @@ pub const ICM20948_ACC_ZOUT_H: u8 = 0x31;
 pub const ICM20948_ACC_ZOUT_L: u8 = 0x32;
 pub const ICM20948_BANK_SEL: u8 = 0x7f;
 
+/// Builds one transaction that reads the six accelerometer registers into `buf`,
+/// each register is addressed with a write followed by a repeated start read.
+fn imu_read_operations(buf: &mut [u8; 6]) -> [I2cOperation<'_>; 12] {
+    let [x_h, x_l, y_h, y_l, z_h, z_l] = buf.each_mut();
+    [
+        I2cOperation::Write(&[ICM20948_ACC_XOUT_H]),
+        I2cOperation::Read(core::slice::from_mut(x_h)),
+        I2cOperation::Write(&[ICM20948_ACC_XOUT_L]),
+        I2cOperation::Read(core::slice::from_mut(x_l)),
+        I2cOperation::Write(&[ICM20948_ACC_YOUT_H]),
+        I2cOperation::Read(core::slice::from_mut(y_h)),
+        I2cOperation::Write(&[ICM20948_ACC_YOUT_L]),
+        I2cOperation::Read(core::slice::from_mut(y_l)),
+        I2cOperation::Write(&[ICM20948_ACC_ZOUT_H]),
+        I2cOperation::Read(core::slice::from_mut(z_h)),
+        I2cOperation::Write(&[ICM20948_ACC_ZOUT_L]),
+        I2cOperation::Read(core::slice::from_mut(z_l)),
+    ]
+}
+
 /// Reads IMU data synchronously.
 pub fn icm20948_read_imu<I2C: I2c<P>, P: Pin>(i2c: &mut I2C) -> Result<[u8; 6], I2cError> {
     let mut buf = [0u8; 6];
-    i2c.write_read(ICM20948_ADDR, &[ICM20948_ACC_XOUT_H], &mut buf[0..1])?;
-    i2c.write_read(ICM20948_ADDR, &[ICM20948_ACC_XOUT_L], &mut buf[1..2])?;
-    i2c.write_read(ICM20948_ADDR, &[ICM20948_ACC_YOUT_H], &mut buf[2..3])?;
-    i2c.write_read(ICM20948_ADDR, &[ICM20948_ACC_YOUT_L], &mut buf[3..4])?;
-    i2c.write_read(ICM20948_ADDR, &[ICM20948_ACC_ZOUT_H], &mut buf[4..5])?;
-    i2c.write_read(ICM20948_ADDR, &[ICM20948_ACC_ZOUT_L], &mut buf[5..6])?;
+    i2c.transaction(ICM20948_ADDR, &mut imu_read_operations(&mut buf))?;
     Ok(buf)
 }
 
@@ -54,21 +69,12 @@ pub fn icm20948_setup<I2C: I2c<P>, P: Pin>(i2c: &mut I2C, fsync: &P) -> Result<(
 }
 
 /// Reads IMU data asynchronously using the Shared I2C Manager.
+/// All six registers are read while holding the bus lock once.
 pub async fn icm20948_read_imu_async<M: RawMutex, I2C: I2c<P>, P: Pin>(
     i2c: &SharedI2cManager<M, I2C, P>,
 ) -> Result<[u8; 6], I2cError> {
     let mut buf = [0u8; 6];
-    i2c.write_read(ICM20948_ADDR, &[ICM20948_ACC_XOUT_H], &mut buf[0..1])
-        .await?;
-    i2c.write_read(ICM20948_ADDR, &[ICM20948_ACC_XOUT_L], &mut buf[1..2])
-        .await?;
-    i2c.write_read(ICM20948_ADDR, &[ICM20948_ACC_YOUT_H], &mut buf[2..3])
-        .await?;
-    i2c.write_read(ICM20948_ADDR, &[ICM20948_ACC_YOUT_L], &mut buf[3..4])
-        .await?;
-    i2c.write_read(ICM20948_ADDR, &[ICM20948_ACC_ZOUT_H], &mut buf[4..5])
-        .await?;
-    i2c.write_read(ICM20948_ADDR, &[ICM20948_ACC_ZOUT_L], &mut buf[5..6])
+    i2c.transaction(ICM20948_ADDR, &mut imu_read_operations(&mut buf))
         .await?;
     Ok(buf)
 }
@@ -1,6 +1,6 @@
 pub mod ov5640_reg;
 use crate::drivers::ov5640::ov5640_reg::*;
-use crate::hal::{Dcmi, Delay, I2c, I2cError, Pin};
+use crate::hal::{Dcmi, Delay, DmaError, I2c, I2cError, Pin};
 use crate::shared_i2c::SharedI2cManager;
 use embassy_sync::blocking_mutex::raw::RawMutex;
 
@@ -186,13 +186,14 @@ pub async fn capture_frame<P: Pin, D: Delay, DCMI: Dcmi>(
     delay: &D,
     dcmi: &DCMI,
     pic_buf: &mut [u8],
-) {
+) -> Result<(), DmaError> {
     pdwn.set_low(); // set power down to low. Enable camera
     delay.delay_ms(2);
     delay.delay_ms(200);
-    dcmi.capture(pic_buf).await;
+    let res = dcmi.capture(pic_buf).await;
     info!("finish take picture");
     pdwn.set_high();
+    res
 }
 
 #[cfg(test)]
@@ -612,10 +613,11 @@ mod tests {
         captured: std::sync::atomic::AtomicBool,
     }
     impl Dcmi for MockDcmi {
-        async fn capture(&self, pic_buf: &mut [u8]) {
+        async fn capture(&self, pic_buf: &mut [u8]) -> Result<(), DmaError> {
             self.captured
                 .store(true, std::sync::atomic::Ordering::SeqCst);
             pic_buf[0] = 0xAA;
+            Ok(())
         }
     }
 
@@ -632,7 +634,10 @@ mod tests {
             captured: std::sync::atomic::AtomicBool::new(false),
         };
         let mut buf = [0u8; 10];
-        block_on(capture_frame(&pin, &delay, &dcmi, &mut buf));
+        assert_eq!(
+            block_on(capture_frame(&pin, &delay, &dcmi, &mut buf)),
+            Ok(())
+        );
         assert!(pin.low_called.load(std::sync::atomic::Ordering::SeqCst));
         assert!(pin.high_called.load(std::sync::atomic::Ordering::SeqCst));
         assert!(dcmi.captured.load(std::sync::atomic::Ordering::SeqCst));
@@ -127,6 +127,10 @@ define_gpio_port_alt!(
     I2C3_SCL_PC0: GPIOC, 0, 4, Moder::ALTERNATE, Ot::OPEN_DRAIN, Pupdr::PULL_UP, Ospeedr::MEDIUM_SPEED,
     I2C3_SDA_PB4: GPIOB, 4, 4, Moder::ALTERNATE, Ot::OPEN_DRAIN, Pupdr::PULL_UP, Ospeedr::MEDIUM_SPEED,
 
+    I2C1_SMBA_PB5: GPIOB, 5, 4, Moder::ALTERNATE, Ot::OPEN_DRAIN, Pupdr::PULL_UP, Ospeedr::MEDIUM_SPEED,
+    I2C2_SMBA_PB12: GPIOB, 12, 4, Moder::ALTERNATE, Ot::OPEN_DRAIN, Pupdr::PULL_UP, Ospeedr::MEDIUM_SPEED,
+    I2C3_SMBA_PB2: GPIOB, 2, 4, Moder::ALTERNATE, Ot::OPEN_DRAIN, Pupdr::PULL_UP, Ospeedr::MEDIUM_SPEED,
+
     DCMI_D0_PA9: GPIOA, 9, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::VERY_HIGH_SPEED,
     DCMI_D1_PA10: GPIOA, 10, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::VERY_HIGH_SPEED,
     DCMI_D2_PE0: GPIOE, 0, 10, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::VERY_HIGH_SPEED,
@@ -174,6 +178,72 @@ define_gpio_port_alt!(
 
     USART_TX_PA9: GPIOA, 9, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
     USART_RX_PA10: GPIOA, 10, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART1_TX_PB6: GPIOB, 6, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART1_TX_PG9: GPIOG, 9, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART1_RX_PB7: GPIOB, 7, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART1_RX_PG10: GPIOG, 10, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+
+    USART2_TX_PA2: GPIOA, 2, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART2_TX_PD5: GPIOD, 5, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART2_RX_PA3: GPIOA, 3, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART2_RX_PD6: GPIOD, 6, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART2_RX_PA15: GPIOA, 15, 3, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+
+    USART3_TX_PB10: GPIOB, 10, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART3_TX_PC4: GPIOC, 4, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART3_TX_PC10: GPIOC, 10, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART3_TX_PD8: GPIOD, 8, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART3_RX_PB11: GPIOB, 11, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART3_RX_PC5: GPIOC, 5, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART3_RX_PC11: GPIOC, 11, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART3_RX_PD9: GPIOD, 9, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+
+    UART4_TX_PA0: GPIOA, 0, 8, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    UART4_TX_PC10: GPIOC, 10, 8, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    UART4_RX_PA1: GPIOA, 1, 8, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    UART4_RX_PC11: GPIOC, 11, 8, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+
+    UART5_TX_PC12: GPIOC, 12, 8, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    UART5_RX_PD2: GPIOD, 2, 8, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+
+    LPUART1_TX_PA2: GPIOA, 2, 8, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    LPUART1_TX_PB11: GPIOB, 11, 8, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    LPUART1_TX_PC1: GPIOC, 1, 8, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    LPUART1_TX_PG7: GPIOG, 7, 8, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    LPUART1_RX_PA3: GPIOA, 3, 8, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    LPUART1_RX_PB10: GPIOB, 10, 8, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    LPUART1_RX_PC0: GPIOC, 0, 8, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    LPUART1_RX_PG8: GPIOG, 8, 8, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+
+    // RTS in hardware flow control, driver enable in RS-485 mode
+    USART1_RTS_DE_PA12: GPIOA, 12, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART1_RTS_DE_PB3: GPIOB, 3, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART2_RTS_DE_PA1: GPIOA, 1, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART2_RTS_DE_PD4: GPIOD, 4, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART3_RTS_DE_PB1: GPIOB, 1, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART3_RTS_DE_PB14: GPIOB, 14, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART3_RTS_DE_PD2: GPIOD, 2, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART3_RTS_DE_PD12: GPIOD, 12, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    UART4_RTS_DE_PA15: GPIOA, 15, 8, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    UART5_RTS_DE_PB4: GPIOB, 4, 8, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    LPUART1_RTS_DE_PB1: GPIOB, 1, 8, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    LPUART1_RTS_DE_PB12: GPIOB, 12, 8, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    LPUART1_RTS_DE_PG6: GPIOG, 6, 8, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+
+    // CTS in hardware flow control
+    USART1_CTS_PA11: GPIOA, 11, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART1_CTS_PB4: GPIOB, 4, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART2_CTS_PA0: GPIOA, 0, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART2_CTS_PD3: GPIOD, 3, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART3_CTS_PA6: GPIOA, 6, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART3_CTS_PB13: GPIOB, 13, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    USART3_CTS_PD11: GPIOD, 11, 7, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    UART4_CTS_PB7: GPIOB, 7, 8, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    UART5_CTS_PB5: GPIOB, 5, 8, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    LPUART1_CTS_PA6: GPIOA, 6, 8, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    LPUART1_CTS_PB13: GPIOB, 13, 8, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+    LPUART1_CTS_PG5: GPIOG, 5, 8, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+
     ADC1_IN3_PC2: GPIOC, 2, 0, Moder::ANALOG, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::LOW_SPEED,
     ADC1_IN1_PC0: GPIOC, 0, 0, Moder::ANALOG, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::LOW_SPEED,
     ADC1_IN6_PC0: GPIOC, 0, 0, Moder::ANALOG, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::LOW_SPEED,
@@ -185,7 +255,50 @@ define_gpio_port_alt!(
     TIM3_CH1_PA6: GPIOA, 6, 2, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
 
     TIM3_CH1_PB4: GPIOB, 4, 2, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
-    TIM3_CH4_PB1: GPIOB, 1, 2, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED
+    TIM3_CH4_PB1: GPIOB, 1, 2, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::MEDIUM_SPEED,
+
+    SPI1_SCK_PA5: GPIOA, 5, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI1_SCK_PB3: GPIOB, 3, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI1_SCK_PE13: GPIOE, 13, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI1_SCK_PG2: GPIOG, 2, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI1_MISO_PA6: GPIOA, 6, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI1_MISO_PB4: GPIOB, 4, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI1_MISO_PE14: GPIOE, 14, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI1_MISO_PG3: GPIOG, 3, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI1_MOSI_PA7: GPIOA, 7, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI1_MOSI_PB5: GPIOB, 5, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI1_MOSI_PE15: GPIOE, 15, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI1_MOSI_PG4: GPIOG, 4, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI1_NSS_PA4: GPIOA, 4, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::PULL_UP, Ospeedr::HIGH_SPEED,
+    SPI1_NSS_PA15: GPIOA, 15, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::PULL_UP, Ospeedr::HIGH_SPEED,
+    SPI1_NSS_PE12: GPIOE, 12, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::PULL_UP, Ospeedr::HIGH_SPEED,
+    SPI1_NSS_PG5: GPIOG, 5, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::PULL_UP, Ospeedr::HIGH_SPEED,
+
+    SPI2_SCK_PB10: GPIOB, 10, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI2_SCK_PB13: GPIOB, 13, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI2_SCK_PD1: GPIOD, 1, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI2_MISO_PB14: GPIOB, 14, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI2_MISO_PC2: GPIOC, 2, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI2_MISO_PD3: GPIOD, 3, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI2_MOSI_PB15: GPIOB, 15, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI2_MOSI_PC3: GPIOC, 3, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI2_MOSI_PD4: GPIOD, 4, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI2_NSS_PB9: GPIOB, 9, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::PULL_UP, Ospeedr::HIGH_SPEED,
+    SPI2_NSS_PB12: GPIOB, 12, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::PULL_UP, Ospeedr::HIGH_SPEED,
+    SPI2_NSS_PD0: GPIOD, 0, 5, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::PULL_UP, Ospeedr::HIGH_SPEED,
+
+    SPI3_SCK_PB3: GPIOB, 3, 6, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI3_SCK_PC10: GPIOC, 10, 6, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI3_SCK_PG9: GPIOG, 9, 6, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI3_MISO_PB4: GPIOB, 4, 6, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI3_MISO_PC11: GPIOC, 11, 6, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI3_MISO_PG10: GPIOG, 10, 6, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI3_MOSI_PB5: GPIOB, 5, 6, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI3_MOSI_PC12: GPIOC, 12, 6, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI3_MOSI_PG11: GPIOG, 11, 6, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::FLOATING, Ospeedr::HIGH_SPEED,
+    SPI3_NSS_PA4: GPIOA, 4, 6, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::PULL_UP, Ospeedr::HIGH_SPEED,
+    SPI3_NSS_PA15: GPIOA, 15, 6, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::PULL_UP, Ospeedr::HIGH_SPEED,
+    SPI3_NSS_PG12: GPIOG, 12, 6, Moder::ALTERNATE, Ot::PUSH_PULL, Pupdr::PULL_UP, Ospeedr::HIGH_SPEED
 );
 pub const I2C1_SCL_PINS: [GpioPort; 2] = [I2C1_SCL_PB6, I2C1_SCL_PB8];
 pub const I2C1_SDA_PINS: [GpioPort; 3] = [I2C1_SDA_PB3, I2C1_SDA_PB7, I2C1_SDA_PB9];
@@ -193,8 +306,46 @@ pub const I2C2_SCL_PINS: [GpioPort; 3] = [I2C2_SCL_PB13, I2C2_SCL_PF1, I2C2_SCL_
 pub const I2C2_SDA_PINS: [GpioPort; 3] = [I2C2_SDA_PB14, I2C2_SDA_PF0, I2C2_SDA_PB11];
 pub const I2C3_SCL_PINS: [GpioPort; 1] = [I2C3_SCL_PC0];
 pub const I2C3_SDA_PINS: [GpioPort; 1] = [I2C3_SDA_PB4];
-pub const USART1_TX_PINS: [GpioPort; 1] = [USART_TX_PA9];
-pub const USART1_RX_PINS: [GpioPort; 1] = [USART_RX_PA10];
+pub const I2C1_SMBA_PINS: [GpioPort; 1] = [I2C1_SMBA_PB5];
+pub const I2C2_SMBA_PINS: [GpioPort; 1] = [I2C2_SMBA_PB12];
+pub const I2C3_SMBA_PINS: [GpioPort; 1] = [I2C3_SMBA_PB2];
+pub const USART1_TX_PINS: [GpioPort; 3] = [USART_TX_PA9, USART1_TX_PB6, USART1_TX_PG9];
+pub const USART1_RX_PINS: [GpioPort; 3] = [USART_RX_PA10, USART1_RX_PB7, USART1_RX_PG10];
+pub const USART2_TX_PINS: [GpioPort; 2] = [USART2_TX_PA2, USART2_TX_PD5];
+pub const USART2_RX_PINS: [GpioPort; 3] = [USART2_RX_PA3, USART2_RX_PD6, USART2_RX_PA15];
+pub const USART3_TX_PINS: [GpioPort; 4] = [USART3_TX_PB10, USART3_TX_PC4, USART3_TX_PC10, USART3_TX_PD8];
+pub const USART3_RX_PINS: [GpioPort; 4] = [USART3_RX_PB11, USART3_RX_PC5, USART3_RX_PC11, USART3_RX_PD9];
+pub const UART4_TX_PINS: [GpioPort; 2] = [UART4_TX_PA0, UART4_TX_PC10];
+pub const UART4_RX_PINS: [GpioPort; 2] = [UART4_RX_PA1, UART4_RX_PC11];
+pub const UART5_TX_PINS: [GpioPort; 1] = [UART5_TX_PC12];
+pub const UART5_RX_PINS: [GpioPort; 1] = [UART5_RX_PD2];
+pub const LPUART1_TX_PINS: [GpioPort; 4] = [LPUART1_TX_PA2, LPUART1_TX_PB11, LPUART1_TX_PC1, LPUART1_TX_PG7];
+pub const LPUART1_RX_PINS: [GpioPort; 4] = [LPUART1_RX_PA3, LPUART1_RX_PB10, LPUART1_RX_PC0, LPUART1_RX_PG8];
+pub const USART1_RTS_DE_PINS: [GpioPort; 2] = [USART1_RTS_DE_PA12, USART1_RTS_DE_PB3];
+pub const USART2_RTS_DE_PINS: [GpioPort; 2] = [USART2_RTS_DE_PA1, USART2_RTS_DE_PD4];
+pub const USART3_RTS_DE_PINS: [GpioPort; 4] =
+    [USART3_RTS_DE_PB1, USART3_RTS_DE_PB14, USART3_RTS_DE_PD2, USART3_RTS_DE_PD12];
+pub const UART4_RTS_DE_PINS: [GpioPort; 1] = [UART4_RTS_DE_PA15];
+pub const UART5_RTS_DE_PINS: [GpioPort; 1] = [UART5_RTS_DE_PB4];
+pub const LPUART1_RTS_DE_PINS: [GpioPort; 3] = [LPUART1_RTS_DE_PB1, LPUART1_RTS_DE_PB12, LPUART1_RTS_DE_PG6];
+pub const USART1_CTS_PINS: [GpioPort; 2] = [USART1_CTS_PA11, USART1_CTS_PB4];
+pub const USART2_CTS_PINS: [GpioPort; 2] = [USART2_CTS_PA0, USART2_CTS_PD3];
+pub const USART3_CTS_PINS: [GpioPort; 3] = [USART3_CTS_PA6, USART3_CTS_PB13, USART3_CTS_PD11];
+pub const UART4_CTS_PINS: [GpioPort; 1] = [UART4_CTS_PB7];
+pub const UART5_CTS_PINS: [GpioPort; 1] = [UART5_CTS_PB5];
+pub const LPUART1_CTS_PINS: [GpioPort; 3] = [LPUART1_CTS_PA6, LPUART1_CTS_PB13, LPUART1_CTS_PG5];
+pub const SPI1_SCK_PINS: [GpioPort; 4] = [SPI1_SCK_PA5, SPI1_SCK_PB3, SPI1_SCK_PE13, SPI1_SCK_PG2];
+pub const SPI1_MISO_PINS: [GpioPort; 4] = [SPI1_MISO_PA6, SPI1_MISO_PB4, SPI1_MISO_PE14, SPI1_MISO_PG3];
+pub const SPI1_MOSI_PINS: [GpioPort; 4] = [SPI1_MOSI_PA7, SPI1_MOSI_PB5, SPI1_MOSI_PE15, SPI1_MOSI_PG4];
+pub const SPI1_NSS_PINS: [GpioPort; 4] = [SPI1_NSS_PA4, SPI1_NSS_PA15, SPI1_NSS_PE12, SPI1_NSS_PG5];
+pub const SPI2_SCK_PINS: [GpioPort; 3] = [SPI2_SCK_PB10, SPI2_SCK_PB13, SPI2_SCK_PD1];
+pub const SPI2_MISO_PINS: [GpioPort; 3] = [SPI2_MISO_PB14, SPI2_MISO_PC2, SPI2_MISO_PD3];
+pub const SPI2_MOSI_PINS: [GpioPort; 3] = [SPI2_MOSI_PB15, SPI2_MOSI_PC3, SPI2_MOSI_PD4];
+pub const SPI2_NSS_PINS: [GpioPort; 3] = [SPI2_NSS_PB9, SPI2_NSS_PB12, SPI2_NSS_PD0];
+pub const SPI3_SCK_PINS: [GpioPort; 3] = [SPI3_SCK_PB3, SPI3_SCK_PC10, SPI3_SCK_PG9];
+pub const SPI3_MISO_PINS: [GpioPort; 3] = [SPI3_MISO_PB4, SPI3_MISO_PC11, SPI3_MISO_PG10];
+pub const SPI3_MOSI_PINS: [GpioPort; 3] = [SPI3_MOSI_PB5, SPI3_MOSI_PC12, SPI3_MOSI_PG11];
+pub const SPI3_NSS_PINS: [GpioPort; 3] = [SPI3_NSS_PA4, SPI3_NSS_PA15, SPI3_NSS_PG12];
 /// Implement the Pin trait for GpioPort
 use crate::hal;
 impl hal::Pin for GpioPort {
@@ -211,3 +362,45 @@ impl hal::Pin for GpioPort {
         self.toggle();
     }
 }
+
+/////////////////////////// embedded-hal implementation /////////////////////////////
+#[cfg(feature = "embedded-hal")]
+impl embedded_hal::digital::ErrorType for GpioPort {
+    type Error = core::convert::Infallible;
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_hal::digital::OutputPin for GpioPort {
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        GpioPort::set_low(self);
+        Ok(())
+    }
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        GpioPort::set_high(self);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_hal::digital::StatefulOutputPin for GpioPort {
+    fn is_set_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.port.odr().read().odr(self.pin) == Odr::HIGH)
+    }
+    fn is_set_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.port.odr().read().odr(self.pin) == Odr::LOW)
+    }
+    fn toggle(&mut self) -> Result<(), Self::Error> {
+        GpioPort::toggle(self);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_hal::digital::InputPin for GpioPort {
+    fn is_high(&mut self) -> Result<bool, Self::Error> {
+        Ok(GpioPort::is_high(self))
+    }
+    fn is_low(&mut self) -> Result<bool, Self::Error> {
+        Ok(!GpioPort::is_high(self))
+    }
+}
@@ -9,17 +9,45 @@ pub trait Pin {
     fn toggle(&self);
 }
 pub trait DMA {
-    fn start(
+    /// Transfer `len` bytes from `src_addr` to `dar_addr` and wait for the end of the transfer.
+    ///
+    /// # Safety
+    /// The memory at `src_addr` and `dar_addr` must stay valid until the returned future has
+    /// finished or is dropped.
+    unsafe fn start(
         &self,
         src_addr: u32,
         src_inc: bool,
         dar_addr: u32,
         dst_inc: bool,
         len: u32,
-    ) -> impl core::future::Future<Output = ()> + Send + Sync;
+    ) -> impl core::future::Future<Output = Result<(), DmaError>> + Send + Sync;
     fn stop(&self);
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DmaError {
+    /// the request or trigger name is not in its table
+    UnknownRequest,
+    /// all suitable channels are taken
+    NoFreeChannel,
+    /// an empty transfer, or a block that does not hold a whole number of data
+    Length,
+    /// the transfer needs more blocks than the linked list of the channel holds
+    TooManyBlocks,
+    /// the linked list crosses a 64 KiB page
+    CrossesPage,
+    /// a 2D rectangle does not fit its buffers or the block, row and offset limits
+    Rect,
+    /// bus error while reading or writing data (`DTEF`)
+    DataTransfer,
+    /// bus error while loading a linked-list item (`ULEF`)
+    LinkUpdate,
+    /// invalid channel programming (`USEF`)
+    UserSetting,
+}
+
 /// Abstraction over hardware delay.
 pub trait Delay {
     fn delay_ms(&self, ms: u32);
@@ -33,7 +61,10 @@ pub trait Rtc {
 
 /// Abstraction over digital camera interface (DCMI).
 pub trait Dcmi {
-    fn capture(&self, pic_buf: &mut [u8]) -> impl core::future::Future<Output = ()> + Send;
+    fn capture(
+        &self,
+        pic_buf: &mut [u8],
+    ) -> impl core::future::Future<Output = Result<(), DmaError>> + Send;
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -47,16 +78,47 @@ pub enum I2cFrequency {
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum I2cError {
     InitError,
+    /// misplaced START/STOP on the bus (`BERR`)
     BusError,
     Nack,
     Timeout,
+    /// overrun/underrun in target mode with clock stretching disabled (`OVR`)
     Overrun,
+    /// another controller won the bus (`ARLO`)
+    ArbitrationLoss,
+    /// the received SMBus PEC does not match (`PECERR`)
+    Pec,
+    Dma(DmaError),
+}
+
+impl From<DmaError> for I2cError {
+    fn from(e: DmaError) -> Self {
+        I2cError::Dma(e)
+    }
+}
+
+impl I2cError {
+    /// The bus may be left in a bad state (e.g. a target holding SDA low), see `I2c::recover_bus`.
+    pub fn needs_bus_recovery(&self) -> bool {
+        matches!(self, I2cError::BusError | I2cError::Timeout)
+    }
 }
 
+/// An address match in slave mode with the address the host used: 7 or 10 bit, 0 for a general
+/// call.
+#[derive(Debug, Copy, Clone, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum I2cSlaveEvent {
-    Read,  // Host wants to read from us
-    Write, // Host wants to write to us
+    Read(u16),  // Host wants to read from us
+    Write(u16), // Host wants to write to us
+}
+
+impl I2cSlaveEvent {
+    pub fn address(&self) -> u16 {
+        match self {
+            I2cSlaveEvent::Read(addr) | I2cSlaveEvent::Write(addr) => *addr,
+        }
+    }
 }
 
 pub trait I2cSlave<T: Pin> {
@@ -64,7 +126,8 @@ pub trait I2cSlave<T: Pin> {
     where
         Self: Sized;
 
-    /// Wait for an address match from a host. Returns whether the host wants to Read or Write.
+    /// Wait for an address match from a host. Returns whether the host wants to Read or Write and
+    /// which of our addresses it used.
     fn slave_wait_address(&self) -> Result<I2cSlaveEvent, I2cError>;
     fn slave_wait_address_async(
         &self,
@@ -83,6 +146,48 @@ pub trait I2cSlave<T: Pin> {
         &self,
         data: &[u8],
     ) -> impl core::future::Future<Output = Result<(), I2cError>> + Send;
+
+    /// Read what the host writes until it ends the write with STOP or a repeated START. Returns
+    /// the number of bytes, `Overrun` if the host writes more than `data` holds.
+    fn slave_receive(&self, data: &mut [u8]) -> Result<usize, I2cError>;
+    fn slave_receive_async(
+        &self,
+        data: &mut [u8],
+    ) -> impl core::future::Future<Output = Result<usize, I2cError>> + Send;
+
+    /// Send `data` until the host ends the read. Returns how many bytes of `data` the host took,
+    /// 0xff is sent if it reads past the end.
+    fn slave_transmit(&self, data: &[u8]) -> Result<usize, I2cError>;
+    fn slave_transmit_async(
+        &self,
+        data: &[u8],
+    ) -> impl core::future::Future<Output = Result<usize, I2cError>> + Send;
+}
+
+/// One operation of an i2c transaction, see `I2c::transaction`.
+#[derive(Debug, PartialEq)]
+pub enum I2cOperation<'a> {
+    /// write the buffer to the target
+    Write(&'a [u8]),
+    /// read from the target, the length is determined by the length of the buffer
+    Read(&'a mut [u8]),
+}
+
+impl I2cOperation<'_> {
+    pub fn is_read(&self) -> bool {
+        matches!(self, I2cOperation::Read(_))
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            I2cOperation::Write(data) => data.len(),
+            I2cOperation::Read(data) => data.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 pub trait I2c<T: Pin> {
@@ -119,9 +224,42 @@ pub trait I2c<T: Pin> {
         read_data: &mut [u8],
     ) -> Result<(), I2cError>;
 
+    /// start -> op[0] -> ... -> op[n - 1] -> stop
+    /// Adjacent operations in the same direction are merged on the bus (no restart in between),
+    /// a change of direction issues a repeated start. The bus is not released until the last
+    /// operation is done, so the whole sequence is atomic for the target.
+    /// The default runs the operations one by one with `write` and `read`, each with its own start
+    /// and stop, drivers that can chain them on the bus override it.
+    fn transaction(&self, addr: u16, operations: &mut [I2cOperation<'_>]) -> Result<(), I2cError> {
+        for op in operations.iter_mut() {
+            match op {
+                I2cOperation::Write(data) => self.write(addr, data)?,
+                I2cOperation::Read(data) => self.read(addr, data)?,
+            }
+        }
+        Ok(())
+    }
+    /// Note: The default keeps using the blocking `transaction`.
+    fn transaction_async(
+        &self,
+        addr: u16,
+        operations: &mut [I2cOperation<'_>],
+    ) -> impl core::future::Future<Output = Result<(), I2cError>> + Send {
+        let res = self.transaction(addr, operations);
+        async move { res }
+    }
+
     /// return the maximum frequency that the I2c can support
     fn capacity(&self) -> I2cFrequency;
 
+    /// Free a bus where a target holds SDA low (clock out up to nine SCL pulses and a STOP),
+    /// then re-initialise the peripheral. The default cannot free the bus and returns `BusError`.
+    fn recover_bus(&self) -> Result<(), I2cError> {
+        Err(I2cError::BusError)
+    }
+
+    /// Write with retries. Errors that can leave the bus stuck trigger `recover_bus` before the
+    /// next attempt.
     fn write_retry(&self, addr: u16, data: &[u8], retry: u8) -> Result<(), I2cError> {
         let mut cnt = 0;
         loop {
@@ -132,19 +270,41 @@ pub trait I2c<T: Pin> {
                     if cnt >= retry {
                         return Err(e);
                     }
+                    if e.needs_bus_recovery() {
+                        let _ = self.recover_bus();
+                    }
                 }
             }
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum UsartError {
     InitError,
     BusError,
     Nack,
     Timeout,
+    /// a software receive buffer (ring) was lapped, received bytes were lost before they were read
+    BufferOverrun,
+    /// a received frame is longer than the buffer it is read into
+    BufferTooSmall,
+    /// a stop bit was not found (FE)
+    Framing,
+    /// noise detected on a received bit (NE)
+    Noise,
+    /// parity check failed (PE)
+    Parity,
+    /// the hardware receiver lost a character, it arrived before the previous one was read (ORE)
+    Overrun,
+    Dma(DmaError),
+}
+
+impl From<DmaError> for UsartError {
+    fn from(e: DmaError) -> Self {
+        UsartError::Dma(e)
+    }
 }
 
 pub trait Usart<T: Pin> {
@@ -184,39 +344,164 @@ pub trait Usart<T: Pin> {
     ) -> impl core::future::Future<Output = Result<(), UsartError>> + Send;
 }
 
-// pub trait Spi: Drop {
-//     pub enum Error {
-//         BusError,
-//         Timeout,
-//     }
-//     pub enum Mode {
-//         Mode0,
-//         Mode1,
-//         Mode2,
-//         Mode3,
-//     }
-//     pub enum Frequency {
-//         FREQ_1MHz,
-//         FREQ_2MHz,
-//         FREQ_4MHz,
-//         FREQ_8MHz,
-//         FREQ_16MHz,
-//         FREQ_32MHz,
-//         FREQ_64MHz,
-//         FREQ_128MHz,
-//     }
-//     /// create a new instance of Spi. The instance should be initialized with the default configuration.
-//     /// After this function is called, the Spi should be ready to use.
-//     pub fn new(freq: u32, mode: Mode, sck: Pin, miso: Pin, mosi: Pin) -> Self;
-//
-//     pub fn write(&self, data: &[u8]) -> Result<(), Error>;
-//
-//     pub fn read(&self, data: &mut [u8]) -> Result<(), Error>;
-//
-//     pub fn write_read(&self, write_data: &[u8], read_data: &mut [u8]) -> Result<(), Error>;
-// }
-//
-//
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SpiMode {
+    /// CPOL = 0, CPHA = 0
+    Mode0,
+    /// CPOL = 0, CPHA = 1
+    Mode1,
+    /// CPOL = 1, CPHA = 0
+    Mode2,
+    /// CPOL = 1, CPHA = 1
+    Mode3,
+}
+
+/// Number of bits shifted per SPI frame.
+/// For 16-bit frames every two consecutive bytes of a buffer form one frame (MSB first),
+/// so the buffers must have an even length.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SpiFrameSize {
+    Bits8,
+    Bits16,
+}
+
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SpiError {
+    InitError,
+    BusError,
+    Overrun,
+    ModeFault,
+    Timeout,
+    /// a buffer does not hold a whole number of frames (an odd length with 16-bit frames)
+    FrameLength,
+    Dma(DmaError),
+}
+
+impl From<DmaError> for SpiError {
+    fn from(e: DmaError) -> Self {
+        SpiError::Dma(e)
+    }
+}
+
+pub trait Spi<T: Pin> {
+    /// create a new instance of Spi in master mode. `freq` is the requested SCK frequency in Hz,
+    /// the closest frequency that is not higher than `freq` is used.
+    /// After this function is called, the Spi should be ready to use.
+    fn new(
+        freq: u32,
+        mode: SpiMode,
+        frame: SpiFrameSize,
+        sck: T,
+        miso: T,
+        mosi: T,
+    ) -> Result<Self, SpiError>
+    where
+        Self: Sized;
+
+    /// write data, the received data is discarded
+    fn write(&self, data: &[u8]) -> Result<(), SpiError>;
+    fn write_async(
+        &self,
+        data: &[u8],
+    ) -> impl core::future::Future<Output = Result<(), SpiError>> + Send;
+
+    /// read data, the length is determined by the length of data. Zeros are sent on MOSI.
+    fn read(&self, data: &mut [u8]) -> Result<(), SpiError>;
+    fn read_async(
+        &self,
+        data: &mut [u8],
+    ) -> impl core::future::Future<Output = Result<(), SpiError>> + Send;
+
+    /// full duplex transfer: write(write_data) and read(read_data) at the same time.
+    /// If the lengths are different, the shorter buffer is padded (zeros sent / data discarded).
+    fn transfer(&self, write_data: &[u8], read_data: &mut [u8]) -> Result<(), SpiError>;
+    fn transfer_async(
+        &self,
+        write_data: &[u8],
+        read_data: &mut [u8],
+    ) -> impl core::future::Future<Output = Result<(), SpiError>> + Send;
+
+    /// return the actual SCK frequency in Hz
+    fn frequency(&self) -> u32;
+}
+
+pub trait SpiSlave<T: Pin> {
+    /// create a new instance of Spi in slave mode. The chip select is taken from the `nss` pin.
+    fn new_slave(
+        mode: SpiMode,
+        frame: SpiFrameSize,
+        sck: T,
+        miso: T,
+        mosi: T,
+        nss: T,
+    ) -> Result<Self, SpiError>
+    where
+        Self: Sized;
+
+    /// Wait for the host to clock a transfer. `write_data` is shifted out on MISO while
+    /// `read_data` is filled from MOSI.
+    fn slave_transfer(&self, write_data: &[u8], read_data: &mut [u8]) -> Result<(), SpiError>;
+    fn slave_transfer_async(
+        &self,
+        write_data: &[u8],
+        read_data: &mut [u8],
+    ) -> impl core::future::Future<Output = Result<(), SpiError>> + Send;
+}
+
+/////////////////////////// embedded-hal error kinds /////////////////////////////
+#[cfg(feature = "embedded-hal")]
+impl embedded_hal::i2c::Error for I2cError {
+    fn kind(&self) -> embedded_hal::i2c::ErrorKind {
+        use embedded_hal::i2c::{ErrorKind, NoAcknowledgeSource};
+        match self {
+            I2cError::Nack => ErrorKind::NoAcknowledge(NoAcknowledgeSource::Unknown),
+            I2cError::BusError => ErrorKind::Bus,
+            I2cError::Overrun => ErrorKind::Overrun,
+            I2cError::ArbitrationLoss => ErrorKind::ArbitrationLoss,
+            I2cError::InitError | I2cError::Timeout | I2cError::Pec | I2cError::Dma(_) => {
+                ErrorKind::Other
+            }
+        }
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_hal::spi::Error for SpiError {
+    fn kind(&self) -> embedded_hal::spi::ErrorKind {
+        use embedded_hal::spi::ErrorKind;
+        match self {
+            SpiError::Overrun => ErrorKind::Overrun,
+            SpiError::ModeFault => ErrorKind::ModeFault,
+            SpiError::InitError
+            | SpiError::BusError
+            | SpiError::Timeout
+            | SpiError::FrameLength
+            | SpiError::Dma(_) => ErrorKind::Other,
+        }
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_io::Error for UsartError {
+    fn kind(&self) -> embedded_io::ErrorKind {
+        match self {
+            UsartError::Timeout => embedded_io::ErrorKind::TimedOut,
+            UsartError::Framing | UsartError::Noise | UsartError::Parity => {
+                embedded_io::ErrorKind::InvalidData
+            }
+            UsartError::BufferTooSmall => embedded_io::ErrorKind::InvalidInput,
+            UsartError::InitError
+            | UsartError::BusError
+            | UsartError::Nack
+            | UsartError::BufferOverrun
+            | UsartError::Overrun
+            | UsartError::Dma(_) => embedded_io::ErrorKind::Other,
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -327,6 +612,91 @@ mod tests {
         }
     }
 
+    /// Loopback SPI: every byte written on MOSI is read back on MISO.
+    struct MockSpi {
+        mode: SpiMode,
+        frame: SpiFrameSize,
+        freq: u32,
+        fail: Cell<bool>,
+    }
+
+    impl MockSpi {
+        fn shift(&self, write_data: &[u8], read_data: &mut [u8]) -> Result<(), SpiError> {
+            if self.fail.get() {
+                return Err(SpiError::Overrun);
+            }
+            if self.frame == SpiFrameSize::Bits16 {
+                assert!(write_data.len().is_multiple_of(2) && read_data.len().is_multiple_of(2));
+            }
+            for (i, byte) in read_data.iter_mut().enumerate() {
+                *byte = write_data.get(i).copied().unwrap_or(0);
+            }
+            Ok(())
+        }
+    }
+
+    impl Spi<DummyPin> for MockSpi {
+        fn new(
+            freq: u32,
+            mode: SpiMode,
+            frame: SpiFrameSize,
+            _sck: DummyPin,
+            _miso: DummyPin,
+            _mosi: DummyPin,
+        ) -> Result<Self, SpiError> {
+            if freq == 0 {
+                return Err(SpiError::InitError);
+            }
+            Ok(Self {
+                mode,
+                frame,
+                freq,
+                fail: Cell::new(false),
+            })
+        }
+
+        fn write(&self, data: &[u8]) -> Result<(), SpiError> {
+            self.shift(data, &mut [])
+        }
+
+        fn write_async(
+            &self,
+            data: &[u8],
+        ) -> impl core::future::Future<Output = Result<(), SpiError>> + Send {
+            let res = self.write(data);
+            async move { res }
+        }
+
+        fn read(&self, data: &mut [u8]) -> Result<(), SpiError> {
+            self.shift(&[], data)
+        }
+
+        fn read_async(
+            &self,
+            data: &mut [u8],
+        ) -> impl core::future::Future<Output = Result<(), SpiError>> + Send {
+            let res = self.read(data);
+            async move { res }
+        }
+
+        fn transfer(&self, write_data: &[u8], read_data: &mut [u8]) -> Result<(), SpiError> {
+            self.shift(write_data, read_data)
+        }
+
+        fn transfer_async(
+            &self,
+            write_data: &[u8],
+            read_data: &mut [u8],
+        ) -> impl core::future::Future<Output = Result<(), SpiError>> + Send {
+            let res = self.transfer(write_data, read_data);
+            async move { res }
+        }
+
+        fn frequency(&self) -> u32 {
+            self.freq
+        }
+    }
+
     #[test]
     fn test_i2c_write_retry() {
         use futures::executor::block_on;
@@ -361,6 +731,26 @@ mod tests {
         assert_eq!(i2c.capacity(), I2cFrequency::Freq100khz);
     }
 
+    #[test]
+    fn test_i2c_transaction() {
+        use futures::executor::block_on;
+
+        let i2c = MockI2c::new(I2cFrequency::Freq100khz, DummyPin, DummyPin).unwrap();
+        let mut buf = [0u8; 2];
+        let mut ops = [I2cOperation::Write(&[0x10]), I2cOperation::Read(&mut buf)];
+        assert!(!ops[0].is_read());
+        assert!(ops[1].is_read());
+        assert_eq!(ops[1].len(), 2);
+        assert!(!ops[1].is_empty());
+        assert!(i2c.transaction(0x50, &mut ops).is_ok());
+        assert!(block_on(i2c.transaction_async(0x50, &mut ops)).is_ok());
+
+        // the first failing operation aborts the transaction
+        i2c.fail_count.set(1);
+        assert_eq!(i2c.transaction(0x50, &mut ops), Err(I2cError::BusError));
+        assert_eq!(i2c.fail_count.get(), 0);
+    }
+
     #[test]
     fn test_usart_write_retry() {
         use futures::executor::block_on;
@@ -384,4 +774,111 @@ mod tests {
         assert!(block_on(usart.write_async(&[])).is_ok());
         assert!(block_on(usart.read_async(&mut buf)).is_ok());
     }
+
+    #[test]
+    fn test_spi_loopback() {
+        use futures::executor::block_on;
+
+        assert_eq!(
+            MockSpi::new(
+                0,
+                SpiMode::Mode0,
+                SpiFrameSize::Bits8,
+                DummyPin,
+                DummyPin,
+                DummyPin
+            )
+            .err(),
+            Some(SpiError::InitError)
+        );
+
+        let spi = MockSpi::new(
+            1_000_000,
+            SpiMode::Mode3,
+            SpiFrameSize::Bits8,
+            DummyPin,
+            DummyPin,
+            DummyPin,
+        )
+        .unwrap();
+        assert_eq!(spi.mode, SpiMode::Mode3);
+        assert_eq!(spi.frequency(), 1_000_000);
+
+        // transfer with a longer read buffer pads with zeros
+        let mut buf = [0xffu8; 4];
+        assert!(spi.transfer(&[1, 2, 3], &mut buf).is_ok());
+        assert_eq!(buf, [1, 2, 3, 0]);
+
+        let mut buf = [0xffu8; 2];
+        assert!(block_on(spi.transfer_async(&[4, 5], &mut buf)).is_ok());
+        assert_eq!(buf, [4, 5]);
+
+        assert!(spi.read(&mut buf).is_ok());
+        assert_eq!(buf, [0, 0]);
+        assert!(block_on(spi.read_async(&mut buf)).is_ok());
+        assert!(spi.write(&[1, 2]).is_ok());
+        assert!(block_on(spi.write_async(&[1, 2])).is_ok());
+
+        // errors are propagated by every method
+        spi.fail.set(true);
+        assert_eq!(spi.write(&[1]), Err(SpiError::Overrun));
+        assert_eq!(spi.read(&mut buf), Err(SpiError::Overrun));
+        assert_eq!(
+            block_on(spi.transfer_async(&[1], &mut buf)),
+            Err(SpiError::Overrun)
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_spi_16bit_frames_require_even_length() {
+        let spi = MockSpi::new(
+            1_000_000,
+            SpiMode::Mode0,
+            SpiFrameSize::Bits16,
+            DummyPin,
+            DummyPin,
+            DummyPin,
+        )
+        .unwrap();
+        let _ = spi.write(&[1, 2, 3]);
+    }
+
+    #[test]
+    #[cfg(feature = "embedded-hal")]
+    fn test_embedded_hal_error_kinds() {
+        use embedded_hal::i2c::Error as _;
+        use embedded_hal::spi::Error as _;
+        use embedded_io::Error as _;
+
+        assert_eq!(
+            I2cError::Nack.kind(),
+            embedded_hal::i2c::ErrorKind::NoAcknowledge(
+                embedded_hal::i2c::NoAcknowledgeSource::Unknown
+            )
+        );
+        assert_eq!(I2cError::BusError.kind(), embedded_hal::i2c::ErrorKind::Bus);
+        assert_eq!(
+            I2cError::ArbitrationLoss.kind(),
+            embedded_hal::i2c::ErrorKind::ArbitrationLoss
+        );
+        assert_eq!(
+            SpiError::ModeFault.kind(),
+            embedded_hal::spi::ErrorKind::ModeFault
+        );
+        assert_eq!(UsartError::Timeout.kind(), embedded_io::ErrorKind::TimedOut);
+        assert_eq!(
+            UsartError::Parity.kind(),
+            embedded_io::ErrorKind::InvalidData
+        );
+        assert_eq!(UsartError::Overrun.kind(), embedded_io::ErrorKind::Other);
+        assert_eq!(
+            UsartError::BufferTooSmall.kind(),
+            embedded_io::ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            SpiError::from(DmaError::DataTransfer).kind(),
+            embedded_hal::spi::ErrorKind::Other
+        );
+    }
 }
@@ -1,36 +1,85 @@
 #![allow(unused)]
 
 use crate::clock;
+use crate::dma::{DmaChannel, Transfer};
+use crate::i2c_timing;
+use crate::i2c_transfer;
+use crate::smbus;
 use core::sync::atomic::{AtomicBool, Ordering};
 use core::task::Poll;
 use cortex_m::peripheral::NVIC;
 use embassy_sync::waitqueue::AtomicWaker;
+use stm32_metapac::i2c::vals::{Autoend, Dir, Oamode, Oamsk, Reload};
 use stm32_metapac::interrupt;
 use stm32_metapac::{I2C1, RCC};
 
 static TAKEN: [AtomicBool; 8] = [const { AtomicBool::new(false) }; 8]; // first bit will be ignored
 static WAKERS: [AtomicWaker; 8] = [const { AtomicWaker::new() }; 8];
+/// transfers shorter than this are not worth setting up the dma
+const DMA_THRESHOLD: usize = 32;
 
 pub struct I2cConfig {
     pub port_num: u8,
+    /// SCL frequency in Hz, at most 1Mhz. The timing is computed by `i2c_timing::compute_timing`.
     pub freq: u32,
     pub scl_pin: crate::gpio::GpioPort,
     pub sda_pin: crate::gpio::GpioPort,
+    pub clock_source: clock::I2cClockSource,
+    /// bus rise time in ns
+    pub rise_ns: u32,
+    /// bus fall time in ns
+    pub fall_ns: u32,
+    pub analog_filter: bool,
+    /// digital filter length in kernel clock cycles (0 - 15)
+    pub digital_filter: u8,
 }
 
 pub struct I2c {
     port_num: u8,
     port: stm32_metapac::i2c::I2c,
     freq: hal::I2cFrequency,
+    /// kernel clock in Hz, the SMBus timeouts count in it
+    kernel_freq: u32,
+    // kept for the bus recovery
+    scl_pin: GpioPort,
+    sda_pin: GpioPort,
+    /// (tx, rx) dma channels, None means interrupt mode
+    dma: Option<(DmaChannel, DmaChannel)>,
+    /// slave mode addresses, to report which one matched
+    own_addresses: Option<i2c_transfer::OwnAddresses>,
+}
+
+pub struct I2cSlaveConfig {
+    pub scl_pin: GpioPort,
+    pub sda_pin: GpioPort,
+    /// own address 1 (7 or 10 bit), own address 2 with mask and general call
+    pub addresses: i2c_transfer::OwnAddresses,
 }
 
 impl I2cConfig {
     pub fn new(port_num: u8, freq: u32, scl_pin: crate::gpio::GpioPort, sda_pin: crate::gpio::GpioPort) -> Self {
+        let timing = i2c_timing::I2cTimingConfig::new(clock::HSI_FREQ, freq);
         Self {
             port_num,
             freq,
             scl_pin,
             sda_pin,
+            clock_source: clock::I2cClockSource::Hsi,
+            rise_ns: timing.rise_ns,
+            fall_ns: timing.fall_ns,
+            analog_filter: timing.analog_filter,
+            digital_filter: timing.digital_filter,
+        }
+    }
+
+    pub fn timing_config(&self) -> i2c_timing::I2cTimingConfig {
+        i2c_timing::I2cTimingConfig {
+            kernel_freq: self.clock_source.freq(),
+            bus_freq: self.freq,
+            rise_ns: self.rise_ns,
+            fall_ns: self.fall_ns,
+            analog_filter: self.analog_filter,
+            digital_filter: self.digital_filter,
         }
     }
 }
@@ -80,343 +129,250 @@ pub fn pin_to_port(scl_pin: &GpioPort, sda_pin: &GpioPort) -> u8 {
 // }
 
 impl I2c {
-    pub async fn write_async_interrupt(&self, addr: u16, data: &[u8]) -> Result<(), hal::I2cError> {
-        let res = self.write_async_interrupt_impl(addr, data).await;
-        if res.is_err() {
-            self.port.cr1().modify(|v| {
-                v.set_txie(false);
-                v.set_rxie(false);
-                v.set_tcie(false);
-                v.set_stopie(false);
-                v.set_nackie(false);
-            });
-            self.port.icr().write(|v| {
-                v.set_stopcf(true);
-                v.set_nackcf(true);
-            });
+    /// Initialize the i2c as controller. The kernel clock and the bus timing are taken from
+    /// `config`; fails with `InitError` if the timing cannot be met (see `i2c_timing`).
+    pub fn new_with_config(config: I2cConfig) -> Result<Self, hal::I2cError> {
+        let port_num = config.port_num;
+        if pin_to_port(&config.scl_pin, &config.sda_pin) != port_num {
+            return Err(hal::I2cError::InitError);
         }
-        res
-    }
+        let timing = i2c_timing::compute_timing(&config.timing_config()).map_err(|_| hal::I2cError::InitError)?;
+        let freq = match timing.freq {
+            0..=100_000 => hal::I2cFrequency::Freq100khz,
+            100_001..=400_000 => hal::I2cFrequency::Freq400khz,
+            _ => hal::I2cFrequency::Freq1Mhz,
+        };
+        if TAKEN[port_num as usize].swap(true, Ordering::SeqCst) {
+            return Err(hal::I2cError::InitError);
+        }
+        config.scl_pin.setup();
+        config.sda_pin.setup();
+        let kernel_freq = clock::set_i2c_clock(port_num, config.clock_source);
+        // delay_ms(1);
+        let port = port_num_to_i2c(port_num);
 
-    async fn write_async_interrupt_impl(&self, addr: u16, data: &[u8]) -> Result<(), hal::I2cError> {
-        assert!(data.len() <= 255);
-        self.port.cr2().modify(|v| {
-            v.set_sadd(addr << 1);
-            v.set_nbytes(data.len() as u8);
-            v.set_dir(stm32_metapac::i2c::vals::Dir::WRITE);
-            v.set_start(true);
+        port.cr1().modify(|v| v.set_pe(false));
+        // dealyt for 6 tick
+        clock::delay_tick(6);
+        port.timingr().modify(|v| {
+            v.set_presc(timing.presc);
+            v.set_scll(timing.scll);
+            v.set_sclh(timing.sclh);
+            v.set_sdadel(timing.sdadel);
+            v.set_scldel(timing.scldel);
         });
 
-        for &byte in data {
-            core::future::poll_fn(|cx| {
-                WAKERS[self.port_num as usize].register(cx.waker());
-                self.port.cr1().modify(|v| {
-                    v.set_txie(true);
-                    v.set_nackie(true);
-                });
-                let isr = self.port.isr().read();
-                if isr.nackf() {
-                    Poll::Ready(Err(hal::I2cError::Nack))
-                } else if isr.txis() {
-                    Poll::Ready(Ok(()))
-                } else {
-                    Poll::Pending
+        // set autoend to true
+        port.cr2()
+            .modify(|v| v.set_autoend(stm32_metapac::i2c::vals::Autoend::AUTOMATIC));
+        // disbale own address
+        port.oar1().modify(|v| v.set_oa1en(false));
+        port.oar2().modify(|v| v.set_oa2en(false));
+        // disable general call
+        port.cr1().modify(|v| v.set_gcen(false));
+        // no stretch and noise filters (only writable while PE = 0)
+        port.cr1().modify(|v| {
+            v.set_nostretch(true);
+            v.set_anfoff(!config.analog_filter);
+            v.set_dnf(stm32_metapac::i2c::vals::Dnf::from_bits(config.digital_filter));
+            v.set_pe(true);
+        });
+        clock::delay_tick(10);
+        unsafe {
+            match port_num {
+                1 => {
+                    NVIC::unmask(interrupt::I2C1_EV);
+                    NVIC::unmask(interrupt::I2C1_ER);
                 }
-            })
-            .await?;
-            self.port.txdr().write(|v| v.set_txdata(byte));
-        }
-
-        core::future::poll_fn(|cx| {
-            WAKERS[self.port_num as usize].register(cx.waker());
-            self.port.cr1().modify(|v| {
-                v.set_tcie(true);
-                v.set_stopie(true);
-                v.set_nackie(true);
-            });
-            let isr = self.port.isr().read();
-            if isr.nackf() {
-                Poll::Ready(Err(hal::I2cError::Nack))
-            } else if isr.stopf() || isr.tc() {
-                Poll::Ready(Ok(()))
-            } else {
-                Poll::Pending
+                2 => {
+                    NVIC::unmask(interrupt::I2C2_EV);
+                    NVIC::unmask(interrupt::I2C2_ER);
+                }
+                3 => {
+                    NVIC::unmask(interrupt::I2C3_EV);
+                    NVIC::unmask(interrupt::I2C3_ER);
+                }
+                4 => {
+                    NVIC::unmask(interrupt::I2C4_EV);
+                    NVIC::unmask(interrupt::I2C4_ER);
+                }
+                _ => {}
             }
+        }
+        Ok(I2c {
+            port,
+            port_num,
+            freq,
+            kernel_freq,
+            scl_pin: config.scl_pin,
+            sda_pin: config.sda_pin,
+            dma: None,
+            own_addresses: None,
         })
-        .await?;
-
-        self.port.icr().write(|v| {
-            v.set_stopcf(true);
-            v.set_nackcf(true);
-        });
+    }
 
-        Ok(())
+    pub async fn write_async_interrupt(&self, addr: u16, data: &[u8]) -> Result<(), hal::I2cError> {
+        i2c_transfer::transaction_async(self, addr, &mut [hal::I2cOperation::Write(data)]).await
     }
 
     pub async fn read_async_interrupt(&self, addr: u16, data: &mut [u8]) -> Result<(), hal::I2cError> {
-        let res = self.read_async_interrupt_impl(addr, data).await;
-        if res.is_err() {
-            self.port.cr1().modify(|v| {
-                v.set_txie(false);
-                v.set_rxie(false);
-                v.set_tcie(false);
-                v.set_stopie(false);
-                v.set_nackie(false);
-            });
-            self.port.icr().write(|v| {
-                v.set_stopcf(true);
-                v.set_nackcf(true);
-            });
-        }
-        res
+        i2c_transfer::transaction_async(self, addr, &mut [hal::I2cOperation::Read(data)]).await
     }
 
-    async fn read_async_interrupt_impl(&self, addr: u16, data: &mut [u8]) -> Result<(), hal::I2cError> {
-        assert!(data.len() <= 255);
-        self.port.cr2().modify(|v| {
-            v.set_sadd(addr << 1);
-            v.set_nbytes(data.len() as u8);
-            v.set_dir(stm32_metapac::i2c::vals::Dir::READ);
-            v.set_start(true);
-        });
+    /// Use dma for `write_async`/`read_async` of at least `DMA_THRESHOLD` bytes, other transfers stay
+    /// interrupt driven.
+    /// for example: `i2c.set_dma(DmaChannel::take("I2C1_TX", Dw::BYTE)?, DmaChannel::take("I2C1_RX", Dw::BYTE)?)`
+    pub fn set_dma(&mut self, tx: DmaChannel, rx: DmaChannel) {
+        self.dma = Some((tx, rx));
+    }
 
-        for i in 0..data.len() {
-            core::future::poll_fn(|cx| {
-                WAKERS[self.port_num as usize].register(cx.waker());
-                self.port.cr1().modify(|v| {
-                    v.set_rxie(true);
-                    v.set_nackie(true);
-                });
-                let isr = self.port.isr().read();
-                if isr.nackf() {
-                    Poll::Ready(Err(hal::I2cError::Nack))
-                } else if isr.rxne() {
-                    Poll::Ready(Ok(()))
-                } else {
-                    Poll::Pending
-                }
-            })
-            .await?;
-            data[i] = self.port.rxdr().read().rxdata();
+    fn can_use_dma(&self, len: usize) -> bool {
+        self.dma.is_some() && len >= DMA_THRESHOLD
+    }
+
+    pub async fn write_async_dma(&self, addr: u16, data: &[u8]) -> Result<(), hal::I2cError> {
+        let Some((tx, _)) = &self.dma else {
+            return Err(hal::I2cError::InitError);
+        };
+        let txdr = self.port.txdr().as_ptr() as u32;
+        // the transfer is a local, dropped (and aborted) before the borrow of `data` ends
+        let mut transfer = unsafe { Transfer::write(tx, data, txdr) }?;
+        self.port.cr1().modify(|v| v.set_txdmaen(true));
+        let mut res = i2c_transfer::dma_transfer_async(self, addr, false, data.len()).await;
+        if res.is_ok() {
+            res = transfer.wait().await.map_err(hal::I2cError::from);
         }
+        self.port.cr1().modify(|v| v.set_txdmaen(false));
+        res
+    }
 
-        core::future::poll_fn(|cx| {
-            WAKERS[self.port_num as usize].register(cx.waker());
-            self.port.cr1().modify(|v| {
-                v.set_tcie(true);
-                v.set_stopie(true);
-                v.set_nackie(true);
-            });
-            let isr = self.port.isr().read();
-            if isr.nackf() {
-                Poll::Ready(Err(hal::I2cError::Nack))
-            } else if isr.stopf() || isr.tc() {
-                Poll::Ready(Ok(()))
-            } else {
-                Poll::Pending
-            }
-        })
-        .await?;
+    pub async fn read_async_dma(&self, addr: u16, data: &mut [u8]) -> Result<(), hal::I2cError> {
+        let Some((_, rx)) = &self.dma else {
+            return Err(hal::I2cError::InitError);
+        };
+        let rxdr = self.port.rxdr().as_ptr() as u32;
+        let len = data.len();
+        // the transfer is a local, dropped (and aborted) before the borrow of `data` ends
+        let mut transfer = unsafe { Transfer::read(rx, rxdr, data) }?;
+        self.port.cr1().modify(|v| v.set_rxdmaen(true));
+        let mut res = i2c_transfer::dma_transfer_async(self, addr, true, len).await;
+        if res.is_ok() {
+            // STOP can be seen before the dma stored the last byte
+            res = transfer.wait().await.map_err(hal::I2cError::from);
+        }
+        self.port.cr1().modify(|v| v.set_rxdmaen(false));
+        res
+    }
 
-        self.port.icr().write(|v| {
-            v.set_stopcf(true);
-            v.set_nackcf(true);
-        });
+    pub async fn transaction_async_interrupt(
+        &self,
+        addr: u16,
+        operations: &mut [hal::I2cOperation<'_>],
+    ) -> Result<(), hal::I2cError> {
+        i2c_transfer::transaction_async(self, addr, operations).await
+    }
 
-        Ok(())
+    fn slave_event(&self, matched: i2c_transfer::AddressMatch) -> hal::I2cSlaveEvent {
+        let addr = match &self.own_addresses {
+            Some(own) => own.matched(matched.addcode),
+            None => matched.addcode as u16,
+        };
+        if matched.read {
+            hal::I2cSlaveEvent::Read(addr)
+        } else {
+            hal::I2cSlaveEvent::Write(addr)
+        }
     }
 
     pub async fn wait_address_async_interrupt(&self) -> Result<hal::I2cSlaveEvent, hal::I2cError> {
-        core::future::poll_fn(|cx| {
-            WAKERS[self.port_num as usize].register(cx.waker());
-            self.port.cr1().modify(|v| {
-                v.set_addrie(true);
-            });
-            let isr = self.port.isr().read();
-            if isr.addr() {
-                let dir = isr.dir();
-                // Clear ADDR flag by writing to ADDRCF in ICR
-                self.port.icr().write(|v| v.set_addrcf(true));
-                if dir == stm32_metapac::i2c::vals::Dir::READ {
-                    Poll::Ready(Ok(hal::I2cSlaveEvent::Read))
-                } else {
-                    Poll::Ready(Ok(hal::I2cSlaveEvent::Write))
-                }
-            } else {
-                Poll::Pending
-            }
-        })
-        .await
+        let matched = i2c_transfer::slave_wait_address_async(self).await?;
+        Ok(self.slave_event(matched))
     }
 
     pub async fn read_async_slave(&self, data: &mut [u8]) -> Result<(), hal::I2cError> {
-        let res = self.read_async_slave_impl(data).await;
+        let res = i2c_transfer::slave_read_async(self, data).await;
         if res.is_err() {
-            self.port.cr1().modify(|v| {
-                v.set_txie(false);
-                v.set_rxie(false);
-                v.set_tcie(false);
-                v.set_stopie(false);
-                v.set_nackie(false);
-            });
-            self.port.icr().write(|v| {
-                v.set_stopcf(true);
-                v.set_nackcf(true);
-            });
+            i2c_transfer::I2cRegisters::abort(self);
         }
         res
     }
 
-    async fn read_async_slave_impl(&self, data: &mut [u8]) -> Result<(), hal::I2cError> {
-        for i in 0..data.len() {
-            core::future::poll_fn(|cx| {
-                WAKERS[self.port_num as usize].register(cx.waker());
-                self.port.cr1().modify(|v| {
-                    v.set_rxie(true);
-                    v.set_stopie(true);
-                });
-                let isr = self.port.isr().read();
-                if isr.rxne() {
-                    Poll::Ready(Ok(()))
-                } else if isr.stopf() {
-                    // Host sent STOP before we read all data
-                    Poll::Ready(Err(hal::I2cError::BusError))
-                } else {
-                    Poll::Pending
-                }
-            })
-            .await?;
-            data[i] = self.port.rxdr().read().rxdata();
-        }
-        // Clear STOPF if it was set
-        self.port.icr().write(|v| v.set_stopcf(true));
-        Ok(())
-    }
-
     pub async fn write_async_slave(&self, data: &[u8]) -> Result<(), hal::I2cError> {
-        let res = self.write_async_slave_impl(data).await;
+        let res = i2c_transfer::slave_write_async(self, data).await;
         if res.is_err() {
-            self.port.cr1().modify(|v| {
-                v.set_txie(false);
-                v.set_rxie(false);
-                v.set_tcie(false);
-                v.set_stopie(false);
-                v.set_nackie(false);
-            });
-            self.port.icr().write(|v| {
-                v.set_stopcf(true);
-                v.set_nackcf(true);
-            });
+            i2c_transfer::I2cRegisters::abort(self);
         }
         res
     }
 
-    async fn write_async_slave_impl(&self, data: &[u8]) -> Result<(), hal::I2cError> {
-        for &byte in data {
-            core::future::poll_fn(|cx| {
-                WAKERS[self.port_num as usize].register(cx.waker());
-                self.port.cr1().modify(|v| {
-                    v.set_txie(true);
-                    v.set_stopie(true);
-                });
-                let isr = self.port.isr().read();
-                if isr.txis() {
-                    Poll::Ready(Ok(()))
-                } else if isr.stopf() {
-                    Poll::Ready(Err(hal::I2cError::BusError))
-                } else {
-                    Poll::Pending
-                }
-            })
-            .await?;
-            self.port.txdr().write(|v| v.set_txdata(byte));
+    pub async fn receive_async_slave(&self, data: &mut [u8]) -> Result<usize, hal::I2cError> {
+        let res = i2c_transfer::slave_receive_async(self, data).await;
+        if res.is_err() {
+            i2c_transfer::I2cRegisters::abort(self);
         }
-        // Wait for TC or STOP
-        core::future::poll_fn(|cx| {
-            WAKERS[self.port_num as usize].register(cx.waker());
-            self.port.cr1().modify(|v| {
-                v.set_stopie(true);
-            });
-            let isr = self.port.isr().read();
-            if isr.stopf() {
-                Poll::Ready(Ok(()))
-            } else {
-                Poll::Pending
-            }
-        })
-        .await?;
-        self.port.icr().write(|v| v.set_stopcf(true));
-        Ok(())
+        res.map(|(len, _)| len)
     }
-}
 
-pub struct I2cMessage<'a> {
-    pub addr: u16,
-    pub data: &'a mut [u8],
+    pub async fn transmit_async_slave(&self, data: &[u8]) -> Result<usize, hal::I2cError> {
+        let res = i2c_transfer::slave_transmit_async(self, data).await;
+        if res.is_err() {
+            i2c_transfer::I2cRegisters::abort(self);
+        }
+        res.map(|(len, _)| len)
+    }
 }
 
-/////////////////////////// HAL implementation /////////////////////////////
-use crate::gpio::{
-    GpioPort, I2C1_SCL_PB6, I2C1_SCL_PB8, I2C1_SCL_PINS, I2C1_SDA_PB7, I2C1_SDA_PINS, I2C2_SCL_PF1, I2C2_SCL_PINS, I2C2_SDA_PINS,
-    I2C3_SCL_PINS, I2C3_SDA_PB4, I2C3_SDA_PINS, PB4, I2C2_SCL_PB13, I2C2_SDA_PB14,
-};
-use crate::hal;
-impl hal::I2c<GpioPort> for I2c {
-    fn new(freq: hal::I2cFrequency, sda_pin: GpioPort, scl_pin: GpioPort) -> Result<Self, hal::I2cError> {
+/////////////////////////// slave /////////////////////////////
+impl I2c {
+    /// Initialize the i2c as slave answering to `config.addresses`; `InitError` if they are out of
+    /// range.
+    pub fn new_slave_with_config(config: I2cSlaveConfig) -> Result<Self, hal::I2cError> {
+        let I2cSlaveConfig { scl_pin, sda_pin, addresses } = config;
+        if !addresses.is_valid() {
+            return Err(hal::I2cError::InitError);
+        }
         let port_num = pin_to_port(&scl_pin, &sda_pin);
-        let freq_val = match freq {
-            hal::I2cFrequency::Freq100khz => 100_000,
-            hal::I2cFrequency::Freq400khz => 400_000,
-            hal::I2cFrequency::Freq1Mhz => 1_000_000,
-        };
         if TAKEN[port_num as usize].swap(true, Ordering::SeqCst) {
             return Err(hal::I2cError::InitError);
         }
         scl_pin.setup();
         sda_pin.setup();
-        clock::set_i2c_clock(port_num);
-        // delay_ms(1);
+        let kernel_freq = clock::set_i2c_clock(port_num, clock::I2cClockSource::Hsi);
         let port = port_num_to_i2c(port_num);
 
         port.cr1().modify(|v| v.set_pe(false));
-        // dealyt for 6 tick
         clock::delay_tick(6);
-        // set timing
-        let kernel_freq = crate::clock::get_hclk();
-
-        // TODO: HSI 16 is used as system clock for easy setup.
-        // The values are from the reference menu
-        // set as 100Khz
-        let (presc, scll, sclh, sdadel, scldel) = match freq_val {
-            10_000 => (3, 0xC7, 0xC3, 0x2, 0x4), // 10khz
-            100_000 => (3, 0x13, 0xF, 0x2, 0x4),  // 100khz
-            400_000 => (1, 9, 3, 2, 3),          // 400khz
-            1_000_000 => (0, 9, 4, 1, 2),        // 1Mhz
-            _ => panic!("invalid frequency"),
-        };
-        port.timingr().modify(|v| {
-            v.set_presc(presc);
-            v.set_scll(scll);
-            v.set_sclh(sclh);
-            v.set_sdadel(sdadel);
-            v.set_scldel(scldel);
-        });
 
-        // set autoend to true
-        port.cr2()
-            .modify(|v| v.set_autoend(stm32_metapac::i2c::vals::Autoend::AUTOMATIC));
-        // disbale own address
+        // own addresses can only be changed while disabled
+        let (oa1, ten_bit) = addresses.oar1();
         port.oar1().modify(|v| v.set_oa1en(false));
+        port.oar1().modify(|v| {
+            v.set_oa1(oa1);
+            v.set_oa1mode(if ten_bit { Oamode::BIT10 } else { Oamode::BIT7 });
+            v.set_oa1en(true);
+        });
         port.oar2().modify(|v| v.set_oa2en(false));
-        // disable general call
-        port.cr1().modify(|v| v.set_gcen(false));
-        // no stretch and enable analog filter
+        if let Some((oa2, mask)) = addresses.oa2 {
+            port.oar2().modify(|v| {
+                v.set_oa2(oa2);
+                v.set_oa2msk(Oamsk::from_bits(mask));
+                v.set_oa2en(true);
+            });
+        }
+
+        // set autoend to false for slave (usually)
+        port.cr2()
+            .modify(|v| v.set_autoend(stm32_metapac::i2c::vals::Autoend::SOFTWARE));
+
         port.cr1().modify(|v| {
-            v.set_nostretch(true);
+            v.set_nostretch(false); // Enable stretching for slave
+            v.set_sbc(true); // count the bytes in NBYTES, reloaded at TCR so the length is not limited
+            v.set_gcen(addresses.general_call);
             v.set_anfoff(false);
             v.set_pe(true);
         });
         clock::delay_tick(10);
+
         unsafe {
             match port_num {
                 1 => {
@@ -438,286 +394,332 @@ impl hal::I2c<GpioPort> for I2c {
                 _ => {}
             }
         }
-        Ok(I2c { port, port_num, freq })
+
+        Ok(I2c {
+            port,
+            port_num,
+            freq: hal::I2cFrequency::Freq100khz,
+            kernel_freq,
+            scl_pin,
+            sda_pin,
+            dma: None,
+            own_addresses: Some(addresses),
+        })
     }
+}
 
-    fn write(&self, addr: u16, data: &[u8]) -> Result<(), hal::I2cError> {
-        assert!(data.len() <= 255);
-        // TODO: check hardware status. Whether it allows to send data.
-        //       id not allowed, return error.
-        // set slave address
-        self.port.cr2().modify(|v| {
-            v.set_sadd(addr << 1);
-            v.set_nbytes(data.len() as u8);
-            v.set_dir(stm32_metapac::i2c::vals::Dir::WRITE);
-            v.set_start(true);
-            // v.set_reload(stm32_metapac::i2c::vals::Reload::COMPLETED);
-            // v.set_autoend(stm32_metapac::i2c::vals::Autoend::SOFTWARE);
-        });
-        for i in data {
-            // wait for the transfer complete
-            loop {
-                let isr = self.port.isr().read();
-                if isr.nackf() {
-                    self.port.icr().write(|v| {
-                        v.set_stopcf(true);
-                        v.set_nackcf(true);
-                    });
-                    return Err(hal::I2cError::Nack);
-                }
-                if isr.txis() {
-                    break;
-                }
-            }
-            // send data
-            self.port.txdr().write(|v| v.set_txdata(*i));
+/////////////////////////// SMBus /////////////////////////////
+impl I2c {
+    /// Enable the SMBus features of the peripheral: host or device mode, SMBALERT and the
+    /// `TIMEOUTA`/`TIMEOUTB` counters. The protocols run through `smbus::SmbusHost` and
+    /// `smbus::SmbusDevice` on top of this port. `alert_pin` (one of `gpio::I2Cx_SMBA_PINS`) is
+    /// needed if `config.alert` is set. Fails with `InitError` if a timeout does not fit the
+    /// 12 bit counters at the kernel clock.
+    pub fn set_smbus(&self, config: &smbus::SmbusConfig, alert_pin: Option<GpioPort>) -> Result<(), hal::I2cError> {
+        let timeout = |us: Option<u32>| match us {
+            Some(us) => smbus::timeout_value(self.kernel_freq, us).map(Some).ok_or(hal::I2cError::InitError),
+            None => Ok(None),
+        };
+        let timeout_a = timeout(config.timeout_us)?;
+        let timeout_b = timeout(config.ext_timeout_us)?;
+        if config.alert {
+            alert_pin.ok_or(hal::I2cError::InitError)?.setup();
         }
-        // wait for the start bit to be cleared
-        loop {
-            let isr = self.port.isr().read();
-            if isr.nackf() {
-                self.port.icr().write(|v| {
-                    v.set_stopcf(true);
-                    v.set_nackcf(true);
-                });
-                return Err(hal::I2cError::Nack);
+
+        // the timeouts can only be changed while they are disabled
+        self.port.timeoutr().write(|v| {
+            v.set_timouten(false);
+            v.set_texten(false);
+        });
+        self.port.timeoutr().write(|v| {
+            // TIDLE = 0: TIMEOUTA checks SCL low
+            if let Some(a) = timeout_a {
+                v.set_timeouta(a);
+                v.set_timouten(true);
             }
-            if !self.port.cr2().read().start() {
-                break;
+            if let Some(b) = timeout_b {
+                v.set_timeoutb(b);
+                v.set_texten(true);
             }
-        }
-        // wait for the transfer complete
-        // while !self.port.isr().read().tc() {} //  don't care about tc flag when no reload
-        // and autoend is set to automatic
-        // clear the transfer complete flag
-        self.port.icr().write(|v| v.set_stopcf(true));
+        });
+        self.port.cr1().modify(|v| {
+            v.set_smbhen(!config.device);
+            v.set_smbden(config.device);
+            // a device only drives SMBALERT when it has something to report, see `set_smbus_alert`
+            v.set_alerten(config.alert && !config.device);
+            // the PEC is only sent or checked in chunks with PECBYTE, see `i2c_transfer::Chunk`
+            v.set_pecen(true);
+        });
         Ok(())
     }
-    // async fn read_async(&self, addr: u16, data: &mut [u8]) -> Result<(), hal::I2cError> {
-    //     todo!( "not implement")
-    // }
-    fn read_async(&self, addr: u16, data: &mut [u8]) -> impl core::future::Future<Output = Result<(), hal::I2cError>> + Send {
-        self.read_async_interrupt(addr, data)
+
+    /// Device mode: pull SMBALERT low until the host answers with an alert response (the
+    /// peripheral then acknowledges the alert response address).
+    pub fn set_smbus_alert(&self, active: bool) {
+        self.port.cr1().modify(|v| v.set_alerten(active));
     }
 
-    fn read(&self, addr: u16, data: &mut [u8]) -> Result<(), hal::I2cError> {
-        // fn receive(&mut self, option: ) -> Result<Self::Response, Self::Error> {
-        assert!(data.len() <= 255);
-        // todo: check hardware status. Whether it allows to send data.
-        // todo: id not allowed, return error.
-        self.port.cr2().modify(|v| {
-            v.set_sadd(addr << 1);
-            v.set_nbytes(data.len() as u8);
-            v.set_dir(stm32_metapac::i2c::vals::Dir::READ);
-            v.set_start(true);
-        });
-        for i in data {
-            // wait for the transfer complete
-            loop {
-                let isr = self.port.isr().read();
-                if isr.nackf() {
-                    self.port.icr().write(|v| {
-                        v.set_stopcf(true);
-                        v.set_nackcf(true);
-                    });
-                    return Err(hal::I2cError::Nack);
-                }
-                if isr.rxne() {
-                    break;
-                }
+    /// Host mode: wait for a device to pull SMBALERT low. Ask which one with
+    /// `smbus::SmbusHost::alert_response`.
+    pub async fn wait_smbus_alert_async(&self) {
+        core::future::poll_fn(|cx| {
+            WAKERS[self.port_num as usize].register(cx.waker());
+            if self.port.isr().read().alert() {
+                self.port.icr().write(|v| v.set_alertcf(true));
+                Poll::Ready(())
+            } else {
+                // ALERT is signalled on the error interrupt
+                self.port.cr1().modify(|v| v.set_errie(true));
+                Poll::Pending
             }
-            // read data
-            *i = self.port.rxdr().read().rxdata();
+        })
+        .await
+    }
+}
+
+/// Register access for the transfer sequencing in `i2c_transfer`
+impl i2c_transfer::I2cRegisters for I2c {
+    fn status(&self) -> i2c_transfer::Status {
+        let isr = self.port.isr().read();
+        i2c_transfer::Status {
+            txis: isr.txis(),
+            txe: isr.txe(),
+            rxne: isr.rxne(),
+            tc: isr.tc(),
+            tcr: isr.tcr(),
+            stopf: isr.stopf(),
+            nackf: isr.nackf(),
+            berr: isr.berr(),
+            arlo: isr.arlo(),
+            ovr: isr.ovr(),
+            timeout: isr.timeout(),
+            pecerr: isr.pecerr(),
+            addr: isr.addr(),
+            dir_read: isr.dir() == Dir::READ,
+            addcode: isr.addcode(),
         }
-        loop {
-            let isr = self.port.isr().read();
-            if isr.nackf() {
-                self.port.icr().write(|v| {
-                    v.set_stopcf(true);
-                    v.set_nackcf(true);
-                });
-                return Err(hal::I2cError::Nack);
-            }
-            if !self.port.cr2().read().start() {
-                break;
+    }
+
+    fn program(&self, addr: u16, read: bool, chunk: i2c_transfer::Chunk) {
+        self.port.cr2().modify(|v| {
+            v.set_nbytes(chunk.nbytes);
+            v.set_reload(if chunk.reload { Reload::NOT_COMPLETED } else { Reload::COMPLETED });
+            v.set_autoend(if chunk.autoend { Autoend::AUTOMATIC } else { Autoend::SOFTWARE });
+            v.set_pecbyte(chunk.pec);
+            if chunk.start {
+                v.set_sadd(addr << 1);
+                v.set_dir(if read { Dir::READ } else { Dir::WRITE });
+                v.set_start(true);
             }
-        }
+        });
+    }
+
+    fn write_data(&self, byte: u8) {
+        self.port.txdr().write(|v| v.set_txdata(byte));
+    }
+
+    fn read_data(&self) -> u8 {
+        self.port.rxdr().read().rxdata()
+    }
+
+    fn clear_stop(&self) {
         self.port.icr().write(|v| v.set_stopcf(true));
-        Ok(())
     }
-    // async fn write_aysnc(&self, addr: u16, data: &[u8]) -> Result<(), hal::I2cError> {
-    //     todo!("not implement")
 
-    // }
-    fn write_async(&self, addr: u16, data: &[u8]) -> impl core::future::Future<Output = Result<(), hal::I2cError>> + Send {
-        self.write_async_interrupt(addr, data)
+    fn flush_tx(&self) {
+        self.port.isr().modify(|v| v.set_txe(true));
     }
 
-    fn write_read(&self, addr: u16, write_data: &[u8], read_data: &mut [u8]) -> Result<(), hal::I2cError> {
-        assert!(write_data.len() <= 255);
-        assert!(read_data.len() <= 255);
+    fn clear_addr(&self) {
+        self.port.icr().write(|v| v.set_addrcf(true));
+    }
 
-        // 1. Write part (no STOP)
-        self.port.cr2().modify(|v| {
-            v.set_sadd(addr << 1);
-            v.set_nbytes(write_data.len() as u8);
-            v.set_dir(stm32_metapac::i2c::vals::Dir::WRITE);
-            v.set_autoend(stm32_metapac::i2c::vals::Autoend::SOFTWARE);
-            v.set_start(true);
+    fn listen(&self, cx: &mut core::task::Context<'_>, events: i2c_transfer::Events) {
+        WAKERS[self.port_num as usize].register(cx.waker());
+        self.port.cr1().modify(|v| {
+            v.set_txie(events.tx);
+            v.set_rxie(events.rx);
+            v.set_tcie(events.transfer_complete);
+            v.set_stopie(events.stop);
+            v.set_nackie(events.nack);
+            v.set_addrie(events.address);
+            v.set_errie(true);
         });
+    }
 
-        for &byte in write_data {
-            loop {
-                let isr = self.port.isr().read();
-                if isr.nackf() {
-                    self.port.icr().write(|v| {
-                        v.set_stopcf(true);
-                        v.set_nackcf(true);
-                    });
-                    return Err(hal::I2cError::Nack);
-                }
-                if isr.txis() {
-                    break;
-                }
-            }
-            self.port.txdr().write(|v| v.set_txdata(byte));
+    /// The peripheral sends STOP by itself when the target NACKs and releases the bus on bus error
+    /// or arbitration loss.
+    fn abort(&self) {
+        self.port.cr1().modify(|v| {
+            v.set_txie(false);
+            v.set_rxie(false);
+            v.set_tcie(false);
+            v.set_stopie(false);
+            v.set_nackie(false);
+            v.set_addrie(false);
+            v.set_errie(false);
+        });
+        self.port.icr().write(|v| {
+            v.set_stopcf(true);
+            v.set_nackcf(true);
+            v.set_berrcf(true);
+            v.set_arlocf(true);
+            v.set_ovrcf(true);
+            v.set_timoutcf(true);
+            v.set_peccf(true);
+        });
+    }
+}
+
+/// The i2c pins as open-drain GPIOs for `i2c_transfer::recover_bus`.
+struct RecoveryLines {
+    scl: GpioPort,
+    sda: GpioPort,
+}
+
+impl RecoveryLines {
+    fn new(scl_pin: &GpioPort, sda_pin: &GpioPort) -> Self {
+        let gpio = |pin: &GpioPort| GpioPort { mode: Moder::OUTPUT, ot: Ot::OPEN_DRAIN, ..pin.clone() };
+        let lines = RecoveryLines { scl: gpio(scl_pin), sda: gpio(sda_pin) };
+        // released before the pins leave the alternate function, no glitch on the bus
+        lines.scl.set_high();
+        lines.sda.set_high();
+        lines.scl.setup();
+        lines.sda.setup();
+        lines
+    }
+}
+
+impl i2c_transfer::BusLines for RecoveryLines {
+    fn set_scl(&self, high: bool) {
+        if high {
+            self.scl.set_high()
+        } else {
+            self.scl.set_low()
         }
+    }
 
-        // Wait for Transfer Complete (TC)
-        loop {
-            let isr = self.port.isr().read();
-            if isr.nackf() {
-                self.port.icr().write(|v| {
-                    v.set_stopcf(true);
-                    v.set_nackcf(true);
-                });
-                return Err(hal::I2cError::Nack);
-            }
-            if isr.tc() {
-                break;
-            }
+    fn set_sda(&self, high: bool) {
+        if high {
+            self.sda.set_high()
+        } else {
+            self.sda.set_low()
         }
+    }
 
-        // 2. Read part (with STOP)
-        self.port.cr2().modify(|v| {
-            v.set_sadd(addr << 1);
-            v.set_nbytes(read_data.len() as u8);
-            v.set_dir(stm32_metapac::i2c::vals::Dir::READ);
-            v.set_autoend(stm32_metapac::i2c::vals::Autoend::AUTOMATIC);
-            v.set_start(true);
-        });
+    fn scl(&self) -> bool {
+        self.scl.is_high()
+    }
 
-        for byte in read_data {
-            loop {
-                let isr = self.port.isr().read();
-                if isr.nackf() {
-                    self.port.icr().write(|v| {
-                        v.set_stopcf(true);
-                        v.set_nackcf(true);
-                    });
-                    return Err(hal::I2cError::Nack);
-                }
-                if isr.rxne() {
-                    break;
-                }
+    fn sda(&self) -> bool {
+        self.sda.is_high()
+    }
+
+    // 100Khz
+    fn delay(&self) {
+        clock::delay_us(5);
+    }
+}
+
+/////////////////////////// HAL implementation /////////////////////////////
+use crate::gpio::{
+    GpioPort, I2C1_SCL_PB6, I2C1_SCL_PB8, I2C1_SCL_PINS, I2C1_SDA_PB7, I2C1_SDA_PINS, I2C2_SCL_PF1, I2C2_SCL_PINS, I2C2_SDA_PINS,
+    I2C3_SCL_PINS, I2C3_SDA_PB4, I2C3_SDA_PINS, PB4, I2C2_SCL_PB13, I2C2_SDA_PB14,
+};
+use crate::gpio::{Moder, Ot};
+use crate::hal;
+impl hal::I2c<GpioPort> for I2c {
+    fn new(freq: hal::I2cFrequency, sda_pin: GpioPort, scl_pin: GpioPort) -> Result<Self, hal::I2cError> {
+        let port_num = pin_to_port(&scl_pin, &sda_pin);
+        let freq_val = match freq {
+            hal::I2cFrequency::Freq100khz => 100_000,
+            hal::I2cFrequency::Freq400khz => 400_000,
+            hal::I2cFrequency::Freq1Mhz => 1_000_000,
+        };
+        I2c::new_with_config(I2cConfig::new(port_num, freq_val, scl_pin, sda_pin))
+    }
+
+    fn write(&self, addr: u16, data: &[u8]) -> Result<(), hal::I2cError> {
+        i2c_transfer::transaction(self, addr, &mut [hal::I2cOperation::Write(data)])
+    }
+
+    fn write_async(&self, addr: u16, data: &[u8]) -> impl core::future::Future<Output = Result<(), hal::I2cError>> + Send {
+        async move {
+            if self.can_use_dma(data.len()) {
+                self.write_async_dma(addr, data).await
+            } else {
+                self.write_async_interrupt(addr, data).await
             }
-            *byte = self.port.rxdr().read().rxdata();
         }
+    }
 
-        // Wait for STOP
-        loop {
-            let isr = self.port.isr().read();
-            if isr.nackf() {
-                self.port.icr().write(|v| {
-                    v.set_stopcf(true);
-                    v.set_nackcf(true);
-                });
-                return Err(hal::I2cError::Nack);
-            }
-            if isr.stopf() {
-                break;
+    fn read(&self, addr: u16, data: &mut [u8]) -> Result<(), hal::I2cError> {
+        i2c_transfer::transaction(self, addr, &mut [hal::I2cOperation::Read(data)])
+    }
+
+    fn read_async(&self, addr: u16, data: &mut [u8]) -> impl core::future::Future<Output = Result<(), hal::I2cError>> + Send {
+        async move {
+            if self.can_use_dma(data.len()) {
+                self.read_async_dma(addr, data).await
+            } else {
+                self.read_async_interrupt(addr, data).await
             }
         }
-        self.port.icr().write(|v| v.set_stopcf(true));
+    }
 
-        Ok(())
+    fn write_read(&self, addr: u16, write_data: &[u8], read_data: &mut [u8]) -> Result<(), hal::I2cError> {
+        i2c_transfer::transaction(
+            self,
+            addr,
+            &mut [hal::I2cOperation::Write(write_data), hal::I2cOperation::Read(read_data)],
+        )
+    }
+
+    fn transaction(&self, addr: u16, operations: &mut [hal::I2cOperation<'_>]) -> Result<(), hal::I2cError> {
+        i2c_transfer::transaction(self, addr, operations)
+    }
+
+    fn transaction_async(
+        &self,
+        addr: u16,
+        operations: &mut [hal::I2cOperation<'_>],
+    ) -> impl core::future::Future<Output = Result<(), hal::I2cError>> + Send {
+        self.transaction_async_interrupt(addr, operations)
     }
 
     fn capacity(&self) -> hal::I2cFrequency {
         self.freq
     }
 
+    fn recover_bus(&self) -> Result<(), hal::I2cError> {
+        // PE = 0 resets the state machine and the flags, the configuration registers are kept
+        self.port.cr1().modify(|v| v.set_pe(false));
+        let res = i2c_transfer::recover_bus(&RecoveryLines::new(&self.scl_pin, &self.sda_pin));
+        self.scl_pin.setup();
+        self.sda_pin.setup();
+        i2c_transfer::I2cRegisters::abort(self);
+        clock::delay_tick(6);
+        self.port.cr1().modify(|v| v.set_pe(true));
+        res
+    }
+
     // fn write_retry(&self, addr: u16, data: &[u8], retry: u8) -> Result<(), hal::I2cError> {
     //     todo!()
     // }
 }
 
 impl hal::I2cSlave<GpioPort> for I2c {
+    /// A 7-bit address, or a 10-bit one if it does not fit 7 bits.
     fn new_slave(sda_pin: GpioPort, scl_pin: GpioPort, addr: u16) -> Result<Self, hal::I2cError> {
-        let port_num = pin_to_port(&scl_pin, &sda_pin);
-        if TAKEN[port_num as usize].swap(true, Ordering::SeqCst) {
-            return Err(hal::I2cError::InitError);
+        let mut addresses = i2c_transfer::OwnAddresses::new(addr as u8);
+        if addr > 0x7f {
+            addresses.oa1 = i2c_transfer::OwnAddress::TenBit(addr);
         }
-        scl_pin.setup();
-        sda_pin.setup();
-        clock::set_i2c_clock(port_num);
-        let port = port_num_to_i2c(port_num);
-
-        port.cr1().modify(|v| v.set_pe(false));
-        clock::delay_tick(6);
-
-        // Set Own Address 1
-        port.oar1().modify(|v| {
-            v.set_oa1(addr << 1);
-            v.set_oa1en(true);
-        });
-
-        // set autoend to false for slave (usually)
-        port.cr2()
-            .modify(|v| v.set_autoend(stm32_metapac::i2c::vals::Autoend::SOFTWARE));
-
-        port.cr1().modify(|v| {
-            v.set_nostretch(false); // Enable stretching for slave
-            v.set_anfoff(false);
-            v.set_pe(true);
-        });
-        clock::delay_tick(10);
-
-        unsafe {
-            match port_num {
-                1 => {
-                    NVIC::unmask(interrupt::I2C1_EV);
-                    NVIC::unmask(interrupt::I2C1_ER);
-                }
-                2 => {
-                    NVIC::unmask(interrupt::I2C2_EV);
-                    NVIC::unmask(interrupt::I2C2_ER);
-                }
-                3 => {
-                    NVIC::unmask(interrupt::I2C3_EV);
-                    NVIC::unmask(interrupt::I2C3_ER);
-                }
-                4 => {
-                    NVIC::unmask(interrupt::I2C4_EV);
-                    NVIC::unmask(interrupt::I2C4_ER);
-                }
-                _ => {}
-            }
-        }
-
-        Ok(I2c { port, port_num, freq: hal::I2cFrequency::Freq100khz })
+        I2c::new_slave_with_config(I2cSlaveConfig { scl_pin, sda_pin, addresses })
     }
 
     fn slave_wait_address(&self) -> Result<hal::I2cSlaveEvent, hal::I2cError> {
-        while !self.port.isr().read().addr() {}
-        let isr = self.port.isr().read();
-        let dir = isr.dir();
-        self.port.icr().write(|v| v.set_addrcf(true));
-        if dir == stm32_metapac::i2c::vals::Dir::READ {
-            Ok(hal::I2cSlaveEvent::Read)
-        } else {
-            Ok(hal::I2cSlaveEvent::Write)
-        }
+        i2c_transfer::slave_wait_address(self).map(|matched| self.slave_event(matched))
     }
 
     fn slave_wait_address_async(&self) -> impl core::future::Future<Output = Result<hal::I2cSlaveEvent, hal::I2cError>> + Send {
@@ -725,17 +727,7 @@ impl hal::I2cSlave<GpioPort> for I2c {
     }
 
     fn slave_read(&self, data: &mut [u8]) -> Result<(), hal::I2cError> {
-        for i in data {
-            while !self.port.isr().read().rxne() && !self.port.isr().read().stopf() {}
-            if self.port.isr().read().stopf() {
-                self.port.icr().write(|v| v.set_stopcf(true));
-                return Err(hal::I2cError::BusError);
-            }
-            *i = self.port.rxdr().read().rxdata();
-        }
-        while !self.port.isr().read().stopf() {}
-        self.port.icr().write(|v| v.set_stopcf(true));
-        Ok(())
+        i2c_transfer::slave_read(self, data)
     }
 
     fn slave_read_async(&self, data: &mut [u8]) -> impl core::future::Future<Output = Result<(), hal::I2cError>> + Send {
@@ -743,22 +735,56 @@ impl hal::I2cSlave<GpioPort> for I2c {
     }
 
     fn slave_write(&self, data: &[u8]) -> Result<(), hal::I2cError> {
-        for &byte in data {
-            while !self.port.isr().read().txis() && !self.port.isr().read().stopf() {}
-            if self.port.isr().read().stopf() {
-                self.port.icr().write(|v| v.set_stopcf(true));
-                return Err(hal::I2cError::BusError);
-            }
-            self.port.txdr().write(|v| v.set_txdata(byte));
-        }
-        while !self.port.isr().read().stopf() {}
-        self.port.icr().write(|v| v.set_stopcf(true));
-        Ok(())
+        i2c_transfer::slave_write(self, data)
     }
 
     fn slave_write_async(&self, data: &[u8]) -> impl core::future::Future<Output = Result<(), hal::I2cError>> + Send {
         self.write_async_slave(data)
     }
+
+    fn slave_receive(&self, data: &mut [u8]) -> Result<usize, hal::I2cError> {
+        i2c_transfer::slave_receive(self, data).map(|(len, _)| len)
+    }
+
+    fn slave_receive_async(&self, data: &mut [u8]) -> impl core::future::Future<Output = Result<usize, hal::I2cError>> + Send {
+        self.receive_async_slave(data)
+    }
+
+    fn slave_transmit(&self, data: &[u8]) -> Result<usize, hal::I2cError> {
+        i2c_transfer::slave_transmit(self, data).map(|(len, _)| len)
+    }
+
+    fn slave_transmit_async(&self, data: &[u8]) -> impl core::future::Future<Output = Result<usize, hal::I2cError>> + Send {
+        self.transmit_async_slave(data)
+    }
+}
+
+/////////////////////////// embedded-hal implementation /////////////////////////////
+#[cfg(feature = "embedded-hal")]
+impl embedded_hal::i2c::ErrorType for I2c {
+    type Error = hal::I2cError;
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_hal::i2c::I2c for I2c {
+    fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [embedded_hal::i2c::Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        i2c_transfer::transaction(self, address as u16, operations)
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_hal_async::i2c::I2c for I2c {
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: &mut [embedded_hal::i2c::Operation<'_>],
+    ) -> Result<(), Self::Error> {
+        i2c_transfer::transaction_async(self, address as u16, operations).await
+    }
 }
 
 #[interrupt]
@@ -801,6 +827,9 @@ fn I2C4_ER() {
     handle_i2c_interrupt(4);
 }
 
+/// Event and error interrupts. The flags are left set for the waiting transfer, which reports
+/// `BERR`/`ARLO`/`OVR`/`TIMEOUT`/`PECERR` as errors and clears them in `abort`. `ALERT` wakes
+/// `wait_smbus_alert_async`.
 fn handle_i2c_interrupt(port_num: u8) {
     let port = port_num_to_i2c(port_num);
     WAKERS[port_num as usize].wake();
@@ -811,5 +840,6 @@ fn handle_i2c_interrupt(port_num: u8) {
         v.set_stopie(false);
         v.set_nackie(false);
         v.set_addrie(false);
+        v.set_errie(false);
     });
 }
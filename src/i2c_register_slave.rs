@@ -0,0 +1,449 @@
+//! # I2C register slave
+//!
+//! Most I2C peripherals present a register map: the host writes a register pointer, followed by
+//! data for the registers starting there, or writes only the pointer and reads from there after a
+//! repeated START. `I2cRegisterSlave` serves such a register map on any `hal::I2cSlave`, so a U5
+//! board can act as a peripheral of another MCU without re-implementing the pointer handling.
+//!
+//! The pointer auto-increments with every byte read or written and wraps at the end of the bank,
+//! a pointer written beyond the bank wraps the same way.
+//! It is kept between transactions, so a plain read continues where the last access stopped.
+//!
+//! ```rust,ignore
+//! use u5_lib::i2c_register_slave::{I2cRegisterSlave, NoCallbacks, Register};
+//!
+//! let slave = I2c::new_slave(I2C1_SDA_PB7, I2C1_SCL_PB6, 0x42).unwrap();
+//! let mut registers = [Register::ReadOnly(0x5a), Register::ReadWrite(0), Register::ReadWrite(0)];
+//! let mut service = I2cRegisterSlave::new(&slave, &mut registers, NoCallbacks);
+//! service.run().await;
+//! ```
+//!
+//! ## Callback registers
+//! The hardware has to get the data before the host clocks it out, so a read is prepared from the
+//! pointer to the end of the bank (wrapping) before it starts. `RegisterHandler::read` is called
+//! for the callback registers in that window; only the registers the host actually read are
+//! reported to `RegisterHandler::read_done` afterwards (e.g. for clear-on-read status registers).
+
+use crate::hal::{I2cError, I2cSlave, I2cSlaveEvent, Pin};
+use core::marker::PhantomData;
+
+/// Registers addressable with the 8-bit pointer.
+pub const MAX_REGISTERS: usize = 256;
+
+/// One register of the bank.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Register {
+    /// host writes are ignored, the application updates the value
+    ReadOnly(u8),
+    ReadWrite(u8),
+    /// read and written through the `RegisterHandler`
+    Callback,
+}
+
+/// Access to the `Register::Callback` registers. `reg` is the index in the bank.
+pub trait RegisterHandler {
+    /// Value of `reg` for a read being prepared, the host may stop before it.
+    fn read(&mut self, reg: usize) -> u8;
+    /// The host read `reg`.
+    fn read_done(&mut self, _reg: usize) {}
+    fn write(&mut self, reg: usize, value: u8);
+}
+
+/// Handler for banks without callback registers.
+pub struct NoCallbacks;
+
+impl RegisterHandler for NoCallbacks {
+    fn read(&mut self, _reg: usize) -> u8 {
+        0xff
+    }
+
+    fn write(&mut self, _reg: usize, _value: u8) {}
+}
+
+/// Serves `registers` on an I2C slave, see the module documentation.
+pub struct I2cRegisterSlave<'a, T: Pin, S: I2cSlave<T>, H: RegisterHandler> {
+    slave: &'a S,
+    registers: &'a mut [Register],
+    handler: H,
+    pointer: usize,
+    _pin: PhantomData<T>,
+}
+
+impl<'a, T: Pin, S: I2cSlave<T>, H: RegisterHandler> I2cRegisterSlave<'a, T, S, H> {
+    /// At most [`MAX_REGISTERS`] registers are reachable.
+    pub fn new(slave: &'a S, registers: &'a mut [Register], handler: H) -> Self {
+        Self {
+            slave,
+            registers,
+            handler,
+            pointer: 0,
+            _pin: PhantomData,
+        }
+    }
+
+    pub fn registers(&mut self) -> &mut [Register] {
+        self.registers
+    }
+
+    pub fn handler(&mut self) -> &mut H {
+        &mut self.handler
+    }
+
+    pub fn pointer(&self) -> usize {
+        self.pointer
+    }
+
+    fn len(&self) -> usize {
+        self.registers.len().min(MAX_REGISTERS)
+    }
+
+    fn set_pointer(&mut self, pointer: u8) {
+        self.pointer = (pointer as usize).checked_rem(self.len()).unwrap_or(0);
+    }
+
+    fn advance(&mut self) {
+        self.pointer += 1;
+        if self.pointer >= self.len() {
+            self.pointer = 0;
+        }
+    }
+
+    fn write_register(&mut self, value: u8) {
+        match self.registers.get_mut(self.pointer) {
+            Some(Register::ReadWrite(v)) => *v = value,
+            Some(Register::Callback) => self.handler.write(self.pointer, value),
+            _ => {}
+        }
+        self.advance();
+    }
+
+    /// The registers from the pointer on, wrapping once. Returns the length.
+    fn prepare_read(&mut self, buf: &mut [u8; MAX_REGISTERS]) -> usize {
+        let len = self.len();
+        for (i, byte) in buf[..len].iter_mut().enumerate() {
+            let reg = (self.pointer + i) % len;
+            *byte = match self.registers[reg] {
+                Register::ReadOnly(v) | Register::ReadWrite(v) => v,
+                Register::Callback => self.handler.read(reg),
+            };
+        }
+        len
+    }
+
+    fn read_done(&mut self, count: usize) {
+        for _ in 0..count {
+            if let Some(Register::Callback) = self.registers.get(self.pointer) {
+                self.handler.read_done(self.pointer);
+            }
+            self.advance();
+        }
+    }
+
+    /// Serve one transfer addressed to us: a write of the pointer and data, or a read from the
+    /// pointer. A write-then-read with repeated START is two transfers.
+    pub async fn serve_async(&mut self) -> Result<(), I2cError> {
+        match self.slave.slave_wait_address_async().await? {
+            I2cSlaveEvent::Write(_) => {
+                let mut buf = [0u8; MAX_REGISTERS + 1];
+                let len = self.slave.slave_receive_async(&mut buf).await?;
+                if let Some((&pointer, data)) = buf[..len].split_first() {
+                    self.set_pointer(pointer);
+                    for &value in data {
+                        self.write_register(value);
+                    }
+                }
+            }
+            I2cSlaveEvent::Read(_) => {
+                let mut buf = [0u8; MAX_REGISTERS];
+                let len = self.prepare_read(&mut buf);
+                let sent = self.slave.slave_transmit_async(&buf[..len]).await?;
+                self.read_done(sent);
+            }
+        }
+        Ok(())
+    }
+
+    /// Serve transfers forever. Errors only end the transfer they occur in.
+    pub async fn run(&mut self) -> ! {
+        loop {
+            if let Err(_err) = self.serve_async().await {
+                #[cfg(feature = "defmt")]
+                defmt::warn!("i2c register slave: {}", _err);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::i2c_transfer::{self, mock::MockRegs};
+    use futures::executor::block_on;
+    use std::collections::VecDeque;
+    use std::sync::Mutex as StdMutex;
+
+    struct MockPin;
+    impl Pin for MockPin {
+        fn setup(&self) {}
+        fn set_high(&self) {}
+        fn set_low(&self) {}
+        fn toggle(&self) {}
+    }
+
+    /// What the host does in one transfer.
+    enum HostTransfer {
+        Write(Vec<u8>),
+        Read(usize),
+    }
+
+    struct MockSlave {
+        transfers: StdMutex<VecDeque<HostTransfer>>,
+        current: StdMutex<Option<HostTransfer>>,
+        /// bytes the host read, 0xff past the prepared data
+        host_reads: StdMutex<Vec<Vec<u8>>>,
+    }
+
+    impl MockSlave {
+        fn new(transfers: Vec<HostTransfer>) -> Self {
+            Self {
+                transfers: StdMutex::new(transfers.into()),
+                current: StdMutex::new(None),
+                host_reads: StdMutex::new(Vec::new()),
+            }
+        }
+    }
+
+    impl I2cSlave<MockPin> for MockSlave {
+        fn new_slave(_sda: MockPin, _scl: MockPin, _addr: u16) -> Result<Self, I2cError> {
+            Ok(Self::new(Vec::new()))
+        }
+
+        fn slave_wait_address(&self) -> Result<I2cSlaveEvent, I2cError> {
+            let transfer = self.transfers.lock().unwrap().pop_front();
+            let event = match transfer {
+                Some(HostTransfer::Write(_)) => I2cSlaveEvent::Write(0x42),
+                Some(HostTransfer::Read(_)) => I2cSlaveEvent::Read(0x42),
+                None => return Err(I2cError::Timeout),
+            };
+            *self.current.lock().unwrap() = transfer;
+            Ok(event)
+        }
+
+        fn slave_wait_address_async(
+            &self,
+        ) -> impl core::future::Future<Output = Result<I2cSlaveEvent, I2cError>> + Send {
+            let res = self.slave_wait_address();
+            async move { res }
+        }
+
+        fn slave_read(&self, data: &mut [u8]) -> Result<(), I2cError> {
+            self.slave_receive(data).map(|_| ())
+        }
+
+        fn slave_read_async(
+            &self,
+            data: &mut [u8],
+        ) -> impl core::future::Future<Output = Result<(), I2cError>> + Send {
+            let res = self.slave_read(data);
+            async move { res }
+        }
+
+        fn slave_write(&self, data: &[u8]) -> Result<(), I2cError> {
+            self.slave_transmit(data).map(|_| ())
+        }
+
+        fn slave_write_async(
+            &self,
+            data: &[u8],
+        ) -> impl core::future::Future<Output = Result<(), I2cError>> + Send {
+            let res = self.slave_write(data);
+            async move { res }
+        }
+
+        fn slave_receive(&self, data: &mut [u8]) -> Result<usize, I2cError> {
+            match self.current.lock().unwrap().take() {
+                Some(HostTransfer::Write(bytes)) if bytes.len() <= data.len() => {
+                    data[..bytes.len()].copy_from_slice(&bytes);
+                    Ok(bytes.len())
+                }
+                Some(HostTransfer::Write(_)) => Err(I2cError::Overrun),
+                _ => Err(I2cError::BusError),
+            }
+        }
+
+        fn slave_receive_async(
+            &self,
+            data: &mut [u8],
+        ) -> impl core::future::Future<Output = Result<usize, I2cError>> + Send {
+            let res = self.slave_receive(data);
+            async move { res }
+        }
+
+        fn slave_transmit(&self, data: &[u8]) -> Result<usize, I2cError> {
+            match self.current.lock().unwrap().take() {
+                Some(HostTransfer::Read(n)) => {
+                    // the host NACKs the n-th byte, sequenced on the register model
+                    let regs = MockRegs::slave_transmitter(n);
+                    let (sent, _) = i2c_transfer::slave_transmit(&regs, data)?;
+                    let read = regs.model.borrow().written.clone();
+                    self.host_reads.lock().unwrap().push(read);
+                    Ok(sent)
+                }
+                _ => Err(I2cError::BusError),
+            }
+        }
+
+        fn slave_transmit_async(
+            &self,
+            data: &[u8],
+        ) -> impl core::future::Future<Output = Result<usize, I2cError>> + Send {
+            let res = self.slave_transmit(data);
+            async move { res }
+        }
+    }
+
+    /// Clear-on-read status register and a command register.
+    #[derive(Default)]
+    struct Device {
+        status: u8,
+        commands: Vec<(usize, u8)>,
+        reads_done: Vec<usize>,
+    }
+
+    impl RegisterHandler for Device {
+        fn read(&mut self, _reg: usize) -> u8 {
+            self.status
+        }
+
+        fn read_done(&mut self, reg: usize) {
+            self.status = 0;
+            self.reads_done.push(reg);
+        }
+
+        fn write(&mut self, reg: usize, value: u8) {
+            self.commands.push((reg, value));
+        }
+    }
+
+    fn serve_all<T: Pin, S: I2cSlave<T>, H: RegisterHandler>(
+        service: &mut I2cRegisterSlave<'_, T, S, H>,
+        transfers: usize,
+    ) {
+        for _ in 0..transfers {
+            assert_eq!(block_on(service.serve_async()), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_write_and_read_back() {
+        use HostTransfer::*;
+        let slave = MockSlave::new(vec![
+            // pointer 1, then two registers
+            Write(vec![1, 0xaa, 0xbb]),
+            // pointer only, then read with repeated START
+            Write(vec![0]),
+            Read(4),
+            // a plain read continues after the last access, wrapping at the end
+            Read(2),
+        ]);
+        let mut registers = [
+            Register::ReadOnly(0x5a),
+            Register::ReadWrite(0),
+            Register::ReadWrite(0),
+        ];
+        let mut service = I2cRegisterSlave::new(&slave, &mut registers, NoCallbacks);
+        serve_all(&mut service, 4);
+        assert_eq!(service.pointer(), 2);
+        assert_eq!(
+            *slave.host_reads.lock().unwrap(),
+            [vec![0x5a, 0xaa, 0xbb, 0xff], vec![0x5a, 0xaa]]
+        );
+        assert_eq!(registers[1], Register::ReadWrite(0xaa));
+    }
+
+    #[test]
+    fn test_read_only_and_wrap() {
+        let slave = MockSlave::new(vec![HostTransfer::Write(vec![2, 1, 2, 3])]);
+        let mut registers = [
+            Register::ReadOnly(0x5a),
+            Register::ReadWrite(0),
+            Register::ReadWrite(0),
+        ];
+        let mut service = I2cRegisterSlave::new(&slave, &mut registers, NoCallbacks);
+        serve_all(&mut service, 1);
+        // register 2, then wrap to the read-only register 0 and on to 1
+        assert_eq!(service.pointer(), 2);
+        assert_eq!(
+            registers,
+            [
+                Register::ReadOnly(0x5a),
+                Register::ReadWrite(3),
+                Register::ReadWrite(1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pointer_beyond_the_bank() {
+        use HostTransfer::*;
+        // pointer 4 of a 3 register bank is register 1
+        let slave = MockSlave::new(vec![Write(vec![4, 0xaa]), Write(vec![5]), Read(1)]);
+        let mut registers = [
+            Register::ReadOnly(0x5a),
+            Register::ReadWrite(0),
+            Register::ReadWrite(0x33),
+        ];
+        let mut service = I2cRegisterSlave::new(&slave, &mut registers, NoCallbacks);
+        serve_all(&mut service, 1);
+        assert_eq!(service.pointer(), 2);
+        serve_all(&mut service, 2);
+        assert_eq!(*slave.host_reads.lock().unwrap(), [vec![0x33]]);
+        assert_eq!(registers[1], Register::ReadWrite(0xaa));
+    }
+
+    #[test]
+    fn test_callback_registers() {
+        use HostTransfer::*;
+        let slave = MockSlave::new(vec![
+            Write(vec![1, 0x10]),
+            Write(vec![0]),
+            Read(1),
+            Write(vec![0]),
+            Read(1),
+        ]);
+        let mut registers = [Register::Callback, Register::Callback];
+        let device = Device {
+            status: 0x81,
+            ..Default::default()
+        };
+        let mut service = I2cRegisterSlave::new(&slave, &mut registers, device);
+        serve_all(&mut service, 5);
+        assert_eq!(service.handler().commands, [(1, 0x10)]);
+        // status cleared by the first read
+        assert_eq!(*slave.host_reads.lock().unwrap(), [vec![0x81], vec![0x00]]);
+    }
+
+    #[test]
+    fn test_read_stopped_before_the_end() {
+        use HostTransfer::*;
+        // the host NACKs the second of four registers, then reads on from the pointer
+        let slave = MockSlave::new(vec![Write(vec![0]), Read(2), Read(1)]);
+        let mut registers = [Register::Callback; 4];
+        let mut service = I2cRegisterSlave::new(&slave, &mut registers, Device::default());
+        serve_all(&mut service, 2);
+        assert_eq!(service.pointer(), 2);
+        assert_eq!(service.handler().reads_done, [0, 1]);
+        serve_all(&mut service, 1);
+        assert_eq!(service.pointer(), 3);
+        assert_eq!(service.handler().reads_done, [0, 1, 2]);
+    }
+
+    #[test]
+    fn test_errors_end_the_transfer() {
+        let slave = MockSlave::new(vec![HostTransfer::Write(vec![0; MAX_REGISTERS + 2])]);
+        let mut registers = [Register::ReadWrite(0)];
+        let mut service = I2cRegisterSlave::new(&slave, &mut registers, NoCallbacks);
+        assert_eq!(block_on(service.serve_async()), Err(I2cError::Overrun));
+        assert_eq!(service.pointer(), 0);
+    }
+}
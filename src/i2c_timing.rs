@@ -0,0 +1,431 @@
+//! # I2C timing calculation
+//!
+//! Computes the `I2C_TIMINGR` fields (`PRESC`, `SCLDEL`, `SDADEL`, `SCLH`, `SCLL`) for a kernel
+//! clock and bus frequency, following the timing rules of the reference manual (RM0456, "I2C
+//! timings"):
+//!
+//! - `tPRESC = (PRESC + 1) * tI2CCLK`
+//! - data hold time: `tSDADEL = SDADEL * tPRESC + tI2CCLK` must be within
+//!   `[tf + tHD;DAT(min) - tAF(min) - (DNF + 3) * tI2CCLK, tVD;DAT(max) - tr - tAF(max) - (DNF + 4) * tI2CCLK]`
+//! - data setup time: `tSCLDEL = (SCLDEL + 1) * tPRESC >= tr + tSU;DAT(min)`
+//! - SCL low/high: `(SCLL + 1) * tPRESC` and `(SCLH + 1) * tPRESC`, each followed by a
+//!   synchronisation delay of `tAF + DNF * tI2CCLK + 2 * tI2CCLK`.
+//!   The SCL period also contains the rise and fall time of the bus.
+//!
+//! The limits of standard mode, fast mode and fast mode plus are taken from the I2C specification
+//! (UM10204). The mode is selected from the requested frequency. The computed bus frequency is never
+//! higher than the requested one.
+
+/// Shortest/longest spike suppressed by the analog filter (datasheet, "I2C analog filter
+/// characteristics"), in ns.
+const ANALOG_FILTER_DELAY_MIN: u64 = 50;
+const ANALOG_FILTER_DELAY_MAX: u64 = 90;
+
+const PS_PER_NS: u64 = 1_000;
+const PS_PER_S: u64 = 1_000_000_000_000;
+
+/// Timing limits of one I2C bus mode, in ns.
+struct Spec {
+    max_freq: u32,
+    low_min: u64,
+    high_min: u64,
+    hd_dat_min: u64,
+    vd_dat_max: u64,
+    su_dat_min: u64,
+    rise_max: u32,
+    fall_max: u32,
+}
+
+const STANDARD_MODE: Spec = Spec {
+    max_freq: 100_000,
+    low_min: 4_700,
+    high_min: 4_000,
+    hd_dat_min: 0,
+    vd_dat_max: 3_450,
+    su_dat_min: 250,
+    rise_max: 1_000,
+    fall_max: 300,
+};
+
+const FAST_MODE: Spec = Spec {
+    max_freq: 400_000,
+    low_min: 1_300,
+    high_min: 600,
+    hd_dat_min: 0,
+    vd_dat_max: 900,
+    su_dat_min: 100,
+    rise_max: 300,
+    fall_max: 300,
+};
+
+const FAST_MODE_PLUS: Spec = Spec {
+    max_freq: 1_000_000,
+    low_min: 500,
+    high_min: 260,
+    hd_dat_min: 0,
+    vd_dat_max: 450,
+    su_dat_min: 50,
+    rise_max: 120,
+    fall_max: 120,
+};
+
+fn spec(freq: u32) -> Option<&'static Spec> {
+    [&STANDARD_MODE, &FAST_MODE, &FAST_MODE_PLUS]
+        .into_iter()
+        .find(|spec| freq <= spec.max_freq)
+}
+
+/// Input of the timing calculation.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct I2cTimingConfig {
+    /// frequency of the i2c kernel clock (`I2CCLK`) in Hz
+    pub kernel_freq: u32,
+    /// requested SCL frequency in Hz, at most 1 MHz
+    pub bus_freq: u32,
+    /// SCL/SDA rise time of the bus in ns. Depends on the pull-ups and the bus capacitance.
+    pub rise_ns: u32,
+    /// SCL/SDA fall time of the bus in ns.
+    pub fall_ns: u32,
+    /// analog noise filter enabled (`ANFOFF = 0`)
+    pub analog_filter: bool,
+    /// digital noise filter length (`DNF`) in kernel clock cycles, 0 (off) to 15
+    pub digital_filter: u8,
+}
+
+impl I2cTimingConfig {
+    /// Analog filter on, digital filter off, 100 ns rise and 10 ns fall time (short traces with
+    /// 4.7k pull-ups).
+    pub fn new(kernel_freq: u32, bus_freq: u32) -> Self {
+        Self {
+            kernel_freq,
+            bus_freq,
+            rise_ns: 100,
+            fall_ns: 10,
+            analog_filter: true,
+            digital_filter: 0,
+        }
+    }
+}
+
+/// `I2C_TIMINGR` fields.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct I2cTiming {
+    pub presc: u8,
+    pub scldel: u8,
+    pub sdadel: u8,
+    pub sclh: u8,
+    pub scll: u8,
+    /// resulting SCL frequency in Hz
+    pub freq: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum I2cTimingError {
+    /// bus frequency is 0 or above 1 MHz (fast mode plus)
+    InvalidFrequency,
+    /// digital filter longer than 15 cycles
+    InvalidFilter,
+    /// rise or fall time above the maximum of the bus mode
+    InvalidRiseFall,
+    /// data hold/setup time or noise filters cannot be met, the kernel clock is too slow
+    KernelClockTooSlow,
+    /// SCL low/high do not fit in 8 bit even with the largest prescaler
+    FrequencyTooLow,
+}
+
+/// Compute `I2C_TIMINGR` for `config`. The smallest prescaler that meets all constraints is used,
+/// which gives the finest resolution of the SCL period.
+pub fn compute_timing(config: &I2cTimingConfig) -> Result<I2cTiming, I2cTimingError> {
+    if config.bus_freq == 0 || config.kernel_freq == 0 {
+        return Err(I2cTimingError::InvalidFrequency);
+    }
+    let spec = spec(config.bus_freq).ok_or(I2cTimingError::InvalidFrequency)?;
+    if config.digital_filter > 15 {
+        return Err(I2cTimingError::InvalidFilter);
+    }
+    if config.rise_ns > spec.rise_max || config.fall_ns > spec.fall_max {
+        return Err(I2cTimingError::InvalidRiseFall);
+    }
+
+    let mut err = I2cTimingError::KernelClockTooSlow;
+    for presc in 0..16 {
+        match timing_for_presc(config, spec, presc) {
+            Ok(timing) => return Ok(timing),
+            Err(e) => err = e,
+        }
+    }
+    Err(err)
+}
+
+fn timing_for_presc(
+    config: &I2cTimingConfig,
+    spec: &Spec,
+    presc: u8,
+) -> Result<I2cTiming, I2cTimingError> {
+    // all times in ps
+    let t_clk = PS_PER_S / config.kernel_freq as u64;
+    let t_presc = (presc as u64 + 1) * t_clk;
+    let rise = config.rise_ns as u64 * PS_PER_NS;
+    let fall = config.fall_ns as u64 * PS_PER_NS;
+    let (af_min, af_max) = if config.analog_filter {
+        (
+            ANALOG_FILTER_DELAY_MIN * PS_PER_NS,
+            ANALOG_FILTER_DELAY_MAX * PS_PER_NS,
+        )
+    } else {
+        (0, 0)
+    };
+    let dnf = config.digital_filter as u64;
+
+    // data hold time
+    let sdadel_min =
+        (fall + spec.hd_dat_min * PS_PER_NS).saturating_sub(af_min + (dnf + 3) * t_clk);
+    let sdadel_max = (spec.vd_dat_max * PS_PER_NS)
+        .checked_sub(rise + af_max + (dnf + 4) * t_clk)
+        .ok_or(I2cTimingError::KernelClockTooSlow)?;
+    let sdadel = sdadel_min.div_ceil(t_presc);
+    if sdadel * t_presc > sdadel_max || sdadel > 15 {
+        return Err(I2cTimingError::KernelClockTooSlow);
+    }
+
+    // data setup time
+    let scldel = (rise + spec.su_dat_min * PS_PER_NS)
+        .div_ceil(t_presc)
+        .saturating_sub(1);
+    if scldel > 15 {
+        return Err(I2cTimingError::KernelClockTooSlow);
+    }
+
+    // SCL low and high. `low` and `high` are in tPRESC (SCLL + 1 and SCLH + 1).
+    let sync = af_min + dnf * t_clk + 2 * t_clk;
+    let low_min = (spec.low_min * PS_PER_NS)
+        .saturating_sub(sync)
+        .div_ceil(t_presc)
+        .max(1);
+    let high_min = (spec.high_min * PS_PER_NS)
+        .saturating_sub(sync)
+        .div_ceil(t_presc)
+        .max(1);
+    let period = PS_PER_S / config.bus_freq as u64;
+    let total = period
+        .saturating_sub(2 * sync + rise + fall)
+        .div_ceil(t_presc)
+        .max(low_min + high_min);
+    // share what is left above the minimum like the minimums are shared
+    let extra = total - low_min - high_min;
+    let low = low_min + extra * spec.low_min / (spec.low_min + spec.high_min);
+    let high = total - low;
+    if low > 256 || high > 256 {
+        return Err(I2cTimingError::FrequencyTooLow);
+    }
+
+    // the peripheral needs tI2CCLK < (tLOW - tfilters) / 4 and tI2CCLK < tHIGH
+    let filters = af_min + dnf * t_clk;
+    if 4 * t_clk >= (low * t_presc + sync).saturating_sub(filters) || t_clk >= high * t_presc + sync
+    {
+        return Err(I2cTimingError::KernelClockTooSlow);
+    }
+
+    let scl_period = total * t_presc + 2 * sync + rise + fall;
+    Ok(I2cTiming {
+        presc,
+        scldel: scldel as u8,
+        sdadel: sdadel as u8,
+        sclh: (high - 1) as u8,
+        scll: (low - 1) as u8,
+        freq: (PS_PER_S / scl_period) as u32,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Check the result against the reference manual formulas, independent of the search above.
+    fn assert_meets_spec(config: &I2cTimingConfig, timing: &I2cTiming) {
+        let spec = spec(config.bus_freq).unwrap();
+        let t_clk = 1e12 / config.kernel_freq as f64;
+        let t_presc = (timing.presc as f64 + 1.0) * t_clk;
+        let rise = config.rise_ns as f64 * 1e3;
+        let fall = config.fall_ns as f64 * 1e3;
+        let (af_min, af_max) = if config.analog_filter {
+            (50e3, 90e3)
+        } else {
+            (0.0, 0.0)
+        };
+        let dnf = config.digital_filter as f64;
+
+        assert!(
+            timing.presc < 16 && timing.scldel < 16 && timing.sdadel < 16,
+            "{timing:?}"
+        );
+
+        let hold = timing.sdadel as f64 * t_presc + t_clk;
+        assert!(
+            hold >= fall + spec.hd_dat_min as f64 * 1e3 - af_min - (dnf + 3.0) * t_clk + t_clk,
+            "{timing:?}"
+        );
+        assert!(
+            hold <= spec.vd_dat_max as f64 * 1e3 - rise - af_max - (dnf + 4.0) * t_clk + t_clk,
+            "{timing:?}"
+        );
+
+        let setup = (timing.scldel as f64 + 1.0) * t_presc;
+        assert!(setup >= rise + spec.su_dat_min as f64 * 1e3, "{timing:?}");
+
+        let sync = af_min + dnf * t_clk + 2.0 * t_clk;
+        let low = (timing.scll as f64 + 1.0) * t_presc + sync;
+        let high = (timing.sclh as f64 + 1.0) * t_presc + sync;
+        assert!(low >= spec.low_min as f64 * 1e3, "{timing:?}");
+        assert!(high >= spec.high_min as f64 * 1e3, "{timing:?}");
+
+        let freq = 1e12 / (low + high + rise + fall);
+        // the calculation works on whole ps
+        assert!(freq <= config.bus_freq as f64 * 1.0001, "{timing:?}");
+        assert!(
+            (freq - timing.freq as f64).abs() <= freq * 1e-4,
+            "{timing:?}"
+        );
+    }
+
+    #[test]
+    fn test_standard_mode_from_msik() {
+        let config = I2cTimingConfig::new(4_000_000, 100_000);
+        let timing = compute_timing(&config).unwrap();
+        assert_eq!(
+            timing,
+            I2cTiming {
+                presc: 0,
+                scldel: 1,
+                sdadel: 0,
+                sclh: 16,
+                scll: 18,
+                freq: 97_943,
+            }
+        );
+        assert_meets_spec(&config, &timing);
+    }
+
+    #[test]
+    fn test_fast_mode_plus_from_hsi() {
+        let config = I2cTimingConfig::new(16_000_000, 1_000_000);
+        let timing = compute_timing(&config).unwrap();
+        assert_eq!((timing.presc, timing.scldel, timing.sdadel), (0, 2, 0));
+        assert_meets_spec(&config, &timing);
+        assert!(timing.freq > 950_000);
+    }
+
+    #[test]
+    fn test_kernel_clocks_and_speeds() {
+        let kernels = [
+            4_000_000,
+            16_000_000,
+            20_000_000,
+            48_000_000,
+            80_000_000,
+            160_000_000,
+        ];
+        let speeds = [
+            10_000, 50_000, 100_000, 250_000, 333_333, 400_000, 800_000, 1_000_000,
+        ];
+        for &kernel_freq in &kernels {
+            for &bus_freq in &speeds {
+                for digital_filter in [0, 4] {
+                    for analog_filter in [true, false] {
+                        let config = I2cTimingConfig {
+                            digital_filter,
+                            analog_filter,
+                            ..I2cTimingConfig::new(kernel_freq, bus_freq)
+                        };
+                        match compute_timing(&config) {
+                            Ok(timing) => {
+                                assert_meets_spec(&config, &timing);
+                                // within 10% of the request
+                                assert!(timing.freq * 10 >= bus_freq * 9, "{config:?} {timing:?}");
+                            }
+                            // fast modes need a fast kernel clock, slow buses a slow one
+                            Err(I2cTimingError::KernelClockTooSlow) => assert!(
+                                kernel_freq <= 20_000_000 && bus_freq > 100_000,
+                                "{config:?}"
+                            ),
+                            Err(I2cTimingError::FrequencyTooLow) => assert!(
+                                kernel_freq > 48_000_000 && bus_freq <= 10_000,
+                                "{config:?}"
+                            ),
+                            Err(err) => panic!("{config:?} {err:?}"),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_fast_mode_needs_fast_kernel_clock() {
+        let config = I2cTimingConfig::new(4_000_000, 400_000);
+        assert_eq!(
+            compute_timing(&config),
+            Err(I2cTimingError::KernelClockTooSlow)
+        );
+        let config = I2cTimingConfig::new(8_000_000, 400_000);
+        assert_meets_spec(&config, &compute_timing(&config).unwrap());
+    }
+
+    #[test]
+    fn test_frequency_too_low() {
+        let config = I2cTimingConfig::new(160_000_000, 10_000);
+        assert_eq!(
+            compute_timing(&config),
+            Err(I2cTimingError::FrequencyTooLow)
+        );
+        let config = I2cTimingConfig::new(16_000_000, 10_000);
+        assert_meets_spec(&config, &compute_timing(&config).unwrap());
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let config = I2cTimingConfig::new(16_000_000, 0);
+        assert_eq!(
+            compute_timing(&config),
+            Err(I2cTimingError::InvalidFrequency)
+        );
+        let config = I2cTimingConfig::new(16_000_000, 1_200_000);
+        assert_eq!(
+            compute_timing(&config),
+            Err(I2cTimingError::InvalidFrequency)
+        );
+        let config = I2cTimingConfig {
+            digital_filter: 16,
+            ..I2cTimingConfig::new(16_000_000, 100_000)
+        };
+        assert_eq!(compute_timing(&config), Err(I2cTimingError::InvalidFilter));
+        let config = I2cTimingConfig {
+            rise_ns: 500,
+            ..I2cTimingConfig::new(16_000_000, 400_000)
+        };
+        assert_eq!(
+            compute_timing(&config),
+            Err(I2cTimingError::InvalidRiseFall)
+        );
+    }
+
+    #[test]
+    fn test_slow_bus_stretches_delays() {
+        let fast_edges = I2cTimingConfig::new(16_000_000, 100_000);
+        let slow_edges = I2cTimingConfig {
+            rise_ns: 1_000,
+            fall_ns: 300,
+            ..fast_edges
+        };
+        let fast = compute_timing(&fast_edges).unwrap();
+        let slow = compute_timing(&slow_edges).unwrap();
+        assert_meets_spec(&slow_edges, &slow);
+        assert!(slow.scldel > fast.scldel);
+        assert!(slow.sdadel > fast.sdadel);
+        // the edges are part of the period, so SCL low + high get shorter
+        assert!(slow.scll as u32 + slow.sclh as u32 <= fast.scll as u32 + fast.sclh as u32);
+    }
+}
@@ -0,0 +1,1817 @@
+//! # I2C transfer sequencing
+//!
+//! The STM32U5 I2C peripheral counts the bytes of a transfer in the 8 bit `NBYTES` field of `CR2`,
+//! so a single START can move at most 255 bytes. Longer transfers are split in chunks chained with
+//! `RELOAD`: when a chunk with `RELOAD = 1` is done the peripheral sets `TCR` and stretches SCL
+//! until `NBYTES` is programmed again. Only the last chunk of a transfer decides whether a STOP
+//! (`AUTOEND = 1`) or a repeated START (`TC`) follows.
+//!
+//! The sequencing is written against the [`I2cRegisters`] trait instead of the register block,
+//! `i2c.rs` implements the trait for the real peripheral.
+//!
+//! Transactions ([`transaction`]) are built from segments, one per operation:
+//! - adjacent operations in the same direction are merged with `RELOAD` (no repeated START),
+//! - a change of direction ends the segment with `TC` and a repeated START,
+//! - the last segment ends with STOP.
+//!
+//! The error flags (`BERR`, `ARLO`, `OVR`) end a transfer with the matching `I2cError`. A bus left
+//! stuck by a target is freed with [`recover_bus`].
+//!
+//! SMBus transfers can end with a Packet Error Code ([`SegmentEnd::StopPec`]). It is generated and
+//! checked by the peripheral (`PECEN`): `PECBYTE` in the last chunk makes its last byte the PEC, a
+//! wrong received PEC sets `PECERR` and a target NACKs it.
+
+use crate::hal::{I2cError, I2cOperation};
+use core::future::poll_fn;
+use core::task::{Context, Poll};
+
+/// Maximum number of bytes in one chunk (`NBYTES` is 8 bit).
+pub const MAX_NBYTES: usize = 255;
+
+/// Snapshot of the `I2C_ISR` flags used by the sequencer.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Status {
+    pub txis: bool,
+    /// `TXDR` is empty (`TXE`). A slave that writes each byte on `TXIS` has the byte after the
+    /// last one the controller read still in `TXDR` when the transfer ends.
+    pub txe: bool,
+    pub rxne: bool,
+    pub tc: bool,
+    pub tcr: bool,
+    pub stopf: bool,
+    pub nackf: bool,
+    /// bus error, arbitration loss, overrun/underrun and SMBus timeout, see [`Status::error`]
+    pub berr: bool,
+    pub arlo: bool,
+    pub ovr: bool,
+    pub timeout: bool,
+    /// the received PEC does not match (`PECERR`)
+    pub pecerr: bool,
+    /// slave mode: own address matched (`ADDR`), SCL is stretched until it is accepted
+    pub addr: bool,
+    /// slave mode: the matched address came with the read bit (`DIR`)
+    pub dir_read: bool,
+    /// slave mode: the matched address (`ADDCODE`), see [`OwnAddresses::matched`]
+    pub addcode: u8,
+}
+
+impl Status {
+    /// The error flags as an error. NACK is not included, in target mode it is the normal end of a
+    /// read by the controller.
+    pub fn error(&self) -> Option<I2cError> {
+        if self.berr {
+            Some(I2cError::BusError)
+        } else if self.arlo {
+            Some(I2cError::ArbitrationLoss)
+        } else if self.ovr {
+            Some(I2cError::Overrun)
+        } else if self.timeout {
+            Some(I2cError::Timeout)
+        } else if self.pecerr {
+            Some(I2cError::Pec)
+        } else {
+            None
+        }
+    }
+}
+
+/// Interrupt sources an async wait is interested in.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Events {
+    pub tx: bool,
+    pub rx: bool,
+    /// `TC` and `TCR`
+    pub transfer_complete: bool,
+    pub stop: bool,
+    pub nack: bool,
+    /// slave mode address match
+    pub address: bool,
+}
+
+/// `CR2` programming for one chunk of a transfer.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Chunk {
+    pub nbytes: u8,
+    /// more bytes in the same direction follow, wait for `TCR`
+    pub reload: bool,
+    /// generate STOP after the chunk
+    pub autoend: bool,
+    /// generate a (repeated) START with the address and direction. Otherwise only `NBYTES`,
+    /// `RELOAD` and `AUTOEND` are updated, which continues the transfer after `TCR`.
+    pub start: bool,
+    /// the last byte is the PEC (`PECBYTE`), only in a chunk without `reload`
+    pub pec: bool,
+}
+
+/// In slave mode (`SBC = 1`) the peripheral keeps reloading so the length is not limited.
+pub const SLAVE_CHUNK: Chunk = Chunk {
+    nbytes: MAX_NBYTES as u8,
+    reload: true,
+    autoend: false,
+    start: false,
+    pec: false,
+};
+
+/// Register level access to one I2C peripheral.
+pub trait I2cRegisters {
+    fn status(&self) -> Status;
+    /// Program `CR2` for `chunk`. `addr` (7 bit) and `read` are only used when `chunk.start` is set.
+    fn program(&self, addr: u16, read: bool, chunk: Chunk);
+    fn write_data(&self, byte: u8);
+    fn read_data(&self) -> u8;
+    fn clear_stop(&self);
+    /// Drop the byte left in `TXDR` (set `TXE`).
+    fn flush_tx(&self);
+    /// Clear `ADDR`, which releases SCL after an address match.
+    fn clear_addr(&self);
+    /// Register the waker of `cx` and enable the interrupts for `events`. The error interrupt
+    /// (`BERR`, `ARLO`, `OVR`, `TIMEOUT`, `PECERR`) is always enabled.
+    fn listen(&self, cx: &mut Context<'_>, events: Events);
+    /// Clean up after a failed transfer: mask the interrupts and clear the NACK/STOP and error
+    /// flags.
+    fn abort(&self);
+}
+
+/// How a segment begins on the bus.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SegmentStart {
+    /// (repeated) START followed by the target address
+    Start,
+    /// continue the previous segment in the same direction after `TCR`
+    Reload,
+}
+
+/// How a segment ends on the bus.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SegmentEnd {
+    /// more bytes in the same direction follow, hold the bus with `RELOAD`
+    Reload,
+    /// the direction changes, a repeated START follows
+    Restart,
+    /// last segment, generate STOP
+    Stop,
+    /// last segment of an SMBus transfer, its last byte is the PEC and STOP follows. A write
+    /// leaves the PEC to the peripheral, a read receives it in the last byte of the buffer and
+    /// fails with `I2cError::Pec` if it is wrong.
+    StopPec,
+}
+
+/// Split a segment of `len` bytes (PEC included) in chunks of at most [`MAX_NBYTES`] bytes.
+/// An empty segment still produces one chunk (address only).
+pub fn plan_chunks(
+    len: usize,
+    start: SegmentStart,
+    end: SegmentEnd,
+) -> impl Iterator<Item = Chunk> {
+    let count = len.div_ceil(MAX_NBYTES).max(1);
+    (0..count).map(move |i| {
+        let last = i + 1 == count;
+        Chunk {
+            nbytes: (len - i * MAX_NBYTES).min(MAX_NBYTES) as u8,
+            reload: !last || end == SegmentEnd::Reload,
+            autoend: last && matches!(end, SegmentEnd::Stop | SegmentEnd::StopPec),
+            start: i == 0 && start == SegmentStart::Start,
+            pec: last && end == SegmentEnd::StopPec,
+        }
+    })
+}
+
+fn chunk_done(status: Status, chunk: Chunk) -> bool {
+    if chunk.reload {
+        status.tcr
+    } else if chunk.autoend {
+        status.stopf
+    } else {
+        status.tc
+    }
+}
+
+/// An operation of a transaction. Implemented for `hal::I2cOperation` and, with the
+/// `embedded-hal` feature, for `embedded_hal::i2c::Operation`.
+pub trait TransferOperation {
+    fn is_read(&self) -> bool;
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    fn as_operation(&mut self) -> I2cOperation<'_>;
+}
+
+impl TransferOperation for I2cOperation<'_> {
+    fn is_read(&self) -> bool {
+        I2cOperation::is_read(self)
+    }
+    fn len(&self) -> usize {
+        I2cOperation::len(self)
+    }
+    fn as_operation(&mut self) -> I2cOperation<'_> {
+        match self {
+            I2cOperation::Write(data) => I2cOperation::Write(data),
+            I2cOperation::Read(data) => I2cOperation::Read(data),
+        }
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl TransferOperation for embedded_hal::i2c::Operation<'_> {
+    fn is_read(&self) -> bool {
+        matches!(self, embedded_hal::i2c::Operation::Read(_))
+    }
+    fn len(&self) -> usize {
+        match self {
+            embedded_hal::i2c::Operation::Write(data) => data.len(),
+            embedded_hal::i2c::Operation::Read(data) => data.len(),
+        }
+    }
+    fn as_operation(&mut self) -> I2cOperation<'_> {
+        match self {
+            embedded_hal::i2c::Operation::Write(data) => I2cOperation::Write(data),
+            embedded_hal::i2c::Operation::Read(data) => I2cOperation::Read(data),
+        }
+    }
+}
+
+/// Link operation `i` with its neighbours. Empty operations in the middle of a transaction have
+/// nothing to put on the bus and are skipped (`None`). A transaction made only of empty operations
+/// sends the address of the first one followed by STOP.
+pub fn operation_links<O: TransferOperation>(
+    operations: &[O],
+    i: usize,
+) -> Option<(SegmentStart, SegmentEnd)> {
+    if operations.iter().all(|op| op.len() == 0) {
+        return (i == 0).then_some((SegmentStart::Start, SegmentEnd::Stop));
+    }
+    let op = &operations[i];
+    if op.len() == 0 {
+        return None;
+    }
+    let read = op.is_read();
+    let start = match operations[..i].iter().rev().find(|op| op.len() > 0) {
+        Some(prev) if prev.is_read() == read => SegmentStart::Reload,
+        _ => SegmentStart::Start,
+    };
+    let end = match operations[i + 1..].iter().find(|op| op.len() > 0) {
+        None => SegmentEnd::Stop,
+        Some(next) if next.is_read() == read => SegmentEnd::Reload,
+        Some(_) => SegmentEnd::Restart,
+    };
+    Some((start, end))
+}
+
+/////////////////////////// blocking /////////////////////////////
+fn wait<R: I2cRegisters>(regs: &R, cond: impl Fn(Status) -> bool) -> Result<Status, I2cError> {
+    loop {
+        let status = regs.status();
+        if let Some(err) = status.error() {
+            return Err(err);
+        }
+        if status.nackf {
+            return Err(I2cError::Nack);
+        }
+        if cond(status) {
+            return Ok(status);
+        }
+    }
+}
+
+fn finish_chunk<R: I2cRegisters>(regs: &R, chunk: Chunk) -> Result<(), I2cError> {
+    wait(regs, |s| chunk_done(s, chunk))?;
+    if !chunk.reload && chunk.autoend {
+        regs.clear_stop();
+    }
+    Ok(())
+}
+
+pub fn write_segment<R: I2cRegisters>(
+    regs: &R,
+    addr: u16,
+    data: &[u8],
+    start: SegmentStart,
+    end: SegmentEnd,
+) -> Result<(), I2cError> {
+    let mut pos = 0;
+    let pec = end == SegmentEnd::StopPec;
+    for chunk in plan_chunks(data.len() + pec as usize, start, end) {
+        regs.program(addr, false, chunk);
+        // the peripheral sends the PEC
+        let len = chunk.nbytes as usize - chunk.pec as usize;
+        for &byte in &data[pos..pos + len] {
+            wait(regs, |s| s.txis)?;
+            regs.write_data(byte);
+        }
+        pos += len;
+        finish_chunk(regs, chunk)?;
+    }
+    Ok(())
+}
+
+pub fn read_segment<R: I2cRegisters>(
+    regs: &R,
+    addr: u16,
+    data: &mut [u8],
+    start: SegmentStart,
+    end: SegmentEnd,
+) -> Result<(), I2cError> {
+    let mut pos = 0;
+    for chunk in plan_chunks(data.len(), start, end) {
+        regs.program(addr, true, chunk);
+        for byte in &mut data[pos..pos + chunk.nbytes as usize] {
+            wait(regs, |s| s.rxne)?;
+            *byte = regs.read_data();
+        }
+        pos += chunk.nbytes as usize;
+        finish_chunk(regs, chunk)?;
+    }
+    Ok(())
+}
+
+fn transaction_impl<R: I2cRegisters, O: TransferOperation>(
+    regs: &R,
+    addr: u16,
+    operations: &mut [O],
+) -> Result<(), I2cError> {
+    for i in 0..operations.len() {
+        let Some((start, end)) = operation_links(operations, i) else {
+            continue;
+        };
+        match operations[i].as_operation() {
+            I2cOperation::Write(data) => write_segment(regs, addr, data, start, end)?,
+            I2cOperation::Read(data) => read_segment(regs, addr, data, start, end)?,
+        }
+    }
+    Ok(())
+}
+
+/// Run `operations` as one transaction, see `hal::I2c::transaction`.
+pub fn transaction<R: I2cRegisters, O: TransferOperation>(
+    regs: &R,
+    addr: u16,
+    operations: &mut [O],
+) -> Result<(), I2cError> {
+    let res = transaction_impl(regs, addr, operations);
+    if res.is_err() {
+        regs.abort();
+    }
+    res
+}
+
+/////////////////////////// async (interrupt) /////////////////////////////
+async fn wait_async<R: I2cRegisters>(
+    regs: &R,
+    events: Events,
+    cond: impl Fn(Status) -> bool,
+) -> Result<Status, I2cError> {
+    poll_fn(|cx| {
+        regs.listen(cx, events);
+        let status = regs.status();
+        if let Some(err) = status.error() {
+            Poll::Ready(Err(err))
+        } else if status.nackf {
+            Poll::Ready(Err(I2cError::Nack))
+        } else if cond(status) {
+            Poll::Ready(Ok(status))
+        } else {
+            Poll::Pending
+        }
+    })
+    .await
+}
+
+const TX_EVENTS: Events = Events {
+    tx: true,
+    rx: false,
+    transfer_complete: false,
+    stop: false,
+    nack: true,
+    address: false,
+};
+
+const RX_EVENTS: Events = Events {
+    tx: false,
+    rx: true,
+    transfer_complete: false,
+    stop: false,
+    nack: true,
+    address: false,
+};
+
+const END_EVENTS: Events = Events {
+    tx: false,
+    rx: false,
+    transfer_complete: true,
+    stop: true,
+    nack: true,
+    address: false,
+};
+
+async fn finish_chunk_async<R: I2cRegisters>(regs: &R, chunk: Chunk) -> Result<(), I2cError> {
+    wait_async(regs, END_EVENTS, |s| chunk_done(s, chunk)).await?;
+    if !chunk.reload && chunk.autoend {
+        regs.clear_stop();
+    }
+    Ok(())
+}
+
+pub async fn write_segment_async<R: I2cRegisters>(
+    regs: &R,
+    addr: u16,
+    data: &[u8],
+    start: SegmentStart,
+    end: SegmentEnd,
+) -> Result<(), I2cError> {
+    let mut pos = 0;
+    let pec = end == SegmentEnd::StopPec;
+    for chunk in plan_chunks(data.len() + pec as usize, start, end) {
+        regs.program(addr, false, chunk);
+        // the peripheral sends the PEC
+        let len = chunk.nbytes as usize - chunk.pec as usize;
+        for &byte in &data[pos..pos + len] {
+            wait_async(regs, TX_EVENTS, |s| s.txis).await?;
+            regs.write_data(byte);
+        }
+        pos += len;
+        finish_chunk_async(regs, chunk).await?;
+    }
+    Ok(())
+}
+
+pub async fn read_segment_async<R: I2cRegisters>(
+    regs: &R,
+    addr: u16,
+    data: &mut [u8],
+    start: SegmentStart,
+    end: SegmentEnd,
+) -> Result<(), I2cError> {
+    let mut pos = 0;
+    for chunk in plan_chunks(data.len(), start, end) {
+        regs.program(addr, true, chunk);
+        for byte in &mut data[pos..pos + chunk.nbytes as usize] {
+            wait_async(regs, RX_EVENTS, |s| s.rxne).await?;
+            *byte = regs.read_data();
+        }
+        pos += chunk.nbytes as usize;
+        finish_chunk_async(regs, chunk).await?;
+    }
+    Ok(())
+}
+
+async fn transaction_async_impl<R: I2cRegisters, O: TransferOperation>(
+    regs: &R,
+    addr: u16,
+    operations: &mut [O],
+) -> Result<(), I2cError> {
+    for i in 0..operations.len() {
+        let Some((start, end)) = operation_links(operations, i) else {
+            continue;
+        };
+        match operations[i].as_operation() {
+            I2cOperation::Write(data) => write_segment_async(regs, addr, data, start, end).await?,
+            I2cOperation::Read(data) => read_segment_async(regs, addr, data, start, end).await?,
+        }
+    }
+    Ok(())
+}
+
+/// Async version of [`transaction`], waits on the peripheral interrupts.
+pub async fn transaction_async<R: I2cRegisters, O: TransferOperation>(
+    regs: &R,
+    addr: u16,
+    operations: &mut [O],
+) -> Result<(), I2cError> {
+    let res = transaction_async_impl(regs, addr, operations).await;
+    if res.is_err() {
+        regs.abort();
+    }
+    res
+}
+
+/////////////////////////// dma /////////////////////////////
+/// Run a write or read of `len` bytes (START ... STOP) while a DMA channel moves the data through
+/// `TXDR`/`RXDR`. Only the chunks are sequenced here, the data registers are not touched.
+pub async fn dma_transfer_async<R: I2cRegisters>(
+    regs: &R,
+    addr: u16,
+    read: bool,
+    len: usize,
+) -> Result<(), I2cError> {
+    let mut res = Ok(());
+    for chunk in plan_chunks(len, SegmentStart::Start, SegmentEnd::Stop) {
+        regs.program(addr, read, chunk);
+        res = finish_chunk_async(regs, chunk).await;
+        if res.is_err() {
+            regs.abort();
+            break;
+        }
+    }
+    res
+}
+
+/////////////////////////// slave /////////////////////////////
+// In slave mode the address match already started the transfer. With target byte control
+// (`SBC = 1`) the peripheral stops after `NBYTES` bytes with `TCR` set and stretches SCL; it is
+// released by programming `NBYTES` again (`SLAVE_CHUNK`).
+
+fn slave_wait<R: I2cRegisters>(
+    regs: &R,
+    cond: impl Fn(Status) -> bool,
+) -> Result<Status, I2cError> {
+    loop {
+        let status = regs.status();
+        if let Some(err) = status.error() {
+            return Err(err);
+        }
+        if cond(status) {
+            return Ok(status);
+        }
+        if status.tcr {
+            regs.program(0, false, SLAVE_CHUNK);
+        }
+    }
+}
+
+/// How the controller ended a slave transfer.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SlaveEnd {
+    /// STOP, the flag is already cleared
+    Stop,
+    /// repeated START with our address, the address match is left pending for [`slave_accept`]
+    Restart,
+    /// all the bytes the counter was armed for arrived, SCL is stretched (`TCR`) until it is
+    /// armed again, see [`slave_receive_counted`]
+    Reload,
+}
+
+/// An address match.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AddressMatch {
+    /// the controller reads
+    pub read: bool,
+    /// `ADDCODE`, see [`OwnAddresses::matched`]
+    pub addcode: u8,
+}
+
+impl From<Status> for AddressMatch {
+    fn from(status: Status) -> Self {
+        AddressMatch {
+            read: status.dir_read,
+            addcode: status.addcode,
+        }
+    }
+}
+
+/// Accept a pending address match: arm the byte counter and release SCL.
+pub fn slave_accept<R: I2cRegisters>(regs: &R) -> AddressMatch {
+    let status = regs.status();
+    regs.program(0, false, SLAVE_CHUNK);
+    regs.clear_addr();
+    status.into()
+}
+
+/// Wait for the controller to address us and accept it.
+pub fn slave_wait_address<R: I2cRegisters>(regs: &R) -> Result<AddressMatch, I2cError> {
+    slave_wait(regs, |s| s.addr)?;
+    Ok(slave_accept(regs))
+}
+
+/// Wait for the controller to address us without accepting it, so the byte counter can be armed
+/// for the transfer (see [`slave_receive_counted`]).
+pub fn slave_wait_match<R: I2cRegisters>(regs: &R) -> Result<AddressMatch, I2cError> {
+    Ok(slave_wait(regs, |s| s.addr)?.into())
+}
+
+/// Program the first chunk of a counted slave transfer, which releases SCL after `TCR`. A pending
+/// address match is accepted.
+fn slave_arm<R: I2cRegisters>(regs: &R, chunk: Chunk) {
+    let accept = regs.status().addr;
+    regs.program(0, false, chunk);
+    if accept {
+        regs.clear_addr();
+    }
+}
+
+/// Chunks of a counted slave transfer of `len` bytes, the last of them a PEC if `pec` is set.
+fn slave_chunks(len: usize, pec: bool) -> impl Iterator<Item = Chunk> {
+    let end = if pec {
+        SegmentEnd::StopPec
+    } else {
+        SegmentEnd::Reload
+    };
+    plan_chunks(len, SegmentStart::Reload, end)
+}
+
+/// Receive `data.len()` bytes with the byte counter armed for exactly that many. With `pec` the
+/// last one is the PEC, which the peripheral checks: a wrong PEC is NACKed and fails with
+/// `I2cError::Pec`. Call it with the address match pending or after [`SlaveEnd::Reload`]. Returns
+/// the number of bytes received and how the controller ended; without `pec` a complete receive
+/// ends with `Reload`.
+pub fn slave_receive_counted<R: I2cRegisters>(
+    regs: &R,
+    data: &mut [u8],
+    pec: bool,
+) -> Result<(usize, SlaveEnd), I2cError> {
+    let mut chunks = slave_chunks(data.len(), pec);
+    slave_arm(regs, chunks.next().unwrap());
+    let mut len = 0;
+    loop {
+        let status = slave_wait(regs, |s| s.rxne || s.tcr || s.stopf || s.addr)?;
+        if status.rxne {
+            let byte = regs.read_data();
+            *data.get_mut(len).ok_or(I2cError::Overrun)? = byte;
+            len += 1;
+        } else if status.tcr {
+            match chunks.next() {
+                Some(chunk) => regs.program(0, false, chunk),
+                None => return Ok((len, SlaveEnd::Reload)),
+            }
+        } else {
+            return Ok((len, slave_end(regs, status)));
+        }
+    }
+}
+
+/// Send `data` followed by the PEC the peripheral computes, with the byte counter armed for
+/// exactly that many. Call it with the address match pending. Returns the number of bytes of
+/// `data` the controller read.
+pub fn slave_transmit_pec<R: I2cRegisters>(
+    regs: &R,
+    data: &[u8],
+) -> Result<(usize, SlaveEnd), I2cError> {
+    let mut chunks = slave_chunks(data.len() + 1, true);
+    slave_arm(regs, chunks.next().unwrap());
+    let mut sent = 0;
+    loop {
+        let status = slave_wait(regs, |s| s.txis || s.tcr || s.stopf || s.addr)?;
+        if status.txis {
+            regs.write_data(data.get(sent).copied().unwrap_or(0xff));
+            sent += 1;
+        } else if let (true, Some(chunk)) = (status.tcr, chunks.next()) {
+            regs.program(0, false, chunk);
+        } else {
+            return Ok(slave_transmit_end(regs, status, sent, data.len()));
+        }
+    }
+}
+
+/// Receive bytes until the controller sends STOP or a repeated START. Returns the number of bytes
+/// received; more bytes than `data` holds is an `Overrun`.
+pub fn slave_receive<R: I2cRegisters>(
+    regs: &R,
+    data: &mut [u8],
+) -> Result<(usize, SlaveEnd), I2cError> {
+    let mut len = 0;
+    loop {
+        let status = slave_wait(regs, |s| s.rxne || s.stopf || s.addr)?;
+        if status.rxne {
+            let byte = regs.read_data();
+            *data.get_mut(len).ok_or(I2cError::Overrun)? = byte;
+            len += 1;
+        } else {
+            return Ok((len, slave_end(regs, status)));
+        }
+    }
+}
+
+/// Send `data` until the controller ends the read with STOP or a repeated START. Returns the
+/// number of bytes of `data` the controller read; 0xff is sent if it reads more.
+pub fn slave_transmit<R: I2cRegisters>(
+    regs: &R,
+    data: &[u8],
+) -> Result<(usize, SlaveEnd), I2cError> {
+    let mut sent = 0;
+    loop {
+        let status = slave_wait(regs, |s| s.txis || s.stopf || s.addr)?;
+        if status.txis {
+            regs.write_data(data.get(sent).copied().unwrap_or(0xff));
+            sent += 1;
+        } else {
+            return Ok(slave_transmit_end(regs, status, sent, data.len()));
+        }
+    }
+}
+
+/// End of a slave transmission after `written` bytes were written to `TXDR`: a byte still in
+/// `TXDR` never reached the controller, it is flushed and not counted.
+fn slave_transmit_end<R: I2cRegisters>(
+    regs: &R,
+    status: Status,
+    written: usize,
+    len: usize,
+) -> (usize, SlaveEnd) {
+    let mut sent = written;
+    if !status.txe {
+        regs.flush_tx();
+        sent = sent.saturating_sub(1);
+    }
+    (sent.min(len), slave_end(regs, status))
+}
+
+fn slave_end<R: I2cRegisters>(regs: &R, status: Status) -> SlaveEnd {
+    if status.stopf {
+        regs.clear_stop();
+        SlaveEnd::Stop
+    } else {
+        SlaveEnd::Restart
+    }
+}
+
+/// Receive exactly `data` from the controller. `BusError` if it stops early.
+pub fn slave_read<R: I2cRegisters>(regs: &R, data: &mut [u8]) -> Result<(), I2cError> {
+    match slave_receive(regs, data)? {
+        (len, _) if len == data.len() => Ok(()),
+        // controller sent STOP before we read all data
+        _ => Err(I2cError::BusError),
+    }
+}
+
+/// Send exactly `data` to the controller. `BusError` if it stops reading early.
+pub fn slave_write<R: I2cRegisters>(regs: &R, data: &[u8]) -> Result<(), I2cError> {
+    match slave_transmit(regs, data)? {
+        (len, _) if len == data.len() => Ok(()),
+        _ => Err(I2cError::BusError),
+    }
+}
+
+async fn slave_wait_async<R: I2cRegisters>(
+    regs: &R,
+    events: Events,
+    cond: impl Fn(Status) -> bool,
+) -> Result<Status, I2cError> {
+    poll_fn(|cx| {
+        regs.listen(cx, events);
+        loop {
+            let status = regs.status();
+            if let Some(err) = status.error() {
+                return Poll::Ready(Err(err));
+            }
+            if cond(status) {
+                return Poll::Ready(Ok(status));
+            }
+            if !status.tcr {
+                return Poll::Pending;
+            }
+            regs.program(0, false, SLAVE_CHUNK);
+        }
+    })
+    .await
+}
+
+const SLAVE_ADDRESS_EVENTS: Events = Events {
+    tx: false,
+    rx: false,
+    transfer_complete: false,
+    stop: false,
+    nack: false,
+    address: true,
+};
+
+const SLAVE_RX_EVENTS: Events = Events {
+    tx: false,
+    rx: true,
+    transfer_complete: true,
+    stop: true,
+    nack: false,
+    address: true,
+};
+
+const SLAVE_TX_EVENTS: Events = Events {
+    tx: true,
+    rx: false,
+    transfer_complete: true,
+    stop: true,
+    nack: false,
+    address: true,
+};
+
+pub async fn slave_wait_address_async<R: I2cRegisters>(regs: &R) -> Result<AddressMatch, I2cError> {
+    slave_wait_async(regs, SLAVE_ADDRESS_EVENTS, |s| s.addr).await?;
+    Ok(slave_accept(regs))
+}
+
+pub async fn slave_wait_match_async<R: I2cRegisters>(regs: &R) -> Result<AddressMatch, I2cError> {
+    Ok(slave_wait_async(regs, SLAVE_ADDRESS_EVENTS, |s| s.addr)
+        .await?
+        .into())
+}
+
+pub async fn slave_receive_counted_async<R: I2cRegisters>(
+    regs: &R,
+    data: &mut [u8],
+    pec: bool,
+) -> Result<(usize, SlaveEnd), I2cError> {
+    let mut chunks = slave_chunks(data.len(), pec);
+    slave_arm(regs, chunks.next().unwrap());
+    let mut len = 0;
+    loop {
+        let status = slave_wait_async(regs, SLAVE_RX_EVENTS, |s| {
+            s.rxne || s.tcr || s.stopf || s.addr
+        })
+        .await?;
+        if status.rxne {
+            let byte = regs.read_data();
+            *data.get_mut(len).ok_or(I2cError::Overrun)? = byte;
+            len += 1;
+        } else if status.tcr {
+            match chunks.next() {
+                Some(chunk) => regs.program(0, false, chunk),
+                None => return Ok((len, SlaveEnd::Reload)),
+            }
+        } else {
+            return Ok((len, slave_end(regs, status)));
+        }
+    }
+}
+
+pub async fn slave_transmit_pec_async<R: I2cRegisters>(
+    regs: &R,
+    data: &[u8],
+) -> Result<(usize, SlaveEnd), I2cError> {
+    let mut chunks = slave_chunks(data.len() + 1, true);
+    slave_arm(regs, chunks.next().unwrap());
+    let mut sent = 0;
+    loop {
+        let status = slave_wait_async(regs, SLAVE_TX_EVENTS, |s| {
+            s.txis || s.tcr || s.stopf || s.addr
+        })
+        .await?;
+        if status.txis {
+            regs.write_data(data.get(sent).copied().unwrap_or(0xff));
+            sent += 1;
+        } else if let (true, Some(chunk)) = (status.tcr, chunks.next()) {
+            regs.program(0, false, chunk);
+        } else {
+            return Ok(slave_transmit_end(regs, status, sent, data.len()));
+        }
+    }
+}
+
+pub async fn slave_receive_async<R: I2cRegisters>(
+    regs: &R,
+    data: &mut [u8],
+) -> Result<(usize, SlaveEnd), I2cError> {
+    let mut len = 0;
+    loop {
+        let status =
+            slave_wait_async(regs, SLAVE_RX_EVENTS, |s| s.rxne || s.stopf || s.addr).await?;
+        if status.rxne {
+            let byte = regs.read_data();
+            *data.get_mut(len).ok_or(I2cError::Overrun)? = byte;
+            len += 1;
+        } else {
+            return Ok((len, slave_end(regs, status)));
+        }
+    }
+}
+
+pub async fn slave_transmit_async<R: I2cRegisters>(
+    regs: &R,
+    data: &[u8],
+) -> Result<(usize, SlaveEnd), I2cError> {
+    let mut sent = 0;
+    loop {
+        let status =
+            slave_wait_async(regs, SLAVE_TX_EVENTS, |s| s.txis || s.stopf || s.addr).await?;
+        if status.txis {
+            regs.write_data(data.get(sent).copied().unwrap_or(0xff));
+            sent += 1;
+        } else {
+            return Ok(slave_transmit_end(regs, status, sent, data.len()));
+        }
+    }
+}
+
+pub async fn slave_read_async<R: I2cRegisters>(regs: &R, data: &mut [u8]) -> Result<(), I2cError> {
+    match slave_receive_async(regs, data).await? {
+        (len, _) if len == data.len() => Ok(()),
+        _ => Err(I2cError::BusError),
+    }
+}
+
+pub async fn slave_write_async<R: I2cRegisters>(regs: &R, data: &[u8]) -> Result<(), I2cError> {
+    match slave_transmit_async(regs, data).await? {
+        (len, _) if len == data.len() => Ok(()),
+        _ => Err(I2cError::BusError),
+    }
+}
+
+/////////////////////////// own addresses /////////////////////////////
+// A slave answers to own address 1 (7 or 10 bit), own address 2 with up to 7 low bits masked
+// (`OA2MSK`) and optionally the general call address 0. `ADDCODE` only holds 7 bits: the address
+// for 7-bit matches and the `11110xx` header for a 10-bit one.
+
+/// Own address 1 of a slave.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum OwnAddress {
+    SevenBit(u8),
+    TenBit(u16),
+}
+
+/// The addresses a slave answers to.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct OwnAddresses {
+    pub oa1: OwnAddress,
+    /// 7-bit own address 2 and the number of its low bits that are ignored (0 - 7). With a mask
+    /// the reserved addresses (`0000xxx`, `1111xxx`) are still not acknowledged.
+    pub oa2: Option<(u8, u8)>,
+    /// acknowledge the general call address 0
+    pub general_call: bool,
+}
+
+impl OwnAddresses {
+    pub fn new(addr: u8) -> Self {
+        Self {
+            oa1: OwnAddress::SevenBit(addr),
+            oa2: None,
+            general_call: false,
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        let oa1 = match self.oa1 {
+            OwnAddress::SevenBit(addr) => addr <= 0x7f,
+            OwnAddress::TenBit(addr) => addr <= 0x3ff,
+        };
+        let oa2 = self
+            .oa2
+            .is_none_or(|(addr, mask)| addr <= 0x7f && mask <= 7);
+        oa1 && oa2
+    }
+
+    /// `OA1` field of `OAR1` and whether it is a 10-bit address (`OA1MODE`).
+    pub fn oar1(&self) -> (u16, bool) {
+        match self.oa1 {
+            OwnAddress::SevenBit(addr) => ((addr as u16) << 1, false),
+            OwnAddress::TenBit(addr) => (addr, true),
+        }
+    }
+
+    /// The address the controller used for `addcode`: a 10-bit own address 1, 0 for a general
+    /// call or the 7-bit address (own address 1 or in the own address 2 range).
+    pub fn matched(&self, addcode: u8) -> u16 {
+        match self.oa1 {
+            OwnAddress::TenBit(addr) if addcode == 0x78 | (addr >> 8) as u8 => addr,
+            _ => addcode as u16,
+        }
+    }
+
+    /// Whether the peripheral acknowledges the 7-bit address `addr`.
+    pub fn matches(&self, addr: u8) -> bool {
+        let oa1 = self.oa1 == OwnAddress::SevenBit(addr);
+        let reserved = addr & 0x78 == 0 || addr & 0x78 == 0x78;
+        let oa2 = self.oa2.is_some_and(|(oa2, mask)| match mask {
+            0 => addr == oa2,
+            _ => !reserved && (addr ^ oa2) >> mask == 0,
+        });
+        oa1 || oa2 || (self.general_call && addr == 0)
+    }
+}
+
+/////////////////////////// bus recovery /////////////////////////////
+// A target that was reset or lost clocks in the middle of a read keeps driving SDA low while it
+// waits for the rest of its byte. The controller cannot generate START or STOP on such a bus, so
+// the pins are taken over as open-drain GPIOs and clocked by hand (I2C specification, "Bus clear").
+
+/// SCL and SDA as open-drain GPIOs, used by [`recover_bus`] while the peripheral is disabled.
+pub trait BusLines {
+    /// Release (`true`, pulled up) or drive low (`false`) SCL.
+    fn set_scl(&self, high: bool);
+    /// Release (`true`, pulled up) or drive low (`false`) SDA.
+    fn set_sda(&self, high: bool);
+    /// Level of SCL, low while a target stretches the clock.
+    fn scl(&self) -> bool;
+    fn sda(&self) -> bool;
+    /// Wait half a SCL period.
+    fn delay(&self);
+}
+
+/// A target in the middle of a byte releases SDA after at most 8 data bits and the ACK bit.
+pub const RECOVERY_PULSES: usize = 9;
+
+/// Half periods a target may stretch SCL during the recovery.
+const RECOVERY_STRETCH_DELAYS: usize = 100;
+
+fn release_scl<L: BusLines>(lines: &L) -> Result<(), I2cError> {
+    lines.set_scl(true);
+    for _ in 0..RECOVERY_STRETCH_DELAYS {
+        lines.delay();
+        if lines.scl() {
+            return Ok(());
+        }
+    }
+    // SCL is held low, nothing the controller can do
+    Err(I2cError::BusError)
+}
+
+/// Clock up to [`RECOVERY_PULSES`] SCL pulses until the target releases SDA, then generate a
+/// STOP so every target is back to idle. Fails with `BusError` if SCL or SDA stays low.
+pub fn recover_bus<L: BusLines>(lines: &L) -> Result<(), I2cError> {
+    lines.set_sda(true);
+    release_scl(lines)?;
+    for _ in 0..RECOVERY_PULSES {
+        if lines.sda() {
+            break;
+        }
+        lines.set_scl(false);
+        lines.delay();
+        release_scl(lines)?;
+    }
+    if !lines.sda() {
+        return Err(I2cError::BusError);
+    }
+    // STOP: SDA low to high while SCL is high
+    lines.set_scl(false);
+    lines.delay();
+    lines.set_sda(false);
+    lines.delay();
+    release_scl(lines)?;
+    lines.set_sda(true);
+    lines.delay();
+    if lines.sda() {
+        Ok(())
+    } else {
+        Err(I2cError::BusError)
+    }
+}
+
+/// Register model shared by the tests of the modules built on the sequencer.
+#[cfg(test)]
+pub(crate) mod mock {
+    use super::*;
+    use crate::smbus::Pec;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+
+    /// Register model of the peripheral with a well behaved target on the bus.
+    #[derive(Default)]
+    pub(crate) struct Model {
+        /// every `CR2` programming
+        pub(crate) chunks: Vec<Chunk>,
+        /// (address, read) of every START
+        pub(crate) starts: Vec<(u16, bool)>,
+        /// a chunk is in progress
+        pub(crate) active: bool,
+        pub(crate) read: bool,
+        pub(crate) remaining: usize,
+        pub(crate) reload: bool,
+        pub(crate) autoend: bool,
+        /// bytes put on the bus by us
+        pub(crate) written: Vec<u8>,
+        /// bytes the other side puts on the bus
+        pub(crate) incoming: VecDeque<u8>,
+        /// the target NACKs once this many bytes are written
+        pub(crate) nack_after: Option<usize>,
+        /// slave mode: the controller sends STOP after writing all of `incoming`
+        /// or after reading `slave_tx_len` bytes
+        pub(crate) slave: bool,
+        pub(crate) slave_tx_len: usize,
+        pub(crate) stops: usize,
+        pub(crate) aborted: bool,
+        /// a dma channel moves the data as soon as a chunk is programmed
+        pub(crate) dma: bool,
+        /// a misplaced START/STOP is seen once this many bytes are written
+        pub(crate) berr_after: Option<usize>,
+        /// another controller wins the bus once this many bytes are written
+        pub(crate) arlo_after: Option<usize>,
+        /// slave mode: an address match is pending
+        pub(crate) addr: bool,
+        pub(crate) dir_read: bool,
+        /// slave mode: after writing `incoming` the controller restarts and reads this many bytes
+        pub(crate) then_read: Option<usize>,
+        /// slave mode: `ADDCODE` of the address matches, the address the controller uses
+        pub(crate) addcode: u8,
+        /// slave mode: the byte written after the last one the controller reads is in `TXDR`
+        pub(crate) tx_pending: bool,
+        pub(crate) tx_flushes: usize,
+        /// the chunk in progress ends with a PEC (`PECBYTE`)
+        pub(crate) pec: bool,
+        /// PEC of the bytes on the bus since the last STOP
+        pub(crate) crc: Pec,
+        pub(crate) pecerr: bool,
+    }
+
+    pub(crate) struct MockRegs {
+        pub(crate) model: RefCell<Model>,
+    }
+
+    impl MockRegs {
+        pub(crate) fn new(incoming: &[u8]) -> Self {
+            Self {
+                model: RefCell::new(Model {
+                    incoming: incoming.iter().copied().collect(),
+                    ..Default::default()
+                }),
+            }
+        }
+
+        pub(crate) fn slave(incoming: &[u8], read: bool, tx_len: usize) -> Self {
+            let regs = Self::new(incoming);
+            {
+                // the address match arms the first chunk
+                let mut m = regs.model.borrow_mut();
+                m.slave = true;
+                m.slave_tx_len = tx_len;
+                m.read = read;
+                m.active = true;
+                m.remaining = MAX_NBYTES;
+                m.reload = true;
+            }
+            regs
+        }
+
+        /// Serve the data requests of the current chunk like a dma channel.
+        pub(crate) fn run_dma(&self) {
+            loop {
+                let status = self.status();
+                if status.txis {
+                    let byte = self.model.borrow().written.len() as u8;
+                    self.write_data(byte);
+                } else if status.rxne {
+                    self.read_data();
+                } else {
+                    break;
+                }
+            }
+        }
+
+        pub(crate) fn slave_receiver(incoming: &[u8]) -> Self {
+            Self::slave(incoming, true, 0)
+        }
+
+        pub(crate) fn slave_transmitter(tx_len: usize) -> Self {
+            Self::slave(&[], false, tx_len)
+        }
+
+        /// Slave whose address just matched, the transfer starts once it is accepted.
+        pub(crate) fn slave_addressed(
+            incoming: &[u8],
+            controller_reads: bool,
+            tx_len: usize,
+        ) -> Self {
+            let regs = Self::new(incoming);
+            {
+                let mut m = regs.model.borrow_mut();
+                m.slave = true;
+                m.slave_tx_len = tx_len;
+                m.read = !controller_reads;
+                m.addr = true;
+                m.dir_read = controller_reads;
+            }
+            regs
+        }
+    }
+
+    impl Model {
+        /// slave mode: SCL is stretched until the counter is armed again, the controller waits
+        fn stretched(&self) -> bool {
+            self.slave && self.active && self.remaining == 0 && self.reload
+        }
+
+        /// the controller sends a repeated START after writing all of `incoming`
+        fn restart(&self) -> bool {
+            self.slave
+                && self.read
+                && self.active
+                && !self.stretched()
+                && self.incoming.is_empty()
+                && self.then_read.is_some()
+        }
+
+        /// The peripheral sends the PEC as last byte of a `PECBYTE` chunk.
+        fn send_pec(&mut self) {
+            if self.pec && self.active && !self.addr && !self.read && self.remaining == 1 {
+                let pec = self.crc.value();
+                self.written.push(pec);
+                self.remaining = 0;
+            }
+        }
+    }
+
+    impl I2cRegisters for MockRegs {
+        fn status(&self) -> Status {
+            let m = self.model.borrow();
+            let nackf = m.nack_after.is_some_and(|n| m.written.len() >= n);
+            let restart = m.restart();
+            let over = m.slave
+                && !restart
+                && !m.stretched()
+                && if m.read {
+                    m.incoming.is_empty()
+                } else {
+                    m.tx_pending
+                };
+            let done = m.active && m.remaining == 0 && !over;
+            Status {
+                txis: m.active && !m.addr && !m.read && m.remaining > 0 && !nackf && !over,
+                txe: !m.tx_pending,
+                rxne: m.active && m.read && m.remaining > 0 && !m.incoming.is_empty(),
+                tc: done && !m.reload && !m.autoend,
+                tcr: done && m.reload,
+                stopf: m.active && (over || (m.remaining == 0 && !m.reload && m.autoend)),
+                nackf,
+                berr: m.berr_after.is_some_and(|n| m.written.len() >= n),
+                arlo: m.arlo_after.is_some_and(|n| m.written.len() >= n),
+                ovr: false,
+                timeout: false,
+                pecerr: m.pecerr,
+                addr: m.addr || restart,
+                dir_read: m.dir_read || restart,
+                addcode: m.addcode,
+            }
+        }
+
+        fn program(&self, addr: u16, read: bool, chunk: Chunk) {
+            let mut m = self.model.borrow_mut();
+            let tcr = m.active && m.remaining == 0 && m.reload;
+            if chunk.start {
+                assert!(!tcr, "START while waiting for a reload");
+                m.starts.push((addr, read));
+                m.read = read;
+                m.crc.address(addr, read);
+            } else {
+                let accept = m.addr || m.restart();
+                assert!(
+                    tcr || accept,
+                    "NBYTES reloaded without TCR or address match"
+                );
+            }
+            m.chunks.push(chunk);
+            m.active = true;
+            m.remaining = chunk.nbytes as usize;
+            m.reload = chunk.reload;
+            m.autoend = chunk.autoend;
+            m.pec = chunk.pec;
+            m.send_pec();
+            drop(m);
+            if self.model.borrow().dma {
+                self.run_dma();
+            }
+        }
+
+        fn write_data(&self, byte: u8) {
+            assert!(self.status().txis, "TXDR written without TXIS");
+            let mut m = self.model.borrow_mut();
+            if m.slave && m.written.len() >= m.slave_tx_len {
+                // the controller NACKs the previous byte, this one stays in TXDR
+                m.tx_pending = true;
+            } else {
+                m.written.push(byte);
+                m.crc.update(&[byte]);
+            }
+            m.remaining -= 1;
+            m.send_pec();
+        }
+
+        fn read_data(&self) -> u8 {
+            assert!(self.status().rxne, "RXDR read without RXNE");
+            let mut m = self.model.borrow_mut();
+            let byte = m.incoming.pop_front().unwrap();
+            if m.pec && m.remaining == 1 {
+                // a target NACKs a wrong PEC
+                m.pecerr |= byte != m.crc.value();
+            }
+            m.crc.update(&[byte]);
+            m.remaining -= 1;
+            byte
+        }
+
+        fn clear_stop(&self) {
+            let mut m = self.model.borrow_mut();
+            m.stops += 1;
+            m.active = false;
+            m.crc = Pec::new();
+        }
+
+        fn flush_tx(&self) {
+            let mut m = self.model.borrow_mut();
+            m.tx_pending = false;
+            m.tx_flushes += 1;
+        }
+
+        fn clear_addr(&self) {
+            let mut m = self.model.borrow_mut();
+            let read = m.dir_read || m.restart();
+            let addr = m.addcode as u16;
+            m.crc.address(addr, read);
+            if m.restart() {
+                m.read = false;
+                m.slave_tx_len = m.then_read.take().unwrap();
+            }
+            m.addr = false;
+            m.dir_read = false;
+            m.send_pec();
+        }
+
+        fn listen(&self, _cx: &mut Context<'_>, _events: Events) {}
+
+        fn abort(&self) {
+            let mut m = self.model.borrow_mut();
+            m.aborted = true;
+            m.berr_after = None;
+            m.arlo_after = None;
+            m.pecerr = false;
+            m.crc = Pec::new();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::*;
+    use super::*;
+    use futures::executor::block_on;
+    use std::cell::RefCell;
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn test_plan_chunks() {
+        let chunks: Vec<_> = plan_chunks(600, SegmentStart::Start, SegmentEnd::Stop).collect();
+        assert_eq!(
+            chunks,
+            [
+                Chunk {
+                    nbytes: 255,
+                    reload: true,
+                    autoend: false,
+                    start: true,
+                    pec: false
+                },
+                Chunk {
+                    nbytes: 255,
+                    reload: true,
+                    autoend: false,
+                    start: false,
+                    pec: false
+                },
+                Chunk {
+                    nbytes: 90,
+                    reload: false,
+                    autoend: true,
+                    start: false,
+                    pec: false
+                },
+            ]
+        );
+
+        // exactly 255 bytes fit in one chunk, a restart follows
+        let chunks: Vec<_> = plan_chunks(255, SegmentStart::Start, SegmentEnd::Restart).collect();
+        assert_eq!(
+            chunks,
+            [Chunk {
+                nbytes: 255,
+                reload: false,
+                autoend: false,
+                start: true,
+                pec: false
+            }]
+        );
+
+        // an empty segment still addresses the target
+        let chunks: Vec<_> = plan_chunks(0, SegmentStart::Start, SegmentEnd::Stop).collect();
+        assert_eq!(
+            chunks,
+            [Chunk {
+                nbytes: 0,
+                reload: false,
+                autoend: true,
+                start: true,
+                pec: false
+            }]
+        );
+
+        // a continued segment keeps reloading into the next one
+        let chunks: Vec<_> = plan_chunks(256, SegmentStart::Reload, SegmentEnd::Reload).collect();
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks.iter().all(|c| c.reload && !c.start && !c.autoend));
+        assert_eq!(chunks[1].nbytes, 1);
+
+        // the PEC is the last byte of the last chunk
+        let chunks: Vec<_> = plan_chunks(256, SegmentStart::Start, SegmentEnd::StopPec).collect();
+        assert!(!chunks[0].pec && chunks[0].reload);
+        assert!(chunks[1].pec && chunks[1].autoend && chunks[1].nbytes == 1);
+    }
+
+    #[test]
+    fn test_operation_links() {
+        let mut buf = [0u8; 2];
+        let ops = [
+            I2cOperation::Write(&[1]),
+            I2cOperation::Write(&[]),
+            I2cOperation::Write(&[2]),
+            I2cOperation::Read(&mut buf),
+        ];
+        assert_eq!(
+            operation_links(&ops, 0),
+            Some((SegmentStart::Start, SegmentEnd::Reload))
+        );
+        assert_eq!(operation_links(&ops, 1), None);
+        assert_eq!(
+            operation_links(&ops, 2),
+            Some((SegmentStart::Reload, SegmentEnd::Restart))
+        );
+        assert_eq!(
+            operation_links(&ops, 3),
+            Some((SegmentStart::Start, SegmentEnd::Stop))
+        );
+
+        // address probe
+        let ops = [I2cOperation::Write(&[]), I2cOperation::Write(&[])];
+        assert_eq!(
+            operation_links(&ops, 0),
+            Some((SegmentStart::Start, SegmentEnd::Stop))
+        );
+        assert_eq!(operation_links(&ops, 1), None);
+    }
+
+    #[test]
+    fn test_long_write() {
+        let regs = MockRegs::new(&[]);
+        let payload = data(600);
+        let mut ops = [I2cOperation::Write(&payload)];
+        assert!(transaction(&regs, 0x3c, &mut ops).is_ok());
+
+        let m = regs.model.borrow();
+        assert_eq!(m.written, payload);
+        assert_eq!(m.starts, [(0x3c, false)]);
+        assert_eq!(
+            m.chunks.iter().map(|c| c.nbytes).collect::<Vec<_>>(),
+            [255, 255, 90]
+        );
+        assert_eq!(m.stops, 1);
+        assert!(!m.aborted);
+    }
+
+    #[test]
+    fn test_long_write_read() {
+        let expected = data(300);
+        let regs = MockRegs::new(&expected);
+        let mut buf = vec![0u8; 300];
+        let mut ops = [
+            I2cOperation::Write(&[0x00, 0x10]),
+            I2cOperation::Read(&mut buf),
+        ];
+        assert!(transaction(&regs, 0x50, &mut ops).is_ok());
+        assert_eq!(buf, expected);
+
+        let m = regs.model.borrow();
+        assert_eq!(m.written, [0x00, 0x10]);
+        // repeated start between the write and the read
+        assert_eq!(m.starts, [(0x50, false), (0x50, true)]);
+        assert_eq!(
+            m.chunks.iter().map(|c| c.nbytes).collect::<Vec<_>>(),
+            [2, 255, 45]
+        );
+        assert_eq!(m.stops, 1);
+    }
+
+    #[test]
+    fn test_adjacent_writes_are_merged() {
+        let regs = MockRegs::new(&[]);
+        let header = [0x30, 0x00];
+        let payload = data(300);
+        let mut ops = [I2cOperation::Write(&header), I2cOperation::Write(&payload)];
+        assert!(transaction(&regs, 0x3c, &mut ops).is_ok());
+
+        let m = regs.model.borrow();
+        assert_eq!(m.starts.len(), 1);
+        assert_eq!(&m.written[..2], &header);
+        assert_eq!(&m.written[2..], &payload[..]);
+        assert_eq!(
+            m.chunks.iter().map(|c| c.nbytes).collect::<Vec<_>>(),
+            [2, 255, 45]
+        );
+    }
+
+    #[test]
+    fn test_nack_aborts() {
+        let regs = MockRegs::new(&[]);
+        regs.model.borrow_mut().nack_after = Some(260);
+        let payload = data(400);
+        let mut ops = [I2cOperation::Write(&payload)];
+        assert_eq!(transaction(&regs, 0x3c, &mut ops), Err(I2cError::Nack));
+        let m = regs.model.borrow();
+        assert_eq!(m.written.len(), 260);
+        assert!(m.aborted);
+    }
+
+    #[test]
+    fn test_long_transfers_async() {
+        let expected = data(520);
+        let regs = MockRegs::new(&expected);
+        let payload = data(256);
+        let mut buf = vec![0u8; 520];
+        let mut ops = [I2cOperation::Write(&payload), I2cOperation::Read(&mut buf)];
+        assert!(block_on(transaction_async(&regs, 0x50, &mut ops)).is_ok());
+        assert_eq!(buf, expected);
+
+        let m = regs.model.borrow();
+        assert_eq!(m.written, payload);
+        assert_eq!(m.starts, [(0x50, false), (0x50, true)]);
+        assert_eq!(
+            m.chunks.iter().map(|c| c.nbytes).collect::<Vec<_>>(),
+            [255, 1, 255, 255, 10]
+        );
+
+        let regs = MockRegs::new(&[]);
+        regs.model.borrow_mut().nack_after = Some(0);
+        let mut ops = [I2cOperation::Write(&payload)];
+        assert_eq!(
+            block_on(transaction_async(&regs, 0x50, &mut ops)),
+            Err(I2cError::Nack)
+        );
+        assert!(regs.model.borrow().aborted);
+    }
+
+    #[test]
+    fn test_slave_long_read() {
+        let expected = data(600);
+        let regs = MockRegs::slave_receiver(&expected);
+        let mut buf = vec![0u8; 600];
+        assert!(slave_read(&regs, &mut buf).is_ok());
+        assert_eq!(buf, expected);
+        let m = regs.model.borrow();
+        // reloaded at every TCR
+        assert_eq!(m.chunks, [SLAVE_CHUNK, SLAVE_CHUNK]);
+        assert_eq!(m.stops, 1);
+
+        let regs = MockRegs::slave_receiver(&expected);
+        let mut buf = vec![0u8; 600];
+        assert!(block_on(slave_read_async(&regs, &mut buf)).is_ok());
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn test_slave_read_early_stop() {
+        let regs = MockRegs::slave_receiver(&data(10));
+        let mut buf = [0u8; 20];
+        assert_eq!(slave_read(&regs, &mut buf), Err(I2cError::BusError));
+        assert_eq!(&buf[..10], &data(10)[..]);
+
+        let regs = MockRegs::slave_receiver(&data(10));
+        assert_eq!(
+            block_on(slave_read_async(&regs, &mut buf)),
+            Err(I2cError::BusError)
+        );
+    }
+
+    #[test]
+    fn test_slave_long_write() {
+        let payload = data(300);
+        let regs = MockRegs::slave_transmitter(300);
+        assert!(slave_write(&regs, &payload).is_ok());
+        let m = regs.model.borrow();
+        assert_eq!(m.written, payload);
+        assert_eq!(m.chunks, [SLAVE_CHUNK]);
+        assert_eq!(m.stops, 1);
+        drop(m);
+
+        let regs = MockRegs::slave_transmitter(300);
+        assert!(block_on(slave_write_async(&regs, &payload)).is_ok());
+        assert_eq!(regs.model.borrow().written, payload);
+
+        // the controller stops reading early
+        let regs = MockRegs::slave_transmitter(100);
+        assert_eq!(slave_write(&regs, &payload), Err(I2cError::BusError));
+        let regs = MockRegs::slave_transmitter(100);
+        assert_eq!(
+            block_on(slave_write_async(&regs, &payload)),
+            Err(I2cError::BusError)
+        );
+    }
+
+    #[test]
+    fn test_slave_transmit_counts_bytes_read() {
+        // the controller NACKs the third byte, the fourth is already in TXDR
+        let regs = MockRegs::slave_transmitter(3);
+        assert_eq!(
+            slave_transmit(&regs, &[1, 2, 3, 4, 5]),
+            Ok((3, SlaveEnd::Stop))
+        );
+        let m = regs.model.borrow();
+        assert_eq!(m.written, [1, 2, 3]);
+        assert_eq!(m.tx_flushes, 1);
+        assert!(!m.tx_pending);
+        drop(m);
+
+        let regs = MockRegs::slave_transmitter(3);
+        assert_eq!(
+            block_on(slave_transmit_async(&regs, &[1, 2, 3, 4, 5])),
+            Ok((3, SlaveEnd::Stop))
+        );
+        assert_eq!(regs.model.borrow().tx_flushes, 1);
+    }
+
+    #[test]
+    fn test_error_flags_abort() {
+        let payload = data(20);
+
+        let regs = MockRegs::new(&[]);
+        regs.model.borrow_mut().berr_after = Some(3);
+        let mut ops = [I2cOperation::Write(&payload)];
+        assert_eq!(transaction(&regs, 0x3c, &mut ops), Err(I2cError::BusError));
+        let m = regs.model.borrow();
+        assert_eq!(m.written.len(), 3);
+        assert!(m.aborted);
+        drop(m);
+
+        let regs = MockRegs::new(&[]);
+        regs.model.borrow_mut().arlo_after = Some(5);
+        let mut ops = [I2cOperation::Write(&payload)];
+        assert_eq!(
+            block_on(transaction_async(&regs, 0x3c, &mut ops)),
+            Err(I2cError::ArbitrationLoss)
+        );
+        assert!(regs.model.borrow().aborted);
+
+        // arbitration lost while sending data to the controller in target mode
+        let regs = MockRegs::slave_transmitter(20);
+        regs.model.borrow_mut().arlo_after = Some(1);
+        assert_eq!(slave_write(&regs, &payload), Err(I2cError::ArbitrationLoss));
+    }
+
+    /// Bus lines with a target that holds SDA low for `hold` more SCL pulses.
+    #[derive(Default)]
+    struct Bus {
+        scl_out: bool,
+        sda_out: bool,
+        hold: usize,
+        /// the target never releases SDA
+        sda_stuck: bool,
+        /// the target holds SCL low
+        scl_stuck: bool,
+        pulses: usize,
+        /// SDA rose while SCL was high
+        stops: usize,
+    }
+
+    struct MockLines(RefCell<Bus>);
+
+    impl MockLines {
+        fn new(hold: usize) -> Self {
+            Self(RefCell::new(Bus {
+                hold,
+                ..Default::default()
+            }))
+        }
+    }
+
+    impl BusLines for MockLines {
+        fn set_scl(&self, high: bool) {
+            let mut b = self.0.borrow_mut();
+            if b.scl_out && !high {
+                // the target shifts out its next bit on the falling edge
+                b.pulses += 1;
+                b.hold = b.hold.saturating_sub(1);
+            }
+            b.scl_out = high;
+        }
+
+        fn set_sda(&self, high: bool) {
+            let sda = self.sda();
+            let mut b = self.0.borrow_mut();
+            if high && !sda && b.scl_out && !b.scl_stuck && b.hold == 0 && !b.sda_stuck {
+                b.stops += 1;
+            }
+            b.sda_out = high;
+        }
+
+        fn scl(&self) -> bool {
+            let b = self.0.borrow();
+            b.scl_out && !b.scl_stuck
+        }
+
+        fn sda(&self) -> bool {
+            let b = self.0.borrow();
+            b.sda_out && b.hold == 0 && !b.sda_stuck
+        }
+
+        fn delay(&self) {}
+    }
+
+    #[test]
+    fn test_recover_bus() {
+        // idle bus: only a STOP
+        let lines = MockLines::new(0);
+        assert_eq!(recover_bus(&lines), Ok(()));
+        assert_eq!((lines.0.borrow().pulses, lines.0.borrow().stops), (1, 1));
+
+        // the target releases SDA after its remaining bits
+        for hold in 1..=RECOVERY_PULSES {
+            let lines = MockLines::new(hold);
+            assert_eq!(recover_bus(&lines), Ok(()));
+            let b = lines.0.borrow();
+            // `hold` clock pulses and the SCL low phase of the STOP
+            assert_eq!(b.pulses, hold + 1);
+            assert_eq!(b.stops, 1);
+            assert!(b.scl_out && b.sda_out);
+        }
+
+        // SDA shorted to ground
+        let lines = MockLines::new(0);
+        lines.0.borrow_mut().sda_stuck = true;
+        assert_eq!(recover_bus(&lines), Err(I2cError::BusError));
+        assert_eq!(lines.0.borrow().pulses, RECOVERY_PULSES);
+
+        // SCL held low by a target
+        let lines = MockLines::new(3);
+        lines.0.borrow_mut().scl_stuck = true;
+        assert_eq!(recover_bus(&lines), Err(I2cError::BusError));
+        assert_eq!(lines.0.borrow().pulses, 0);
+    }
+
+    #[test]
+    fn test_own_addresses() {
+        let mut own = OwnAddresses::new(0x42);
+        assert!(own.is_valid());
+        assert_eq!(own.oar1(), (0x84, false));
+        assert!(own.matches(0x42) && !own.matches(0x43) && !own.matches(0));
+
+        // 0x30 - 0x37 on own address 2, general call
+        own.oa2 = Some((0x35, 3));
+        own.general_call = true;
+        assert!((0x30..=0x37).all(|addr| own.matches(addr)));
+        assert!(!own.matches(0x2f) && !own.matches(0x38));
+        assert!(own.matches(0));
+        assert_eq!(own.matched(0x33), 0x33);
+        assert_eq!(own.matched(0), 0);
+
+        // a full mask still leaves out the reserved addresses
+        own.oa2 = Some((0x10, 7));
+        assert!(own.matches(0x08) && own.matches(0x77));
+        assert!(!own.matches(0x03) && !own.matches(0x7c));
+        own.oa2 = Some((0x10, 8));
+        assert!(!own.is_valid());
+
+        // a 10-bit match reports the 11110xx header in ADDCODE
+        let own = OwnAddresses {
+            oa1: OwnAddress::TenBit(0x2a5),
+            oa2: Some((0x20, 0)),
+            general_call: false,
+        };
+        assert!(own.is_valid());
+        assert_eq!(own.oar1(), (0x2a5, true));
+        assert_eq!(own.matched(0x7a), 0x2a5);
+        assert_eq!(own.matched(0x20), 0x20);
+        assert!(!OwnAddresses {
+            oa1: OwnAddress::TenBit(0x400),
+            ..own
+        }
+        .is_valid());
+    }
+
+    #[test]
+    fn test_slave_address_match() {
+        let regs = MockRegs::slave_addressed(&[0x10], false, 0);
+        regs.model.borrow_mut().addcode = 0x35;
+        regs.model.borrow_mut().then_read = Some(2);
+        let matched = slave_wait_address(&regs).unwrap();
+        assert_eq!(
+            matched,
+            AddressMatch {
+                read: false,
+                addcode: 0x35
+            }
+        );
+        let mut buf = [0u8; 4];
+        assert_eq!(slave_receive(&regs, &mut buf), Ok((1, SlaveEnd::Restart)));
+        // repeated START, now reading
+        assert!(slave_accept(&regs).read);
+        assert_eq!(slave_transmit(&regs, &[1, 2]), Ok((2, SlaveEnd::Stop)));
+        assert_eq!(regs.model.borrow().written, [1, 2]);
+    }
+
+    #[test]
+    fn test_dma_transfers() {
+        let regs = MockRegs::new(&[]);
+        regs.model.borrow_mut().dma = true;
+        assert!(block_on(dma_transfer_async(&regs, 0x3c, false, 600)).is_ok());
+        let m = regs.model.borrow();
+        assert_eq!(m.written, data(600));
+        assert_eq!(m.starts, [(0x3c, false)]);
+        assert_eq!(
+            m.chunks.iter().map(|c| c.nbytes).collect::<Vec<_>>(),
+            [255, 255, 90]
+        );
+        assert_eq!(m.stops, 1);
+        drop(m);
+
+        let regs = MockRegs::new(&data(300));
+        regs.model.borrow_mut().dma = true;
+        assert!(block_on(dma_transfer_async(&regs, 0x3c, true, 300)).is_ok());
+        let m = regs.model.borrow();
+        assert!(m.incoming.is_empty());
+        assert_eq!(m.starts, [(0x3c, true)]);
+        assert_eq!(m.stops, 1);
+        drop(m);
+
+        let regs = MockRegs::new(&[]);
+        regs.model.borrow_mut().dma = true;
+        regs.model.borrow_mut().nack_after = Some(0);
+        assert_eq!(
+            block_on(dma_transfer_async(&regs, 0x3c, false, 100)),
+            Err(I2cError::Nack)
+        );
+        assert!(regs.model.borrow().aborted);
+    }
+}
@@ -36,6 +36,7 @@ mcu_modules!(
     lptim,
     rtc,
     sd_device,
+    spi,
     tim,
     usart,
     nucleo_u575,
@@ -47,9 +48,20 @@ pub use embassy_executor::{self, Spawner};
 #[cfg(all(target_arch = "arm", target_os = "none"))]
 pub use embassy_executor_macros::task;
 
+pub mod byte_ring;
+pub mod dma_lli;
+pub mod dma_request;
+pub mod dma_ring;
 pub mod drivers;
 pub mod hal;
+pub mod i2c_register_slave;
+pub mod i2c_timing;
+pub mod i2c_transfer;
+pub mod lin;
+pub mod modbus;
 pub mod shared_i2c;
+pub mod smbus;
+pub mod usart_config;
 pub mod utils;
 
 #[cfg(all(target_arch = "arm", target_os = "none", dcmi))]
@@ -0,0 +1,341 @@
+//! # LIN frame format
+//!
+//! A LIN frame is a header sent by the master (break, sync byte `0x55` and the protected
+//! identifier) followed by a response of 1 - 8 data bytes and a checksum, sent by the master or by
+//! the slave publishing the frame.
+//!
+//! - The protected identifier (PID) is the 6-bit frame ID with two parity bits:
+//!   `P0 = ID0 ^ ID1 ^ ID2 ^ ID4` (bit 6), `P1 = !(ID1 ^ ID3 ^ ID4 ^ ID5)` (bit 7).
+//! - The checksum is the inverted 8-bit sum with carry of the data bytes (classic, LIN 1.x), or of
+//!   the PID and the data bytes (enhanced, LIN 2.x). Diagnostic frames (IDs 0x3c, 0x3d) always use
+//!   the classic checksum.
+//!
+//! `LinSlave` holds the frames a slave node publishes or subscribes to. The break detection and
+//! the transfers are in `usart::Usart`.
+
+use crate::hal::UsartError;
+
+/// Sync byte of the header.
+pub const SYNC: u8 = 0x55;
+/// Most data bytes of a frame.
+pub const MAX_DATA: usize = 8;
+/// Master request diagnostic frame.
+pub const MASTER_REQUEST_ID: u8 = 0x3c;
+/// Slave response diagnostic frame.
+pub const SLAVE_RESPONSE_ID: u8 = 0x3d;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LinError {
+    Usart(UsartError),
+    /// frame ID above 0x3f
+    InvalidId,
+    /// the parity bits of a received PID are wrong
+    Parity,
+    /// the byte after the break is not `SYNC`
+    Sync,
+    /// the checksum of a received response is wrong
+    Checksum,
+    /// no data or more than `MAX_DATA` bytes
+    Length,
+}
+
+impl From<UsartError> for LinError {
+    fn from(err: UsartError) -> Self {
+        LinError::Usart(err)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ChecksumModel {
+    /// data bytes only (LIN 1.x)
+    Classic,
+    /// PID and data bytes (LIN 2.x)
+    Enhanced,
+}
+
+/// Protected identifier of frame `id` (0 - 0x3f).
+pub fn pid(id: u8) -> Result<u8, LinError> {
+    if id > 0x3f {
+        return Err(LinError::InvalidId);
+    }
+    let bit = |n: u8| (id >> n) & 1;
+    let p0 = bit(0) ^ bit(1) ^ bit(2) ^ bit(4);
+    let p1 = !(bit(1) ^ bit(3) ^ bit(4) ^ bit(5)) & 1;
+    Ok(id | p0 << 6 | p1 << 7)
+}
+
+/// Frame ID of a received protected identifier, checking the parity bits.
+pub fn id_from_pid(pid_byte: u8) -> Result<u8, LinError> {
+    let id = pid_byte & 0x3f;
+    if pid(id)? != pid_byte {
+        return Err(LinError::Parity);
+    }
+    Ok(id)
+}
+
+/// Checksum of the response `data` of the frame with protected identifier `pid`.
+pub fn checksum(model: ChecksumModel, pid: u8, data: &[u8]) -> u8 {
+    let diagnostic = matches!(pid & 0x3f, MASTER_REQUEST_ID | SLAVE_RESPONSE_ID);
+    let init = if model == ChecksumModel::Enhanced && !diagnostic {
+        pid as u16
+    } else {
+        0
+    };
+    let sum = data.iter().fold(init, |sum, &b| {
+        let sum = sum + b as u16;
+        // add the carry back in
+        (sum & 0xff) + (sum >> 8)
+    });
+    !(sum as u8)
+}
+
+/// Write the response `data` and its checksum to `out`. Returns the response length.
+pub fn encode_response(
+    model: ChecksumModel,
+    pid: u8,
+    data: &[u8],
+    out: &mut [u8],
+) -> Result<usize, LinError> {
+    let len = data.len() + 1;
+    if data.is_empty() || data.len() > MAX_DATA || out.len() < len {
+        return Err(LinError::Length);
+    }
+    out[..data.len()].copy_from_slice(data);
+    out[data.len()] = checksum(model, pid, data);
+    Ok(len)
+}
+
+/// Check the checksum of a received `response` (data and checksum). Returns the data.
+pub fn decode_response(model: ChecksumModel, pid: u8, response: &[u8]) -> Result<&[u8], LinError> {
+    if response.len() < 2 || response.len() > MAX_DATA + 1 {
+        return Err(LinError::Length);
+    }
+    let (data, sum) = response.split_at(response.len() - 1);
+    if checksum(model, pid, data) != sum[0] {
+        return Err(LinError::Checksum);
+    }
+    Ok(data)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum LinDirection {
+    /// the slave sends the response
+    Publish,
+    /// the slave receives the response
+    Subscribe,
+}
+
+/// A frame scheduled by the master that a slave takes part in.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LinFrame {
+    pub id: u8,
+    pub direction: LinDirection,
+    pub model: ChecksumModel,
+    len: u8,
+    data: [u8; MAX_DATA],
+    /// a subscribed response was received since the last `take_updated`
+    updated: bool,
+}
+
+impl LinFrame {
+    /// Frame `id` with `len` data bytes, initially zero.
+    pub const fn new(id: u8, direction: LinDirection, model: ChecksumModel, len: u8) -> Self {
+        assert!(id <= 0x3f && len >= 1 && len as usize <= MAX_DATA);
+        Self {
+            id,
+            direction,
+            model,
+            len,
+            data: [0; MAX_DATA],
+            updated: false,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data[..self.len as usize]
+    }
+
+    /// Set the data to publish, `data` must have the frame length.
+    pub fn set_data(&mut self, data: &[u8]) -> Result<(), LinError> {
+        if data.len() != self.len as usize {
+            return Err(LinError::Length);
+        }
+        self.data[..data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    /// Whether a subscribed response was received since the last call.
+    pub fn take_updated(&mut self) -> bool {
+        core::mem::replace(&mut self.updated, false)
+    }
+}
+
+/// Frame table of a slave node.
+pub struct LinSlave<'a> {
+    frames: &'a mut [LinFrame],
+}
+
+impl<'a> LinSlave<'a> {
+    pub fn new(frames: &'a mut [LinFrame]) -> Self {
+        Self { frames }
+    }
+
+    pub fn frame(&mut self, id: u8) -> Option<&mut LinFrame> {
+        self.frames.iter_mut().find(|f| f.id == id)
+    }
+
+    /// The frame of a received header, `None` if the slave does not take part in it.
+    pub fn on_header(&mut self, pid_byte: u8) -> Result<Option<&mut LinFrame>, LinError> {
+        let id = id_from_pid(pid_byte)?;
+        Ok(self.frame(id))
+    }
+
+    /// The response to send for a published `frame`. Returns its length.
+    pub fn response(frame: &LinFrame, out: &mut [u8]) -> Result<usize, LinError> {
+        encode_response(frame.model, pid(frame.id)?, frame.data(), out)
+    }
+
+    /// Store a received response (data and checksum) of a subscribed `frame`.
+    pub fn on_response(frame: &mut LinFrame, response: &[u8]) -> Result<(), LinError> {
+        let data = decode_response(frame.model, pid(frame.id)?, response)?;
+        frame.set_data(data)?;
+        frame.updated = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pid() {
+        assert_eq!(pid(0x00), Ok(0x80));
+        assert_eq!(pid(0x01), Ok(0xc1));
+        assert_eq!(pid(0x10), Ok(0x50));
+        assert_eq!(pid(0x23), Ok(0xa3));
+        assert_eq!(pid(0x3c), Ok(0x3c));
+        assert_eq!(pid(0x3d), Ok(0x7d));
+        assert_eq!(pid(0x3f), Ok(0xbf));
+        assert_eq!(pid(0x40), Err(LinError::InvalidId));
+    }
+
+    #[test]
+    fn test_id_from_pid() {
+        for id in 0..=0x3f {
+            let p = pid(id).unwrap();
+            assert_eq!(id_from_pid(p), Ok(id));
+            // any single flipped bit is detected
+            for bit in 0..8 {
+                assert_eq!(id_from_pid(p ^ 1 << bit), Err(LinError::Parity));
+            }
+        }
+    }
+
+    #[test]
+    fn test_checksum() {
+        // classic: 0x4a + 0x55 + 0x93 + 0xe5 = 0x217, 0x17 + 0x02 = 0x19, inverted 0xe6
+        let data = [0x4a, 0x55, 0x93, 0xe5];
+        assert_eq!(checksum(ChecksumModel::Classic, 0xa3, &data), 0xe6);
+        // enhanced adds the PID: 0x19 + 0xa3 = 0xbc, inverted 0x43
+        assert_eq!(checksum(ChecksumModel::Enhanced, 0xa3, &data), 0x43);
+        // carry on every byte
+        assert_eq!(checksum(ChecksumModel::Classic, 0, &[0xff, 0xff]), 0x00);
+        assert_eq!(checksum(ChecksumModel::Classic, 0, &[0x01]), 0xfe);
+        // diagnostic frames always use the classic checksum
+        let request = [0x7f, 0x06, 0xb2, 0x00, 0xff, 0x7f, 0xff, 0xff];
+        assert_eq!(
+            checksum(ChecksumModel::Enhanced, 0x3c, &request),
+            checksum(ChecksumModel::Classic, 0x3c, &request)
+        );
+    }
+
+    #[test]
+    fn test_response() {
+        let mut out = [0; MAX_DATA + 1];
+        let len = encode_response(
+            ChecksumModel::Enhanced,
+            0xa3,
+            &[0x4a, 0x55, 0x93, 0xe5],
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(out[..len], [0x4a, 0x55, 0x93, 0xe5, 0x43]);
+        assert_eq!(
+            decode_response(ChecksumModel::Enhanced, 0xa3, &out[..len]),
+            Ok(&out[..4])
+        );
+        assert_eq!(
+            decode_response(ChecksumModel::Classic, 0xa3, &out[..len]),
+            Err(LinError::Checksum)
+        );
+        assert_eq!(
+            decode_response(ChecksumModel::Classic, 0xa3, &out[..1]),
+            Err(LinError::Length)
+        );
+        assert_eq!(
+            encode_response(ChecksumModel::Classic, 0, &[], &mut out),
+            Err(LinError::Length)
+        );
+        assert_eq!(
+            encode_response(ChecksumModel::Classic, 0, &[0; 9], &mut out),
+            Err(LinError::Length)
+        );
+    }
+
+    #[test]
+    fn test_slave() {
+        let mut frames = [
+            LinFrame::new(0x10, LinDirection::Publish, ChecksumModel::Enhanced, 2),
+            LinFrame::new(0x23, LinDirection::Subscribe, ChecksumModel::Enhanced, 4),
+        ];
+        let mut slave = LinSlave::new(&mut frames);
+        slave.frame(0x10).unwrap().set_data(&[0x12, 0x34]).unwrap();
+        assert_eq!(
+            slave.frame(0x10).unwrap().set_data(&[1]),
+            Err(LinError::Length)
+        );
+
+        // publish
+        let frame = slave.on_header(0x50).unwrap().unwrap();
+        assert_eq!(frame.direction, LinDirection::Publish);
+        let mut out = [0; MAX_DATA + 1];
+        let len = LinSlave::response(frame, &mut out).unwrap();
+        assert_eq!(
+            out[..len],
+            [
+                0x12,
+                0x34,
+                checksum(ChecksumModel::Enhanced, 0x50, &[0x12, 0x34])
+            ]
+        );
+
+        // subscribe
+        let frame = slave.on_header(0xa3).unwrap().unwrap();
+        assert_eq!(
+            LinSlave::on_response(frame, &[0x4a, 0x55, 0x93, 0xe5, 0xe6]),
+            Err(LinError::Checksum)
+        );
+        assert!(!frame.take_updated());
+        LinSlave::on_response(frame, &[0x4a, 0x55, 0x93, 0xe5, 0x43]).unwrap();
+        assert!(frame.take_updated());
+        assert!(!frame.take_updated());
+        assert_eq!(slave.frame(0x23).unwrap().data(), [0x4a, 0x55, 0x93, 0xe5]);
+
+        // other frames and corrupted identifiers
+        assert!(slave.on_header(pid(0x11).unwrap()).unwrap().is_none());
+        assert_eq!(slave.on_header(0x10).err(), Some(LinError::Parity));
+    }
+}
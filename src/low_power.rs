@@ -58,16 +58,17 @@ static mut _REF_COUNT_STOP3: u32 = 0;
 static mut _REF_COUNT_STANDBY: u32 = 0;
 
 /// NO DEEP SLEEP if this function is called, the mcu will not go deep sleep
+/// (request and release may also be called from interrupt handlers)
 pub fn no_deep_sleep_request() {
-    unsafe {
+    cortex_m::interrupt::free(|_| unsafe {
         REF_COUNT_DEEP += 1;
-    }
+    });
 }
 
 pub fn no_deep_sleep_release() {
-    unsafe {
+    cortex_m::interrupt::free(|_| unsafe {
         REF_COUNT_DEEP -= 1;
-    }
+    });
 }
 
 pub fn run_no_deep_sleep<F>(code: F)
@@ -81,17 +82,23 @@ where
     }
 }
 
+/// Releases the request when dropped, also when the future holding it is cancelled.
+struct NoDeepSleep;
+
+impl Drop for NoDeepSleep {
+    fn drop(&mut self) {
+        no_deep_sleep_release();
+    }
+}
+
 pub async fn run_no_deep_sleep_async<F, R, T>(code: F) -> T
 where
     F: FnOnce() -> R,
     R: core::future::Future<Output = T>,
 {
-    unsafe {
-        REF_COUNT_DEEP += 1;
-        let result = code().await;
-        REF_COUNT_DEEP -= 1;
-        result
-    }
+    no_deep_sleep_request();
+    let _request = NoDeepSleep;
+    code().await
 }
 
 impl Executor {
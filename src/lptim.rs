@@ -243,6 +243,25 @@ impl Drop for Lptim {
     }
 }
 
+#[cfg(feature = "embedded-hal")]
+impl embedded_hal_async::delay::DelayNs for Lptim {
+    async fn delay_ns(&mut self, ns: u32) {
+        self.after(Duration::from_nanos(ns as u64)).await;
+    }
+    async fn delay_us(&mut self, us: u32) {
+        self.after(Duration::from_micros(us as u64)).await;
+    }
+    async fn delay_ms(&mut self, ms: u32) {
+        self.after(Duration::from_millis(ms as u64)).await;
+    }
+}
+
+impl crate::modbus::ResponseTimer for Lptim {
+    async fn delay_ms(&self, ms: u32) {
+        self.after(Duration::from_millis(ms as u64)).await;
+    }
+}
+
 #[interrupt]
 fn LPTIM1() {
     Lptim::on_interrupt(1);
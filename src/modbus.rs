@@ -0,0 +1,1180 @@
+//! # Modbus RTU
+//!
+//! RTU framing (`unit | PDU | CRC-16`), a slave serving a coil/register table and an async
+//! master with retries.
+//!
+//! The serial line is reached through [`RtuPort`], which reads and writes whole frames. RTU
+//! delimits frames by 3.5 character times of silence; `usart::Usart` implements it with the
+//! receiver timeout (`set_receiver_timeout` with [`rtu_timeout_bits`]). [`UsartPort`] runs on any
+//! `hal::Usart` instead and finds the end of a frame from its header, which only works for the
+//! function codes implemented here.
+//!
+//! Neither port ends a read when no frame arrives. A master made with
+//! [`ModbusMaster::with_response_timeout`] waits for each response on a [`ResponseTimer`]
+//! (`lptim::Lptim` is one) and ends the attempt with `UsartError::Timeout`, which it retries like
+//! a corrupted response.
+
+use crate::hal::{Pin, Usart, UsartError};
+use core::future::Future;
+use core::marker::PhantomData;
+use core::pin::pin;
+use futures::future::{select, Either};
+
+/// Longest RTU frame: unit, 253 bytes PDU and CRC.
+pub const MAX_ADU: usize = 256;
+/// Unit address the master uses to address all slaves, they do not answer.
+pub const BROADCAST: u8 = 0;
+
+pub const READ_COILS: u8 = 0x01;
+pub const READ_DISCRETE_INPUTS: u8 = 0x02;
+pub const READ_HOLDING_REGISTERS: u8 = 0x03;
+pub const READ_INPUT_REGISTERS: u8 = 0x04;
+pub const WRITE_SINGLE_COIL: u8 = 0x05;
+pub const WRITE_SINGLE_REGISTER: u8 = 0x06;
+pub const WRITE_MULTIPLE_COILS: u8 = 0x0f;
+pub const WRITE_MULTIPLE_REGISTERS: u8 = 0x10;
+
+/// Most coils of a read request.
+const MAX_READ_BITS: u16 = 2000;
+/// Most registers of a read request.
+const MAX_READ_REGISTERS: u16 = 125;
+const MAX_WRITE_BITS: u16 = 1968;
+const MAX_WRITE_REGISTERS: u16 = 123;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ExceptionCode {
+    IllegalFunction,
+    IllegalDataAddress,
+    IllegalDataValue,
+    SlaveDeviceFailure,
+    /// a code not defined above
+    Other(u8),
+}
+
+impl ExceptionCode {
+    pub fn code(&self) -> u8 {
+        match self {
+            ExceptionCode::IllegalFunction => 0x01,
+            ExceptionCode::IllegalDataAddress => 0x02,
+            ExceptionCode::IllegalDataValue => 0x03,
+            ExceptionCode::SlaveDeviceFailure => 0x04,
+            ExceptionCode::Other(code) => *code,
+        }
+    }
+
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            0x01 => ExceptionCode::IllegalFunction,
+            0x02 => ExceptionCode::IllegalDataAddress,
+            0x03 => ExceptionCode::IllegalDataValue,
+            0x04 => ExceptionCode::SlaveDeviceFailure,
+            code => ExceptionCode::Other(code),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ModbusError {
+    Usart(UsartError),
+    /// the CRC of a received frame does not match
+    Crc,
+    /// a frame shorter than unit, function and CRC, or longer than `MAX_ADU`
+    FrameLength,
+    /// the response does not belong to the request (unit, function, echoed fields, length)
+    InvalidResponse,
+    /// the request can not be encoded (quantity, buffer size)
+    InvalidRequest,
+    /// the slave answered with an exception
+    Exception(ExceptionCode),
+}
+
+impl From<UsartError> for ModbusError {
+    fn from(err: UsartError) -> Self {
+        ModbusError::Usart(err)
+    }
+}
+
+/// CRC-16/MODBUS (reflected polynomial 0xa001, initial value 0xffff). Sent low byte first.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xffffu16;
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xa001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+/// Receiver timeout in bit times that marks the end of a frame: 3.5 characters of 11 bits, and a
+/// fixed 1750 us above 19200 baud as the specification recommends.
+pub fn rtu_timeout_bits(baudrate: u32) -> u32 {
+    if baudrate <= 19_200 {
+        39
+    } else {
+        (1_750 * baudrate as u64).div_ceil(1_000_000) as u32
+    }
+}
+
+/// Build the frame for `unit` and `pdu` in `buf`. Returns the frame length.
+pub fn encode_frame(buf: &mut [u8], unit: u8, pdu: &[u8]) -> Result<usize, ModbusError> {
+    let len = pdu.len() + 3;
+    if pdu.is_empty() || len > MAX_ADU || len > buf.len() {
+        return Err(ModbusError::FrameLength);
+    }
+    buf[0] = unit;
+    buf[1..len - 2].copy_from_slice(pdu);
+    let crc = crc16(&buf[..len - 2]);
+    buf[len - 2..len].copy_from_slice(&crc.to_le_bytes());
+    Ok(len)
+}
+
+/// Check the length and CRC of a received frame. Returns the unit and the PDU.
+pub fn decode_frame(frame: &[u8]) -> Result<(u8, &[u8]), ModbusError> {
+    if frame.len() < 4 || frame.len() > MAX_ADU {
+        return Err(ModbusError::FrameLength);
+    }
+    let (data, crc) = frame.split_at(frame.len() - 2);
+    if crc16(data).to_le_bytes() != crc {
+        return Err(ModbusError::Crc);
+    }
+    Ok((data[0], &data[1..]))
+}
+
+/// Direction of a frame, the PDU layout of a function code differs between request and response.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FrameKind {
+    Request,
+    Response,
+}
+
+/// Number of leading bytes needed by `frame_len`.
+pub const FRAME_HEADER_LEN: usize = 7;
+
+/// Length of a whole frame from its first bytes (up to `FRAME_HEADER_LEN`). `Ok(None)` if more
+/// bytes are needed, an error for a function code whose layout is unknown.
+pub fn frame_len(header: &[u8], kind: FrameKind) -> Result<Option<usize>, ModbusError> {
+    if header.len() < 2 {
+        return Ok(None);
+    }
+    // unit, function, the bytes the function adds and the CRC
+    let fixed = |n: usize| Ok(Some(2 + n + 2));
+    let counted = |at: usize| match header.get(at) {
+        Some(&count) => Ok(Some(at + 1 + count as usize + 2)),
+        None => Ok(None),
+    };
+    let function = header[1];
+    match (kind, function) {
+        (FrameKind::Response, f) if f & 0x80 != 0 => fixed(1),
+        (FrameKind::Request, READ_COILS..=WRITE_SINGLE_REGISTER) => fixed(4),
+        (FrameKind::Request, WRITE_MULTIPLE_COILS | WRITE_MULTIPLE_REGISTERS) => counted(6),
+        (FrameKind::Response, READ_COILS..=READ_INPUT_REGISTERS) => counted(2),
+        (
+            FrameKind::Response,
+            WRITE_SINGLE_COIL
+            | WRITE_SINGLE_REGISTER
+            | WRITE_MULTIPLE_COILS
+            | WRITE_MULTIPLE_REGISTERS,
+        ) => fixed(4),
+        _ => Err(ModbusError::InvalidRequest),
+    }
+}
+
+/// Frame level access to the serial line.
+pub trait RtuPort {
+    fn write_frame(&self, frame: &[u8]) -> impl Future<Output = Result<(), UsartError>>;
+    /// Receive one frame into `buf` and return its length.
+    fn read_frame(
+        &self,
+        buf: &mut [u8],
+        kind: FrameKind,
+    ) -> impl Future<Output = Result<usize, UsartError>>;
+}
+
+/// `RtuPort` on any `hal::Usart`, the frame length is taken from the frame header.
+pub struct UsartPort<'a, U, P> {
+    usart: &'a U,
+    _pin: PhantomData<P>,
+}
+
+impl<'a, U: Usart<P>, P: Pin> UsartPort<'a, U, P> {
+    pub fn new(usart: &'a U) -> Self {
+        Self {
+            usart,
+            _pin: PhantomData,
+        }
+    }
+}
+
+impl<U: Usart<P>, P: Pin> RtuPort for UsartPort<'_, U, P> {
+    async fn write_frame(&self, frame: &[u8]) -> Result<(), UsartError> {
+        self.usart.write_async(frame).await
+    }
+
+    async fn read_frame(&self, buf: &mut [u8], kind: FrameKind) -> Result<usize, UsartError> {
+        let mut n = 0;
+        loop {
+            let len = match frame_len(&buf[..n], kind) {
+                Ok(Some(len)) => len,
+                // read the header up to the byte count
+                Ok(None) => (n + 1).max(2),
+                Err(_) => return Err(UsartError::BusError),
+            };
+            if len > buf.len() || len > MAX_ADU {
+                return Err(UsartError::BufferTooSmall);
+            }
+            if n == len {
+                return Ok(n);
+            }
+            self.usart.read_async(&mut buf[n..len]).await?;
+            n = len;
+        }
+    }
+}
+
+/// Data model of a slave. Coils and holding registers are written by the master.
+pub struct SlaveTable<'a> {
+    pub coils: &'a mut [bool],
+    pub discrete_inputs: &'a [bool],
+    pub holding_registers: &'a mut [u16],
+    pub input_registers: &'a [u16],
+}
+
+/// Slave (server) answering requests for `unit` from a [`SlaveTable`].
+pub struct ModbusSlave<'a> {
+    unit: u8,
+    table: SlaveTable<'a>,
+}
+
+fn be16(data: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([data[at], data[at + 1]])
+}
+
+/// The range `addr..addr + quantity` of a table with `len` entries.
+fn table_range(
+    addr: u16,
+    quantity: u16,
+    len: usize,
+) -> Result<core::ops::Range<usize>, ExceptionCode> {
+    let start = addr as usize;
+    let end = start + quantity as usize;
+    if end > len {
+        return Err(ExceptionCode::IllegalDataAddress);
+    }
+    Ok(start..end)
+}
+
+/// Pack `bits` LSB first, as in read coils responses and write multiple coils requests.
+fn pack_bits(bits: &[bool], out: &mut [u8]) {
+    out.fill(0);
+    for (i, &bit) in bits.iter().enumerate() {
+        out[i / 8] |= (bit as u8) << (i % 8);
+    }
+}
+
+fn unpack_bits(data: &[u8], bits: &mut [bool]) {
+    for (i, bit) in bits.iter_mut().enumerate() {
+        *bit = data[i / 8] & (1 << (i % 8)) != 0;
+    }
+}
+
+impl<'a> ModbusSlave<'a> {
+    pub fn new(unit: u8, table: SlaveTable<'a>) -> Self {
+        Self { unit, table }
+    }
+
+    pub fn unit(&self) -> u8 {
+        self.unit
+    }
+
+    pub fn table(&mut self) -> &mut SlaveTable<'a> {
+        &mut self.table
+    }
+
+    /// Execute the request `pdu` and write the response PDU to `response` (at least 253 bytes).
+    /// Returns the response length; failed requests are answered with an exception response.
+    pub fn process(&mut self, pdu: &[u8], response: &mut [u8]) -> usize {
+        let function = pdu.first().copied().unwrap_or(0);
+        match self.execute(pdu, response) {
+            Ok(len) => len,
+            Err(exception) => {
+                response[0] = function | 0x80;
+                response[1] = exception.code();
+                2
+            }
+        }
+    }
+
+    fn execute(&mut self, pdu: &[u8], response: &mut [u8]) -> Result<usize, ExceptionCode> {
+        let &function = pdu.first().ok_or(ExceptionCode::IllegalFunction)?;
+        response[0] = function;
+        match function {
+            READ_COILS | READ_DISCRETE_INPUTS | READ_HOLDING_REGISTERS | READ_INPUT_REGISTERS => {
+                if pdu.len() != 5 {
+                    return Err(ExceptionCode::IllegalDataValue);
+                }
+                let (addr, quantity) = (be16(pdu, 1), be16(pdu, 3));
+                let bits = matches!(function, READ_COILS | READ_DISCRETE_INPUTS);
+                let max = if bits {
+                    MAX_READ_BITS
+                } else {
+                    MAX_READ_REGISTERS
+                };
+                if quantity == 0 || quantity > max {
+                    return Err(ExceptionCode::IllegalDataValue);
+                }
+                let count = if bits {
+                    let table: &[bool] = if function == READ_COILS {
+                        self.table.coils
+                    } else {
+                        self.table.discrete_inputs
+                    };
+                    let range = table_range(addr, quantity, table.len())?;
+                    let count = (quantity as usize).div_ceil(8);
+                    pack_bits(&table[range], &mut response[2..2 + count]);
+                    count
+                } else {
+                    let table: &[u16] = if function == READ_HOLDING_REGISTERS {
+                        self.table.holding_registers
+                    } else {
+                        self.table.input_registers
+                    };
+                    let range = table_range(addr, quantity, table.len())?;
+                    for (i, value) in table[range].iter().enumerate() {
+                        response[2 + 2 * i..4 + 2 * i].copy_from_slice(&value.to_be_bytes());
+                    }
+                    2 * quantity as usize
+                };
+                response[1] = count as u8;
+                Ok(2 + count)
+            }
+            WRITE_SINGLE_COIL | WRITE_SINGLE_REGISTER => {
+                if pdu.len() != 5 {
+                    return Err(ExceptionCode::IllegalDataValue);
+                }
+                let (addr, value) = (be16(pdu, 1), be16(pdu, 3));
+                if function == WRITE_SINGLE_COIL {
+                    let on = match value {
+                        0xff00 => true,
+                        0x0000 => false,
+                        _ => return Err(ExceptionCode::IllegalDataValue),
+                    };
+                    let range = table_range(addr, 1, self.table.coils.len())?;
+                    self.table.coils[range.start] = on;
+                } else {
+                    let range = table_range(addr, 1, self.table.holding_registers.len())?;
+                    self.table.holding_registers[range.start] = value;
+                }
+                // echo of the request
+                response[..5].copy_from_slice(pdu);
+                Ok(5)
+            }
+            WRITE_MULTIPLE_COILS | WRITE_MULTIPLE_REGISTERS => {
+                if pdu.len() < 6 {
+                    return Err(ExceptionCode::IllegalDataValue);
+                }
+                let (addr, quantity, count) = (be16(pdu, 1), be16(pdu, 3), pdu[5] as usize);
+                let (max, expected) = if function == WRITE_MULTIPLE_COILS {
+                    (MAX_WRITE_BITS, (quantity as usize).div_ceil(8))
+                } else {
+                    (MAX_WRITE_REGISTERS, 2 * quantity as usize)
+                };
+                if quantity == 0 || quantity > max || count != expected || pdu.len() != 6 + count {
+                    return Err(ExceptionCode::IllegalDataValue);
+                }
+                let data = &pdu[6..];
+                if function == WRITE_MULTIPLE_COILS {
+                    let range = table_range(addr, quantity, self.table.coils.len())?;
+                    unpack_bits(data, &mut self.table.coils[range]);
+                } else {
+                    let range = table_range(addr, quantity, self.table.holding_registers.len())?;
+                    for (i, value) in self.table.holding_registers[range].iter_mut().enumerate() {
+                        *value = be16(data, 2 * i);
+                    }
+                }
+                response[..5].copy_from_slice(&pdu[..5]);
+                Ok(5)
+            }
+            _ => Err(ExceptionCode::IllegalFunction),
+        }
+    }
+
+    /// Handle a received frame and build the response frame in `response`. Returns `None` if the
+    /// frame is corrupted, for another unit or a broadcast (which is executed but not answered).
+    pub fn handle_frame(&mut self, frame: &[u8], response: &mut [u8; MAX_ADU]) -> Option<usize> {
+        let (unit, pdu) = decode_frame(frame).ok()?;
+        if unit != self.unit && unit != BROADCAST {
+            return None;
+        }
+        let mut pdu_response = [0u8; MAX_ADU - 3];
+        let len = self.process(pdu, &mut pdu_response);
+        if unit == BROADCAST {
+            return None;
+        }
+        encode_frame(response, self.unit, &pdu_response[..len]).ok()
+    }
+
+    /// Receive one request from `port` and answer it. Frames for other units, broadcasts and
+    /// corrupted frames are dropped without an answer.
+    pub async fn serve_async<T: RtuPort>(&mut self, port: &T) -> Result<(), ModbusError> {
+        let mut request = [0u8; MAX_ADU];
+        let len = port.read_frame(&mut request, FrameKind::Request).await?;
+        let mut response = [0u8; MAX_ADU];
+        if let Some(len) = self.handle_frame(&request[..len], &mut response) {
+            port.write_frame(&response[..len]).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Clock for the response timeout of a [`ModbusMaster`].
+pub trait ResponseTimer {
+    /// Complete after `ms` milliseconds.
+    fn delay_ms(&self, ms: u32) -> impl Future<Output = ()>;
+}
+
+/// No response timeout, for ports whose reads end by themselves.
+pub struct NoTimeout;
+
+impl ResponseTimer for NoTimeout {
+    fn delay_ms(&self, _ms: u32) -> impl Future<Output = ()> {
+        core::future::pending()
+    }
+}
+
+/// Master (client) sending requests over an [`RtuPort`].
+pub struct ModbusMaster<'a, T, D = NoTimeout> {
+    port: &'a T,
+    /// attempts after the first one when the response is missing or corrupted
+    retries: u8,
+    timer: D,
+    /// how long an attempt waits for its response
+    timeout_ms: u32,
+}
+
+impl<'a, T: RtuPort> ModbusMaster<'a, T> {
+    /// A master that waits for each response as long as the port does.
+    pub fn new(port: &'a T, retries: u8) -> Self {
+        Self::with_response_timeout(port, retries, NoTimeout, 0)
+    }
+}
+
+impl<'a, T: RtuPort, D: ResponseTimer> ModbusMaster<'a, T, D> {
+    /// A master that gives up on a response after `timeout_ms`, measured by `timer`.
+    pub fn with_response_timeout(port: &'a T, retries: u8, timer: D, timeout_ms: u32) -> Self {
+        Self {
+            port,
+            retries,
+            timer,
+            timeout_ms,
+        }
+    }
+
+    /// Send the request `pdu` to `unit` and receive the response PDU into `response`. Returns its
+    /// length, 0 for a broadcast. Missing, corrupted or mismatched responses are retried,
+    /// exceptions are returned as they are.
+    pub async fn request(
+        &self,
+        unit: u8,
+        pdu: &[u8],
+        response: &mut [u8],
+    ) -> Result<usize, ModbusError> {
+        let mut frame = [0u8; MAX_ADU];
+        let len = encode_frame(&mut frame, unit, pdu)?;
+        let mut attempt = 0;
+        loop {
+            let res = self.attempt(unit, pdu[0], &frame[..len], response).await;
+            match res {
+                Err(ModbusError::Exception(_)) | Err(ModbusError::InvalidRequest) | Ok(_) => {
+                    return res
+                }
+                Err(_) if attempt < self.retries => attempt += 1,
+                Err(_) => return res,
+            }
+        }
+    }
+
+    async fn attempt(
+        &self,
+        unit: u8,
+        function: u8,
+        frame: &[u8],
+        response: &mut [u8],
+    ) -> Result<usize, ModbusError> {
+        self.port.write_frame(frame).await?;
+        if unit == BROADCAST {
+            return Ok(0);
+        }
+        let mut buf = [0u8; MAX_ADU];
+        let read = self.port.read_frame(&mut buf, FrameKind::Response);
+        let timeout = self.timer.delay_ms(self.timeout_ms);
+        let len = match select(pin!(read), pin!(timeout)).await {
+            Either::Left((res, _)) => res?,
+            Either::Right(_) => return Err(ModbusError::Usart(UsartError::Timeout)),
+        };
+        let (from, pdu) = decode_frame(&buf[..len])?;
+        if from != unit || pdu[0] & 0x7f != function {
+            return Err(ModbusError::InvalidResponse);
+        }
+        if pdu[0] & 0x80 != 0 {
+            return match pdu.get(1) {
+                Some(&code) if pdu.len() == 2 => {
+                    Err(ModbusError::Exception(ExceptionCode::from_code(code)))
+                }
+                _ => Err(ModbusError::InvalidResponse),
+            };
+        }
+        if pdu.len() > response.len() {
+            return Err(ModbusError::InvalidResponse);
+        }
+        response[..pdu.len()].copy_from_slice(pdu);
+        Ok(pdu.len())
+    }
+
+    /// Request for a read function; checks the byte count and returns the data bytes.
+    async fn read(
+        &self,
+        unit: u8,
+        function: u8,
+        addr: u16,
+        quantity: u16,
+        data_len: usize,
+        response: &mut [u8; MAX_ADU],
+    ) -> Result<(), ModbusError> {
+        let [a0, a1] = addr.to_be_bytes();
+        let [q0, q1] = quantity.to_be_bytes();
+        let len = self
+            .request(unit, &[function, a0, a1, q0, q1], response)
+            .await?;
+        if len != 2 + data_len || response[1] as usize != data_len {
+            return Err(ModbusError::InvalidResponse);
+        }
+        Ok(())
+    }
+
+    async fn read_bits(
+        &self,
+        unit: u8,
+        function: u8,
+        addr: u16,
+        out: &mut [bool],
+    ) -> Result<(), ModbusError> {
+        if out.is_empty() || out.len() > MAX_READ_BITS as usize {
+            return Err(ModbusError::InvalidRequest);
+        }
+        let mut response = [0u8; MAX_ADU];
+        let count = out.len().div_ceil(8);
+        self.read(unit, function, addr, out.len() as u16, count, &mut response)
+            .await?;
+        unpack_bits(&response[2..], out);
+        Ok(())
+    }
+
+    async fn read_registers(
+        &self,
+        unit: u8,
+        function: u8,
+        addr: u16,
+        out: &mut [u16],
+    ) -> Result<(), ModbusError> {
+        if out.is_empty() || out.len() > MAX_READ_REGISTERS as usize {
+            return Err(ModbusError::InvalidRequest);
+        }
+        let mut response = [0u8; MAX_ADU];
+        self.read(
+            unit,
+            function,
+            addr,
+            out.len() as u16,
+            2 * out.len(),
+            &mut response,
+        )
+        .await?;
+        for (i, value) in out.iter_mut().enumerate() {
+            *value = be16(&response, 2 + 2 * i);
+        }
+        Ok(())
+    }
+
+    /// Request for a write function; the response echoes the first 4 bytes after the function.
+    async fn write(&self, unit: u8, pdu: &[u8]) -> Result<(), ModbusError> {
+        let mut response = [0u8; MAX_ADU];
+        let len = self.request(unit, pdu, &mut response).await?;
+        if unit != BROADCAST && (len != 5 || response[..5] != pdu[..5]) {
+            return Err(ModbusError::InvalidResponse);
+        }
+        Ok(())
+    }
+
+    pub async fn read_coils(
+        &self,
+        unit: u8,
+        addr: u16,
+        out: &mut [bool],
+    ) -> Result<(), ModbusError> {
+        self.read_bits(unit, READ_COILS, addr, out).await
+    }
+
+    pub async fn read_discrete_inputs(
+        &self,
+        unit: u8,
+        addr: u16,
+        out: &mut [bool],
+    ) -> Result<(), ModbusError> {
+        self.read_bits(unit, READ_DISCRETE_INPUTS, addr, out).await
+    }
+
+    pub async fn read_holding_registers(
+        &self,
+        unit: u8,
+        addr: u16,
+        out: &mut [u16],
+    ) -> Result<(), ModbusError> {
+        self.read_registers(unit, READ_HOLDING_REGISTERS, addr, out)
+            .await
+    }
+
+    pub async fn read_input_registers(
+        &self,
+        unit: u8,
+        addr: u16,
+        out: &mut [u16],
+    ) -> Result<(), ModbusError> {
+        self.read_registers(unit, READ_INPUT_REGISTERS, addr, out)
+            .await
+    }
+
+    pub async fn write_single_coil(
+        &self,
+        unit: u8,
+        addr: u16,
+        on: bool,
+    ) -> Result<(), ModbusError> {
+        let [a0, a1] = addr.to_be_bytes();
+        let value = if on { 0xff } else { 0x00 };
+        self.write(unit, &[WRITE_SINGLE_COIL, a0, a1, value, 0])
+            .await
+    }
+
+    pub async fn write_single_register(
+        &self,
+        unit: u8,
+        addr: u16,
+        value: u16,
+    ) -> Result<(), ModbusError> {
+        let [a0, a1] = addr.to_be_bytes();
+        let [v0, v1] = value.to_be_bytes();
+        self.write(unit, &[WRITE_SINGLE_REGISTER, a0, a1, v0, v1])
+            .await
+    }
+
+    pub async fn write_multiple_coils(
+        &self,
+        unit: u8,
+        addr: u16,
+        coils: &[bool],
+    ) -> Result<(), ModbusError> {
+        if coils.is_empty() || coils.len() > MAX_WRITE_BITS as usize {
+            return Err(ModbusError::InvalidRequest);
+        }
+        let count = coils.len().div_ceil(8);
+        let mut pdu = [0u8; MAX_ADU - 3];
+        pdu[0] = WRITE_MULTIPLE_COILS;
+        pdu[1..3].copy_from_slice(&addr.to_be_bytes());
+        pdu[3..5].copy_from_slice(&(coils.len() as u16).to_be_bytes());
+        pdu[5] = count as u8;
+        pack_bits(coils, &mut pdu[6..6 + count]);
+        self.write(unit, &pdu[..6 + count]).await
+    }
+
+    pub async fn write_multiple_registers(
+        &self,
+        unit: u8,
+        addr: u16,
+        values: &[u16],
+    ) -> Result<(), ModbusError> {
+        if values.is_empty() || values.len() > MAX_WRITE_REGISTERS as usize {
+            return Err(ModbusError::InvalidRequest);
+        }
+        let count = 2 * values.len();
+        let mut pdu = [0u8; MAX_ADU - 3];
+        pdu[0] = WRITE_MULTIPLE_REGISTERS;
+        pdu[1..3].copy_from_slice(&addr.to_be_bytes());
+        pdu[3..5].copy_from_slice(&(values.len() as u16).to_be_bytes());
+        pdu[5] = count as u8;
+        for (i, value) in values.iter().enumerate() {
+            pdu[6 + 2 * i..8 + 2 * i].copy_from_slice(&value.to_be_bytes());
+        }
+        self.write(unit, &pdu[..6 + count]).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use std::cell::{Cell, RefCell};
+    use std::collections::VecDeque;
+
+    struct DummyPin;
+    impl Pin for DummyPin {
+        fn setup(&self) {}
+        fn set_high(&self) {}
+        fn set_low(&self) {}
+        fn toggle(&self) {}
+    }
+
+    type Responder = Box<dyn FnMut(&[u8]) -> Vec<u8>>;
+
+    /// Loopback USART: every written frame is handed to `responder` and its answer is queued for
+    /// reading. A read of more bytes than queued times out.
+    struct MockUsart {
+        rx: RefCell<VecDeque<u8>>,
+        written: RefCell<Vec<Vec<u8>>>,
+        responder: RefCell<Responder>,
+    }
+
+    impl MockUsart {
+        fn with_responder(responder: impl FnMut(&[u8]) -> Vec<u8> + 'static) -> Self {
+            let usart = Self::new(9600, DummyPin, DummyPin).unwrap();
+            *usart.responder.borrow_mut() = Box::new(responder);
+            usart
+        }
+
+        fn receive(&self, data: &[u8]) {
+            self.rx.borrow_mut().extend(data);
+        }
+    }
+
+    impl Usart<DummyPin> for MockUsart {
+        fn new(_baudrate: u32, _tx: DummyPin, _rx: DummyPin) -> Result<Self, UsartError> {
+            Ok(Self {
+                rx: RefCell::new(VecDeque::new()),
+                written: RefCell::new(Vec::new()),
+                responder: RefCell::new(Box::new(|_| Vec::new())),
+            })
+        }
+
+        fn write(&self, data: &[u8]) -> Result<(), UsartError> {
+            self.written.borrow_mut().push(data.to_vec());
+            let answer = (self.responder.borrow_mut())(data);
+            self.receive(&answer);
+            Ok(())
+        }
+
+        fn read(&self, data: &mut [u8]) -> Result<(), UsartError> {
+            let mut rx = self.rx.borrow_mut();
+            if rx.len() < data.len() {
+                rx.clear();
+                return Err(UsartError::Timeout);
+            }
+            for byte in data.iter_mut() {
+                *byte = rx.pop_front().unwrap();
+            }
+            Ok(())
+        }
+
+        fn write_async(
+            &self,
+            data: &[u8],
+        ) -> impl core::future::Future<Output = Result<(), UsartError>> + Send {
+            let res = self.write(data);
+            async move { res }
+        }
+
+        fn read_async(
+            &self,
+            data: &mut [u8],
+        ) -> impl core::future::Future<Output = Result<(), UsartError>> + Send {
+            let res = self.read(data);
+            async move { res }
+        }
+    }
+
+    /// A slave with 16 coils, 8 discrete inputs, 8 holding and 4 input registers behind a
+    /// loopback usart.
+    fn slave_usart(unit: u8) -> MockUsart {
+        let mut coils = [false; 16];
+        let inputs = [true, false, true, true, false, false, false, true];
+        let mut holding = [0u16, 1, 2, 3, 4, 5, 6, 7];
+        let input_registers = [0x1234u16, 0x5678, 0x9abc, 0xdef0];
+        MockUsart::with_responder(move |request| {
+            let mut slave = ModbusSlave::new(
+                unit,
+                SlaveTable {
+                    coils: &mut coils,
+                    discrete_inputs: &inputs,
+                    holding_registers: &mut holding,
+                    input_registers: &input_registers,
+                },
+            );
+            let mut response = [0u8; MAX_ADU];
+            match slave.handle_frame(request, &mut response) {
+                Some(len) => response[..len].to_vec(),
+                None => Vec::new(),
+            }
+        })
+    }
+
+    #[test]
+    fn test_crc16() {
+        // CRC-16/MODBUS check value
+        assert_eq!(crc16(b"123456789"), 0x4b37);
+        // read holding registers 0..2 of unit 1
+        assert_eq!(
+            crc16(&[0x01, 0x03, 0x00, 0x00, 0x00, 0x02]).to_le_bytes(),
+            [0xc4, 0x0b]
+        );
+        assert_eq!(crc16(&[]), 0xffff);
+    }
+
+    #[test]
+    fn test_frame_encode_decode() {
+        let mut buf = [0u8; MAX_ADU];
+        let len = encode_frame(&mut buf, 0x11, &[0x03, 0x00, 0x6b, 0x00, 0x03]).unwrap();
+        // example frame of the Modbus serial line specification
+        assert_eq!(buf[..len], [0x11, 0x03, 0x00, 0x6b, 0x00, 0x03, 0x76, 0x87]);
+        assert_eq!(decode_frame(&buf[..len]), Ok((0x11, &buf[1..6])));
+
+        buf[3] ^= 1;
+        assert_eq!(decode_frame(&buf[..len]), Err(ModbusError::Crc));
+        assert_eq!(decode_frame(&buf[..3]), Err(ModbusError::FrameLength));
+        assert_eq!(
+            encode_frame(&mut buf, 1, &[]),
+            Err(ModbusError::FrameLength)
+        );
+        assert_eq!(
+            encode_frame(&mut buf, 1, &[0; 254]),
+            Err(ModbusError::FrameLength)
+        );
+        assert_eq!(
+            encode_frame(&mut buf[..4], 1, &[1, 2]),
+            Err(ModbusError::FrameLength)
+        );
+    }
+
+    #[test]
+    fn test_frame_len() {
+        use FrameKind::*;
+        assert_eq!(frame_len(&[1], Request), Ok(None));
+        assert_eq!(
+            frame_len(&[1, READ_HOLDING_REGISTERS], Request),
+            Ok(Some(8))
+        );
+        assert_eq!(
+            frame_len(&[1, WRITE_MULTIPLE_REGISTERS, 0, 0, 0, 2], Request),
+            Ok(None)
+        );
+        assert_eq!(
+            frame_len(&[1, WRITE_MULTIPLE_REGISTERS, 0, 0, 0, 2, 4], Request),
+            Ok(Some(13))
+        );
+        assert_eq!(frame_len(&[1, READ_COILS], Response), Ok(None));
+        assert_eq!(frame_len(&[1, READ_COILS, 2], Response), Ok(Some(7)));
+        assert_eq!(frame_len(&[1, WRITE_SINGLE_COIL], Response), Ok(Some(8)));
+        assert_eq!(frame_len(&[1, 0x83], Response), Ok(Some(5)));
+        assert_eq!(
+            frame_len(&[1, 0x2b], Request),
+            Err(ModbusError::InvalidRequest)
+        );
+    }
+
+    #[test]
+    fn test_rtu_timeout_bits() {
+        assert_eq!(rtu_timeout_bits(9_600), 39);
+        assert_eq!(rtu_timeout_bits(19_200), 39);
+        assert_eq!(rtu_timeout_bits(115_200), 202);
+    }
+
+    #[test]
+    fn test_slave_process() {
+        let mut coils = [false; 10];
+        let mut holding = [0u16; 4];
+        let mut slave = ModbusSlave::new(
+            1,
+            SlaveTable {
+                coils: &mut coils,
+                discrete_inputs: &[],
+                holding_registers: &mut holding,
+                input_registers: &[7, 8],
+            },
+        );
+        let mut response = [0u8; MAX_ADU];
+
+        let len = slave.process(&[WRITE_SINGLE_COIL, 0, 3, 0xff, 0], &mut response);
+        assert_eq!(response[..len], [WRITE_SINGLE_COIL, 0, 3, 0xff, 0]);
+        let len = slave.process(&[WRITE_MULTIPLE_COILS, 0, 8, 0, 2, 1, 0b10], &mut response);
+        assert_eq!(response[..len], [WRITE_MULTIPLE_COILS, 0, 8, 0, 2]);
+        let len = slave.process(&[READ_COILS, 0, 0, 0, 10], &mut response);
+        assert_eq!(response[..len], [READ_COILS, 2, 0b1000, 0b10]);
+
+        let len = slave.process(
+            &[WRITE_MULTIPLE_REGISTERS, 0, 1, 0, 2, 4, 0xab, 0xcd, 0, 1],
+            &mut response,
+        );
+        assert_eq!(response[..len], [WRITE_MULTIPLE_REGISTERS, 0, 1, 0, 2]);
+        let len = slave.process(&[READ_HOLDING_REGISTERS, 0, 0, 0, 3], &mut response);
+        assert_eq!(
+            response[..len],
+            [READ_HOLDING_REGISTERS, 6, 0, 0, 0xab, 0xcd, 0, 1]
+        );
+        let len = slave.process(&[READ_INPUT_REGISTERS, 0, 1, 0, 1], &mut response);
+        assert_eq!(response[..len], [READ_INPUT_REGISTERS, 2, 0, 8]);
+
+        // exceptions
+        let len = slave.process(&[READ_INPUT_REGISTERS, 0, 1, 0, 2], &mut response);
+        assert_eq!(response[..len], [0x84, 0x02]);
+        let len = slave.process(&[READ_COILS, 0, 0, 0, 0], &mut response);
+        assert_eq!(response[..len], [0x81, 0x03]);
+        // empty request
+        let len = slave.process(&[], &mut response);
+        assert_eq!(response[..len], [0x80, 0x01]);
+        let len = slave.process(&[WRITE_SINGLE_COIL, 0, 0, 0x12, 0x34], &mut response);
+        assert_eq!(response[..len], [0x85, 0x03]);
+        let len = slave.process(
+            &[WRITE_MULTIPLE_REGISTERS, 0, 0, 0, 2, 3, 0, 0, 0],
+            &mut response,
+        );
+        assert_eq!(response[..len], [0x90, 0x03]);
+        let len = slave.process(&[0x2b, 0x0e], &mut response);
+        assert_eq!(response[..len], [0xab, 0x01]);
+
+        assert!(slave.table().coils[3]);
+        assert_eq!(slave.table().holding_registers[1], 0xabcd);
+    }
+
+    #[test]
+    fn test_slave_handle_frame() {
+        let mut coils = [false; 4];
+        let mut slave = ModbusSlave::new(
+            5,
+            SlaveTable {
+                coils: &mut coils,
+                discrete_inputs: &[],
+                holding_registers: &mut [],
+                input_registers: &[],
+            },
+        );
+        let mut frame = [0u8; MAX_ADU];
+        let mut response = [0u8; MAX_ADU];
+        let len = encode_frame(&mut frame, 5, &[WRITE_SINGLE_COIL, 0, 1, 0xff, 0]).unwrap();
+        let answer = slave.handle_frame(&frame[..len], &mut response).unwrap();
+        assert_eq!(response[..answer], frame[..len]);
+
+        // other unit, corrupted frame: no answer
+        let len = encode_frame(&mut frame, 6, &[WRITE_SINGLE_COIL, 0, 1, 0xff, 0]).unwrap();
+        assert_eq!(slave.handle_frame(&frame[..len], &mut response), None);
+        frame[0] = 5;
+        assert_eq!(slave.handle_frame(&frame[..len], &mut response), None);
+
+        // broadcast: executed, no answer
+        let len = encode_frame(&mut frame, BROADCAST, &[WRITE_SINGLE_COIL, 0, 2, 0xff, 0]).unwrap();
+        assert_eq!(slave.handle_frame(&frame[..len], &mut response), None);
+        assert_eq!(slave.table().coils, [false, true, true, false]);
+    }
+
+    #[test]
+    fn test_slave_serve() {
+        let usart = MockUsart::new(9600, DummyPin, DummyPin).unwrap();
+        let port = UsartPort::new(&usart);
+        let mut holding = [0u16; 4];
+        let mut slave = ModbusSlave::new(
+            1,
+            SlaveTable {
+                coils: &mut [],
+                discrete_inputs: &[],
+                holding_registers: &mut holding,
+                input_registers: &[],
+            },
+        );
+        let mut frame = [0u8; MAX_ADU];
+        let len = encode_frame(
+            &mut frame,
+            1,
+            &[WRITE_MULTIPLE_REGISTERS, 0, 2, 0, 2, 4, 0, 9, 1, 0],
+        )
+        .unwrap();
+        usart.receive(&frame[..len]);
+        block_on(slave.serve_async(&port)).unwrap();
+        assert_eq!(slave.table().holding_registers, [0, 0, 9, 0x100]);
+        let written = usart.written.borrow();
+        assert_eq!(
+            decode_frame(&written[0]),
+            Ok((1, &[WRITE_MULTIPLE_REGISTERS, 0, 2, 0, 2][..]))
+        );
+    }
+
+    #[test]
+    fn test_master_slave_loopback() {
+        let usart = slave_usart(3);
+        let port = UsartPort::new(&usart);
+        let master = ModbusMaster::new(&port, 0);
+
+        let mut registers = [0u16; 4];
+        block_on(master.read_input_registers(3, 0, &mut registers)).unwrap();
+        assert_eq!(registers, [0x1234, 0x5678, 0x9abc, 0xdef0]);
+
+        let mut inputs = [false; 8];
+        block_on(master.read_discrete_inputs(3, 0, &mut inputs)).unwrap();
+        assert_eq!(inputs, [true, false, true, true, false, false, false, true]);
+
+        block_on(master.write_single_register(3, 2, 0xbeef)).unwrap();
+        block_on(master.write_multiple_registers(3, 5, &[10, 11])).unwrap();
+        let mut holding = [0u16; 8];
+        block_on(master.read_holding_registers(3, 0, &mut holding)).unwrap();
+        assert_eq!(holding, [0, 1, 0xbeef, 3, 4, 10, 11, 7]);
+
+        block_on(master.write_single_coil(3, 0, true)).unwrap();
+        block_on(master.write_multiple_coils(3, 7, &[true, false, true])).unwrap();
+        let mut coils = [false; 10];
+        block_on(master.read_coils(3, 0, &mut coils)).unwrap();
+        assert_eq!(
+            coils,
+            [true, false, false, false, false, false, false, true, false, true]
+        );
+
+        // exception of the slave
+        let mut registers = [0u16; 2];
+        assert_eq!(
+            block_on(master.read_input_registers(3, 3, &mut registers)),
+            Err(ModbusError::Exception(ExceptionCode::IllegalDataAddress))
+        );
+        assert_eq!(
+            block_on(master.read_coils(3, 0, &mut [])),
+            Err(ModbusError::InvalidRequest)
+        );
+    }
+
+    #[test]
+    fn test_master_retries() {
+        let slave = RefCell::new(slave_usart(1));
+        let failures = std::rc::Rc::new(Cell::new(2));
+        let f = failures.clone();
+        // corrupt the first responses, then answer from the slave
+        let usart = MockUsart::with_responder(move |request| {
+            let slave = slave.borrow();
+            slave.write(request).unwrap();
+            let mut answer: Vec<u8> = slave.rx.borrow_mut().drain(..).collect();
+            if f.get() > 0 {
+                f.set(f.get() - 1);
+                *answer.last_mut().unwrap() ^= 0xff;
+            }
+            answer
+        });
+        let port = UsartPort::new(&usart);
+        let mut registers = [0u16; 2];
+
+        let master = ModbusMaster::new(&port, 1);
+        assert_eq!(
+            block_on(master.read_input_registers(1, 0, &mut registers)),
+            Err(ModbusError::Crc)
+        );
+        assert_eq!(usart.written.borrow().len(), 2);
+
+        failures.set(2);
+        let master = ModbusMaster::new(&port, 2);
+        block_on(master.read_input_registers(1, 0, &mut registers)).unwrap();
+        assert_eq!(registers, [0x1234, 0x5678]);
+        assert_eq!(usart.written.borrow().len(), 5);
+    }
+
+    #[test]
+    fn test_master_timeout_and_broadcast() {
+        // nobody answers
+        let usart = MockUsart::new(9600, DummyPin, DummyPin).unwrap();
+        let port = UsartPort::new(&usart);
+        let master = ModbusMaster::new(&port, 2);
+        assert_eq!(
+            block_on(master.write_single_register(1, 0, 1)),
+            Err(ModbusError::Usart(UsartError::Timeout))
+        );
+        assert_eq!(usart.written.borrow().len(), 3);
+
+        // broadcasts are not answered and not retried
+        block_on(master.write_single_register(BROADCAST, 0, 1)).unwrap();
+        assert_eq!(usart.written.borrow().len(), 4);
+    }
+
+    /// Port that, like `usart::Usart`, waits forever for a response that is lost: the first
+    /// `lost` responses of the slave behind it never arrive.
+    struct LossyPort {
+        usart: MockUsart,
+        lost: Cell<u8>,
+    }
+
+    impl RtuPort for LossyPort {
+        async fn write_frame(&self, frame: &[u8]) -> Result<(), UsartError> {
+            self.usart.write(frame)
+        }
+
+        async fn read_frame(&self, buf: &mut [u8], kind: FrameKind) -> Result<usize, UsartError> {
+            if self.lost.get() > 0 {
+                self.lost.set(self.lost.get() - 1);
+                self.usart.rx.borrow_mut().clear();
+                core::future::pending::<()>().await;
+            }
+            UsartPort::new(&self.usart).read_frame(buf, kind).await
+        }
+    }
+
+    /// Timer that expires as soon as it is polled.
+    struct ExpiredTimer {
+        expired: Cell<u32>,
+    }
+
+    impl ResponseTimer for ExpiredTimer {
+        async fn delay_ms(&self, ms: u32) {
+            assert_eq!(ms, 100);
+            self.expired.set(self.expired.get() + 1);
+        }
+    }
+
+    #[test]
+    fn test_master_response_timeout() {
+        let timer = ExpiredTimer {
+            expired: Cell::new(0),
+        };
+        let port = LossyPort {
+            usart: slave_usart(1),
+            lost: Cell::new(2),
+        };
+        let master = ModbusMaster::with_response_timeout(&port, 2, timer, 100);
+        let mut registers = [0u16; 2];
+        block_on(master.read_input_registers(1, 0, &mut registers)).unwrap();
+        assert_eq!(registers, [0x1234, 0x5678]);
+        assert_eq!(port.usart.written.borrow().len(), 3);
+        // the answered attempt does not wait on the timer
+        assert_eq!(master.timer.expired.get(), 2);
+
+        port.lost.set(3);
+        assert_eq!(
+            block_on(master.read_input_registers(1, 0, &mut registers)),
+            Err(ModbusError::Usart(UsartError::Timeout))
+        );
+        assert_eq!(port.usart.written.borrow().len(), 6);
+    }
+
+    #[test]
+    fn test_master_rejects_foreign_response() {
+        // answer from unit 2 to a request for unit 1
+        let usart = MockUsart::with_responder(|request| {
+            let (_, pdu) = decode_frame(request).unwrap();
+            let mut frame = [0u8; MAX_ADU];
+            let len = encode_frame(&mut frame, 2, pdu).unwrap();
+            frame[..len].to_vec()
+        });
+        let port = UsartPort::new(&usart);
+        let master = ModbusMaster::new(&port, 0);
+        assert_eq!(
+            block_on(master.write_single_coil(1, 0, true)),
+            Err(ModbusError::InvalidResponse)
+        );
+    }
+}
@@ -73,7 +73,7 @@
 //! }
 //! ```
 
-use crate::hal::{I2c, I2cError, Pin};
+use crate::hal::{I2c, I2cError, I2cOperation, Pin};
 use embassy_sync::blocking_mutex::raw::RawMutex;
 use embassy_sync::mutex::Mutex;
 
@@ -144,7 +144,33 @@ impl<M: RawMutex, I: I2c<P>, P: Pin> SharedI2cManager<M, I, P> {
         }
     }
 
+    /// Asynchronously runs a sequence of writes and reads chained with repeated starts under the
+    /// same lock. See `I2c::transaction`.
+    pub async fn transaction(
+        &self,
+        addr: u16,
+        operations: &mut [I2cOperation<'_>],
+    ) -> Result<(), I2cError> {
+        let mut guard = self.mutex.lock().await;
+        if let Some(i2c) = guard.as_mut() {
+            i2c.transaction_async(addr, operations).await
+        } else {
+            Err(I2cError::InitError)
+        }
+    }
+
+    /// Frees a stuck bus and re-initialises the peripheral. See `I2c::recover_bus`.
+    pub async fn recover_bus(&self) -> Result<(), I2cError> {
+        let guard = self.mutex.lock().await;
+        if let Some(i2c) = guard.as_ref() {
+            i2c.recover_bus()
+        } else {
+            Err(I2cError::InitError)
+        }
+    }
+
     /// Asynchronously writes to a device with retry logic.
+    /// Errors that can leave the bus stuck trigger `recover_bus` before the next attempt.
     pub async fn write_retry(&self, addr: u16, data: &[u8], retry: u8) -> Result<(), I2cError> {
         let mut cnt = 0;
         loop {
@@ -155,6 +181,33 @@ impl<M: RawMutex, I: I2c<P>, P: Pin> SharedI2cManager<M, I, P> {
                     if cnt >= retry {
                         return Err(e);
                     }
+                    if e.needs_bus_recovery() {
+                        let _ = self.recover_bus().await;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Runs a transaction with retry logic, recovering the bus like `write_retry`.
+    pub async fn transaction_retry(
+        &self,
+        addr: u16,
+        operations: &mut [I2cOperation<'_>],
+        retry: u8,
+    ) -> Result<(), I2cError> {
+        let mut cnt = 0;
+        loop {
+            match self.transaction(addr, operations).await {
+                Ok(_) => return Ok(()),
+                Err(e) => {
+                    cnt += 1;
+                    if cnt >= retry {
+                        return Err(e);
+                    }
+                    if e.needs_bus_recovery() {
+                        let _ = self.recover_bus().await;
+                    }
                 }
             }
         }
@@ -178,6 +231,9 @@ mod tests {
     struct MockI2c {
         write_log: StdMutex<Vec<(u16, Vec<u8>)>>,
         read_data: StdMutex<Vec<u8>>,
+        /// the next writes fail with this error
+        write_errors: StdMutex<Vec<I2cError>>,
+        recoveries: StdMutex<usize>,
     }
 
     impl I2c<MockPin> for MockI2c {
@@ -185,10 +241,15 @@ mod tests {
             Ok(Self {
                 write_log: StdMutex::new(Vec::new()),
                 read_data: StdMutex::new(Vec::new()),
+                write_errors: StdMutex::new(Vec::new()),
+                recoveries: StdMutex::new(0),
             })
         }
 
         fn write(&self, addr: u16, data: &[u8]) -> Result<(), I2cError> {
+            if let Some(err) = self.write_errors.lock().unwrap().pop() {
+                return Err(err);
+            }
             self.write_log.lock().unwrap().push((addr, data.to_vec()));
             Ok(())
         }
@@ -234,6 +295,11 @@ mod tests {
         fn capacity(&self) -> I2cFrequency {
             I2cFrequency::Freq400khz
         }
+
+        fn recover_bus(&self) -> Result<(), I2cError> {
+            *self.recoveries.lock().unwrap() += 1;
+            Ok(())
+        }
     }
 
     #[test]
@@ -273,6 +339,54 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_shared_i2c_manager_transaction() {
+        use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+        use futures::executor::block_on;
+
+        let manager: SharedI2cManager<CriticalSectionRawMutex, MockI2c, MockPin> =
+            SharedI2cManager::new();
+        let mock_driver = MockI2c::new(I2cFrequency::Freq100khz, MockPin, MockPin).unwrap();
+        *mock_driver.read_data.lock().unwrap() = vec![0x11, 0x22, 0x33];
+
+        block_on(async {
+            // uninitialized manager
+            assert_eq!(
+                manager.transaction(0x50, &mut []).await,
+                Err(I2cError::InitError)
+            );
+
+            manager.init(mock_driver).await;
+
+            let mut first = [0u8; 1];
+            let mut second = [0u8; 2];
+            let mut ops = [
+                I2cOperation::Write(&[0x01]),
+                I2cOperation::Read(&mut first),
+                I2cOperation::Write(&[0x02]),
+                I2cOperation::Read(&mut second),
+            ];
+            assert!(manager.transaction(0x50, &mut ops).await.is_ok());
+            assert_eq!(first, [0x11]);
+            assert_eq!(second, [0x22, 0x33]);
+
+            // the read buffer is drained, the next read fails
+            let mut buf = [0u8; 1];
+            let mut ops = [I2cOperation::Write(&[0x03]), I2cOperation::Read(&mut buf)];
+            assert_eq!(
+                manager.transaction(0x50, &mut ops).await,
+                Err(I2cError::BusError)
+            );
+
+            let guard = manager.mutex.lock().await;
+            let log = guard.as_ref().unwrap().write_log.lock().unwrap();
+            assert_eq!(
+                *log,
+                vec![(0x50, vec![0x01]), (0x50, vec![0x02]), (0x50, vec![0x03])]
+            );
+        });
+    }
+
     #[test]
     fn test_shared_i2c_manager_uninitialized_and_edge_cases() {
         use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
@@ -377,4 +491,39 @@ mod tests {
             manager.init(driver2).await;
         });
     }
+
+    #[test]
+    fn test_shared_i2c_retry_recovers_bus() {
+        use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+        use futures::executor::block_on;
+
+        let manager: SharedI2cManager<CriticalSectionRawMutex, MockI2c, MockPin> =
+            SharedI2cManager::new();
+        let mock_driver = MockI2c::new(I2cFrequency::Freq100khz, MockPin, MockPin).unwrap();
+        // popped from the back: NACK first, then a bus error
+        *mock_driver.write_errors.lock().unwrap() = vec![I2cError::BusError, I2cError::Nack];
+
+        block_on(async {
+            assert_eq!(manager.recover_bus().await, Err(I2cError::InitError));
+            manager.init(mock_driver).await;
+
+            // a NACK is only retried, a bus error recovers the bus first
+            assert_eq!(manager.write_retry(0x50, &[0xAA], 3).await, Ok(()));
+            let guard = manager.mutex.lock().await;
+            let i2c = guard.as_ref().unwrap();
+            assert_eq!(*i2c.recoveries.lock().unwrap(), 1);
+            assert_eq!(i2c.write_log.lock().unwrap().len(), 1);
+            *i2c.write_errors.lock().unwrap() = vec![I2cError::BusError, I2cError::BusError];
+            drop(guard);
+
+            // the last error is returned without recovering again
+            let mut ops = [I2cOperation::Write(&[0xBB])];
+            assert_eq!(
+                manager.transaction_retry(0x50, &mut ops, 2).await,
+                Err(I2cError::BusError)
+            );
+            let guard = manager.mutex.lock().await;
+            assert_eq!(*guard.as_ref().unwrap().recoveries.lock().unwrap(), 2);
+        });
+    }
 }
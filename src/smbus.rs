@@ -0,0 +1,1064 @@
+//! SMBus protocols on top of the `i2c_transfer` sequencer.
+//!
+//! [`SmbusHost`] runs the SMBus bus protocols (quick command, send/receive byte, read/write
+//! byte and word, block read/write, process calls) as controller, [`SmbusDevice`] serves them in
+//! target mode through an [`SmbusHandler`]. Both work on any `I2cRegisters`, `i2c::I2c` on the
+//! MCU and the register model in the tests.
+//!
+//! The Packet Error Code is generated and checked by the peripheral (`PECBYTE` in the last chunk,
+//! see [`SegmentEnd::StopPec`]), a wrong one is reported as [`SmbusError::Pec`] and NACKed by a
+//! device. A device has to arm its byte counter for the exact frame length to know which byte is
+//! the PEC, so with PEC its handler tells the length of each command
+//! ([`SmbusHandler::write_len`]). SMBALERT and the `TIMEOUTA`/`TIMEOUTB` counters are peripheral
+//! features too, see `i2c::I2c::set_smbus`; a timeout shows up as `I2cError::Timeout`.
+
+use crate::hal::{I2cError, I2cOperation};
+use crate::i2c_transfer::{self, AddressMatch, I2cRegisters, SegmentEnd, SegmentStart, SlaveEnd};
+
+/// Address a host reads to find out which device pulled SMBALERT low.
+pub const ALERT_RESPONSE_ADDRESS: u16 = 0x0c;
+/// Longest block of a block read/write (SMBus 3.0).
+pub const MAX_BLOCK_LEN: usize = 255;
+/// command, byte count, block and PEC
+const FRAME_LEN: usize = MAX_BLOCK_LEN + 3;
+
+/// CRC-8 Packet Error Code (polynomial x^8 + x^2 + x + 1, initial value 0) over all bytes of a
+/// transaction, address bytes included. The peripheral computes it on the bus, this is for the
+/// other side (e.g. building test frames).
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Pec(u8);
+
+impl Pec {
+    pub const fn new() -> Self {
+        Pec(0)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            let mut crc = self.0 ^ byte;
+            for _ in 0..8 {
+                crc = if crc & 0x80 != 0 {
+                    (crc << 1) ^ 0x07
+                } else {
+                    crc << 1
+                };
+            }
+            self.0 = crc;
+        }
+    }
+
+    /// Add the address byte of a (repeated) START.
+    pub fn address(&mut self, addr: u16, read: bool) {
+        self.update(&[((addr as u8) << 1) | read as u8]);
+    }
+
+    pub fn value(&self) -> u8 {
+        self.0
+    }
+}
+
+/// PEC of `data` in one go.
+pub fn pec(data: &[u8]) -> u8 {
+    let mut pec = Pec::new();
+    pec.update(data);
+    pec.value()
+}
+
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SmbusError {
+    I2c(I2cError),
+    /// the received PEC does not match the transferred bytes
+    Pec,
+    /// the byte count of a block does not fit the buffer or the protocol
+    BlockLength,
+}
+
+impl From<I2cError> for SmbusError {
+    fn from(err: I2cError) -> Self {
+        match err {
+            I2cError::Pec => SmbusError::Pec,
+            err => SmbusError::I2c(err),
+        }
+    }
+}
+
+impl SmbusError {
+    /// The peripheral is left with flags or interrupts of the failed transfer, see
+    /// `I2cRegisters::abort`.
+    fn needs_abort(&self) -> bool {
+        matches!(self, SmbusError::I2c(_) | SmbusError::Pec)
+    }
+}
+
+/////////////////////////// timeouts /////////////////////////////
+/// SMBus timing options for `i2c::I2c::set_smbus`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SmbusConfig {
+    /// device mode (`SMBDEN`) instead of host mode (`SMBHEN`)
+    pub device: bool,
+    /// SCL low timeout (`TIMEOUTA`) in us, tTIMEOUT is 25 - 35 ms
+    pub timeout_us: Option<u32>,
+    /// cumulative clock low extension (`TIMEOUTB`) in us, tLOW:MEXT is 10 ms for a host and
+    /// tLOW:SEXT 25 ms for a device
+    pub ext_timeout_us: Option<u32>,
+    /// host: interrupt on SMBALERT, device: enable the SMBALERT output
+    pub alert: bool,
+}
+
+impl SmbusConfig {
+    pub fn host() -> Self {
+        Self {
+            device: false,
+            timeout_us: Some(25_000),
+            ext_timeout_us: Some(10_000),
+            alert: true,
+        }
+    }
+
+    pub fn device() -> Self {
+        Self {
+            device: true,
+            timeout_us: Some(25_000),
+            ext_timeout_us: Some(25_000),
+            alert: false,
+        }
+    }
+}
+
+/// `TIMEOUTA`/`TIMEOUTB` value for a timeout of at least `timeout_us`. The counters run at
+/// kernel clock / 2048: t = (value + 1) * 2048 / kernel_freq. `None` if it does not fit 12 bits.
+pub fn timeout_value(kernel_freq: u32, timeout_us: u32) -> Option<u16> {
+    let ticks = (timeout_us as u64 * kernel_freq as u64).div_ceil(2048 * 1_000_000);
+    let value = ticks.max(1) - 1;
+    if value > 0xfff {
+        None
+    } else {
+        Some(value as u16)
+    }
+}
+
+/////////////////////////// host /////////////////////////////
+/// SMBus host (controller) on the I2C peripheral `regs`.
+pub struct SmbusHost<'a, R: I2cRegisters> {
+    regs: &'a R,
+    pec: bool,
+}
+
+impl<'a, R: I2cRegisters> SmbusHost<'a, R> {
+    /// With `pec` every transaction except the quick command carries a PEC byte.
+    pub fn new(regs: &'a R, pec: bool) -> Self {
+        Self { regs, pec }
+    }
+
+    /// How the last segment ends: with the PEC if enabled.
+    fn end(&self) -> SegmentEnd {
+        if self.pec {
+            SegmentEnd::StopPec
+        } else {
+            SegmentEnd::Stop
+        }
+    }
+
+    /// Write `write`, then read `read` (at most 2 bytes) after a repeated START. Either may be
+    /// empty but not both.
+    fn exchange_impl(&self, addr: u16, write: &[u8], read: &mut [u8]) -> Result<(), I2cError> {
+        if read.is_empty() {
+            return i2c_transfer::write_segment(
+                self.regs,
+                addr,
+                write,
+                SegmentStart::Start,
+                self.end(),
+            );
+        }
+        if !write.is_empty() {
+            i2c_transfer::write_segment(
+                self.regs,
+                addr,
+                write,
+                SegmentStart::Start,
+                SegmentEnd::Restart,
+            )?;
+        }
+        let mut buf = [0u8; 3];
+        let len = read.len() + self.pec as usize;
+        i2c_transfer::read_segment(
+            self.regs,
+            addr,
+            &mut buf[..len],
+            SegmentStart::Start,
+            self.end(),
+        )?;
+        read.copy_from_slice(&buf[..read.len()]);
+        Ok(())
+    }
+
+    fn exchange(&self, addr: u16, write: &[u8], read: &mut [u8]) -> Result<(), SmbusError> {
+        let res = self.exchange_impl(addr, write, read);
+        if res.is_err() {
+            self.regs.abort();
+        }
+        Ok(res?)
+    }
+
+    async fn exchange_async_impl(
+        &self,
+        addr: u16,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), I2cError> {
+        if read.is_empty() {
+            return i2c_transfer::write_segment_async(
+                self.regs,
+                addr,
+                write,
+                SegmentStart::Start,
+                self.end(),
+            )
+            .await;
+        }
+        if !write.is_empty() {
+            i2c_transfer::write_segment_async(
+                self.regs,
+                addr,
+                write,
+                SegmentStart::Start,
+                SegmentEnd::Restart,
+            )
+            .await?;
+        }
+        let mut buf = [0u8; 3];
+        let len = read.len() + self.pec as usize;
+        i2c_transfer::read_segment_async(
+            self.regs,
+            addr,
+            &mut buf[..len],
+            SegmentStart::Start,
+            self.end(),
+        )
+        .await?;
+        read.copy_from_slice(&buf[..read.len()]);
+        Ok(())
+    }
+
+    async fn exchange_async(
+        &self,
+        addr: u16,
+        write: &[u8],
+        read: &mut [u8],
+    ) -> Result<(), SmbusError> {
+        let res = self.exchange_async_impl(addr, write, read).await;
+        if res.is_err() {
+            self.regs.abort();
+        }
+        Ok(res?)
+    }
+
+    /// Write `write`, then read a block (byte count first) into `data`. The count byte is read
+    /// in its own chunk with `RELOAD` so the rest of the read can be sized by it.
+    fn block_exchange_impl(
+        &self,
+        addr: u16,
+        write: &[u8],
+        data: &mut [u8],
+    ) -> Result<usize, SmbusError> {
+        let mut frame = [0u8; FRAME_LEN];
+        i2c_transfer::write_segment(
+            self.regs,
+            addr,
+            write,
+            SegmentStart::Start,
+            SegmentEnd::Restart,
+        )?;
+        i2c_transfer::read_segment(
+            self.regs,
+            addr,
+            &mut frame[..1],
+            SegmentStart::Start,
+            SegmentEnd::Reload,
+        )?;
+        let count = frame[0] as usize;
+        // a block of 0 bytes without PEC still needs one more byte to release the reload
+        let len = (1 + count + self.pec as usize).max(2);
+        i2c_transfer::read_segment(
+            self.regs,
+            addr,
+            &mut frame[1..len],
+            SegmentStart::Reload,
+            self.end(),
+        )?;
+        let block = data.get_mut(..count).ok_or(SmbusError::BlockLength)?;
+        block.copy_from_slice(&frame[1..1 + count]);
+        Ok(count)
+    }
+
+    fn block_exchange(
+        &self,
+        addr: u16,
+        write: &[u8],
+        data: &mut [u8],
+    ) -> Result<usize, SmbusError> {
+        let res = self.block_exchange_impl(addr, write, data);
+        if res.as_ref().is_err_and(SmbusError::needs_abort) {
+            self.regs.abort();
+        }
+        res
+    }
+
+    async fn block_exchange_async_impl(
+        &self,
+        addr: u16,
+        write: &[u8],
+        data: &mut [u8],
+    ) -> Result<usize, SmbusError> {
+        let mut frame = [0u8; FRAME_LEN];
+        i2c_transfer::write_segment_async(
+            self.regs,
+            addr,
+            write,
+            SegmentStart::Start,
+            SegmentEnd::Restart,
+        )
+        .await?;
+        i2c_transfer::read_segment_async(
+            self.regs,
+            addr,
+            &mut frame[..1],
+            SegmentStart::Start,
+            SegmentEnd::Reload,
+        )
+        .await?;
+        let count = frame[0] as usize;
+        let len = (1 + count + self.pec as usize).max(2);
+        i2c_transfer::read_segment_async(
+            self.regs,
+            addr,
+            &mut frame[1..len],
+            SegmentStart::Reload,
+            self.end(),
+        )
+        .await?;
+        let block = data.get_mut(..count).ok_or(SmbusError::BlockLength)?;
+        block.copy_from_slice(&frame[1..1 + count]);
+        Ok(count)
+    }
+
+    async fn block_exchange_async(
+        &self,
+        addr: u16,
+        write: &[u8],
+        data: &mut [u8],
+    ) -> Result<usize, SmbusError> {
+        let res = self.block_exchange_async_impl(addr, write, data).await;
+        if res.as_ref().is_err_and(SmbusError::needs_abort) {
+            self.regs.abort();
+        }
+        res
+    }
+
+    /// command, byte count and `data`
+    fn block_frame(
+        command: u8,
+        data: &[u8],
+        frame: &mut [u8; FRAME_LEN],
+    ) -> Result<usize, SmbusError> {
+        if data.len() > MAX_BLOCK_LEN {
+            return Err(SmbusError::BlockLength);
+        }
+        frame[0] = command;
+        frame[1] = data.len() as u8;
+        frame[2..2 + data.len()].copy_from_slice(data);
+        Ok(2 + data.len())
+    }
+
+    /// The R/W bit is the whole message, no PEC.
+    pub fn quick_command(&self, addr: u16, read: bool) -> Result<(), SmbusError> {
+        let mut ops = if read {
+            [I2cOperation::Read(&mut [])]
+        } else {
+            [I2cOperation::Write(&[])]
+        };
+        i2c_transfer::transaction(self.regs, addr, &mut ops)?;
+        Ok(())
+    }
+
+    pub fn send_byte(&self, addr: u16, byte: u8) -> Result<(), SmbusError> {
+        self.exchange(addr, &[byte], &mut [])
+    }
+
+    pub fn receive_byte(&self, addr: u16) -> Result<u8, SmbusError> {
+        let mut byte = [0u8];
+        self.exchange(addr, &[], &mut byte)?;
+        Ok(byte[0])
+    }
+
+    pub fn write_byte(&self, addr: u16, command: u8, value: u8) -> Result<(), SmbusError> {
+        self.exchange(addr, &[command, value], &mut [])
+    }
+
+    pub fn read_byte(&self, addr: u16, command: u8) -> Result<u8, SmbusError> {
+        let mut byte = [0u8];
+        self.exchange(addr, &[command], &mut byte)?;
+        Ok(byte[0])
+    }
+
+    /// Words are sent low byte first.
+    pub fn write_word(&self, addr: u16, command: u8, value: u16) -> Result<(), SmbusError> {
+        let [lo, hi] = value.to_le_bytes();
+        self.exchange(addr, &[command, lo, hi], &mut [])
+    }
+
+    pub fn read_word(&self, addr: u16, command: u8) -> Result<u16, SmbusError> {
+        let mut word = [0u8; 2];
+        self.exchange(addr, &[command], &mut word)?;
+        Ok(u16::from_le_bytes(word))
+    }
+
+    /// Write a word and read the answer in the same transaction.
+    pub fn process_call(&self, addr: u16, command: u8, value: u16) -> Result<u16, SmbusError> {
+        let [lo, hi] = value.to_le_bytes();
+        let mut word = [0u8; 2];
+        self.exchange(addr, &[command, lo, hi], &mut word)?;
+        Ok(u16::from_le_bytes(word))
+    }
+
+    pub fn block_write(&self, addr: u16, command: u8, data: &[u8]) -> Result<(), SmbusError> {
+        let mut frame = [0u8; FRAME_LEN];
+        let len = Self::block_frame(command, data, &mut frame)?;
+        self.exchange(addr, &frame[..len], &mut [])
+    }
+
+    /// Read a block into `data`, returns its length. `BlockLength` if it does not fit.
+    pub fn block_read(&self, addr: u16, command: u8, data: &mut [u8]) -> Result<usize, SmbusError> {
+        self.block_exchange(addr, &[command], data)
+    }
+
+    /// Write the block `data` and read the answer block into `response`, returns its length.
+    pub fn block_process_call(
+        &self,
+        addr: u16,
+        command: u8,
+        data: &[u8],
+        response: &mut [u8],
+    ) -> Result<usize, SmbusError> {
+        let mut frame = [0u8; FRAME_LEN];
+        let len = Self::block_frame(command, data, &mut frame)?;
+        self.block_exchange(addr, &frame[..len], response)
+    }
+
+    /// Ask which device pulls SMBALERT low, returns its 7-bit address.
+    pub fn alert_response(&self) -> Result<u16, SmbusError> {
+        Ok(self.receive_byte(ALERT_RESPONSE_ADDRESS)? as u16 >> 1)
+    }
+
+    pub async fn quick_command_async(&self, addr: u16, read: bool) -> Result<(), SmbusError> {
+        let mut ops = if read {
+            [I2cOperation::Read(&mut [])]
+        } else {
+            [I2cOperation::Write(&[])]
+        };
+        i2c_transfer::transaction_async(self.regs, addr, &mut ops).await?;
+        Ok(())
+    }
+
+    pub async fn send_byte_async(&self, addr: u16, byte: u8) -> Result<(), SmbusError> {
+        self.exchange_async(addr, &[byte], &mut []).await
+    }
+
+    pub async fn receive_byte_async(&self, addr: u16) -> Result<u8, SmbusError> {
+        let mut byte = [0u8];
+        self.exchange_async(addr, &[], &mut byte).await?;
+        Ok(byte[0])
+    }
+
+    pub async fn write_byte_async(
+        &self,
+        addr: u16,
+        command: u8,
+        value: u8,
+    ) -> Result<(), SmbusError> {
+        self.exchange_async(addr, &[command, value], &mut []).await
+    }
+
+    pub async fn read_byte_async(&self, addr: u16, command: u8) -> Result<u8, SmbusError> {
+        let mut byte = [0u8];
+        self.exchange_async(addr, &[command], &mut byte).await?;
+        Ok(byte[0])
+    }
+
+    pub async fn write_word_async(
+        &self,
+        addr: u16,
+        command: u8,
+        value: u16,
+    ) -> Result<(), SmbusError> {
+        let [lo, hi] = value.to_le_bytes();
+        self.exchange_async(addr, &[command, lo, hi], &mut []).await
+    }
+
+    pub async fn read_word_async(&self, addr: u16, command: u8) -> Result<u16, SmbusError> {
+        let mut word = [0u8; 2];
+        self.exchange_async(addr, &[command], &mut word).await?;
+        Ok(u16::from_le_bytes(word))
+    }
+
+    pub async fn process_call_async(
+        &self,
+        addr: u16,
+        command: u8,
+        value: u16,
+    ) -> Result<u16, SmbusError> {
+        let [lo, hi] = value.to_le_bytes();
+        let mut word = [0u8; 2];
+        self.exchange_async(addr, &[command, lo, hi], &mut word)
+            .await?;
+        Ok(u16::from_le_bytes(word))
+    }
+
+    pub async fn block_write_async(
+        &self,
+        addr: u16,
+        command: u8,
+        data: &[u8],
+    ) -> Result<(), SmbusError> {
+        let mut frame = [0u8; FRAME_LEN];
+        let len = Self::block_frame(command, data, &mut frame)?;
+        self.exchange_async(addr, &frame[..len], &mut []).await
+    }
+
+    pub async fn block_read_async(
+        &self,
+        addr: u16,
+        command: u8,
+        data: &mut [u8],
+    ) -> Result<usize, SmbusError> {
+        self.block_exchange_async(addr, &[command], data).await
+    }
+
+    pub async fn block_process_call_async(
+        &self,
+        addr: u16,
+        command: u8,
+        data: &[u8],
+        response: &mut [u8],
+    ) -> Result<usize, SmbusError> {
+        let mut frame = [0u8; FRAME_LEN];
+        let len = Self::block_frame(command, data, &mut frame)?;
+        self.block_exchange_async(addr, &frame[..len], response)
+            .await
+    }
+
+    pub async fn alert_response_async(&self) -> Result<u16, SmbusError> {
+        Ok(self.receive_byte_async(ALERT_RESPONSE_ADDRESS).await? as u16 >> 1)
+    }
+}
+
+/////////////////////////// device /////////////////////////////
+/// Application side of an [`SmbusDevice`]. The device only sees bytes, so the handler decides
+/// from the command code which protocol it is (e.g. word or block).
+pub trait SmbusHandler {
+    /// Quick command with the R/W bit `read`.
+    fn quick_command(&mut self, _read: bool) {}
+
+    /// The host wrote `data` after `command` (send byte: `data` is empty).
+    fn write(&mut self, command: u8, data: &[u8]);
+
+    /// With PEC: how many bytes the host writes after `command` before the PEC or a repeated
+    /// START, e.g. 2 for write word and process call, 0 for send byte and the reads. `None` for a
+    /// block, its byte count comes first.
+    fn write_len(&mut self, _command: u8) -> Option<usize> {
+        Some(0)
+    }
+
+    /// The host reads. `command` is `None` for receive byte, `written` holds what the host wrote
+    /// after the command before the repeated START (process calls). Fill `response` (block reads
+    /// start with the byte count) and return its length.
+    fn read(&mut self, command: Option<u8>, written: &[u8], response: &mut [u8]) -> usize;
+
+    /// The host read our address from the alert response address, release SMBALERT with
+    /// `i2c::I2c::set_smbus_alert(false)`.
+    fn alert_answered(&mut self) {}
+}
+
+/// SMBus device (target) serving the transactions addressed to the own address of `regs`.
+pub struct SmbusDevice<'a, R: I2cRegisters, H: SmbusHandler> {
+    regs: &'a R,
+    addr: u16,
+    pec: bool,
+    handler: H,
+}
+
+impl<'a, R: I2cRegisters, H: SmbusHandler> SmbusDevice<'a, R, H> {
+    /// `addr` is the own address the peripheral is configured with, the answer to an alert
+    /// response. With `pec` every transaction except the quick command carries a PEC byte.
+    pub fn new(regs: &'a R, addr: u16, pec: bool, handler: H) -> Self {
+        Self {
+            regs,
+            addr,
+            pec,
+            handler,
+        }
+    }
+
+    pub fn handler(&mut self) -> &mut H {
+        &mut self.handler
+    }
+
+    /// A write ended with STOP, hand it to the handler.
+    fn written(&mut self, frame: &[u8]) {
+        match frame.split_first() {
+            Some((&command, data)) => self.handler.write(command, data),
+            None => self.handler.quick_command(false),
+        }
+    }
+
+    /// Build the answer to a read after `written` (empty for receive byte). Returns its length.
+    fn response(&mut self, written: &[u8], frame: &mut [u8; FRAME_LEN]) -> usize {
+        let command = written.first().copied();
+        let args = written.get(1..).unwrap_or(&[]);
+        self.handler
+            .read(command, args, &mut frame[..FRAME_LEN - 1])
+    }
+
+    /// The frame length after the command, `None` for a block.
+    fn data_len(&mut self, command: u8) -> Option<usize> {
+        self.handler
+            .write_len(command)
+            .map(|len| len.min(FRAME_LEN - 2))
+    }
+
+    /// Receive a write after the address match. With PEC the counter is armed for the command,
+    /// the byte count of a block and then the rest of the frame with its PEC, so a wrong PEC is
+    /// NACKed. Returns the frame without the PEC and how the host ended it.
+    fn receive(&mut self, frame: &mut [u8; FRAME_LEN]) -> Result<(usize, SlaveEnd), SmbusError> {
+        if !self.pec {
+            i2c_transfer::slave_accept(self.regs);
+            return Ok(i2c_transfer::slave_receive(self.regs, frame)?);
+        }
+        // the command and the byte count of a block in chunks of their own, they size the rest
+        let mut len = 0;
+        let count = loop {
+            let (received, end) =
+                i2c_transfer::slave_receive_counted(self.regs, &mut frame[len..len + 1], false)?;
+            if end != SlaveEnd::Reload {
+                return Ok((len + received, end));
+            }
+            len += 1;
+            let count = if len == 1 {
+                self.data_len(frame[0])
+            } else {
+                Some(frame[1] as usize)
+            };
+            if let Some(count) = count {
+                break count;
+            }
+        };
+        let frame_len = len + count;
+        let (received, end) =
+            i2c_transfer::slave_receive_counted(self.regs, &mut frame[len..frame_len + 1], true)?;
+        Self::pec_frame(len + received, frame_len, end)
+    }
+
+    async fn receive_async(
+        &mut self,
+        frame: &mut [u8; FRAME_LEN],
+    ) -> Result<(usize, SlaveEnd), SmbusError> {
+        if !self.pec {
+            i2c_transfer::slave_accept(self.regs);
+            return Ok(i2c_transfer::slave_receive_async(self.regs, frame).await?);
+        }
+        let mut len = 0;
+        let count = loop {
+            let (received, end) = i2c_transfer::slave_receive_counted_async(
+                self.regs,
+                &mut frame[len..len + 1],
+                false,
+            )
+            .await?;
+            if end != SlaveEnd::Reload {
+                return Ok((len + received, end));
+            }
+            len += 1;
+            let count = if len == 1 {
+                self.data_len(frame[0])
+            } else {
+                Some(frame[1] as usize)
+            };
+            if let Some(count) = count {
+                break count;
+            }
+        };
+        let frame_len = len + count;
+        let (received, end) = i2c_transfer::slave_receive_counted_async(
+            self.regs,
+            &mut frame[len..frame_len + 1],
+            true,
+        )
+        .await?;
+        Self::pec_frame(len + received, frame_len, end)
+    }
+
+    /// `received` bytes of a frame of `frame_len` bytes and its PEC arrived. A write ended with
+    /// STOP must carry the PEC, a repeated START comes before it.
+    fn pec_frame(
+        received: usize,
+        frame_len: usize,
+        end: SlaveEnd,
+    ) -> Result<(usize, SlaveEnd), SmbusError> {
+        match end {
+            SlaveEnd::Stop if received != frame_len + 1 => Err(SmbusError::Pec),
+            _ => Ok((received.min(frame_len), end)),
+        }
+    }
+
+    fn transmit(&self, data: &[u8]) -> Result<(usize, SlaveEnd), I2cError> {
+        if self.pec {
+            i2c_transfer::slave_transmit_pec(self.regs, data)
+        } else {
+            i2c_transfer::slave_accept(self.regs);
+            i2c_transfer::slave_transmit(self.regs, data)
+        }
+    }
+
+    async fn transmit_async(&self, data: &[u8]) -> Result<(usize, SlaveEnd), I2cError> {
+        if self.pec {
+            i2c_transfer::slave_transmit_pec_async(self.regs, data).await
+        } else {
+            i2c_transfer::slave_accept(self.regs);
+            i2c_transfer::slave_transmit_async(self.regs, data).await
+        }
+    }
+
+    /// The host reads the alert response address while we pull SMBALERT low (`ALERTEN` makes
+    /// the peripheral acknowledge it).
+    fn is_alert_response(&self, matched: AddressMatch) -> bool {
+        matched.read && matched.addcode as u16 == ALERT_RESPONSE_ADDRESS
+    }
+
+    fn serve_impl(&mut self) -> Result<(), SmbusError> {
+        let mut written = [0u8; FRAME_LEN];
+        let mut frame = [0u8; FRAME_LEN];
+        let mut len = 0;
+        let matched = i2c_transfer::slave_wait_match(self.regs)?;
+        if self.is_alert_response(matched) {
+            self.transmit(&[(self.addr as u8) << 1])?;
+            self.handler.alert_answered();
+            return Ok(());
+        }
+        if !matched.read {
+            let (received, end) = self.receive(&mut written)?;
+            if end == SlaveEnd::Stop {
+                self.written(&written[..received]);
+                return Ok(());
+            }
+            len = received;
+        }
+        let response = self.response(&written[..len], &mut frame);
+        let (sent, _) = self.transmit(&frame[..response])?;
+        if sent == 0 && len == 0 {
+            self.handler.quick_command(true);
+        }
+        Ok(())
+    }
+
+    /// Serve one transaction addressed to us.
+    pub fn serve(&mut self) -> Result<(), SmbusError> {
+        let res = self.serve_impl();
+        if res.as_ref().is_err_and(SmbusError::needs_abort) {
+            self.regs.abort();
+        }
+        res
+    }
+
+    async fn serve_async_impl(&mut self) -> Result<(), SmbusError> {
+        let mut written = [0u8; FRAME_LEN];
+        let mut frame = [0u8; FRAME_LEN];
+        let mut len = 0;
+        let matched = i2c_transfer::slave_wait_match_async(self.regs).await?;
+        if self.is_alert_response(matched) {
+            self.transmit_async(&[(self.addr as u8) << 1]).await?;
+            self.handler.alert_answered();
+            return Ok(());
+        }
+        if !matched.read {
+            let (received, end) = self.receive_async(&mut written).await?;
+            if end == SlaveEnd::Stop {
+                self.written(&written[..received]);
+                return Ok(());
+            }
+            len = received;
+        }
+        let response = self.response(&written[..len], &mut frame);
+        let (sent, _) = self.transmit_async(&frame[..response]).await?;
+        if sent == 0 && len == 0 {
+            self.handler.quick_command(true);
+        }
+        Ok(())
+    }
+
+    pub async fn serve_async(&mut self) -> Result<(), SmbusError> {
+        let res = self.serve_async_impl().await;
+        if res.as_ref().is_err_and(SmbusError::needs_abort) {
+            self.regs.abort();
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::i2c_transfer::mock::MockRegs;
+    use futures::executor::block_on;
+
+    #[test]
+    fn test_pec() {
+        // CRC-8/SMBUS check value
+        assert_eq!(pec(b"123456789"), 0xf4);
+        assert_eq!(pec(&[]), 0);
+        // write byte 0x55 to command 0x01 of device 0x5a (SMBus 3.0 appendix)
+        let mut inc = Pec::new();
+        inc.address(0x5a, false);
+        inc.update(&[0x01]);
+        inc.update(&[0x55]);
+        assert_eq!(inc.value(), pec(&[0xb4, 0x01, 0x55]));
+    }
+
+    #[test]
+    fn test_timeout_value() {
+        // 25 ms at 16 MHz: 195.3 ticks of 2048 cycles
+        assert_eq!(timeout_value(16_000_000, 25_000), Some(195));
+        assert_eq!(timeout_value(16_000_000, 0), Some(0));
+        // at most 4096 * 2048 kernel cycles
+        assert_eq!(timeout_value(160_000_000, 52_428), Some(0xfff));
+        assert_eq!(timeout_value(160_000_000, 60_000), None);
+    }
+
+    #[test]
+    fn test_host_write_word() {
+        let regs = MockRegs::new(&[]);
+        let host = SmbusHost::new(&regs, true);
+        assert!(host.write_word(0x0b, 0x09, 0x1234).is_ok());
+        let m = regs.model.borrow();
+        assert_eq!(m.starts, [(0x0b, false)]);
+        // the peripheral appends the PEC
+        assert!(m.chunks[0].pec);
+        assert_eq!(
+            m.written,
+            [0x09, 0x34, 0x12, pec(&[0x16, 0x09, 0x34, 0x12])]
+        );
+        assert_eq!(m.stops, 1);
+        drop(m);
+
+        let regs = MockRegs::new(&[]);
+        let host = SmbusHost::new(&regs, false);
+        assert!(block_on(host.send_byte_async(0x0b, 0x03)).is_ok());
+        assert_eq!(regs.model.borrow().written, [0x03]);
+    }
+
+    #[test]
+    fn test_host_read_word() {
+        let word_pec = pec(&[0x16, 0x08, 0x17, 0xcd, 0xab]);
+        let regs = MockRegs::new(&[0xcd, 0xab, word_pec]);
+        let host = SmbusHost::new(&regs, true);
+        assert_eq!(host.read_word(0x0b, 0x08), Ok(0xabcd));
+        let m = regs.model.borrow();
+        assert_eq!(m.starts, [(0x0b, false), (0x0b, true)]);
+        assert_eq!(m.written, [0x08]);
+        drop(m);
+
+        // corrupted PEC, checked by the peripheral
+        let regs = MockRegs::new(&[0xcd, 0xab, word_pec ^ 1]);
+        let host = SmbusHost::new(&regs, true);
+        assert_eq!(
+            block_on(host.read_word_async(0x0b, 0x08)),
+            Err(SmbusError::Pec)
+        );
+        assert!(regs.model.borrow().aborted);
+
+        // receive byte has no write phase
+        let regs = MockRegs::new(&[0x42, pec(&[0x17, 0x42])]);
+        let host = SmbusHost::new(&regs, true);
+        assert_eq!(host.receive_byte(0x0b), Ok(0x42));
+        assert_eq!(regs.model.borrow().starts, [(0x0b, true)]);
+    }
+
+    #[test]
+    fn test_host_process_call() {
+        let regs = MockRegs::new(&[0x78, 0x56, pec(&[0x20, 0x01, 0x34, 0x12, 0x21, 0x78, 0x56])]);
+        let host = SmbusHost::new(&regs, true);
+        assert_eq!(host.process_call(0x10, 0x01, 0x1234), Ok(0x5678));
+        assert_eq!(regs.model.borrow().written, [0x01, 0x34, 0x12]);
+    }
+
+    #[test]
+    fn test_host_block() {
+        let block = b"bq40z50";
+        let mut incoming = vec![block.len() as u8];
+        incoming.extend_from_slice(block);
+        let mut frame = vec![0x16, 0x21, 0x17];
+        frame.extend_from_slice(&incoming);
+        incoming.push(pec(&frame));
+
+        let regs = MockRegs::new(&incoming);
+        let host = SmbusHost::new(&regs, true);
+        let mut buf = [0u8; 32];
+        assert_eq!(host.block_read(0x0b, 0x21, &mut buf), Ok(block.len()));
+        assert_eq!(&buf[..block.len()], block);
+        let m = regs.model.borrow();
+        // count byte held with RELOAD, the rest sized by it
+        assert_eq!(
+            m.chunks.iter().map(|c| c.nbytes).collect::<Vec<_>>(),
+            [1, 1, 8]
+        );
+        assert_eq!(m.stops, 1);
+        drop(m);
+
+        // block longer than the buffer
+        let regs = MockRegs::new(&incoming);
+        let host = SmbusHost::new(&regs, true);
+        let mut buf = [0u8; 4];
+        assert_eq!(
+            block_on(host.block_read_async(0x0b, 0x21, &mut buf)),
+            Err(SmbusError::BlockLength)
+        );
+        assert_eq!(regs.model.borrow().stops, 1);
+
+        let regs = MockRegs::new(&[]);
+        let host = SmbusHost::new(&regs, true);
+        assert!(host.block_write(0x0b, 0x44, &[1, 2, 3]).is_ok());
+        assert_eq!(
+            regs.model.borrow().written,
+            [0x44, 3, 1, 2, 3, pec(&[0x16, 0x44, 3, 1, 2, 3])]
+        );
+        assert_eq!(
+            host.block_write(0x0b, 0x44, &[0; MAX_BLOCK_LEN + 1]),
+            Err(SmbusError::BlockLength)
+        );
+    }
+
+    #[test]
+    fn test_host_quick_command() {
+        let regs = MockRegs::new(&[]);
+        let host = SmbusHost::new(&regs, true);
+        assert!(host.quick_command(0x0b, true).is_ok());
+        let m = regs.model.borrow();
+        assert_eq!(m.starts, [(0x0b, true)]);
+        assert!(m.written.is_empty());
+    }
+
+    #[derive(Default)]
+    struct Battery {
+        writes: Vec<(u8, Vec<u8>)>,
+        reads: Vec<(Option<u8>, Vec<u8>)>,
+    }
+
+    impl SmbusHandler for Battery {
+        fn write(&mut self, command: u8, data: &[u8]) {
+            self.writes.push((command, data.to_vec()));
+        }
+
+        fn write_len(&mut self, command: u8) -> Option<usize> {
+            match command {
+                // ManufacturerBlockAccess
+                0x44 => None,
+                0x09 => Some(2),
+                _ => Some(0),
+            }
+        }
+
+        fn read(&mut self, command: Option<u8>, written: &[u8], response: &mut [u8]) -> usize {
+            self.reads.push((command, written.to_vec()));
+            response[..2].copy_from_slice(&[0x34, 0x12]);
+            2
+        }
+    }
+
+    /// Device 0x0b, addressed by the host.
+    fn device_regs(incoming: &[u8], controller_reads: bool, tx_len: usize) -> MockRegs {
+        let regs = MockRegs::slave_addressed(incoming, controller_reads, tx_len);
+        regs.model.borrow_mut().addcode = 0x0b;
+        regs
+    }
+
+    #[test]
+    fn test_device_write_word() {
+        let frame = [0x09, 0x34, 0x12, pec(&[0x16, 0x09, 0x34, 0x12])];
+        let regs = device_regs(&frame, false, 0);
+        let mut device = SmbusDevice::new(&regs, 0x0b, true, Battery::default());
+        assert!(device.serve().is_ok());
+        assert_eq!(device.handler().writes, [(0x09, vec![0x34, 0x12])]);
+        // command, then the word and its PEC
+        let m = regs.model.borrow();
+        assert_eq!(
+            m.chunks
+                .iter()
+                .map(|c| (c.nbytes, c.pec))
+                .collect::<Vec<_>>(),
+            [(1, false), (3, true)]
+        );
+        drop(m);
+
+        // a wrong PEC is NACKed by the peripheral and not handed to the application
+        let regs = device_regs(&[0x09, 0x34, 0x12, frame[3] ^ 1], false, 0);
+        let mut device = SmbusDevice::new(&regs, 0x0b, true, Battery::default());
+        assert_eq!(device.serve(), Err(SmbusError::Pec));
+        assert!(device.handler().writes.is_empty());
+        assert!(regs.model.borrow().aborted);
+
+        // missing PEC
+        let regs = device_regs(&frame[..3], false, 0);
+        let mut device = SmbusDevice::new(&regs, 0x0b, true, Battery::default());
+        assert_eq!(block_on(device.serve_async()), Err(SmbusError::Pec));
+        assert!(device.handler().writes.is_empty());
+    }
+
+    #[test]
+    fn test_device_block_write() {
+        let frame = [0x44, 3, 1, 2, 3, pec(&[0x16, 0x44, 3, 1, 2, 3])];
+        let regs = device_regs(&frame, false, 0);
+        let mut device = SmbusDevice::new(&regs, 0x0b, true, Battery::default());
+        assert!(block_on(device.serve_async()).is_ok());
+        assert_eq!(device.handler().writes, [(0x44, vec![3, 1, 2, 3])]);
+        // the byte count sizes the rest of the frame
+        assert_eq!(
+            regs.model
+                .borrow()
+                .chunks
+                .iter()
+                .map(|c| c.nbytes)
+                .collect::<Vec<_>>(),
+            [1, 1, 4]
+        );
+    }
+
+    #[test]
+    fn test_device_read_word() {
+        // write the command, repeated START, read word and PEC
+        let regs = device_regs(&[0x08], false, 0);
+        regs.model.borrow_mut().then_read = Some(3);
+        let mut device = SmbusDevice::new(&regs, 0x0b, true, Battery::default());
+        assert!(device.serve().is_ok());
+        assert_eq!(device.handler().reads, [(Some(0x08), vec![])]);
+        assert_eq!(
+            regs.model.borrow().written,
+            [0x34, 0x12, pec(&[0x16, 0x08, 0x17, 0x34, 0x12])]
+        );
+
+        // alert response
+        let regs = MockRegs::slave_addressed(&[], true, 2);
+        regs.model.borrow_mut().addcode = ALERT_RESPONSE_ADDRESS as u8;
+        let mut device = SmbusDevice::new(&regs, 0x0b, true, Battery::default());
+        assert!(device.serve().is_ok());
+        assert!(device.handler().reads.is_empty());
+        assert_eq!(regs.model.borrow().written, [0x16, pec(&[0x19, 0x16])]);
+
+        // receive byte
+        let regs = device_regs(&[], true, 3);
+        let mut device = SmbusDevice::new(&regs, 0x0b, false, Battery::default());
+        assert!(block_on(device.serve_async()).is_ok());
+        assert_eq!(device.handler().reads, [(None, vec![])]);
+        assert_eq!(regs.model.borrow().written, [0x34, 0x12, 0xff]);
+    }
+}
@@ -0,0 +1,659 @@
+//! SPI driver for SPI1, SPI2 and SPI3 (master and slave).
+//! The kernel clock is HSI16, so the maximum SCK frequency is 8Mhz.
+//! Transfers are blocking, interrupt driven or dma driven (8-bit frames only).
+#![allow(unused)]
+
+use crate::clock;
+use crate::dma::{DmaChannel, Transfer};
+use crate::gpio::{
+    GpioPort, SPI1_MISO_PINS, SPI1_MOSI_PINS, SPI1_NSS_PINS, SPI1_SCK_PINS, SPI2_MISO_PINS, SPI2_MOSI_PINS,
+    SPI2_NSS_PINS, SPI2_SCK_PINS, SPI3_MISO_PINS, SPI3_MOSI_PINS, SPI3_NSS_PINS, SPI3_SCK_PINS,
+};
+use crate::hal;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::task::Poll;
+use cortex_m::peripheral::NVIC;
+use embassy_sync::waitqueue::AtomicWaker;
+use stm32_metapac::interrupt;
+use stm32_metapac::spi::vals::{Comm, Cpha, Cpol, Lsbfirst, Master, Mbr};
+
+static TAKEN: [AtomicBool; 4] = [const { AtomicBool::new(false) }; 4]; // first bit will be ignored
+static WAKERS: [AtomicWaker; 4] = [const { AtomicWaker::new() }; 4];
+
+/// transfers shorter than this are not worth setting up the dma
+const DMA_THRESHOLD: usize = 32;
+/// TSIZE is a 16-bit field on SPI1 and SPI2, longer transfers are split
+const MAX_TSIZE: usize = 0xFFFF;
+/// SPI3 is a limited feature instance with a 10-bit TSIZE
+const MAX_TSIZE_SPI3: usize = 0x3FF;
+
+/// dma source for write-only transfers
+static DMA_DUMMY_TX: u8 = 0;
+/// dma destination for read data that is discarded
+static mut DMA_DUMMY_RX: u8 = 0;
+
+pub struct Spi {
+    port_num: u8,
+    port: stm32_metapac::spi::Spi,
+    frame: hal::SpiFrameSize,
+    master: bool,
+    freq: u32,
+    /// (tx, rx) dma channels, None means interrupt mode
+    dma: Option<(DmaChannel, DmaChannel)>,
+}
+
+impl Drop for Spi {
+    fn drop(&mut self) {
+        self.port.cr1().modify(|v| v.set_spe(false));
+        TAKEN[self.port_num as usize].store(false, Ordering::SeqCst);
+    }
+}
+
+pub fn port_num_to_spi(port_num: u8) -> stm32_metapac::spi::Spi {
+    match port_num {
+        1 => stm32_metapac::SPI1,
+        2 => stm32_metapac::SPI2,
+        3 => stm32_metapac::SPI3,
+        _ => panic!("invalid port number"),
+    }
+}
+
+pub fn pin_to_port(sck_pin: &GpioPort, miso_pin: &GpioPort, mosi_pin: &GpioPort) -> u8 {
+    if SPI1_SCK_PINS.contains(sck_pin) && SPI1_MISO_PINS.contains(miso_pin) && SPI1_MOSI_PINS.contains(mosi_pin) {
+        1
+    } else if SPI2_SCK_PINS.contains(sck_pin) && SPI2_MISO_PINS.contains(miso_pin) && SPI2_MOSI_PINS.contains(mosi_pin)
+    {
+        2
+    } else if SPI3_SCK_PINS.contains(sck_pin) && SPI3_MISO_PINS.contains(miso_pin) && SPI3_MOSI_PINS.contains(mosi_pin)
+    {
+        3
+    } else {
+        panic!("invalid sck, miso and mosi pins or not implemented!");
+    }
+}
+
+fn nss_pin_valid(port_num: u8, nss_pin: &GpioPort) -> bool {
+    match port_num {
+        1 => SPI1_NSS_PINS.contains(nss_pin),
+        2 => SPI2_NSS_PINS.contains(nss_pin),
+        3 => SPI3_NSS_PINS.contains(nss_pin),
+        _ => false,
+    }
+}
+
+/// Return the baud rate divider (MBR) and the resulting SCK frequency.
+/// The highest frequency that is not higher than `freq` is selected.
+fn calc_mbr(kernel_freq: u32, freq: u32) -> (u8, u32) {
+    let mut mbr = 0;
+    // MBR = 0 -> /2, MBR = 7 -> /256
+    while mbr < 7 && kernel_freq / (2 << mbr) > freq {
+        mbr += 1;
+    }
+    (mbr, kernel_freq / (2 << mbr))
+}
+
+fn mode_to_cpol_cpha(mode: hal::SpiMode) -> (Cpol, Cpha) {
+    match mode {
+        hal::SpiMode::Mode0 => (Cpol::IDLE_LOW, Cpha::FIRST_EDGE),
+        hal::SpiMode::Mode1 => (Cpol::IDLE_LOW, Cpha::SECOND_EDGE),
+        hal::SpiMode::Mode2 => (Cpol::IDLE_HIGH, Cpha::FIRST_EDGE),
+        hal::SpiMode::Mode3 => (Cpol::IDLE_HIGH, Cpha::SECOND_EDGE),
+    }
+}
+
+fn unmask_interrupt(port_num: u8) {
+    unsafe {
+        match port_num {
+            1 => NVIC::unmask(interrupt::SPI1),
+            2 => NVIC::unmask(interrupt::SPI2),
+            3 => NVIC::unmask(interrupt::SPI3),
+            _ => {}
+        }
+    }
+}
+
+fn check_error(sr: stm32_metapac::spi::regs::Sr) -> Result<(), hal::SpiError> {
+    if sr.ovr() {
+        Err(hal::SpiError::Overrun)
+    } else if sr.modf() {
+        Err(hal::SpiError::ModeFault)
+    } else if sr.udr() {
+        Err(hal::SpiError::BusError)
+    } else {
+        Ok(())
+    }
+}
+
+impl Spi {
+    fn init(port_num: u8, mode: hal::SpiMode, frame: hal::SpiFrameSize, master: bool, mbr: u8) -> Self {
+        let port = port_num_to_spi(port_num);
+        port.cr1().modify(|v| v.set_spe(false));
+        clock::delay_tick(6);
+        let (cpol, cpha) = mode_to_cpol_cpha(mode);
+        port.cfg2().modify(|v| {
+            v.set_cpol(cpol);
+            v.set_cpha(cpha);
+            v.set_lsbfirst(Lsbfirst::MSBFIRST);
+            v.set_comm(Comm::FULL_DUPLEX);
+            if master {
+                // software nss, the chip select is driven by the user with a gpio
+                v.set_master(Master::MASTER);
+                v.set_ssm(true);
+                v.set_afcntr(true); // keep the pins driven when spi is disabled
+            } else {
+                v.set_master(Master::SLAVE);
+                v.set_ssm(false);
+            }
+        });
+        if master {
+            port.cr1().modify(|v| v.set_ssi(true));
+        }
+        port.cfg1().modify(|v| {
+            v.set_mbr(Mbr::from_bits(mbr));
+            v.set_crcen(false);
+            v.set_dsize(match frame {
+                hal::SpiFrameSize::Bits8 => 7,
+                hal::SpiFrameSize::Bits16 => 15,
+            });
+            v.set_fthlv(stm32_metapac::spi::vals::Fthlv::ONE_FRAME);
+        });
+        unmask_interrupt(port_num);
+        Spi {
+            port_num,
+            port,
+            frame,
+            master,
+            freq: 0,
+            dma: None,
+        }
+    }
+
+    /// Use dma for transfers of at least `DMA_THRESHOLD` bytes. Only 8-bit frames are transferred with dma.
+    /// for example: `spi.set_dma(DmaChannel::take("SPI1_TX", Dw::BYTE)?, DmaChannel::take("SPI1_RX", Dw::BYTE)?)`
+    pub fn set_dma(&mut self, tx: DmaChannel, rx: DmaChannel) {
+        self.dma = Some((tx, rx));
+    }
+
+    fn frame_bytes(&self) -> usize {
+        match self.frame {
+            hal::SpiFrameSize::Bits8 => 1,
+            hal::SpiFrameSize::Bits16 => 2,
+        }
+    }
+
+    /// the most frames one transfer (`TSIZE`) can hold
+    fn max_tsize(&self) -> usize {
+        if self.port_num == 3 {
+            MAX_TSIZE_SPI3
+        } else {
+            MAX_TSIZE
+        }
+    }
+
+    /// the number of bytes to transfer, both buffers hold whole frames
+    fn transfer_len(&self, write_data: &[u8], read_data: &[u8]) -> Result<usize, hal::SpiError> {
+        let step = self.frame_bytes();
+        if !write_data.len().is_multiple_of(step) || !read_data.len().is_multiple_of(step) {
+            return Err(hal::SpiError::FrameLength);
+        }
+        Ok(write_data.len().max(read_data.len()))
+    }
+
+    /// program the number of frames and start the transfer
+    fn begin(&self, frames: usize) {
+        self.port.cr1().modify(|v| v.set_spe(false));
+        self.port.ifcr().write(|v| {
+            v.set_eotc(true);
+            v.set_txtfc(true);
+            v.set_ovrc(true);
+            v.set_modfc(true);
+            v.set_udrc(true);
+        });
+        self.port.cr2().modify(|v| v.set_tsize(frames as u16));
+        self.port.cr1().modify(|v| v.set_spe(true));
+        if self.master {
+            self.port.cr1().modify(|v| v.set_cstart(true));
+        }
+    }
+
+    fn end(&self) {
+        self.port.ier().write(|v| v.0 = 0);
+        self.port.ifcr().write(|v| {
+            v.set_eotc(true);
+            v.set_txtfc(true);
+            v.set_ovrc(true);
+            v.set_modfc(true);
+            v.set_udrc(true);
+        });
+        self.port.cfg1().modify(|v| {
+            v.set_txdmaen(false);
+            v.set_rxdmaen(false);
+        });
+        self.port.cr1().modify(|v| v.set_spe(false));
+    }
+
+    fn write_frame(&self, write_data: &[u8], i: usize) {
+        let b = |n: usize| write_data.get(n).copied().unwrap_or(0);
+        unsafe {
+            match self.frame {
+                hal::SpiFrameSize::Bits8 => core::ptr::write_volatile(self.port.txdr().as_ptr() as *mut u8, b(i)),
+                hal::SpiFrameSize::Bits16 => core::ptr::write_volatile(
+                    self.port.txdr().as_ptr() as *mut u16,
+                    ((b(i) as u16) << 8) | b(i + 1) as u16,
+                ),
+            }
+        }
+    }
+
+    fn read_frame(&self, read_data: &mut [u8], i: usize) {
+        unsafe {
+            match self.frame {
+                hal::SpiFrameSize::Bits8 => {
+                    let val = core::ptr::read_volatile(self.port.rxdr().as_ptr() as *const u8);
+                    if i < read_data.len() {
+                        read_data[i] = val;
+                    }
+                }
+                hal::SpiFrameSize::Bits16 => {
+                    let val = core::ptr::read_volatile(self.port.rxdr().as_ptr() as *const u16);
+                    // the length is a multiple of the frame, checked by `transfer_len`
+                    if i < read_data.len() {
+                        read_data[i] = (val >> 8) as u8;
+                        read_data[i + 1] = val as u8;
+                    }
+                }
+            }
+        }
+    }
+
+    fn transfer_blocking(&self, write_data: &[u8], read_data: &mut [u8]) -> Result<(), hal::SpiError> {
+        let step = self.frame_bytes();
+        let len = self.transfer_len(write_data, read_data)?;
+        let res = self.transfer_blocking_impl(write_data, read_data, len, step);
+        self.end();
+        res
+    }
+
+    fn transfer_blocking_impl(
+        &self,
+        write_data: &[u8],
+        read_data: &mut [u8],
+        len: usize,
+        step: usize,
+    ) -> Result<(), hal::SpiError> {
+        let max = self.max_tsize() * step;
+        for chunk in (0..len).step_by(max) {
+            let chunk_end = len.min(chunk + max);
+            self.begin((chunk_end - chunk) / step);
+            for i in (chunk..chunk_end).step_by(step) {
+                loop {
+                    let sr = self.port.sr().read();
+                    check_error(sr)?;
+                    if sr.txp() {
+                        break;
+                    }
+                }
+                self.write_frame(write_data, i);
+                loop {
+                    let sr = self.port.sr().read();
+                    check_error(sr)?;
+                    if sr.rxp() {
+                        break;
+                    }
+                }
+                self.read_frame(read_data, i);
+            }
+            while !self.port.sr().read().eot() {}
+        }
+        Ok(())
+    }
+
+    pub async fn transfer_async_interrupt(&self, write_data: &[u8], read_data: &mut [u8]) -> Result<(), hal::SpiError> {
+        let step = self.frame_bytes();
+        let len = self.transfer_len(write_data, read_data)?;
+        let res = self
+            .transfer_async_interrupt_impl(write_data, read_data, len, step)
+            .await;
+        self.end();
+        res
+    }
+
+    async fn transfer_async_interrupt_impl(
+        &self,
+        write_data: &[u8],
+        read_data: &mut [u8],
+        len: usize,
+        step: usize,
+    ) -> Result<(), hal::SpiError> {
+        let max = self.max_tsize() * step;
+        for chunk in (0..len).step_by(max) {
+            let chunk_end = len.min(chunk + max);
+            self.begin((chunk_end - chunk) / step);
+            for i in (chunk..chunk_end).step_by(step) {
+                core::future::poll_fn(|cx| {
+                    WAKERS[self.port_num as usize].register(cx.waker());
+                    self.port.ier().modify(|v| {
+                        v.set_txpie(true);
+                        v.set_ovrie(true);
+                        v.set_modfie(true);
+                    });
+                    let sr = self.port.sr().read();
+                    if let Err(e) = check_error(sr) {
+                        Poll::Ready(Err(e))
+                    } else if sr.txp() {
+                        Poll::Ready(Ok(()))
+                    } else {
+                        Poll::Pending
+                    }
+                })
+                .await?;
+                self.write_frame(write_data, i);
+                core::future::poll_fn(|cx| {
+                    WAKERS[self.port_num as usize].register(cx.waker());
+                    self.port.ier().modify(|v| {
+                        v.set_rxpie(true);
+                        v.set_ovrie(true);
+                        v.set_modfie(true);
+                    });
+                    let sr = self.port.sr().read();
+                    if let Err(e) = check_error(sr) {
+                        Poll::Ready(Err(e))
+                    } else if sr.rxp() {
+                        Poll::Ready(Ok(()))
+                    } else {
+                        Poll::Pending
+                    }
+                })
+                .await?;
+                self.read_frame(read_data, i);
+            }
+            self.wait_eot_async().await?;
+        }
+        Ok(())
+    }
+
+    async fn wait_eot_async(&self) -> Result<(), hal::SpiError> {
+        core::future::poll_fn(|cx| {
+            WAKERS[self.port_num as usize].register(cx.waker());
+            self.port.ier().modify(|v| {
+                v.set_eotie(true);
+                v.set_ovrie(true);
+                v.set_modfie(true);
+            });
+            let sr = self.port.sr().read();
+            if let Err(e) = check_error(sr) {
+                Poll::Ready(Err(e))
+            } else if sr.eot() {
+                Poll::Ready(Ok(()))
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// dma can be used when dma channels are assigned, the frame is 8-bit and each buffer is either
+    /// empty or covers the whole transfer.
+    fn can_use_dma(&self, write_data: &[u8], read_data: &[u8]) -> bool {
+        let len = write_data.len().max(read_data.len());
+        self.dma.is_some()
+            && self.frame == hal::SpiFrameSize::Bits8
+            && len >= DMA_THRESHOLD
+            && (write_data.is_empty() || write_data.len() == len)
+            && (read_data.is_empty() || read_data.len() == len)
+    }
+
+    pub async fn transfer_async_dma(&self, write_data: &[u8], read_data: &mut [u8]) -> Result<(), hal::SpiError> {
+        let res = self.transfer_async_dma_impl(write_data, read_data).await;
+        if let Some((tx, rx)) = &self.dma {
+            tx.stop();
+            rx.stop();
+        }
+        self.end();
+        res
+    }
+
+    async fn transfer_async_dma_impl(&self, write_data: &[u8], read_data: &mut [u8]) -> Result<(), hal::SpiError> {
+        let (tx, rx) = match &self.dma {
+            Some(dma) => dma,
+            None => return Err(hal::SpiError::InitError),
+        };
+        let len = write_data.len().max(read_data.len());
+        let txdr = self.port.txdr().as_ptr() as u32;
+        let rxdr = self.port.rxdr().as_ptr() as u32;
+        let max = self.max_tsize();
+        for chunk in (0..len).step_by(max) {
+            let chunk_len = (len - chunk).min(max);
+            self.port.cr1().modify(|v| v.set_spe(false));
+            self.port.cr2().modify(|v| v.set_tsize(chunk_len as u16));
+            // rx dma has to be enabled before tx dma, refer to rm0456 "communication using DMA"
+            self.port.cfg1().modify(|v| v.set_rxdmaen(true));
+            // both channels are started before the transfer runs, each needs the other side
+            let mut rx_transfer = if read_data.is_empty() {
+                let dst = unsafe { core::ptr::addr_of_mut!(DMA_DUMMY_RX) } as u32;
+                // the dummy byte is static
+                unsafe { rx.start(rxdr, false, dst, false, chunk_len as u32) }?
+            } else {
+                // dropped (and aborted) at the end of the chunk, before the borrow ends
+                unsafe { Transfer::read(rx, rxdr, &mut read_data[chunk..chunk + chunk_len]) }?
+            };
+            let mut tx_transfer = if write_data.is_empty() {
+                let src = &DMA_DUMMY_TX as *const u8 as u32;
+                unsafe { tx.start(src, false, txdr, false, chunk_len as u32) }?
+            } else {
+                unsafe { Transfer::write(tx, &write_data[chunk..chunk + chunk_len], txdr) }?
+            };
+            self.port.cfg1().modify(|v| v.set_txdmaen(true));
+            self.port.ifcr().write(|v| {
+                v.set_eotc(true);
+                v.set_txtfc(true);
+            });
+            self.port.cr1().modify(|v| v.set_spe(true));
+            if self.master {
+                self.port.cr1().modify(|v| v.set_cstart(true));
+            }
+            self.wait_eot_async().await?;
+            tx_transfer.wait().await?;
+            rx_transfer.wait().await?;
+            self.port.cfg1().modify(|v| {
+                v.set_txdmaen(false);
+                v.set_rxdmaen(false);
+            });
+        }
+        Ok(())
+    }
+
+    pub async fn transfer_async(&self, write_data: &[u8], read_data: &mut [u8]) -> Result<(), hal::SpiError> {
+        if self.can_use_dma(write_data, read_data) {
+            self.transfer_async_dma(write_data, read_data).await
+        } else {
+            self.transfer_async_interrupt(write_data, read_data).await
+        }
+    }
+}
+
+/////////////////////////// HAL implementation /////////////////////////////
+impl hal::Spi<GpioPort> for Spi {
+    fn new(
+        freq: u32,
+        mode: hal::SpiMode,
+        frame: hal::SpiFrameSize,
+        sck_pin: GpioPort,
+        miso_pin: GpioPort,
+        mosi_pin: GpioPort,
+    ) -> Result<Self, hal::SpiError> {
+        if freq == 0 {
+            return Err(hal::SpiError::InitError);
+        }
+        let port_num = pin_to_port(&sck_pin, &miso_pin, &mosi_pin);
+        if TAKEN[port_num as usize].swap(true, Ordering::SeqCst) {
+            return Err(hal::SpiError::InitError);
+        }
+        sck_pin.setup();
+        miso_pin.setup();
+        mosi_pin.setup();
+        let kernel_freq = clock::set_spi_clock(port_num);
+        let (mbr, actual_freq) = calc_mbr(kernel_freq, freq);
+        let mut spi = Spi::init(port_num, mode, frame, true, mbr);
+        spi.freq = actual_freq;
+        Ok(spi)
+    }
+
+    fn write(&self, data: &[u8]) -> Result<(), hal::SpiError> {
+        self.transfer_blocking(data, &mut [])
+    }
+
+    fn write_async(&self, data: &[u8]) -> impl core::future::Future<Output = Result<(), hal::SpiError>> + Send {
+        self.transfer_async(data, &mut [])
+    }
+
+    fn read(&self, data: &mut [u8]) -> Result<(), hal::SpiError> {
+        self.transfer_blocking(&[], data)
+    }
+
+    fn read_async(&self, data: &mut [u8]) -> impl core::future::Future<Output = Result<(), hal::SpiError>> + Send {
+        self.transfer_async(&[], data)
+    }
+
+    fn transfer(&self, write_data: &[u8], read_data: &mut [u8]) -> Result<(), hal::SpiError> {
+        self.transfer_blocking(write_data, read_data)
+    }
+
+    fn transfer_async(
+        &self,
+        write_data: &[u8],
+        read_data: &mut [u8],
+    ) -> impl core::future::Future<Output = Result<(), hal::SpiError>> + Send {
+        self.transfer_async(write_data, read_data)
+    }
+
+    fn frequency(&self) -> u32 {
+        self.freq
+    }
+}
+
+impl hal::SpiSlave<GpioPort> for Spi {
+    fn new_slave(
+        mode: hal::SpiMode,
+        frame: hal::SpiFrameSize,
+        sck_pin: GpioPort,
+        miso_pin: GpioPort,
+        mosi_pin: GpioPort,
+        nss_pin: GpioPort,
+    ) -> Result<Self, hal::SpiError> {
+        let port_num = pin_to_port(&sck_pin, &miso_pin, &mosi_pin);
+        if !nss_pin_valid(port_num, &nss_pin) {
+            panic!("invalid nss pin or not implemented!");
+        }
+        if TAKEN[port_num as usize].swap(true, Ordering::SeqCst) {
+            return Err(hal::SpiError::InitError);
+        }
+        sck_pin.setup();
+        miso_pin.setup();
+        mosi_pin.setup();
+        nss_pin.setup();
+        clock::set_spi_clock(port_num);
+        Ok(Spi::init(port_num, mode, frame, false, 0))
+    }
+
+    fn slave_transfer(&self, write_data: &[u8], read_data: &mut [u8]) -> Result<(), hal::SpiError> {
+        self.transfer_blocking(write_data, read_data)
+    }
+
+    fn slave_transfer_async(
+        &self,
+        write_data: &[u8],
+        read_data: &mut [u8],
+    ) -> impl core::future::Future<Output = Result<(), hal::SpiError>> + Send {
+        self.transfer_async(write_data, read_data)
+    }
+}
+
+/////////////////////////// embedded-hal implementation /////////////////////////////
+/// `transfer_in_place` goes through a small stack buffer, the length is a multiple of both frame sizes
+#[cfg(feature = "embedded-hal")]
+const IN_PLACE_CHUNK: usize = 16;
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_hal::spi::ErrorType for Spi {
+    type Error = hal::SpiError;
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_hal::spi::SpiBus for Spi {
+    fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.transfer_blocking(&[], words)
+    }
+
+    fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.transfer_blocking(words, &mut [])
+    }
+
+    fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        self.transfer_blocking(write, read)
+    }
+
+    fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        let mut tmp = [0u8; IN_PLACE_CHUNK];
+        for chunk in words.chunks_mut(IN_PLACE_CHUNK) {
+            tmp[..chunk.len()].copy_from_slice(chunk);
+            self.transfer_blocking(&tmp[..chunk.len()], chunk)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        // every transfer waits for EOT before returning
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_hal_async::spi::SpiBus for Spi {
+    async fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        self.transfer_async(&[], words).await
+    }
+
+    async fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+        self.transfer_async(words, &mut []).await
+    }
+
+    async fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+        self.transfer_async(write, read).await
+    }
+
+    async fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+        let mut tmp = [0u8; IN_PLACE_CHUNK];
+        for chunk in words.chunks_mut(IN_PLACE_CHUNK) {
+            tmp[..chunk.len()].copy_from_slice(chunk);
+            self.transfer_async(&tmp[..chunk.len()], chunk).await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[interrupt]
+fn SPI1() {
+    handle_spi_interrupt(1);
+}
+
+#[interrupt]
+fn SPI2() {
+    handle_spi_interrupt(2);
+}
+
+#[interrupt]
+fn SPI3() {
+    handle_spi_interrupt(3);
+}
+
+fn handle_spi_interrupt(port_num: u8) {
+    let port = port_num_to_spi(port_num);
+    WAKERS[port_num as usize].wake();
+    // mask all interrupts, the waiting task enables the ones it needs again
+    port.ier().write(|v| v.0 = 0);
+}
@@ -1,21 +1,34 @@
 //! USART Driver with Async Support and Separated TX/RX Wakers
+//!
+//! Supports USART1 - USART3, UART4, UART5 and LPUART1. The instance is selected by the TX/RX pins.
 #![allow(unused)]
 
-use crate::dma::DmaChannel;
-use crate::low_power::run_no_deep_sleep_async;
+use crate::byte_ring::ByteRing;
+use crate::dma::{DmaChannel, Transfer};
+use crate::dma_lli::MAX_BLOCK;
+use crate::dma_ring::{self, DmaRing};
+use crate::low_power::{self, run_no_deep_sleep_async};
 use crate::{
     clock,
-    gpio::{self, USART1_RX_PINS, USART1_TX_PINS},
-    hal,
+    gpio::{self, *},
+    hal, lin, modbus,
+    usart_config::{
+        baudrate_from_brr, compute_baud, compute_lpuart_baud, Oversampling, Parity, Rs485Config, StopBits, UsartConfig,
+        WordLength,
+    },
 };
+use core::cell::{Cell, RefCell};
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, AtomicUsize, Ordering};
 use core::task::Poll;
 use cortex_m::peripheral::NVIC;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
 use embassy_sync::waitqueue::AtomicWaker;
 use gpio::GpioPort;
 use stm32_metapac::interrupt;
 use stm32_metapac::{
     common::R,
-    usart::vals::{Over8, Stop, M0, M1},
+    usart::regs::Brr,
+    usart::vals::{Abrmod, Addm7, Dep, Lbdl, Msbfirst, Over8, Presc, Ps, Rxinv, Stop, Txinv, Wus, M0, M1},
 };
 
 pub struct Usart {
@@ -23,9 +36,21 @@ pub struct Usart {
     port_num: u8,
     use_dma: bool,
     dma: Option<DmaChannel>,
+    /// data bits of a received character, without the parity bit
+    data_mask: u16,
+    /// actual baud rate
+    baudrate: u32,
+    /// oversampling, for the baud rate found by the auto baud rate detection
+    oversampling: Oversampling,
+    /// reads may run in stop mode (`set_stop_wakeup`)
+    stop_wakeup: bool,
+    /// RTS flow control is enabled (`RTSE`)
+    rts: bool,
 }
 
 const USART_CLOCK: u32 = 16_000_000; // default use HSI16
+/// `port_num` of LPUART1, after USART1 - USART3, UART4 and UART5.
+const LPUART1_PORT: u8 = 6;
 
 #[derive(core::fmt::Debug)]
 pub enum UsartError {
@@ -38,21 +63,93 @@ pub enum UsartError {
 static RX_WAKERS: [AtomicWaker; 8] = [const { AtomicWaker::new() }; 8];
 static TX_WAKERS: [AtomicWaker; 8] = [const { AtomicWaker::new() }; 8];
 static TAKEN: [core::sync::atomic::AtomicBool; 8] = [const { core::sync::atomic::AtomicBool::new(false) }; 8];
+/// queued bytes of `BufferedUartTx`, drained by the interrupt handler
+static TX_RINGS: [ByteRing; 8] = [const { ByteRing::new() }; 8];
+/// dma channel draining `TX_RINGS`, None for TXE interrupt draining
+static TX_DMA: [Mutex<CriticalSectionRawMutex, RefCell<Option<DmaChannel>>>; 8] =
+    [const { Mutex::new(RefCell::new(None)) }; 8];
+/// bytes of `TX_RINGS` the dma is sending
+static TX_IN_FLIGHT: [AtomicUsize; 8] = [const { AtomicUsize::new(0) }; 8];
+/// `TX_RINGS` holds a `no_deep_sleep_request` until it is drained
+static TX_AWAKE: [AtomicBool; 8] = [const { AtomicBool::new(false) }; 8];
+/// A LIN break was detected, set by the interrupt which also drops the break character
+static LIN_BREAK: [AtomicBool; 8] = [const { AtomicBool::new(false) }; 8];
+/// an auto baud rate detection waits for ABRF/ABRE
+static ABR_PENDING: [AtomicBool; 8] = [const { AtomicBool::new(false) }; 8];
+
+// bits of `RX_ERRORS`, also the index in `ERROR_COUNTS`
+const RX_ERROR_FRAMING: u8 = 1 << 0;
+const RX_ERROR_NOISE: u8 = 1 << 1;
+const RX_ERROR_PARITY: u8 = 1 << 2;
+const RX_ERROR_OVERRUN: u8 = 1 << 3;
+/// receive errors recorded by the interrupt handler that were not reported to a reader yet
+static RX_ERRORS: [AtomicU8; 8] = [const { AtomicU8::new(0) }; 8];
+/// framing, noise, parity and overrun errors per port
+static ERROR_COUNTS: [[AtomicU32; 4]; 8] = [const { [const { AtomicU32::new(0) }; 4] }; 8];
+
+/// Receive errors of a port, see `Usart::error_counts`.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct UsartErrorCounts {
+    pub framing: u32,
+    pub noise: u32,
+    pub parity: u32,
+    pub overrun: u32,
+}
 
 fn port_num_to_usart(port_num: u8) -> stm32_metapac::usart::Usart {
     match port_num {
         1 => stm32_metapac::USART1,
         2 => stm32_metapac::USART2,
         3 => stm32_metapac::USART3,
+        4 => stm32_metapac::UART4,
+        5 => stm32_metapac::UART5,
+        // same register layout, except the 20-bit BRR and no OVER8
+        LPUART1_PORT => unsafe { stm32_metapac::usart::Usart::from_ptr(stm32_metapac::LPUART1.as_ptr()) },
         _ => panic!("invalid port number"),
     }
 }
 
+/// (TX, RX, RTS/DE, CTS) pins of the instances, indexed by `port_num - 1`
+type PortPins = (&'static [GpioPort], &'static [GpioPort], &'static [GpioPort], &'static [GpioPort]);
+const PINS: [PortPins; 6] = [
+    (&USART1_TX_PINS, &USART1_RX_PINS, &USART1_RTS_DE_PINS, &USART1_CTS_PINS),
+    (&USART2_TX_PINS, &USART2_RX_PINS, &USART2_RTS_DE_PINS, &USART2_CTS_PINS),
+    (&USART3_TX_PINS, &USART3_RX_PINS, &USART3_RTS_DE_PINS, &USART3_CTS_PINS),
+    (&UART4_TX_PINS, &UART4_RX_PINS, &UART4_RTS_DE_PINS, &UART4_CTS_PINS),
+    (&UART5_TX_PINS, &UART5_RX_PINS, &UART5_RTS_DE_PINS, &UART5_CTS_PINS),
+    (&LPUART1_TX_PINS, &LPUART1_RX_PINS, &LPUART1_RTS_DE_PINS, &LPUART1_CTS_PINS),
+];
+
 fn pin_to_port(tx: &gpio::GpioPort, rx: &gpio::GpioPort) -> u8 {
-    if USART1_TX_PINS.contains(tx) && USART1_RX_PINS.contains(rx) {
-        1
-    } else {
-        panic!("not defined");
+    for (i, (tx_pins, rx_pins, _, _)) in PINS.iter().enumerate() {
+        if tx_pins.contains(tx) && rx_pins.contains(rx) {
+            return i as u8 + 1;
+        }
+    }
+    panic!("not defined");
+}
+
+fn tx_pin_to_port(tx: &gpio::GpioPort) -> u8 {
+    for (i, (tx_pins, _, _, _)) in PINS.iter().enumerate() {
+        if tx_pins.contains(tx) {
+            return i as u8 + 1;
+        }
+    }
+    panic!("not defined");
+}
+
+fn unmask_interrupt(port_num: u8) {
+    unsafe {
+        match port_num {
+            1 => NVIC::unmask(interrupt::USART1),
+            2 => NVIC::unmask(interrupt::USART2),
+            3 => NVIC::unmask(interrupt::USART3),
+            4 => NVIC::unmask(interrupt::UART4),
+            5 => NVIC::unmask(interrupt::UART5),
+            LPUART1_PORT => NVIC::unmask(interrupt::LPUART1),
+            _ => {}
+        }
     }
 }
 
@@ -64,62 +161,24 @@ impl Drop for Usart {
 
 impl hal::Usart<GpioPort> for Usart {
     fn new(baudrate: u32, tx: GpioPort, rx: GpioPort) -> Result<Self, hal::UsartError> {
-        let port_num = pin_to_port(&tx, &rx);
-
-        if TAKEN[port_num as usize].swap(true, core::sync::atomic::Ordering::AcqRel) {
-            return Err(hal::UsartError::InitError);
-        }
-
-        tx.setup();
-        rx.setup();
-        clock::set_usart_clock();
-
-        let port = port_num_to_usart(port_num);
-
-        port.cr1().modify(|v| {
-            v.set_m0(M0::BIT8);
-            v.set_m1(M1::M0);
-            v.set_pce(false);
-            v.set_over8(Over8::OVERSAMPLING16);
-            v.set_ue(true);
-            v.set_te(true);
-            v.set_re(true);
-        });
-
-        port.cr2().modify(|v| {
-            v.set_stop(Stop::STOP1);
-        });
-
-        port.brr().write(|v| {
-            v.set_brr((USART_CLOCK / baudrate) as u16);
-        });
-
-        unsafe {
-            match port_num {
-                1 => NVIC::unmask(interrupt::USART1),
-                2 => NVIC::unmask(interrupt::USART2),
-                3 => NVIC::unmask(interrupt::USART3),
-                _ => {}
-            }
-        }
-
-        Ok(Usart {
-            port,
-            port_num,
-            use_dma: false,
-            dma: None,
-        })
+        Self::new_with_config(UsartConfig::new(baudrate), tx, rx)
     }
 
     fn read(&self, data: &mut [u8]) -> Result<(), hal::UsartError> {
         for i in 0..data.len() {
             while !self.port.isr().read().rxne() {}
-            data[i] = self.port.rdr().read().dr() as u8;
+            record_rx_errors(self.port, self.port_num as usize, self.port.isr().read());
+            data[i] = (self.port.rdr().read().dr() & self.data_mask) as u8;
+            take_rx_error(self.port_num as usize)?;
         }
         Ok(())
     }
 
     async fn read_async(&self, data: &mut [u8]) -> Result<(), hal::UsartError> {
+        if self.stop_wakeup && !self.use_dma {
+            // the usart wakes the core from stop mode
+            return self.read_async_interrupt(data).await;
+        }
         return run_no_deep_sleep_async(|| {
             if self.use_dma {
                 #[cfg(feature = "usart_dma")]
@@ -167,13 +226,262 @@ impl hal::Usart<GpioPort> for Usart {
 }
 
 impl Usart {
-    // todo: test this function
+    /// Create a USART with the frame format and baud rate of `config`.
+    ///
+    /// The baud rate is derived from the HSI16 kernel clock, configurations whose error exceeds
+    /// `config.max_error_ppm` fail with `InitError`. LPUART1 only supports 1 and 2 stop bits.
+    pub fn new_with_config(config: UsartConfig, tx: GpioPort, rx: GpioPort) -> Result<Self, hal::UsartError> {
+        let port_num = pin_to_port(&tx, &rx);
+        Self::open(config, port_num, || {
+            tx.setup();
+            rx.setup();
+        })
+    }
+
+    /// Create a USART with hardware flow control: the receiver drives `rts` low while it can take
+    /// data (`RTSE`), the transmitter only starts a character while `cts` is low (`CTSE`). Either
+    /// pin can be left out; pins that do not belong to the instance of `tx`/`rx` fail with
+    /// `InitError`.
+    pub fn new_with_flow_control(
+        config: UsartConfig,
+        tx: GpioPort,
+        rx: GpioPort,
+        rts: Option<GpioPort>,
+        cts: Option<GpioPort>,
+    ) -> Result<Self, hal::UsartError> {
+        let port_num = pin_to_port(&tx, &rx);
+        let (_, _, rts_pins, cts_pins) = PINS[port_num as usize - 1];
+        if rts.is_some_and(|p| !rts_pins.contains(&p)) || cts.is_some_and(|p| !cts_pins.contains(&p)) {
+            return Err(hal::UsartError::InitError);
+        }
+        let mut usart = Self::open(config, port_num, || {
+            tx.setup();
+            rx.setup();
+            rts.iter().chain(cts.iter()).for_each(|p| p.setup());
+        })?;
+        usart.with_disabled(|port| {
+            port.cr3().modify(|v| {
+                v.set_rtse(rts.is_some());
+                v.set_ctse(cts.is_some());
+            })
+        });
+        usart.rts = rts.is_some();
+        Ok(usart)
+    }
+
+    /// Create a single-wire half-duplex USART (`HDSEL`): TX and RX share the TX pin, which is set
+    /// to open drain with pull-up. The transmitted bytes are received as well.
+    pub fn new_half_duplex(config: UsartConfig, tx: GpioPort) -> Result<Self, hal::UsartError> {
+        let port_num = tx_pin_to_port(&tx);
+        let tx = GpioPort {
+            ot: Ot::OPEN_DRAIN,
+            pupd: Pupdr::PULL_UP,
+            ..tx
+        };
+        let usart = Self::open(config, port_num, || tx.setup())?;
+        usart.with_disabled(|port| port.cr3().modify(|v| v.set_hdsel(true)));
+        Ok(usart)
+    }
+
+    fn open(config: UsartConfig, port_num: u8, setup_pins: impl FnOnce()) -> Result<Self, hal::UsartError> {
+        let word_length = config.word_length().map_err(|_| hal::UsartError::InitError)?;
+        let lpuart = port_num == LPUART1_PORT;
+        let baud = if lpuart {
+            compute_lpuart_baud(USART_CLOCK, &config)
+        } else {
+            compute_baud(USART_CLOCK, &config)
+        }
+        .map_err(|_| hal::UsartError::InitError)?;
+
+        if TAKEN[port_num as usize].swap(true, core::sync::atomic::Ordering::AcqRel) {
+            return Err(hal::UsartError::InitError);
+        }
+
+        setup_pins();
+        clock::set_usart_clock(port_num);
+
+        let port = port_num_to_usart(port_num);
+
+        // the frame format can only be changed while UE = 0
+        port.cr1().modify(|v| v.set_ue(false));
+
+        port.cr1().modify(|v| {
+            let (m1, m0) = match word_length {
+                WordLength::Bits7 => (M1::BIT7, M0::BIT8),
+                WordLength::Bits8 => (M1::M0, M0::BIT8),
+                WordLength::Bits9 => (M1::M0, M0::BIT9),
+            };
+            v.set_m0(m0);
+            v.set_m1(m1);
+            v.set_pce(config.parity != Parity::None);
+            v.set_ps(if config.parity == Parity::Odd { Ps::ODD } else { Ps::EVEN });
+            v.set_over8(match config.oversampling {
+                Oversampling::By8 if !lpuart => Over8::OVERSAMPLING8,
+                _ => Over8::OVERSAMPLING16,
+            });
+        });
+
+        port.cr2().modify(|v| {
+            v.set_stop(match config.stop_bits {
+                StopBits::Half => Stop::STOP0P5,
+                StopBits::One => Stop::STOP1,
+                StopBits::OneAndHalf => Stop::STOP1P5,
+                StopBits::Two => Stop::STOP2,
+            });
+            v.set_msbfirst(if config.msb_first { Msbfirst::MSB } else { Msbfirst::LSB });
+            v.set_txinv(if config.tx_invert { Txinv::INVERTED } else { Txinv::STANDARD });
+            v.set_rxinv(if config.rx_invert { Rxinv::INVERTED } else { Rxinv::STANDARD });
+        });
+
+        port.presc().write(|v| v.set_prescaler(Presc::from_bits(baud.presc)));
+        port.brr().write_value(Brr(baud.brr));
+
+        // receive errors are recorded by the interrupt handler
+        port.cr1().modify(|v| v.set_peie(true));
+        port.cr3().modify(|v| v.set_eie(true));
+        RX_ERRORS[port_num as usize].store(0, Ordering::Relaxed);
+        ERROR_COUNTS[port_num as usize].iter().for_each(|c| c.store(0, Ordering::Relaxed));
+
+        port.cr1().modify(|v| {
+            v.set_ue(true);
+            v.set_te(true);
+            v.set_re(true);
+        });
+
+        unmask_interrupt(port_num);
+
+        Ok(Usart {
+            port,
+            port_num,
+            use_dma: false,
+            dma: None,
+            data_mask: config.data_mask(),
+            baudrate: baud.baudrate,
+            oversampling: match config.oversampling {
+                Oversampling::By8 if !lpuart => Oversampling::By8,
+                _ => Oversampling::By16,
+            },
+            stop_wakeup: false,
+            rts: false,
+        })
+    }
+
+    /// The baud rate actually generated, which differs from the requested one by the divider
+    /// rounding.
+    pub fn baudrate(&self) -> u32 {
+        self.baudrate
+    }
+
+    /// Run `f` with UE cleared, for the settings that can only be changed while the USART is
+    /// disabled. Transfers in progress are aborted.
+    fn with_disabled(&self, f: impl FnOnce(stm32_metapac::usart::Usart)) {
+        self.port.cr1().modify(|v| v.set_ue(false));
+        f(self.port);
+        self.port.cr1().modify(|v| v.set_ue(true));
+    }
+
+    /// Enable the RS-485 mode: the transceiver's driver enable follows the transmitter on `de`
+    /// (one of the RTS/DE pins of the instance), with the guard times of `rs485`.
+    pub fn set_rs485(&self, de: GpioPort, rs485: Rs485Config) -> Result<(), hal::UsartError> {
+        let (_, _, de_pins, _) = PINS[self.port_num as usize - 1];
+        if !rs485.is_valid() || !de_pins.contains(&de) {
+            return Err(hal::UsartError::InitError);
+        }
+        de.setup();
+        self.with_disabled(|port| {
+            port.cr1().modify(|v| {
+                v.set_deat(rs485.assertion_time);
+                v.set_dedt(rs485.deassertion_time);
+            });
+            port.cr3().modify(|v| {
+                v.set_dem(true);
+                v.set_dep(if rs485.active_high { Dep::HIGH } else { Dep::LOW });
+            });
+        });
+        Ok(())
+    }
+
+    /// Enable the receiver timeout: `read_frame_async` ends a frame when the line stays idle for
+    /// `bits` bit times after a stop bit (see `usart_config::receiver_timeout_bits`). `None`
+    /// disables it. LPUART1 has no receiver timeout.
+    pub fn set_receiver_timeout(&self, bits: Option<u32>) -> Result<(), hal::UsartError> {
+        if self.port_num == LPUART1_PORT {
+            return Err(hal::UsartError::InitError);
+        }
+        match bits {
+            Some(bits) if bits <= 0xff_ffff => {
+                self.port.rtor().modify(|v| v.set_rto(bits));
+                self.port.cr2().modify(|v| v.set_rtoen(true));
+            }
+            Some(_) => return Err(hal::UsartError::InitError),
+            None => self.port.cr2().modify(|v| v.set_rtoen(false)),
+        }
+        Ok(())
+    }
+
+    /// Receive a frame ended by the receiver timeout (`set_receiver_timeout`), for example a
+    /// Modbus RTU frame. Waits for the first byte and returns the frame length. A frame longer than
+    /// `data` is received to its end and reported as `BufferTooSmall`.
+    pub async fn read_frame_async(&self, data: &mut [u8]) -> Result<usize, hal::UsartError> {
+        run_no_deep_sleep_async(|| self.read_frame_interrupt(data)).await
+    }
+
+    async fn read_frame_interrupt(&self, data: &mut [u8]) -> Result<usize, hal::UsartError> {
+        // a timeout of earlier traffic
+        self.port.icr().write(|v| v.set_rtocf(true));
+        let mut n = 0;
+        let mut overflow = false;
+        core::future::poll_fn(|cx| {
+            RX_WAKERS[self.port_num as usize].register(cx.waker());
+            loop {
+                let isr = self.port.isr().read();
+                if isr.rxne() {
+                    let b = (self.port.rdr().read().dr() & self.data_mask) as u8;
+                    if n < data.len() {
+                        data[n] = b;
+                        n += 1;
+                    } else {
+                        overflow = true;
+                    }
+                } else if isr.rtof() {
+                    self.port.icr().write(|v| v.set_rtocf(true));
+                    if n > 0 || overflow {
+                        return Poll::Ready(if overflow {
+                            Err(hal::UsartError::BufferTooSmall)
+                        } else {
+                            take_rx_error(self.port_num as usize).map(|_| n)
+                        });
+                    }
+                } else {
+                    self.port.cr1().modify(|v| {
+                        v.set_rxneie(true);
+                        v.set_rtoie(true);
+                    });
+                    return Poll::Pending;
+                }
+            }
+        })
+        .await
+    }
+
+    /// Use dma for `write_async`/`read_async` (requires the `usart_dma` feature).
+    /// for example: `usart.set_dma(DmaChannel::take("USART1_TX", Dw::BYTE)?)`
+    pub fn set_dma(&mut self, dma: DmaChannel) {
+        self.dma = Some(dma);
+        self.use_dma = true;
+    }
+
+    /// Receive `data` with the RXNE interrupt. A framing, noise, parity or overrun error since the
+    /// previous read ends it with the matching error, the character in error is dropped.
     pub async fn read_async_interrupt(&self, data: &mut [u8]) -> Result<(), hal::UsartError> {
+        let index = self.port_num as usize;
         for i in 0..data.len() {
             core::future::poll_fn(|cx| {
-                RX_WAKERS[self.port_num as usize].register(cx.waker());
+                RX_WAKERS[index].register(cx.waker());
                 self.port.cr1().modify(|v| v.set_rxneie(true));
-                if self.port.isr().read().rxne() {
+                let isr = self.port.isr().read();
+                record_rx_errors(self.port, index, isr);
+                if isr.rxne() {
                     Poll::Ready(())
                 } else {
                     Poll::Pending
@@ -181,11 +489,30 @@ impl Usart {
             })
             .await;
 
-            data[i] = self.port.rdr().read().dr() as u8;
+            data[i] = (self.port.rdr().read().dr() & self.data_mask) as u8;
+            take_rx_error(index)?;
         }
         Ok(())
     }
 
+    /// Receive errors counted since the usart was created or `reset_error_counts`, including
+    /// the ones during buffered and dma reception.
+    pub fn error_counts(&self) -> UsartErrorCounts {
+        let counts = &ERROR_COUNTS[self.port_num as usize];
+        UsartErrorCounts {
+            framing: counts[0].load(Ordering::Relaxed),
+            noise: counts[1].load(Ordering::Relaxed),
+            parity: counts[2].load(Ordering::Relaxed),
+            overrun: counts[3].load(Ordering::Relaxed),
+        }
+    }
+
+    pub fn reset_error_counts(&self) {
+        for count in &ERROR_COUNTS[self.port_num as usize] {
+            count.store(0, Ordering::Relaxed);
+        }
+    }
+
     // todo: test this function
     pub async fn write_async_interrupt(&self, data: &[u8]) -> Result<(), hal::UsartError> {
         for &c in data {
@@ -202,6 +529,12 @@ impl Usart {
             self.port.tdr().write(|v| v.set_dr(c as u16));
         }
 
+        self.wait_tc_async().await;
+        Ok(())
+    }
+
+    /// wait for the transmission complete, the last stop bit is on the line
+    async fn wait_tc_async(&self) {
         core::future::poll_fn(|cx| {
             TX_WAKERS[self.port_num as usize].register(cx.waker());
             self.port.cr1().modify(|v| v.set_tcie(true));
@@ -212,27 +545,733 @@ impl Usart {
             }
         })
         .await;
-
-        Ok(())
     }
 
     #[cfg(feature = "usart_dma")]
     pub async fn write_async_dma(&self, data: &[u8]) -> Result<(), hal::UsartError> {
-        let src_addr = data.as_ptr() as u32;
-        let dst_addr = &self.port.tdr().as_ptr().cast::<u8>() as *const _ as u32;
+        let Some(dma) = &self.dma else {
+            return Err(hal::UsartError::InitError);
+        };
+        let dst_addr = self.port.tdr().as_ptr() as u32;
 
-        self.dma.start(src_addr, true, dst_addr, false, data.len() as u32).await;
+        // the transfer is a local, dropped (and aborted) before the borrow of `data` ends
+        let mut transfer = unsafe { Transfer::write(dma, data, dst_addr) }?;
+        self.port.cr3().modify(|v| v.set_dmat(true));
+        let res = transfer.wait().await;
+        if res.is_ok() {
+            self.wait_tc_async().await;
+        }
+        self.port.cr3().modify(|v| v.set_dmat(false));
 
-        Ok(())
+        res.map_err(hal::UsartError::from)
     }
 
     #[cfg(feature = "usart_dma")]
     pub async fn read_async_dma(&self, buffer: &mut [u8]) -> Result<(), hal::UsartError> {
-        let src_addr = &self.port.rdr().as_ptr().cast::<u8>() as *const _ as u32;
-        let dst_addr = buffer.as_mut_ptr() as u32;
+        let Some(dma) = &self.dma else {
+            return Err(hal::UsartError::InitError);
+        };
+        let src_addr = self.port.rdr().as_ptr() as u32;
+
+        // the transfer is a local, dropped (and aborted) before the borrow of `buffer` ends
+        let mut transfer = unsafe { Transfer::read(dma, src_addr, buffer) }?;
+        self.port.cr3().modify(|v| v.set_dmar(true));
+        let res = transfer.wait().await;
+        self.port.cr3().modify(|v| v.set_dmar(false));
+
+        res.map_err(hal::UsartError::from)
+    }
+}
+
+/// Receiver that keeps the incoming stream in a ring buffer filled by a circular GPDMA transfer.
+///
+/// Readers are woken at idle line, half buffer and full buffer, so bursts are handled in chunks
+/// instead of one wakeup per byte. Bytes are only lost (`BufferOverrun`) when the reader falls a
+/// whole buffer behind, so `buf` should hold what arrives during the longest gap between reads.
+pub struct BufferedUartRx<'a> {
+    usart: &'a Usart,
+    dma: DmaChannel,
+    buf: &'a mut [u8],
+    ring: DmaRing,
+}
+
+/// Running total of the bytes the circular dma stored, consistent with a counted lap.
+fn dma_written(dma: &DmaChannel, len: usize) -> u64 {
+    loop {
+        let laps = dma.laps();
+        let remaining = dma.remaining() as usize;
+        if !dma.is_complete_pending() && laps == dma.laps() {
+            return dma_ring::written(laps, remaining, len);
+        }
+    }
+}
+
+/// Reader position of a `BufferedUartRx` with RTS flow control, for the dma interrupt hook.
+#[derive(Copy, Clone)]
+struct RxFlow {
+    len: usize,
+    /// total bytes consumed by the reader
+    read: u64,
+    /// the dma requests are off, RDR stays full and RTS deasserted
+    paused: bool,
+}
+
+static RX_FLOW: [Mutex<CriticalSectionRawMutex, Cell<Option<RxFlow>>>; 8] = [const { Mutex::new(Cell::new(None)) }; 8];
+
+/// Dma hook of `BufferedUartRx`: once half of the ring is unread, the next half or complete
+/// transfer would fill it, so stop the dma requests (`DMAR`). The character then stays in RDR and
+/// the usart deasserts RTS until the reader catches up.
+fn on_buffered_rx_dma(index: usize, laps: u32, remaining: u32) {
+    RX_FLOW[index].lock(|flow| {
+        let Some(mut f) = flow.get() else {
+            return;
+        };
+        let unread = dma_ring::written(laps, remaining as usize, f.len).saturating_sub(f.read);
+        if unread as usize >= f.len / 2 {
+            port_num_to_usart(index as u8).cr3().modify(|v| v.set_dmar(false));
+            f.paused = true;
+            flow.set(Some(f));
+        }
+    });
+}
+
+impl<'a> BufferedUartRx<'a> {
+    /// Start receiving into `buf`, for example
+    /// `BufferedUartRx::new(&usart, DmaChannel::take("USART1_RX", Dw::BYTE)?, &mut RX_BUF)?`.
+    /// `buf` holds 1 to `MAX_BLOCK` (65532) bytes, one dma block.
+    ///
+    /// With RTS flow control (`Usart::new_with_flow_control`) RTS is deasserted while the ring is
+    /// full, so the sender waits instead of overrunning it.
+    pub fn new(usart: &'a Usart, dma: DmaChannel, buf: &'a mut [u8]) -> Result<Self, hal::UsartError> {
+        if buf.is_empty() || buf.len() > MAX_BLOCK as usize {
+            return Err(hal::UsartError::InitError);
+        }
+        let rdr = usart.port.rdr().as_ptr() as u32;
+        let index = usart.port_num as usize;
+        if usart.rts {
+            let flow = RxFlow {
+                len: buf.len(),
+                read: 0,
+                paused: false,
+            };
+            RX_FLOW[index].lock(|f| f.set(Some(flow)));
+            dma.set_interrupt_hook(Some((on_buffered_rx_dma, index)));
+        }
+        usart.port.icr().write(|v| v.set_idlecf(true));
+        usart.port.cr3().modify(|v| v.set_dmar(true));
+        // `buf` stays borrowed by `self`, which stops the channel on drop
+        unsafe { dma.start_circular(rdr, buf.as_mut_ptr() as u32, buf.len() as u32) }?;
+        usart.port.cr1().modify(|v| v.set_idleie(true));
+        let ring = DmaRing::new(buf.len());
+        Ok(Self { usart, dma, buf, ring })
+    }
+
+    /// Number of received bytes waiting to be read.
+    pub fn available(&self) -> Result<usize, hal::UsartError> {
+        self.ring
+            .available(dma_written(&self.dma, self.buf.len()))
+            .map_err(|_| hal::UsartError::BufferOverrun)
+    }
+
+    /// Copy the received bytes to `data` without waiting. Returns 0 if nothing was received.
+    /// After an overrun the buffered bytes are dropped and reception continues.
+    pub fn try_read(&mut self, data: &mut [u8]) -> Result<usize, hal::UsartError> {
+        let dma = &self.dma;
+        let len = self.buf.len();
+        let written = dma_written(dma, len);
+        let res = match self.ring.read(&*self.buf, written, data, || dma_written(dma, len)) {
+            Ok(n) => Ok(n),
+            Err(_) => {
+                self.ring.clear(dma_written(dma, len));
+                Err(hal::UsartError::BufferOverrun)
+            }
+        };
+        if self.usart.rts {
+            self.update_flow();
+        }
+        res
+    }
+
+    /// Publish the reader position to the dma hook and resume a paused ring once less than half
+    /// of it is unread.
+    fn update_flow(&self) {
+        let index = self.usart.port_num as usize;
+        let unread = self.ring.available(dma_written(&self.dma, self.buf.len())).unwrap_or(usize::MAX);
+        let read = self.ring.read_total();
+        RX_FLOW[index].lock(|flow| {
+            let Some(mut f) = flow.get() else {
+                return;
+            };
+            f.read = read;
+            if f.paused && unread < f.len / 2 {
+                f.paused = false;
+                self.usart.port.cr3().modify(|v| v.set_dmar(true));
+            }
+            flow.set(Some(f));
+        });
+    }
+
+    /// Wait until at least one byte is received, then copy what is available to `data`.
+    pub async fn read_async(&mut self, data: &mut [u8]) -> Result<usize, hal::UsartError> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let port_num = self.usart.port_num as usize;
+        run_no_deep_sleep_async(move || {
+            core::future::poll_fn(move |cx| {
+                self.dma.register_waker(cx.waker());
+                RX_WAKERS[port_num].register(cx.waker());
+                match self.try_read(data) {
+                    Ok(0) => Poll::Pending,
+                    res => Poll::Ready(res),
+                }
+            })
+        })
+        .await
+    }
+}
+
+impl Drop for BufferedUartRx<'_> {
+    fn drop(&mut self) {
+        self.usart.port.cr1().modify(|v| v.set_idleie(false));
+        self.dma.set_interrupt_hook(None);
+        RX_FLOW[self.usart.port_num as usize].lock(|f| f.set(None));
+        self.dma.stop();
+        self.usart.port.cr3().modify(|v| v.set_dmar(false));
+    }
+}
+
+/// Transmitter that queues data in a static ring buffer, drained in the background by the TXE
+/// interrupt or by dma.
+///
+/// `write_async` only waits when the queue is full, so a task can hand over a line and go on.
+/// `flush_async` waits until everything queued has left the shift register (TC).
+pub struct BufferedUartTx<'a> {
+    usart: &'a Usart,
+    use_dma: bool,
+}
+
+/// Send the next contiguous run of queued bytes with `dma`. Called with `TX_DMA` locked.
+fn start_tx_dma(port: stm32_metapac::usart::Usart, index: usize, dma: &DmaChannel) {
+    let (ptr, n) = TX_RINGS[index].readable();
+    if n == 0 {
+        return;
+    }
+    // one block at most, it always fits the linked list
+    let n = n.min(MAX_BLOCK as usize);
+    port.icr().write(|v| v.set_tccf(true));
+    // the queued bytes stay in the static ring until `TX_IN_FLIGHT` is released
+    if unsafe { dma.start_transfer(ptr as u32, true, port.tdr().as_ptr() as u32, false, n as u32) }.is_ok() {
+        TX_IN_FLIGHT[index].store(n, Ordering::Release);
+        // TC is set once the dma wrote the last byte and it was sent
+        port.cr1().modify(|v| v.set_tcie(true));
+    }
+}
+
+impl<'a> BufferedUartTx<'a> {
+    /// Queue transmissions in `buf`, sent by the TXE interrupt.
+    pub fn new(usart: &'a Usart, buf: &'static mut [u8]) -> Result<Self, hal::UsartError> {
+        Self::init(usart, None, buf)
+    }
+
+    /// Queue transmissions in `buf`, sent by `dma`. for example:
+    /// `BufferedUartTx::new_with_dma(&usart, DmaChannel::take("USART1_TX", Dw::BYTE)?, &mut TX_BUF)`
+    pub fn new_with_dma(usart: &'a Usart, dma: DmaChannel, buf: &'static mut [u8]) -> Result<Self, hal::UsartError> {
+        Self::init(usart, Some(dma), buf)
+    }
+
+    fn init(usart: &'a Usart, dma: Option<DmaChannel>, buf: &'static mut [u8]) -> Result<Self, hal::UsartError> {
+        let index = usart.port_num as usize;
+        if TX_RINGS[index].is_active() || buf.len() < 2 {
+            return Err(hal::UsartError::InitError);
+        }
+        let use_dma = dma.is_some();
+        TX_IN_FLIGHT[index].store(0, Ordering::Relaxed);
+        TX_DMA[index].lock(|d| *d.borrow_mut() = dma);
+        unsafe { TX_RINGS[index].init(buf.as_mut_ptr(), buf.len()) };
+        if use_dma {
+            usart.port.cr3().modify(|v| v.set_dmat(true));
+        }
+        Ok(Self { usart, use_dma })
+    }
+
+    fn index(&self) -> usize {
+        self.usart.port_num as usize
+    }
+
+    /// Start draining the queue if it is not already running.
+    fn kick(&self) {
+        let index = self.index();
+        // deep sleep would stop the usart clock with bytes queued
+        if !TX_AWAKE[index].swap(true, Ordering::AcqRel) {
+            low_power::no_deep_sleep_request();
+        }
+        if self.use_dma {
+            TX_DMA[index].lock(|dma| {
+                if TX_IN_FLIGHT[index].load(Ordering::Acquire) == 0 {
+                    if let Some(dma) = dma.borrow().as_ref() {
+                        start_tx_dma(self.usart.port, index, dma);
+                    }
+                }
+            });
+        } else {
+            self.usart.port.cr1().modify(|v| v.set_txeie(true));
+        }
+    }
+
+    /// Queue as much of `data` as fits without waiting. Returns the number of bytes queued.
+    pub fn try_write(&mut self, data: &[u8]) -> usize {
+        let n = TX_RINGS[self.index()].push(data);
+        if n > 0 {
+            self.kick();
+        }
+        n
+    }
+
+    /// Queue `data`, waiting only while the queue is full. Returns the number of bytes queued,
+    /// at least one.
+    pub async fn write_async(&mut self, data: &[u8]) -> Result<usize, hal::UsartError> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+        let index = self.index();
+        core::future::poll_fn(|cx| {
+            TX_WAKERS[index].register(cx.waker());
+            match self.try_write(data) {
+                0 => Poll::Pending,
+                n => Poll::Ready(Ok(n)),
+            }
+        })
+        .await
+    }
+
+    /// Wait until the queue is empty and the last stop bit is sent.
+    pub async fn flush_async(&mut self) -> Result<(), hal::UsartError> {
+        let index = self.index();
+        let port = self.usart.port;
+        run_no_deep_sleep_async(|| {
+            core::future::poll_fn(move |cx| {
+                TX_WAKERS[index].register(cx.waker());
+                let idle = TX_RINGS[index].is_empty() && TX_IN_FLIGHT[index].load(Ordering::Acquire) == 0;
+                if idle && port.isr().read().tc() {
+                    Poll::Ready(Ok(()))
+                } else {
+                    port.cr1().modify(|v| v.set_tcie(true));
+                    Poll::Pending
+                }
+            })
+        })
+        .await
+    }
+}
+
+impl Drop for BufferedUartTx<'_> {
+    /// Queued bytes that are not sent yet are dropped, `flush_async` first to keep them.
+    fn drop(&mut self) {
+        let index = self.index();
+        TX_DMA[index].lock(|dma| {
+            self.usart.port.cr1().modify(|v| {
+                v.set_txeie(false);
+                v.set_tcie(false);
+            });
+            if let Some(dma) = dma.borrow_mut().take() {
+                dma.stop();
+            }
+            TX_IN_FLIGHT[index].store(0, Ordering::Relaxed);
+            TX_RINGS[index].deinit();
+        });
+        release_tx_awake(index);
+        self.usart.port.cr3().modify(|v| v.set_dmat(false));
+    }
+}
+
+fn release_tx_awake(index: usize) {
+    if TX_AWAKE[index].swap(false, Ordering::AcqRel) {
+        low_power::no_deep_sleep_release();
+    }
+}
+
+/// Interrupt part of `BufferedUartTx`: refill TDR or the dma from the queue and wake the writer.
+/// Deep sleep is allowed again at TC once the queue is empty.
+fn on_buffered_tx(usart: stm32_metapac::usart::Usart, index: usize, isr: stm32_metapac::usart::regs::Isr) {
+    let ring = &TX_RINGS[index];
+    let cr1 = usart.cr1().read();
+    if cr1.txeie() && isr.txe() {
+        match ring.pop() {
+            Some(b) => usart.tdr().write(|v| v.set_dr(b as u16)),
+            None => usart.cr1().modify(|v| {
+                v.set_txeie(false);
+                v.set_tcie(true);
+            }),
+        }
+        TX_WAKERS[index].wake();
+    }
+    if cr1.tcie() && isr.tc() {
+        TX_DMA[index].lock(|dma| {
+            let sent = TX_IN_FLIGHT[index].swap(0, Ordering::AcqRel);
+            ring.consume(sent);
+            match dma.borrow().as_ref() {
+                Some(dma) if !ring.is_empty() => start_tx_dma(usart, index, dma),
+                _ => usart.cr1().modify(|v| v.set_tcie(false)),
+            }
+            if ring.is_empty() && TX_IN_FLIGHT[index].load(Ordering::Acquire) == 0 {
+                release_tx_awake(index);
+            }
+        });
+        TX_WAKERS[index].wake();
+    }
+}
+
+/// Auto baud rate detection modes (`ABRMOD`), by the character the remote end sends first.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AutoBaudMode {
+    /// length of the start bit, the character must start with a 1 bit
+    StartBit,
+    /// falling edge to falling edge, the character must start with `10xx`
+    FallingEdge,
+    /// a 0x7f character
+    Frame7F,
+    /// a 0x55 character
+    Frame55,
+}
+
+/// Event waking the core from stop mode while the usart receives (`WUS`).
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StopWakeup {
+    /// a character whose 7-bit address matches, for multiprocessor links (`ADD`)
+    AddressMatch(u8),
+    /// a start bit
+    StartBit,
+    /// a received character
+    RxNotEmpty,
+}
+
+impl Usart {
+    /// Measure the baud rate from the next received character and switch to it (`ABREN`). Returns
+    /// the new baud rate. The measured character is received as data as well. LPUART1 has no auto
+    /// baud rate detection.
+    pub async fn detect_baudrate_async(&mut self, mode: AutoBaudMode) -> Result<u32, hal::UsartError> {
+        if self.port_num == LPUART1_PORT {
+            return Err(hal::UsartError::InitError);
+        }
+        self.with_disabled(|port| {
+            port.cr2().modify(|v| {
+                v.set_abrmod(match mode {
+                    AutoBaudMode::StartBit => Abrmod::START,
+                    AutoBaudMode::FallingEdge => Abrmod::EDGE,
+                    AutoBaudMode::Frame7F => Abrmod::FRAME7F,
+                    AutoBaudMode::Frame55 => Abrmod::FRAME55,
+                });
+                v.set_abren(true);
+            })
+        });
+        ABR_PENDING[self.port_num as usize].store(true, Ordering::Release);
+        self.port.rqr().write(|v| v.set_abrrq(true));
+        let port = self.port;
+        let port_num = self.port_num;
+        let res = run_no_deep_sleep_async(|| {
+            core::future::poll_fn(move |cx| {
+                RX_WAKERS[port_num as usize].register(cx.waker());
+                let isr = port.isr().read();
+                if isr.abre() {
+                    Poll::Ready(Err(hal::UsartError::BusError))
+                } else if isr.abrf() && isr.rxne() {
+                    Poll::Ready(Ok(()))
+                } else {
+                    // the measured character sets RXNE
+                    port.cr1().modify(|v| v.set_rxneie(true));
+                    Poll::Pending
+                }
+            })
+        })
+        .await;
+        ABR_PENDING[self.port_num as usize].store(false, Ordering::Release);
+        res?;
+        let presc = self.port.presc().read().prescaler().to_bits();
+        let brr = self.port.brr().read().0;
+        self.baudrate = baudrate_from_brr(USART_CLOCK, presc, brr, self.oversampling);
+        Ok(self.baudrate)
+    }
+
+    /// Keep receiving in stop mode and wake the core on `wakeup` (`UESM`, `WUS`, `WUFIE`). Reads
+    /// through `read_async` then let `low_power::Executor` enter deep sleep instead of holding it
+    /// off. `None` disarms it.
+    ///
+    /// USART1 - USART3, UART4 and UART5 run in stop 0 and 1, LPUART1 also in stop 2. The kernel
+    /// clock is HSI16, which the usart turns on by itself when a character arrives (`UCESM`).
+    pub fn set_stop_wakeup(&mut self, wakeup: Option<StopWakeup>) {
+        let Some(wakeup) = wakeup else {
+            self.port.cr3().modify(|v| v.set_wufie(false));
+            self.port.cr1().modify(|v| v.set_uesm(false));
+            self.stop_wakeup = false;
+            return;
+        };
+        self.with_disabled(|port| {
+            if let StopWakeup::AddressMatch(address) = wakeup {
+                port.cr2().modify(|v| {
+                    v.set_addm7(Addm7::BIT7);
+                    v.set_add(address & 0x7f);
+                });
+            }
+            port.cr3().modify(|v| {
+                v.set_wus(match wakeup {
+                    StopWakeup::AddressMatch(_) => Wus::ADDRESS,
+                    StopWakeup::StartBit => Wus::START,
+                    StopWakeup::RxNotEmpty => Wus::RXNE,
+                });
+                v.set_ucesm(true);
+            });
+        });
+        self.port.icr().write(|v| v.set_wucf(true));
+        self.port.cr3().modify(|v| v.set_wufie(true));
+        self.port.cr1().modify(|v| v.set_uesm(true));
+        self.stop_wakeup = true;
+    }
+}
+
+/////////////////////////// LIN /////////////////////////////
+impl Usart {
+    /// Create a USART in LIN mode: 8 data bits, no parity, 1 stop bit, 11-bit break detection
+    /// (`LINEN`, `LBDL`). The transceiver loops the transmitted bytes back to RX, the LIN functions
+    /// read each one back before sending the next. LPUART1 has no LIN mode.
+    pub fn new_lin(baudrate: u32, tx: GpioPort, rx: GpioPort) -> Result<Self, hal::UsartError> {
+        if pin_to_port(&tx, &rx) == LPUART1_PORT {
+            return Err(hal::UsartError::InitError);
+        }
+        let usart = Self::new_with_config(UsartConfig::new(baudrate), tx, rx)?;
+        usart.with_disabled(|port| {
+            // LIN excludes synchronous, smartcard, IrDA and half-duplex mode
+            port.cr2().modify(|v| {
+                v.set_clken(false);
+                v.set_linen(true);
+                v.set_lbdl(Lbdl::BIT11);
+            });
+            port.cr3().modify(|v| {
+                v.set_scen(false);
+                v.set_iren(false);
+                v.set_hdsel(false);
+            });
+        });
+        Ok(usart)
+    }
+
+    /// Send `data` and read back the echo of each byte before the next one, so nothing but the
+    /// other nodes' bytes is left in RX. A different echo means another node drove the bus at the
+    /// same time (`BusError`).
+    async fn lin_write_echoed_async(&self, data: &[u8]) -> Result<(), hal::UsartError> {
+        for &byte in data {
+            self.write_async_interrupt(&[byte]).await?;
+            let mut echo = [0u8];
+            self.read_async_interrupt(&mut echo).await?;
+            if echo[0] != byte {
+                return Err(hal::UsartError::BusError);
+            }
+        }
+        Ok(())
+    }
+
+    /// Master: send the header of frame `id`, a break followed by the sync byte and the PID.
+    pub async fn lin_send_header_async(&self, id: u8) -> Result<(), lin::LinError> {
+        let pid = lin::pid(id)?;
+        self.wait_lin_break_async(true).await;
+        self.lin_write_echoed_async(&[lin::SYNC, pid]).await?;
+        Ok(())
+    }
+
+    /// Master: send frame `id` including its response `data`.
+    pub async fn lin_write_frame_async(
+        &self,
+        id: u8,
+        data: &[u8],
+        model: lin::ChecksumModel,
+    ) -> Result<(), lin::LinError> {
+        let mut response = [0u8; lin::MAX_DATA + 1];
+        let len = lin::encode_response(model, lin::pid(id)?, data, &mut response)?;
+        self.lin_send_header_async(id).await?;
+        self.lin_write_echoed_async(&response[..len]).await?;
+        Ok(())
+    }
+
+    /// Master: send the header of frame `id` and receive the response of the publishing slave into
+    /// `data`, whose length is the frame length. A missing response waits without limit, bound it
+    /// with `lptim::timeout`.
+    pub async fn lin_read_frame_async(
+        &self,
+        id: u8,
+        data: &mut [u8],
+        model: lin::ChecksumModel,
+    ) -> Result<(), lin::LinError> {
+        if data.is_empty() || data.len() > lin::MAX_DATA {
+            return Err(lin::LinError::Length);
+        }
+        let mut response = [0u8; lin::MAX_DATA + 1];
+        let response = &mut response[..data.len() + 1];
+        self.lin_send_header_async(id).await?;
+        hal::Usart::read_async(self, response).await?;
+        data.copy_from_slice(lin::decode_response(model, lin::pid(id)?, response)?);
+        Ok(())
+    }
+
+    /// Wait for a break on the bus (`LBDF`), sending one first if `send` is set. The interrupt
+    /// drops the break character, the sync byte that follows is the next byte received.
+    async fn wait_lin_break_async(&self, send: bool) {
+        let index = self.port_num as usize;
+        LIN_BREAK[index].store(false, Ordering::Release);
+        if send {
+            // the break goes out on its own, before the next character
+            self.port.rqr().write(|v| v.set_sbkrq(true));
+        }
+        core::future::poll_fn(|cx| {
+            RX_WAKERS[index].register(cx.waker());
+            if LIN_BREAK[index].swap(false, Ordering::AcqRel) {
+                Poll::Ready(())
+            } else {
+                self.port.cr2().modify(|v| v.set_lbdie(true));
+                Poll::Pending
+            }
+        })
+        .await;
+    }
+
+    /// Slave: wait for the next header and take part in the frame if `slave` has it, sending
+    /// the response of a published frame or storing the response of a subscribed one. Returns the
+    /// frame ID, `None` for frames of other nodes.
+    pub async fn lin_serve_async(&self, slave: &mut lin::LinSlave<'_>) -> Result<Option<u8>, lin::LinError> {
+        run_no_deep_sleep_async(|| async move {
+            self.wait_lin_break_async(false).await;
+            let mut header = [0u8; 2];
+            self.read_async_interrupt(&mut header).await?;
+            if header[0] != lin::SYNC {
+                return Err(lin::LinError::Sync);
+            }
+            let Some(frame) = slave.on_header(header[1])? else {
+                return Ok(None);
+            };
+            let mut response = [0u8; lin::MAX_DATA + 1];
+            match frame.direction {
+                lin::LinDirection::Publish => {
+                    let len = lin::LinSlave::response(frame, &mut response)?;
+                    self.lin_write_echoed_async(&response[..len]).await?;
+                }
+                lin::LinDirection::Subscribe => {
+                    let response = &mut response[..frame.len() + 1];
+                    self.read_async_interrupt(response).await?;
+                    lin::LinSlave::on_response(frame, response)?;
+                }
+            }
+            Ok(Some(frame.id))
+        })
+        .await
+    }
+}
+
+/// Modbus RTU frames delimited by the receiver timeout, enable it first with
+/// `set_receiver_timeout(Some(modbus::rtu_timeout_bits(baudrate)))`. A read waits for the first
+/// byte without limit, so a lost response needs the master's response timeout
+/// (`ModbusMaster::with_response_timeout`, e.g. on an `lptim::Lptim`).
+impl modbus::RtuPort for Usart {
+    async fn write_frame(&self, frame: &[u8]) -> Result<(), hal::UsartError> {
+        hal::Usart::write_async(self, frame).await
+    }
+
+    async fn read_frame(&self, buf: &mut [u8], _kind: modbus::FrameKind) -> Result<usize, hal::UsartError> {
+        self.read_frame_async(buf).await
+    }
+}
+
+/////////////////////////// embedded-io implementation /////////////////////////////
+#[cfg(feature = "embedded-hal")]
+impl embedded_io::ErrorType for Usart {
+    type Error = hal::UsartError;
+}
 
-        self.dma.start(src_addr, false, dst_addr, true, buffer.len() as u32).await;
+#[cfg(feature = "embedded-hal")]
+impl Usart {
+    /// take the bytes that are already received without waiting
+    fn drain_rx(&self, buf: &mut [u8]) -> usize {
+        let mut n = 0;
+        while n < buf.len() && self.port.isr().read().rxne() {
+            buf[n] = (self.port.rdr().read().dr() & self.data_mask) as u8;
+            n += 1;
+        }
+        n
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_io::Read for Usart {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        // block for the first byte only
+        <Self as hal::Usart<GpioPort>>::read(self, &mut buf[..1])?;
+        Ok(1 + self.drain_rx(&mut buf[1..]))
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_io::Write for Usart {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        <Self as hal::Usart<GpioPort>>::write(self, buf)?;
+        Ok(buf.len())
+    }
 
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        // write returns after TC
+        Ok(())
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_io_async::Read for Usart {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        <Self as hal::Usart<GpioPort>>::read_async(self, &mut buf[..1]).await?;
+        Ok(1 + self.drain_rx(&mut buf[1..]))
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_io::ErrorType for BufferedUartRx<'_> {
+    type Error = hal::UsartError;
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_io_async::Read for BufferedUartRx<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.read_async(buf).await
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_io::ErrorType for BufferedUartTx<'_> {
+    type Error = hal::UsartError;
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_io_async::Write for BufferedUartTx<'_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.write_async(buf).await
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
+        self.flush_async().await
+    }
+}
+
+#[cfg(feature = "embedded-hal")]
+impl embedded_io_async::Write for Usart {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        <Self as hal::Usart<GpioPort>>::write_async(self, buf).await?;
+        Ok(buf.len())
+    }
+
+    async fn flush(&mut self) -> Result<(), Self::Error> {
         Ok(())
     }
 }
@@ -251,15 +1290,116 @@ fn USART2() {
 fn USART3() {
     handle_usart_interrupt(stm32_metapac::USART3, 3);
 }
+
+#[interrupt]
+fn UART4() {
+    handle_usart_interrupt(stm32_metapac::UART4, 4);
+}
+
+#[interrupt]
+fn UART5() {
+    handle_usart_interrupt(stm32_metapac::UART5, 5);
+}
+
+#[interrupt]
+fn LPUART1() {
+    handle_usart_interrupt(port_num_to_usart(LPUART1_PORT), LPUART1_PORT as usize);
+}
+/// Count and clear the receive error flags of `isr` and keep them for the next reader. In LIN mode
+/// the break is a framing error by design and is not counted.
+fn record_rx_errors(usart: stm32_metapac::usart::Usart, index: usize, isr: stm32_metapac::usart::regs::Isr) {
+    if !(isr.fe() || isr.ne() || isr.pe() || isr.ore()) {
+        return;
+    }
+    usart.icr().write(|v| {
+        v.set_fecf(isr.fe());
+        v.set_necf(isr.ne());
+        v.set_pecf(isr.pe());
+        v.set_orecf(isr.ore());
+    });
+    let framing = isr.fe() && !usart.cr2().read().linen();
+    let flags = [framing, isr.ne(), isr.pe(), isr.ore()];
+    let mut errors = 0;
+    for (bit, &flag) in flags.iter().enumerate() {
+        if flag {
+            ERROR_COUNTS[index][bit].fetch_add(1, Ordering::Relaxed);
+            errors |= 1 << bit;
+        }
+    }
+    RX_ERRORS[index].fetch_or(errors, Ordering::AcqRel);
+}
+
+/// Report the receive errors recorded since the last call, the most severe first.
+fn take_rx_error(index: usize) -> Result<(), hal::UsartError> {
+    let errors = RX_ERRORS[index].swap(0, Ordering::AcqRel);
+    if errors & RX_ERROR_OVERRUN != 0 {
+        Err(hal::UsartError::Overrun)
+    } else if errors & RX_ERROR_FRAMING != 0 {
+        Err(hal::UsartError::Framing)
+    } else if errors & RX_ERROR_NOISE != 0 {
+        Err(hal::UsartError::Noise)
+    } else if errors & RX_ERROR_PARITY != 0 {
+        Err(hal::UsartError::Parity)
+    } else {
+        Ok(())
+    }
+}
+
 fn handle_usart_interrupt(usart: stm32_metapac::usart::Usart, index: usize) {
     let isr = usart.isr().read();
 
+    if isr.idle() {
+        // end of a burst, wake the buffered reader
+        usart.icr().write(|v| v.set_idlecf(true));
+        RX_WAKERS[index].wake();
+    }
+
     if isr.rxne() {
         RX_WAKERS[index].wake();
         usart.cr1().modify(|v| v.set_rxneie(false));
     }
 
-    if isr.txe() || isr.tc() {
+    if isr.wuf() {
+        // woken from stop mode, the received character follows with RXNE
+        usart.icr().write(|v| v.set_wucf(true));
+        RX_WAKERS[index].wake();
+    }
+
+    if ABR_PENDING[index].load(Ordering::Acquire) && (isr.abre() || isr.abrf()) {
+        // auto baud rate detection done
+        RX_WAKERS[index].wake();
+    }
+
+    if isr.pe() || isr.fe() || isr.ne() || isr.ore() {
+        record_rx_errors(usart, index, isr);
+        RX_WAKERS[index].wake();
+    }
+
+    if isr.lbdf() {
+        // LIN break: drop the break character and its framing error right away, the sync byte
+        // follows within a bit time and the reader may be late
+        if isr.rxne() {
+            let _ = usart.rdr().read();
+        }
+        usart.icr().write(|v| {
+            v.set_lbdcf(true);
+            v.set_fecf(true);
+            v.set_orecf(true);
+        });
+        LIN_BREAK[index].store(true, Ordering::Release);
+        RX_WAKERS[index].wake();
+        usart.cr2().modify(|v| v.set_lbdie(false));
+    }
+
+    if isr.rtof() {
+        // end of a frame, cleared by the reader
+        RX_WAKERS[index].wake();
+        usart.cr1().modify(|v| v.set_rtoie(false));
+    }
+
+    if TX_RINGS[index].is_active() {
+        on_buffered_tx(usart, index, isr);
+    } else if isr.txe() || isr.tc() {
         TX_WAKERS[index].wake();
         usart.cr1().modify(|v| {
             v.set_txeie(false);
@@ -0,0 +1,551 @@
+//! # USART frame format and baud rate calculation
+//!
+//! `UsartConfig` describes the character format (data bits, parity, stop bits, bit order,
+//! inversion) and the baud rate; `compute_baud` derives the `USART_PRESC` and `USART_BRR` values
+//! for a kernel clock following the reference manual (RM0456, "USART baud rate generation"):
+//!
+//! - 16x oversampling: `baud = fCK / PRESC / USARTDIV`, `BRR = USARTDIV`
+//! - 8x oversampling: `baud = 2 * fCK / PRESC / USARTDIV`, `BRR[15:4] = USARTDIV[15:4]`,
+//!   `BRR[3] = 0`, `BRR[2:0] = USARTDIV[3:1]`
+//!
+//! with `16 <= USARTDIV <= 0xffff`. The divider is rounded to the nearest value and the prescaler
+//! is only raised when the divider does not fit, so the error is as small as the clock allows.
+//! The resulting baud error is reported, configurations beyond `UsartConfig::max_error_ppm` are
+//! rejected.
+//!
+//! LPUART1 has a 20-bit divider and no oversampling setting; `compute_lpuart_baud` uses
+//! `baud = 256 * fCK / PRESC / BRR` with `0x300 <= BRR <= 0xfffff`, which also keeps
+//! `3 * baud <= fCK / PRESC <= 4096 * baud`. Only 1 and 2 stop bits are supported by the LPUART.
+//!
+//! `baudrate_from_brr` goes the other way, for the divider found by the auto baud rate detection.
+//!
+//! `Rs485Config` holds the driver enable timing of the RS-485 mode; `de_time` and
+//! `receiver_timeout_bits` convert times to the sample and bit units of `DEAT`/`DEDT` and `RTOR`.
+
+/// Kernel clock dividers selected by `USART_PRESC` 0 - 11.
+const PRESCALERS: [u32; 12] = [1, 2, 4, 6, 8, 10, 12, 16, 32, 64, 128, 256];
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DataBits {
+    Seven,
+    Eight,
+    Nine,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum StopBits {
+    /// 0.5 stop bits, smartcard receive
+    Half,
+    One,
+    /// 1.5 stop bits, smartcard transmit
+    OneAndHalf,
+    Two,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Oversampling {
+    /// 8 samples per bit, up to fCK / 8 baud but less tolerant to clock deviation
+    By8,
+    By16,
+}
+
+/// Word length on the wire (`M1:M0`), data bits plus the parity bit.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum WordLength {
+    Bits7,
+    Bits8,
+    Bits9,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct UsartConfig {
+    pub baudrate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    pub oversampling: Oversampling,
+    /// send the most significant bit first (`MSBFIRST`)
+    pub msb_first: bool,
+    /// idle low TX line (`TXINV`)
+    pub tx_invert: bool,
+    /// idle low RX line (`RXINV`)
+    pub rx_invert: bool,
+    /// largest accepted baud rate error in ppm of the requested rate
+    pub max_error_ppm: u32,
+}
+
+impl UsartConfig {
+    /// 8N1, 16x oversampling, LSB first, no inversion and at most 1 % baud error.
+    pub fn new(baudrate: u32) -> Self {
+        Self {
+            baudrate,
+            data_bits: DataBits::Eight,
+            parity: Parity::None,
+            stop_bits: StopBits::One,
+            oversampling: Oversampling::By16,
+            msb_first: false,
+            tx_invert: false,
+            rx_invert: false,
+            max_error_ppm: 10_000,
+        }
+    }
+
+    /// `M1:M0` for the data bits and parity. 9 data bits with parity do not fit.
+    pub fn word_length(&self) -> Result<WordLength, UsartConfigError> {
+        let parity = self.parity != Parity::None;
+        match (self.data_bits, parity) {
+            (DataBits::Seven, false) => Ok(WordLength::Bits7),
+            (DataBits::Seven, true) | (DataBits::Eight, false) => Ok(WordLength::Bits8),
+            (DataBits::Eight, true) | (DataBits::Nine, false) => Ok(WordLength::Bits9),
+            (DataBits::Nine, true) => Err(UsartConfigError::InvalidWordLength),
+        }
+    }
+
+    /// Mask of the data bits in `RDR`, the parity bit is not part of the data.
+    pub fn data_mask(&self) -> u16 {
+        match self.data_bits {
+            DataBits::Seven => 0x7f,
+            DataBits::Eight => 0xff,
+            DataBits::Nine => 0x1ff,
+        }
+    }
+}
+
+/// `USART_PRESC`/`USART_BRR` values and the baud rate they give.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct UsartBaud {
+    /// `PRESC` field (0 - 11)
+    pub presc: u8,
+    pub brr: u32,
+    /// actual baud rate in Hz
+    pub baudrate: u32,
+    /// deviation from the requested rate in ppm
+    pub error_ppm: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum UsartConfigError {
+    InvalidBaudrate,
+    /// 9 data bits plus parity
+    InvalidWordLength,
+    /// the divider would be below 16
+    KernelClockTooSlow,
+    /// the divider does not fit even with the largest prescaler
+    BaudrateTooLow,
+    /// 0.5 or 1.5 stop bits on the LPUART
+    UnsupportedStopBits,
+    /// the closest rate is off by more than `max_error_ppm`
+    BaudError {
+        baudrate: u32,
+        error_ppm: u32,
+    },
+}
+
+/// Deviation of `clock / div` from `requested` in ppm.
+fn error_ppm(clock: u64, div: u64, requested: u64) -> u32 {
+    let nominal = div * requested;
+    (clock.abs_diff(nominal) * 1_000_000 / nominal) as u32
+}
+
+/// Compute `PRESC` and `BRR` for `config` with the kernel clock `kernel_freq`.
+pub fn compute_baud(kernel_freq: u32, config: &UsartConfig) -> Result<UsartBaud, UsartConfigError> {
+    if config.baudrate == 0 || kernel_freq == 0 {
+        return Err(UsartConfigError::InvalidBaudrate);
+    }
+    config.word_length()?;
+    let samples = match config.oversampling {
+        Oversampling::By8 => 2,
+        Oversampling::By16 => 1,
+    };
+    let baudrate = config.baudrate as u64;
+    for (presc, &prescaler) in PRESCALERS.iter().enumerate() {
+        // baud = kernel * samples / (prescaler * USARTDIV); with 8x oversampling bit 0 of
+        // USARTDIV is not in BRR, so the divider moves in steps of 2
+        let clock = kernel_freq as u64 * samples;
+        let step = prescaler as u64 * samples;
+        let usartdiv = (clock + step * baudrate / 2) / (step * baudrate) * samples;
+        if usartdiv < 16 {
+            return Err(UsartConfigError::KernelClockTooSlow);
+        }
+        if usartdiv > 0xffff {
+            continue;
+        }
+        let div = prescaler as u64 * usartdiv;
+        let actual = ((clock + div / 2) / div) as u32;
+        let error_ppm = error_ppm(clock, div, baudrate);
+        if error_ppm > config.max_error_ppm {
+            return Err(UsartConfigError::BaudError {
+                baudrate: actual,
+                error_ppm,
+            });
+        }
+        let usartdiv = usartdiv as u16;
+        let brr = match config.oversampling {
+            Oversampling::By8 => (usartdiv & 0xfff0) | ((usartdiv & 0xf) >> 1),
+            Oversampling::By16 => usartdiv,
+        } as u32;
+        return Ok(UsartBaud {
+            presc: presc as u8,
+            brr,
+            baudrate: actual,
+            error_ppm,
+        });
+    }
+    Err(UsartConfigError::BaudrateTooLow)
+}
+
+/// Compute `PRESC` and `BRR` of the LPUART for `config` with the kernel clock `kernel_freq`.
+/// `config.oversampling` is ignored.
+pub fn compute_lpuart_baud(
+    kernel_freq: u32,
+    config: &UsartConfig,
+) -> Result<UsartBaud, UsartConfigError> {
+    if config.baudrate == 0 || kernel_freq == 0 {
+        return Err(UsartConfigError::InvalidBaudrate);
+    }
+    config.word_length()?;
+    if matches!(config.stop_bits, StopBits::Half | StopBits::OneAndHalf) {
+        return Err(UsartConfigError::UnsupportedStopBits);
+    }
+    let baudrate = config.baudrate as u64;
+    let clock = kernel_freq as u64 * 256;
+    for (presc, &prescaler) in PRESCALERS.iter().enumerate() {
+        let step = prescaler as u64 * baudrate;
+        let brr = (clock + step / 2) / step;
+        if brr < 0x300 {
+            return Err(UsartConfigError::KernelClockTooSlow);
+        }
+        if brr > 0xfffff {
+            continue;
+        }
+        let div = prescaler as u64 * brr;
+        let actual = ((clock + div / 2) / div) as u32;
+        let error_ppm = error_ppm(clock, div, baudrate);
+        if error_ppm > config.max_error_ppm {
+            return Err(UsartConfigError::BaudError {
+                baudrate: actual,
+                error_ppm,
+            });
+        }
+        return Ok(UsartBaud {
+            presc: presc as u8,
+            brr: brr as u32,
+            baudrate: actual,
+            error_ppm,
+        });
+    }
+    Err(UsartConfigError::BaudrateTooLow)
+}
+
+/// Baud rate given by `PRESC`/`BRR` values, for example the ones measured by the auto baud rate
+/// detection. 0 for a divider below 16.
+pub fn baudrate_from_brr(kernel_freq: u32, presc: u8, brr: u32, oversampling: Oversampling) -> u32 {
+    let prescaler = PRESCALERS[(presc as usize).min(PRESCALERS.len() - 1)] as u64;
+    let (clock, usartdiv) = match oversampling {
+        Oversampling::By8 => (
+            2 * kernel_freq as u64,
+            ((brr & 0xfff0) | ((brr & 0x7) << 1)) as u64,
+        ),
+        Oversampling::By16 => (kernel_freq as u64, (brr & 0xffff) as u64),
+    };
+    if usartdiv < 16 {
+        return 0;
+    }
+    let div = prescaler * usartdiv;
+    ((clock + div / 2) / div) as u32
+}
+
+/// Driver enable (DE) output of the RS-485 mode.
+#[derive(Debug, Copy, Clone, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Rs485Config {
+    /// DE is high while transmitting (`DEP = 0`)
+    pub active_high: bool,
+    /// DE asserted this long before the start bit, in sample times (1/16 or 1/8 bit), 0 - 31
+    pub assertion_time: u8,
+    /// DE kept asserted this long after the last stop bit, in sample times, 0 - 31
+    pub deassertion_time: u8,
+}
+
+impl Default for Rs485Config {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Rs485Config {
+    /// DE active high, no guard times.
+    pub fn new() -> Self {
+        Self {
+            active_high: true,
+            assertion_time: 0,
+            deassertion_time: 0,
+        }
+    }
+
+    /// The times fit the 5-bit `DEAT`/`DEDT` fields.
+    pub fn is_valid(&self) -> bool {
+        self.assertion_time <= 31 && self.deassertion_time <= 31
+    }
+}
+
+/// Sample times covering at least `ns` nanoseconds with the baud rate and oversampling of
+/// `config`, for `Rs485Config`. None if it does not fit in 31 sample times.
+pub fn de_time(config: &UsartConfig, ns: u32) -> Option<u8> {
+    let samples_per_bit: u64 = match config.oversampling {
+        Oversampling::By8 => 8,
+        Oversampling::By16 => 16,
+    };
+    let samples = (ns as u64 * config.baudrate as u64 * samples_per_bit).div_ceil(1_000_000_000);
+    (samples <= 31).then_some(samples as u8)
+}
+
+/// Bit times covering at least `us` microseconds at `baudrate`, for the receiver timeout
+/// (`RTOR`). None if it does not fit in 24 bits.
+pub fn receiver_timeout_bits(baudrate: u32, us: u32) -> Option<u32> {
+    let bits = (us as u64 * baudrate as u64).div_ceil(1_000_000);
+    (bits <= 0xff_ffff).then_some(bits as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Baud rate from the register values, the reference manual formulas.
+    fn baud_from_registers(kernel_freq: u32, oversampling: Oversampling, baud: &UsartBaud) -> f64 {
+        let clock = kernel_freq as f64 / PRESCALERS[baud.presc as usize] as f64;
+        match oversampling {
+            Oversampling::By16 => clock / baud.brr as f64,
+            Oversampling::By8 => {
+                let usartdiv = (baud.brr & 0xfff0) | ((baud.brr & 0x7) << 1);
+                2.0 * clock / usartdiv as f64
+            }
+        }
+    }
+
+    #[test]
+    fn test_common_rates() {
+        let baud = compute_baud(16_000_000, &UsartConfig::new(115_200)).unwrap();
+        // 16 MHz / 115200 = 138.9
+        assert_eq!((baud.presc, baud.brr), (0, 139));
+        assert_eq!(baud.baudrate, 115_108);
+        assert_eq!(baud.error_ppm, 799);
+
+        // 8x oversampling: USARTDIV = 278 = 0x116, BRR[2:0] = USARTDIV[3:1]
+        let mut config = UsartConfig::new(115_200);
+        config.oversampling = Oversampling::By8;
+        let baud = compute_baud(16_000_000, &config).unwrap();
+        assert_eq!(baud.brr, 0x113);
+        assert_eq!((baud.baudrate, baud.error_ppm), (115_108, 799));
+
+        // 2 Mbaud only with 8x oversampling at 16 MHz
+        let mut config = UsartConfig::new(2_000_000);
+        assert_eq!(
+            compute_baud(16_000_000, &config),
+            Err(UsartConfigError::KernelClockTooSlow)
+        );
+        config.oversampling = Oversampling::By8;
+        let baud = compute_baud(16_000_000, &config).unwrap();
+        assert_eq!((baud.brr, baud.error_ppm), (0x10, 0));
+    }
+
+    #[test]
+    fn test_prescaler_for_low_rates() {
+        // 160 MHz / 300 does not fit 16 bits without the prescaler
+        let baud = compute_baud(160_000_000, &UsartConfig::new(300)).unwrap();
+        assert_eq!(PRESCALERS[baud.presc as usize], 10);
+        assert!(baud.error_ppm < 10);
+
+        assert_eq!(
+            compute_baud(160_000_000, &UsartConfig::new(2)),
+            Err(UsartConfigError::BaudrateTooLow)
+        );
+    }
+
+    #[test]
+    fn test_baud_error_is_rejected() {
+        // 4 MHz / 16 / 230400: divider 17.4, rounds to 17 (2.1 % fast)
+        let mut config = UsartConfig::new(230_400);
+        config.max_error_ppm = 20_000;
+        match compute_baud(4_000_000, &config) {
+            Err(UsartConfigError::BaudError {
+                baudrate,
+                error_ppm,
+            }) => {
+                assert_eq!(baudrate, 235_294);
+                assert_eq!(error_ppm, 21_241);
+            }
+            res => panic!("{res:?}"),
+        }
+        config.max_error_ppm = 25_000;
+        assert!(compute_baud(4_000_000, &config).is_ok());
+        assert_eq!(
+            compute_baud(4_000_000, &UsartConfig::new(0)),
+            Err(UsartConfigError::InvalidBaudrate)
+        );
+    }
+
+    #[test]
+    fn test_matrix() {
+        let kernels = [4_000_000, 16_000_000, 48_000_000, 160_000_000];
+        let rates = [
+            1_200, 9_600, 19_200, 57_600, 115_200, 460_800, 921_600, 4_000_000,
+        ];
+        for &kernel in &kernels {
+            for &rate in &rates {
+                for oversampling in [Oversampling::By8, Oversampling::By16] {
+                    let mut config = UsartConfig::new(rate);
+                    config.oversampling = oversampling;
+                    config.max_error_ppm = u32::MAX;
+                    let Ok(baud) = compute_baud(kernel, &config) else {
+                        continue;
+                    };
+                    let actual = baud_from_registers(kernel, oversampling, &baud);
+                    assert!(
+                        (actual - baud.baudrate as f64).abs() < 1.0,
+                        "{kernel} {rate} {baud:?}"
+                    );
+                    let error = (actual - rate as f64).abs() / rate as f64 * 1e6;
+                    assert!(
+                        (error - baud.error_ppm as f64).abs() < 2.0,
+                        "{kernel} {rate} {baud:?}"
+                    );
+                    // rounding to the nearest divider: within half a step of fck / baud
+                    let div = kernel as f64 / PRESCALERS[baud.presc as usize] as f64 / actual;
+                    assert!(error <= 0.5 / div * 1e6 * 1.01 + 1.0, "{kernel} {rate}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_lpuart_baud() {
+        // 16 MHz: 256 * 16e6 / 9600 = 426666.7
+        let baud = compute_lpuart_baud(16_000_000, &UsartConfig::new(9_600)).unwrap();
+        assert_eq!((baud.presc, baud.brr), (0, 426_667));
+        assert_eq!(baud.baudrate, 9_600);
+        assert!(baud.error_ppm < 1);
+        let baud = compute_lpuart_baud(16_000_000, &UsartConfig::new(115_200)).unwrap();
+        assert_eq!((baud.presc, baud.brr), (0, 0x8ae4));
+
+        // LSE for STOP2 reception: 256 * 32768 / 9600 = 873.8, 3 * 9600 <= 32768
+        let baud = compute_lpuart_baud(32_768, &UsartConfig::new(9_600)).unwrap();
+        assert_eq!(baud.brr, 874);
+        assert_eq!(baud.baudrate, 9_598);
+        assert_eq!(
+            compute_lpuart_baud(32_768, &UsartConfig::new(19_200)),
+            Err(UsartConfigError::KernelClockTooSlow)
+        );
+
+        // fCK > 4096 * baud needs the prescaler
+        let baud = compute_lpuart_baud(160_000_000, &UsartConfig::new(9_600)).unwrap();
+        assert_eq!(PRESCALERS[baud.presc as usize], 6);
+        assert_eq!(baud.brr, 711_111);
+        assert_eq!(
+            compute_lpuart_baud(160_000_000, &UsartConfig::new(1)),
+            Err(UsartConfigError::BaudrateTooLow)
+        );
+    }
+
+    #[test]
+    fn test_lpuart_stop_bits() {
+        let mut config = UsartConfig::new(9_600);
+        config.stop_bits = StopBits::Two;
+        assert!(compute_lpuart_baud(16_000_000, &config).is_ok());
+        for stop_bits in [StopBits::Half, StopBits::OneAndHalf] {
+            config.stop_bits = stop_bits;
+            assert_eq!(
+                compute_lpuart_baud(16_000_000, &config),
+                Err(UsartConfigError::UnsupportedStopBits)
+            );
+        }
+    }
+
+    #[test]
+    fn test_de_time() {
+        let mut config = UsartConfig::new(115_200);
+        // one bit is 8.68 us, 16 samples
+        assert_eq!(de_time(&config, 0), Some(0));
+        assert_eq!(de_time(&config, 8_680), Some(16));
+        assert_eq!(de_time(&config, 1_000), Some(2));
+        assert_eq!(de_time(&config, 17_000), None);
+        config.oversampling = Oversampling::By8;
+        assert_eq!(de_time(&config, 17_000), Some(16));
+
+        assert!(Rs485Config::new().is_valid());
+        let rs485 = Rs485Config {
+            assertion_time: 32,
+            ..Rs485Config::new()
+        };
+        assert!(!rs485.is_valid());
+    }
+
+    #[test]
+    fn test_baudrate_from_brr() {
+        for oversampling in [Oversampling::By16, Oversampling::By8] {
+            for baudrate in [1_200, 9_600, 57_600, 115_200, 250_000] {
+                let config = UsartConfig {
+                    oversampling,
+                    ..UsartConfig::new(baudrate)
+                };
+                let baud = compute_baud(16_000_000, &config).unwrap();
+                assert_eq!(
+                    baudrate_from_brr(16_000_000, baud.presc, baud.brr, oversampling),
+                    baud.baudrate
+                );
+            }
+        }
+        // 0x55 measured at 115200 baud with 16 MHz
+        assert_eq!(
+            baudrate_from_brr(16_000_000, 0, 139, Oversampling::By16),
+            115_108
+        );
+        assert_eq!(baudrate_from_brr(16_000_000, 0, 15, Oversampling::By16), 0);
+    }
+
+    #[test]
+    fn test_receiver_timeout_bits() {
+        // modbus t3.5 at 9600 baud: 3.5 * 11 bits = 4010 us
+        assert_eq!(receiver_timeout_bits(9_600, 4_010), Some(39));
+        // fixed 1750 us above 19200 baud
+        assert_eq!(receiver_timeout_bits(115_200, 1_750), Some(202));
+        assert_eq!(receiver_timeout_bits(4_000_000, 5_000_000), None);
+    }
+
+    #[test]
+    fn test_word_length() {
+        let mut config = UsartConfig::new(9_600);
+        assert_eq!(config.word_length(), Ok(WordLength::Bits8));
+        config.data_bits = DataBits::Seven;
+        assert_eq!(config.word_length(), Ok(WordLength::Bits7));
+        config.parity = Parity::Even;
+        assert_eq!(config.word_length(), Ok(WordLength::Bits8));
+        assert_eq!(config.data_mask(), 0x7f);
+        config.data_bits = DataBits::Eight;
+        assert_eq!(config.word_length(), Ok(WordLength::Bits9));
+        config.data_bits = DataBits::Nine;
+        assert_eq!(
+            config.word_length(),
+            Err(UsartConfigError::InvalidWordLength)
+        );
+        assert_eq!(
+            compute_baud(16_000_000, &config),
+            Err(UsartConfigError::InvalidWordLength)
+        );
+    }
+}
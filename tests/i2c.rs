@@ -85,7 +85,7 @@ mod tests {
                 .await
                 .expect("Slave wait_address failed");
             match event {
-                I2cSlaveEvent::Write => {
+                I2cSlaveEvent::Write(_) => {
                     let mut rx_buf = [0u8; 2];
                     slave
                         .slave_read_async(&mut rx_buf)
@@ -94,7 +94,7 @@ mod tests {
                     #[cfg(feature = "defmt")]
                     defmt::info!("Slave: Received data 1: {:x}", rx_buf);
                 }
-                I2cSlaveEvent::Read => panic!("Slave expected Write, got Read"),
+                I2cSlaveEvent::Read(_) => panic!("Slave expected Write, got Read"),
             }
 
             // Slave: Wait for second write (2 bytes)
@@ -105,7 +105,7 @@ mod tests {
                 .await
                 .expect("Slave wait_address failed");
             match event {
-                I2cSlaveEvent::Write => {
+                I2cSlaveEvent::Write(_) => {
                     let mut rx_buf = [0u8; 2];
                     slave
                         .slave_read_async(&mut rx_buf)
@@ -114,7 +114,7 @@ mod tests {
                     #[cfg(feature = "defmt")]
                     defmt::info!("Slave: Received data 2: {:x}", rx_buf);
                 }
-                I2cSlaveEvent::Read => panic!("Slave expected Write, got Read"),
+                I2cSlaveEvent::Read(_) => panic!("Slave expected Write, got Read"),
             }
 
             // Slave: Wait for read request and reply
@@ -125,7 +125,7 @@ mod tests {
                 .await
                 .expect("Slave wait_address failed");
             match event {
-                I2cSlaveEvent::Read => {
+                I2cSlaveEvent::Read(_) => {
                     let tx_data = [0x11, 0x22, 0x33, 0x44];
                     slave
                         .slave_write_async(&tx_data)
@@ -134,7 +134,7 @@ mod tests {
                     #[cfg(feature = "defmt")]
                     defmt::info!("Slave: Sent data: {:x}", tx_data);
                 }
-                I2cSlaveEvent::Write => panic!("Slave expected Read, got Write"),
+                I2cSlaveEvent::Write(_) => panic!("Slave expected Read, got Write"),
             }
         };
 